@@ -1,6 +1,8 @@
 #[macro_use]
 extern crate hlua;
 
+use hlua::{UserdataMut, UserdataRef};
+
 fn main() {
     let mut lua = hlua::Lua::new();
     lua.openlibs();
@@ -21,6 +23,9 @@ fn main() {
         print("hello world from within lua!");
         print("is the sound playing:", s:is_playing());
 
+        s.volume = 0.5;
+        print("the volume is:", s.volume);
+
         s:stop();
         print("is the sound playing:", s:is_playing());
 
@@ -32,31 +37,45 @@ fn main() {
 // this `Sound` struct is the object that we will use to demonstrate hlua
 struct Sound {
     playing: bool,
+    volume: f64,
 }
 
 // this macro implements the required trait so that we can *push* the object to lua
 // (ie. move it inside lua)
-implement_lua_push!(Sound, |mut metatable| {
-    // we create a `__index` entry in the metatable
-    // when the lua code calls `sound:play()`, it will look for `play` in there
-    let mut index = metatable.empty_array("__index");
-
-    index.set("play", hlua::function1(|snd: &mut Sound| snd.play()));
-
-    index.set("stop", hlua::function1(|snd: &mut Sound| snd.stop()));
-
-    index.set(
-        "is_playing",
-        hlua::function1(|snd: &Sound| snd.is_playing()),
-    );
+implement_lua_push!(Sound, |metatable| {
+    // `UserdataMethods` generates the `__index` and `__newindex` entries of the metatable
+    // when the lua code calls `sound:play()`, it will look for `play` in the methods
+    // when it reads or writes `sound.volume`, the getter or the setter is called instead
+    hlua::UserdataMethods::<Sound>::new()
+        .add_method(
+            "play",
+            hlua::function1(|mut snd: UserdataMut<Sound>| snd.play()),
+        )
+        .add_method(
+            "stop",
+            hlua::function1(|mut snd: UserdataMut<Sound>| snd.stop()),
+        )
+        .add_method(
+            "is_playing",
+            hlua::function1(|snd: UserdataRef<Sound>| snd.is_playing()),
+        )
+        .add_field_getter(
+            "volume",
+            hlua::function1(|snd: UserdataRef<Sound>| snd.volume),
+        )
+        .add_field_setter(
+            "volume",
+            hlua::function2(|mut snd: UserdataMut<Sound>, volume: f64| snd.volume = volume),
+        )
+        .apply(metatable);
 });
 
-// this macro implements the require traits so that we can *read* the object back
-implement_lua_read!(Sound);
-
 impl Sound {
     pub fn new() -> Sound {
-        Sound { playing: false }
+        Sound {
+            playing: false,
+            volume: 1.0,
+        }
     }
 
     pub fn play(&mut self) {
@@ -1,3 +1,5 @@
+use std::ptr;
+
 use ffi;
 
 use AsLua;
@@ -240,6 +242,14 @@ where
                     Err(lua) => lua,
                 };
 
+            // Since Lua 5.3, numbers that aren't integers can't be read as `i32`. They are
+            // truncated like Lua 5.2 does, instead of being converted to strings in place, which
+            // would break the iteration of the table they are the keys of.
+            if data_type == ffi::LUA_TNUMBER {
+                let number = unsafe { ffi::lua_tonumberx(lua.as_lua().0, index, ptr::null_mut()) };
+                return Ok(AnyHashableLuaValue::LuaNumber(number as i32));
+            }
+
             let mut lua =
                 match LuaRead::lua_read_at_position(&mut lua as &mut AsMutLua<'lua>, index) {
                     Ok(v) => return Ok(AnyHashableLuaValue::LuaBoolean(v)),
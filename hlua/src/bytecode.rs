@@ -0,0 +1,216 @@
+// Support for the format of precompiled chunks of Lua 5.2, as produced by `lua_dump`.
+
+// Size of the header: signature, version, format, endianness, sizes of `int`, `size_t`,
+// `Instruction` and `lua_Number`, integral flag, and the `LUAC_TAIL` bytes.
+const HEADER_SIZE: usize = 18;
+
+// Type tags of the constants.
+const TNIL: u8 = 0;
+const TBOOLEAN: u8 = 1;
+const TNUMBER: u8 = 3;
+const TSTRING: u8 = 4;
+
+/// Removes the debug information (source name, line numbers, names of local variables and
+/// upvalues) from a precompiled chunk, like `luac -s` does.
+///
+/// Returns `None` if the chunk is malformed.
+pub(crate) fn strip(chunk: &[u8]) -> Option<Vec<u8>> {
+    if chunk.len() < HEADER_SIZE || !chunk.starts_with(b"\x1bLua") {
+        return None;
+    }
+
+    let mut stripper = Stripper {
+        input: chunk,
+        pos: 0,
+        output: Vec::with_capacity(chunk.len()),
+        little_endian: chunk[6] == 1,
+        int_size: chunk[7] as usize,
+        size_t_size: chunk[8] as usize,
+        instruction_size: chunk[9] as usize,
+        number_size: chunk[10] as usize,
+    };
+
+    stripper.copy(HEADER_SIZE)?;
+    stripper.function()?;
+    if stripper.pos != chunk.len() {
+        return None;
+    }
+    Some(stripper.output)
+}
+
+struct Stripper<'a> {
+    input: &'a [u8],
+    pos: usize,
+    output: Vec<u8>,
+    little_endian: bool,
+    int_size: usize,
+    size_t_size: usize,
+    instruction_size: usize,
+    number_size: usize,
+}
+
+impl<'a> Stripper<'a> {
+    fn function(&mut self) -> Option<()> {
+        // Line defined, last line defined, number of parameters, vararg flag, stack size.
+        self.copy(2 * self.int_size + 3)?;
+
+        let code = self.copy_uint(self.int_size)?;
+        self.copy(code.checked_mul(self.instruction_size)?)?;
+
+        for _ in 0..self.copy_uint(self.int_size)? {
+            match self.copy(1)?[0] {
+                TNIL => {}
+                TBOOLEAN => {
+                    self.copy(1)?;
+                }
+                TNUMBER => {
+                    self.copy(self.number_size)?;
+                }
+                TSTRING => {
+                    let len = self.copy_uint(self.size_t_size)?;
+                    self.copy(len)?;
+                }
+                _ => return None,
+            }
+        }
+
+        for _ in 0..self.copy_uint(self.int_size)? {
+            self.function()?;
+        }
+
+        let upvalues = self.copy_uint(self.int_size)?;
+        self.copy(upvalues.checked_mul(2)?)?;
+
+        // Debug information: source, line info, local variables and upvalue names.
+        let source = self.read_uint(self.size_t_size)?;
+        self.skip(source)?;
+        let line_info = self.read_uint(self.int_size)?;
+        self.skip(line_info.checked_mul(self.int_size)?)?;
+        for _ in 0..self.read_uint(self.int_size)? {
+            let name = self.read_uint(self.size_t_size)?;
+            self.skip(name)?;
+            self.skip(2 * self.int_size)?;
+        }
+        for _ in 0..self.read_uint(self.int_size)? {
+            let name = self.read_uint(self.size_t_size)?;
+            self.skip(name)?;
+        }
+
+        self.write_zero(self.size_t_size);
+        self.write_zero(self.int_size);
+        self.write_zero(self.int_size);
+        self.write_zero(self.int_size);
+        Some(())
+    }
+
+    fn skip(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.input.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn copy(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.skip(len)?;
+        self.output.extend_from_slice(bytes);
+        Some(bytes)
+    }
+
+    fn read_uint(&mut self, size: usize) -> Option<usize> {
+        let bytes = self.skip(size)?;
+        let mut value: u64 = 0;
+        for i in 0..size {
+            let byte = if self.little_endian {
+                bytes[size - 1 - i]
+            } else {
+                bytes[i]
+            };
+            value = value.checked_mul(256)? | u64::from(byte);
+        }
+        if value > self.input.len() as u64 {
+            // Every count is bounded by the size of the chunk.
+            return None;
+        }
+        Some(value as usize)
+    }
+
+    fn copy_uint(&mut self, size: usize) -> Option<usize> {
+        let start = self.pos;
+        let value = self.read_uint(size)?;
+        self.output.extend_from_slice(&self.input[start..self.pos]);
+        Some(value)
+    }
+
+    fn write_zero(&mut self, size: usize) {
+        self.output.extend((0..size).map(|_| 0));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip;
+
+    use Lua;
+    use LuaBytecode;
+    use LuaFunction;
+
+    #[test]
+    fn malformed() {
+        assert!(strip(b"").is_none());
+        assert!(strip(b"return 5").is_none());
+        assert!(strip(b"\x1bLua\x52\x00\x01\x04\x08\x04\x08\x00\x19\x93\r\n\x1a\n").is_none());
+    }
+
+    // Nested functions with upvalues, and constants of every type.
+    const CODE: &str = "local prefix = 'n\\0' .. 'x'\n\
+                        local function counter(start)\n\
+                        \x20 local n = start\n\
+                        \x20 return function(step)\n\
+                        \x20   n = n + (step or 1) * 0.5\n\
+                        \x20   if n == nil or n == true or n == false then return nil end\n\
+                        \x20   return prefix .. n\n\
+                        \x20 end\n\
+                        end\n\
+                        local c = counter(2)\n\
+                        c()\n\
+                        return c(4)";
+
+    fn dump(lua: &mut Lua) -> Vec<u8> {
+        let mut f = LuaFunction::load_with_name(lua, "@script.lua", CODE).unwrap();
+        f.dump(false).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut lua = Lua::new();
+        let full = dump(&mut lua);
+        let stripped = strip(&full).unwrap();
+        assert!(stripped.len() < full.len());
+        assert!(!String::from_utf8_lossy(&stripped).contains("script.lua"));
+        assert!(!String::from_utf8_lossy(&stripped).contains("counter"));
+        assert_eq!(strip(&stripped), Some(stripped.clone()));
+
+        lua.checked_set("f", LuaBytecode(&stripped)).unwrap();
+        let value: String = lua.execute("return f()").unwrap();
+        assert_eq!(value, "n\0x4.5");
+    }
+
+    #[test]
+    fn truncated_or_corrupted() {
+        let mut lua = Lua::new();
+        let full = dump(&mut lua);
+        for len in 0..full.len() {
+            assert!(strip(&full[..len]).is_none(), "{}", len);
+        }
+
+        let mut longer = full.clone();
+        longer.push(0);
+        assert!(strip(&longer).is_none());
+
+        // Corrupted chunks may look valid, but must not make `strip` panic.
+        for i in 0..full.len() {
+            let mut corrupted = full.clone();
+            corrupted[i] ^= 0xff;
+            let _ = strip(&corrupted);
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+
+use ffi;
+use libc;
+
+use debug::{current_hook, remove_hook};
+use AsMutLua;
+use HookContext;
+use HookEvent;
+use HookMask;
+use Lua;
+
+/// Records which lines of Lua code are executed.
+///
+/// The collector is installed as the hook of a `Lua` context (see
+/// [the `set_hook` method](struct.Lua.html#method.set_hook)), which means that it replaces any
+/// hook that was previously set. Dropping the collector without calling `stop` removes the hook
+/// as well, unless another hook has replaced it in the meantime.
+///
+/// Lines are grouped by chunk name, which is the source of the chunk as returned by
+/// [`DebugInfo::source`](struct.DebugInfo.html#method.source) without its leading `@` or `=` if
+/// any. For example a chunk loaded from the file `scripts/init.lua` is named `scripts/init.lua`,
+/// and the code executed by hlua without a name is named `chunk`.
+///
+/// Only the functions that have been called at least once are known to the collector, so the
+/// lines of a function that is never called are not reported as missed.
+///
+/// # Example
+///
+/// ```
+/// use hlua::{CoverageCollector, Lua};
+///
+/// let mut lua = Lua::new();
+/// lua.openlibs();
+///
+/// let collector = CoverageCollector::start(&mut lua);
+/// lua.execute::<()>(r#"load("local a = 1\nif a > 2 then\n  a = 2\nend", "@script.lua")()"#)
+///    .unwrap();
+/// let coverage = collector.stop(&mut lua);
+///
+/// assert_eq!(coverage.hits("script.lua", 1), Some(1));
+/// assert_eq!(coverage.hits("script.lua", 3), Some(0));
+/// coverage.write_lcov(std::io::sink()).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct CoverageCollector {
+    coverage: Rc<RefCell<CollectorState>>,
+    // The context and the closure of the hook, to remove the hook when the collector is dropped.
+    lua: *mut ffi::lua_State,
+    hook: *const libc::c_void,
+}
+
+#[derive(Debug, Default)]
+struct CollectorState {
+    coverage: Coverage,
+    // Functions whose lines have been added to `coverage`, indexed by source and line where they
+    // are defined.
+    known_functions: HashSet<(String, u32)>,
+}
+
+impl CoverageCollector {
+    /// Starts collecting the lines executed by `lua`.
+    pub fn start(lua: &mut Lua) -> CoverageCollector {
+        let coverage = Rc::new(RefCell::new(CollectorState::default()));
+
+        let hook_coverage = coverage.clone();
+        let mask = HookMask::CALL | HookMask::LINE;
+        lua.set_hook(mask, 0, move |ctx, event| {
+            hook_coverage.borrow_mut().on_event(ctx, event)
+        });
+
+        let lua = lua.as_mut_lua().0;
+        CoverageCollector {
+            coverage,
+            lua,
+            hook: unsafe { current_hook(lua) },
+        }
+    }
+
+    /// Returns the lines collected so far, without stopping the collector.
+    #[inline]
+    pub fn snapshot(&self) -> Coverage {
+        self.coverage.borrow().coverage.clone()
+    }
+
+    /// Removes the collector from `lua` and returns the lines that have been collected.
+    ///
+    /// If another hook has replaced the collector in the meantime, that hook is left in place.
+    #[inline]
+    pub fn stop(self, lua: &mut Lua) -> Coverage {
+        unsafe {
+            let lua = lua.as_mut_lua().0;
+            if current_hook(lua) == self.hook {
+                remove_hook(lua);
+            }
+        }
+        self.snapshot()
+    }
+}
+
+impl Drop for CoverageCollector {
+    fn drop(&mut self) {
+        // The closure of the hook owns a reference to the state until it is collected, which
+        // happens at the latest when the context is closed. The context is therefore still open
+        // if there is such a reference.
+        if Rc::strong_count(&self.coverage) > 1 {
+            unsafe {
+                if current_hook(self.lua) == self.hook {
+                    remove_hook(self.lua);
+                }
+            }
+        }
+    }
+}
+
+impl CollectorState {
+    fn on_event(&mut self, ctx: &mut HookContext, event: HookEvent) {
+        match event {
+            HookEvent::Call | HookEvent::TailCall => {
+                let (chunk, line_defined) = {
+                    let info = ctx.info();
+                    match info.line_defined() {
+                        Some(line) => (chunk_name(info.source(), info.short_src()), line),
+                        None => return,
+                    }
+                };
+
+                if self.known_functions.insert((chunk.clone(), line_defined)) {
+                    let lines = self.coverage.chunks.entry(chunk).or_default();
+                    for line in ctx.active_lines() {
+                        lines.entry(line).or_insert(0);
+                    }
+                }
+            }
+            HookEvent::Line(line) => {
+                let chunk = {
+                    let info = ctx.info();
+                    chunk_name(info.source(), info.short_src())
+                };
+                let lines = self.coverage.chunks.entry(chunk).or_default();
+                *lines.entry(line).or_insert(0) += 1;
+            }
+            HookEvent::Return | HookEvent::Count => {}
+        }
+    }
+}
+
+// Turns the source of a function into the name under which it is reported. Unlike `short_src`,
+// the source isn't truncated, so different chunks get different names.
+fn chunk_name(source: Option<&str>, short_src: &str) -> String {
+    match source {
+        Some(source) if source.starts_with('@') || source.starts_with('=') => {
+            source[1..].to_owned()
+        }
+        Some(source) => source.to_owned(),
+        None => short_src.to_owned(),
+    }
+}
+
+/// Lines executed by Lua code, as collected by a `CoverageCollector`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Coverage {
+    /// Number of times each line has been executed, indexed by chunk name and line number.
+    ///
+    /// Lines that contain code but haven't been executed have a count of zero. Lines that don't
+    /// contain code are absent.
+    pub chunks: BTreeMap<String, BTreeMap<u32, u64>>,
+}
+
+impl Coverage {
+    /// Returns the number of times a line has been executed, or `None` if it doesn't contain any
+    /// code that has been loaded.
+    #[inline]
+    pub fn hits(&self, chunk: &str, line: u32) -> Option<u64> {
+        self.chunks
+            .get(chunk)
+            .and_then(|lines| lines.get(&line))
+            .cloned()
+    }
+
+    /// Adds the lines of `other` to this coverage, for example in order to combine the results
+    /// of multiple `Lua` contexts.
+    pub fn merge(&mut self, other: &Coverage) {
+        for (chunk, other_lines) in &other.chunks {
+            let lines = self.chunks.entry(chunk.clone()).or_default();
+            for (&line, &hits) in other_lines {
+                *lines.entry(line).or_insert(0) += hits;
+            }
+        }
+    }
+
+    /// Writes the coverage in the LCOV tracefile format (`.info` files), which can be used by
+    /// tools such as `genhtml`.
+    ///
+    /// Chunk names are used as source file names.
+    pub fn write_lcov<W>(&self, mut output: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        writeln!(output, "TN:")?;
+        for (chunk, lines) in &self.chunks {
+            writeln!(output, "SF:{}", chunk)?;
+            for (line, hits) in lines {
+                writeln!(output, "DA:{},{}", line, hits)?;
+            }
+            writeln!(output, "LF:{}", lines.len())?;
+            writeln!(output, "LH:{}", lines.values().filter(|&&h| h != 0).count())?;
+            writeln!(output, "end_of_record")?;
+        }
+
+        Ok(())
+    }
+
+    /// Same as `write_lcov`, but returns a `String`.
+    pub fn lcov(&self) -> String {
+        let mut output = Vec::new();
+        self.write_lcov(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use debug::current_hook;
+    use AsMutLua;
+    use CoverageCollector;
+    use HookMask;
+    use Lua;
+
+    #[test]
+    fn hit_lines() {
+        let mut lua = Lua::new();
+        let collector = CoverageCollector::start(&mut lua);
+        lua.execute::<()>("local a = 0\nfor i = 1, 3 do\n  a = a + i\nend\n\nlocal b = a")
+            .unwrap();
+        let coverage = collector.stop(&mut lua);
+
+        let chunk = "chunk";
+        assert_eq!(coverage.hits(chunk, 1), Some(1));
+        assert_eq!(coverage.hits(chunk, 3), Some(3));
+        assert_eq!(coverage.hits(chunk, 5), None);
+        assert_eq!(coverage.hits(chunk, 6), Some(1));
+    }
+
+    #[test]
+    fn missed_lines_of_called_functions() {
+        let mut lua = Lua::new();
+        let collector = CoverageCollector::start(&mut lua);
+        lua.execute::<()>(
+            "local function foo(a)\n  if a then\n    return 1\n  end\n  return 2\nend\nfoo(false)",
+        )
+        .unwrap();
+        let coverage = collector.stop(&mut lua);
+
+        let chunk = "chunk";
+        assert_eq!(coverage.hits(chunk, 2), Some(1));
+        assert_eq!(coverage.hits(chunk, 3), Some(0));
+        assert_eq!(coverage.hits(chunk, 5), Some(1));
+    }
+
+    #[test]
+    fn chunk_names() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        let collector = CoverageCollector::start(&mut lua);
+        lua.execute::<()>(
+            r#"
+            load("local a = 1", "@scripts/a.lua")()
+            load("local b = 2\nlocal c = 3", "=b")()
+            load("local d = 4", "d")()
+        "#,
+        )
+        .unwrap();
+        let coverage = collector.stop(&mut lua);
+
+        assert_eq!(coverage.hits("scripts/a.lua", 1), Some(1));
+        assert_eq!(coverage.hits("b", 2), Some(1));
+        assert_eq!(coverage.hits("d", 1), Some(1));
+    }
+
+    #[test]
+    fn long_chunk_names() {
+        // Lua truncates the printable description of these names to the same string.
+        let first = format!("{}1", "x".repeat(80));
+        let second = format!("{}2", "x".repeat(80));
+
+        let mut lua = Lua::new();
+        let collector = CoverageCollector::start(&mut lua);
+        lua.execute_named::<()>(&first, "local a = 1").unwrap();
+        lua.execute_named::<()>(&second, "local a = 1\nlocal b = 2")
+            .unwrap();
+        let coverage = collector.stop(&mut lua);
+
+        assert_eq!(coverage.hits(&first, 1), Some(1));
+        assert_eq!(coverage.hits(&first, 2), None);
+        assert_eq!(coverage.hits(&second, 2), Some(1));
+    }
+
+    #[test]
+    fn no_hook_when_dropped() {
+        let mut lua = Lua::new();
+        let collector = CoverageCollector::start(&mut lua);
+        drop(collector);
+        assert!(unsafe { current_hook(lua.as_mut_lua().0) }.is_null());
+
+        // A hook that replaced the collector is left in place.
+        let collector = CoverageCollector::start(&mut lua);
+        lua.set_hook(HookMask::LINE, 0, |_, _| {});
+        drop(collector);
+        assert!(!unsafe { current_hook(lua.as_mut_lua().0) }.is_null());
+    }
+
+    #[test]
+    fn lcov() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        let collector = CoverageCollector::start(&mut lua);
+        lua.execute::<()>(r#"load("local a = 1\nif a > 1 then\n  a = 2\nend", "@a.lua")()"#)
+            .unwrap();
+        let coverage = collector.stop(&mut lua);
+
+        let lcov = coverage.lcov();
+        let record = lcov
+            .split("end_of_record\n")
+            .find(|r| r.contains("SF:a.lua\n"))
+            .unwrap();
+        assert!(record.contains("DA:1,1\n"));
+        assert!(record.contains("DA:2,1\n"));
+        assert!(record.contains("DA:3,0\n"));
+        assert!(record.contains("LH:3\n"));
+        assert!(lcov.starts_with("TN:\n"));
+    }
+
+    #[test]
+    fn merge() {
+        let mut total = {
+            let mut lua = Lua::new();
+            let collector = CoverageCollector::start(&mut lua);
+            lua.execute::<()>("local a = 1").unwrap();
+            collector.stop(&mut lua)
+        };
+
+        let other = {
+            let mut lua = Lua::new();
+            let collector = CoverageCollector::start(&mut lua);
+            lua.execute::<()>("local a = 1").unwrap();
+            collector.stop(&mut lua)
+        };
+
+        total.merge(&other);
+        assert_eq!(total.hits("chunk", 1), Some(2));
+    }
+}
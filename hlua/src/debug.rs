@@ -0,0 +1,1206 @@
+use std::any::Any;
+use std::ffi::CStr;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::BitOr;
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+
+use ffi;
+use functions_write::raise;
+use libc;
+
+use AnyLuaValue;
+use AsMutLua;
+use Lua;
+use LuaFunction;
+use LuaRead;
+use Push;
+
+/// Selects the events for which a hook is called.
+///
+/// Masks can be combined with the `|` operator.
+///
+/// # Example
+///
+/// ```
+/// use hlua::HookMask;
+/// let mask = HookMask::CALL | HookMask::RETURN;
+/// assert!(mask.contains(HookMask::CALL));
+/// assert!(!mask.contains(HookMask::LINE));
+/// ```
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct HookMask(libc::c_int);
+
+impl HookMask {
+    /// Called when the interpreter calls a function, just after Lua enters the new function and
+    /// before the function gets its arguments.
+    pub const CALL: HookMask = HookMask(ffi::LUA_MASKCALL);
+    /// Called when the interpreter is about to leave a function.
+    pub const RETURN: HookMask = HookMask(ffi::LUA_MASKRET);
+    /// Called when the interpreter is about to start the execution of a new line of code, or
+    /// when it jumps back in the code (even to the same line).
+    pub const LINE: HookMask = HookMask(ffi::LUA_MASKLINE);
+    /// Called after the interpreter executes every `count` instructions.
+    pub const COUNT: HookMask = HookMask(ffi::LUA_MASKCOUNT);
+
+    /// Returns a mask that doesn't select any event.
+    #[inline]
+    pub fn empty() -> HookMask {
+        HookMask(0)
+    }
+
+    /// Returns true if no event is selected.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Returns true if all the events of `other` are selected by this mask.
+    #[inline]
+    pub fn contains(&self, other: HookMask) -> bool {
+        (self.0 & other.0) == other.0
+    }
+}
+
+impl BitOr for HookMask {
+    type Output = HookMask;
+
+    #[inline]
+    fn bitor(self, other: HookMask) -> HookMask {
+        HookMask(self.0 | other.0)
+    }
+}
+
+/// Event that triggered a call to a hook.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum HookEvent {
+    /// A function is being called.
+    Call,
+    /// A function is being called through a tail call. There is no matching `Return` event for
+    /// the function that performed the tail call.
+    ///
+    /// Lua 5.1 reported a "tail return" event instead, when a function that had been called
+    /// through a tail call returned. Lua 5.2 and later replaced it with `LUA_HOOKTAILCALL`, which
+    /// this event represents. LuaJIT follows Lua 5.1 but reports tail calls as `Call` events and
+    /// never emits tail returns, so this event never happens with it.
+    TailCall,
+    /// A function is about to return.
+    Return,
+    /// The interpreter is about to execute a new line. Contains the line number.
+    Line(u32),
+    /// The number of instructions requested when setting the hook have been executed.
+    Count,
+}
+
+impl HookEvent {
+    /// Returns `None` for events that this library doesn't know about. The hook runs inside a C
+    /// function, where panicking would abort the process.
+    #[inline]
+    fn from_raw(ar: &ffi::lua_Debug) -> Option<HookEvent> {
+        match ar.event {
+            ffi::LUA_HOOKCALL => Some(HookEvent::Call),
+            ffi::LUA_HOOKTAILCALL => Some(HookEvent::TailCall),
+            ffi::LUA_HOOKRET => Some(HookEvent::Return),
+            ffi::LUA_HOOKLINE => Some(HookEvent::Line(ar.currentline as u32)),
+            ffi::LUA_HOOKCOUNT => Some(HookEvent::Count),
+            _ => None,
+        }
+    }
+}
+
+/// Information about a function or a function activation, as returned by `lua_getinfo`.
+///
+/// The strings returned by this struct point inside the Lua context, which is why they can't
+/// outlive it.
+pub struct DebugInfo<'a> {
+    raw: &'a ffi::lua_Debug,
+}
+
+impl<'a> DebugInfo<'a> {
+    /// Builds a `DebugInfo` from a `lua_Debug` that has been filled with the `n`, `S` and `l`
+    /// options of `lua_getinfo`.
+    #[inline]
+    fn from_raw(raw: &'a ffi::lua_Debug) -> DebugInfo<'a> {
+        DebugInfo { raw }
+    }
+
+    /// Source of the chunk that created the function.
+    ///
+    /// Starts with `@` if the function was defined in a file, with `=` if the source is a
+    /// user-provided description, and is the source code itself otherwise.
+    #[inline]
+    pub fn source(&self) -> Option<&'a str> {
+        unsafe { opt_str(self.raw.source) }
+    }
+
+    /// Printable version of `source`, to be used in error messages.
+    #[inline]
+    pub fn short_src(&self) -> &'a str {
+        unsafe { opt_str(self.raw.short_src.as_ptr()).unwrap_or("") }
+    }
+
+    /// Line currently being executed, if any.
+    #[inline]
+    pub fn current_line(&self) -> Option<u32> {
+        line(self.raw.currentline)
+    }
+
+    /// Line where the definition of the function starts, if any.
+    #[inline]
+    pub fn line_defined(&self) -> Option<u32> {
+        line(self.raw.linedefined)
+    }
+
+    /// Line where the definition of the function ends, if any.
+    #[inline]
+    pub fn last_line_defined(&self) -> Option<u32> {
+        line(self.raw.lastlinedefined)
+    }
+
+    /// A reasonable name for the function, if Lua could find one.
+    #[inline]
+    pub fn name(&self) -> Option<&'a str> {
+        unsafe { opt_str(self.raw.name) }
+    }
+
+    /// Explains the `name` field. Can be `"global"`, `"local"`, `"method"`, `"field"`,
+    /// `"upvalue"`, or `""` if no name was found.
+    #[inline]
+    pub fn namewhat(&self) -> &'a str {
+        unsafe { opt_str(self.raw.namewhat).unwrap_or("") }
+    }
+
+    /// `"Lua"` if the function is a Lua function, `"C"` if it is a C or Rust function, and
+    /// `"main"` if it is the main part of a chunk.
+    #[inline]
+    pub fn what(&self) -> &'a str {
+        unsafe { opt_str(self.raw.what).unwrap_or("") }
+    }
+}
+
+impl<'a> fmt::Debug for DebugInfo<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("DebugInfo")
+            .field("source", &self.source())
+            .field("short_src", &self.short_src())
+            .field("current_line", &self.current_line())
+            .field("name", &self.name())
+            .field("namewhat", &self.namewhat())
+            .field("what", &self.what())
+            .finish()
+    }
+}
+
+#[inline]
+unsafe fn opt_str<'a>(ptr: *const libc::c_char) -> Option<&'a str> {
+    if ptr.is_null() {
+        None
+    } else {
+        CStr::from_ptr(ptr).to_str().ok()
+    }
+}
+
+#[inline]
+fn line(raw: libc::c_int) -> Option<u32> {
+    if raw < 0 {
+        None
+    } else {
+        Some(raw as u32)
+    }
+}
+
+/// Context passed to a hook when it is called.
+///
+/// See [the `set_hook` method](struct.Lua.html#method.set_hook).
+#[derive(Debug)]
+pub struct HookContext {
+    lua: *mut ffi::lua_State,
+    ar: *mut ffi::lua_Debug,
+    info_loaded: bool,
+}
+
+impl HookContext {
+    /// Returns information about the function that is running and triggered the hook.
+    #[inline]
+    pub fn info(&mut self) -> DebugInfo<'_> {
+        unsafe {
+            if !self.info_loaded {
+                ffi::lua_getinfo(self.lua, b"nSl\0".as_ptr() as *const _, self.ar);
+                self.info_loaded = true;
+            }
+
+            DebugInfo::from_raw(&*self.ar)
+        }
+    }
+
+    /// Returns the functions currently running, starting with the one that triggered the hook.
+    ///
+    /// See [the `StackFrame` struct](struct.StackFrame.html).
+    #[inline]
+    pub fn stack_frames(&mut self) -> Vec<StackFrame<'_>> {
+        unsafe { stack_frames(self.lua) }
+    }
+
+    /// Returns the lines of the running function that contain code, in increasing order.
+    ///
+    /// Always empty for Rust and C functions.
+    pub fn active_lines(&mut self) -> Vec<u32> {
+        unsafe {
+            let mut ar = ptr::read(self.ar);
+            ffi::lua_getinfo(self.lua, b"L\0".as_ptr() as *const _, &mut ar);
+            if !ffi::lua_istable(self.lua, -1) {
+                ffi::lua_pop(self.lua, 1);
+                return Vec::new();
+            }
+
+            let mut lines = Vec::new();
+            ffi::lua_pushnil(self.lua);
+            while ffi::lua_next(self.lua, -2) != 0 {
+                lines.push(ffi::lua_tointegerx(self.lua, -2, ptr::null_mut()) as u32);
+                ffi::lua_pop(self.lua, 1);
+            }
+            ffi::lua_pop(self.lua, 1);
+
+            lines.sort();
+            lines
+        }
+    }
+
+    // Identifies the function activation that triggered the hook. Two activations that are
+    // running at the same time never have the same identifier, but the identifier of an
+    // activation that has returned is reused by the next call at the same depth.
+    #[inline]
+    pub(crate) fn activation_id(&self) -> (usize, usize) {
+        unsafe { (self.lua as usize, (*self.ar).i_ci as usize) }
+    }
+
+    // Identifies the activation of the function that called the one that triggered the hook, or
+    // returns `None` if the latter is at the bottom of the stack of its thread.
+    #[inline]
+    pub(crate) fn caller_activation_id(&self) -> Option<(usize, usize)> {
+        unsafe {
+            let mut ar = ffi::lua_Debug::default();
+            if ffi::lua_getstack(self.lua, 1, &mut ar) == 0 {
+                return None;
+            }
+            Some((self.lua as usize, ar.i_ci as usize))
+        }
+    }
+
+    #[inline]
+    pub(crate) fn raw_lua(&self) -> *mut ffi::lua_State {
+        self.lua
+    }
+}
+
+/// Function activation on the Lua call stack.
+///
+/// Level 0 is the function that is currently running, level 1 is the function that called it,
+/// and so on. Stack frames are obtained from within a hook with
+/// [`HookContext::stack_frames`](struct.HookContext.html#method.stack_frames).
+///
+/// Local variables are numbered from 1 in the order in which they are declared, and only the
+/// variables that are active at the current position of the function are visible. Lua also
+/// reports its own internal variables, whose name starts with `(`, such as `(for index)`.
+///
+/// # Example
+///
+/// ```
+/// use hlua::{AnyLuaValue, HookEvent, HookMask, Lua};
+///
+/// let mut b = None;
+/// {
+///     let mut lua = Lua::new();
+///     lua.set_hook(HookMask::LINE, 0, |ctx, event| {
+///         if event == HookEvent::Line(3) {
+///             b = ctx.stack_frames()[0].local(2);
+///         }
+///     });
+///     lua.execute::<()>("local a = 5\nlocal b = a * 2\nreturn b").unwrap();
+/// }
+/// assert_eq!(b, Some(("b".to_owned(), AnyLuaValue::LuaNumber(10.0))));
+/// ```
+pub struct StackFrame<'a> {
+    lua: *mut ffi::lua_State,
+    level: u32,
+    ar: ffi::lua_Debug,
+    marker: PhantomData<&'a mut ()>,
+}
+
+impl<'a> StackFrame<'a> {
+    /// Returns the level of this frame in the stack.
+    #[inline]
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
+    /// Returns information about the function and the position in its source.
+    #[inline]
+    pub fn info(&self) -> DebugInfo<'_> {
+        DebugInfo::from_raw(&self.ar)
+    }
+
+    /// Returns the name and value of the local variable number `n`, if it exists.
+    ///
+    /// Tables are read with their content. Where a table contains one of the tables that
+    /// enclose it, such as `_G` in `_ENV`, it is read as `LuaOther`.
+    pub fn local(&self, n: u32) -> Option<(String, AnyLuaValue)> {
+        unsafe {
+            let name = ffi::lua_getlocal(self.lua, &self.ar, n as libc::c_int);
+            if name.is_null() {
+                return None;
+            }
+            Some((owned_str(name), read_top(self.lua)))
+        }
+    }
+
+    /// Returns the names and values of all the active local variables, in order.
+    pub fn locals(&self) -> Vec<(String, AnyLuaValue)> {
+        (1..)
+            .map(|n| self.local(n))
+            .take_while(|l| l.is_some())
+            .map(|l| l.unwrap())
+            .collect()
+    }
+
+    /// Modifies the value of the local variable number `n`. Returns its name, or `None` if it
+    /// doesn't exist.
+    pub fn set_local(&mut self, n: u32, value: AnyLuaValue) -> Option<String> {
+        unsafe {
+            push_value(self.lua, value);
+            let name = ffi::lua_setlocal(self.lua, &mut self.ar, n as libc::c_int);
+            if name.is_null() {
+                ffi::lua_pop(self.lua, 1);
+                return None;
+            }
+            Some(owned_str(name))
+        }
+    }
+
+    /// Returns the name and value of the upvalue number `n` of the running function, if it
+    /// exists.
+    pub fn upvalue(&self, n: u32) -> Option<(String, AnyLuaValue)> {
+        unsafe {
+            self.push_function();
+            let upvalue = get_upvalue(self.lua, n);
+            ffi::lua_pop(self.lua, 1);
+            upvalue
+        }
+    }
+
+    /// Returns the names and values of all the upvalues of the running function.
+    pub fn upvalues(&self) -> Vec<(String, AnyLuaValue)> {
+        unsafe {
+            self.push_function();
+            let upvalues = get_upvalues(self.lua);
+            ffi::lua_pop(self.lua, 1);
+            upvalues
+        }
+    }
+
+    /// Modifies the value of the upvalue number `n` of the running function. Returns its name,
+    /// or `None` if it doesn't exist.
+    pub fn set_upvalue(&mut self, n: u32, value: AnyLuaValue) -> Option<String> {
+        unsafe {
+            self.push_function();
+            let name = set_upvalue(self.lua, n, value);
+            ffi::lua_pop(self.lua, 1);
+            name
+        }
+    }
+
+    // Pushes the function running at this level on the stack.
+    #[inline]
+    unsafe fn push_function(&self) {
+        let mut ar = ptr::read(&self.ar);
+        ffi::lua_getinfo(self.lua, b"f\0".as_ptr() as *const _, &mut ar);
+    }
+}
+
+impl<'a> fmt::Debug for StackFrame<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StackFrame")
+            .field("level", &self.level)
+            .field("info", &self.info())
+            .finish()
+    }
+}
+
+pub(crate) unsafe fn stack_frames<'a>(lua: *mut ffi::lua_State) -> Vec<StackFrame<'a>> {
+    let mut frames = Vec::new();
+
+    loop {
+        let mut ar = ffi::lua_Debug::default();
+        if ffi::lua_getstack(lua, frames.len() as libc::c_int, &mut ar) == 0 {
+            break;
+        }
+        ffi::lua_getinfo(lua, b"nSl\0".as_ptr() as *const _, &mut ar);
+
+        frames.push(StackFrame {
+            lua,
+            level: frames.len() as u32,
+            ar,
+            marker: PhantomData,
+        });
+    }
+
+    frames
+}
+
+#[inline]
+unsafe fn owned_str(ptr: *const libc::c_char) -> String {
+    String::from_utf8_lossy(CStr::from_ptr(ptr).to_bytes()).into_owned()
+}
+
+// Tables nested deeper than this are read as `LuaOther`.
+const MAX_TABLE_DEPTH: usize = 64;
+
+// Pops the value at the top of the stack and turns it into an `AnyLuaValue`.
+unsafe fn read_top(lua: *mut ffi::lua_State) -> AnyLuaValue {
+    let value = read_value(lua, -1, &mut Vec::new());
+    ffi::lua_pop(lua, 1);
+    value
+}
+
+// Unlike the `LuaRead` implementation of `AnyLuaValue`, this doesn't follow cycles. Locals and
+// upvalues often contain one: every function that uses a global has `_ENV` as upvalue, and
+// `_ENV._G` is `_ENV` itself. A table that contains one of its parents is read as `LuaOther` at
+// the point where reading it would recurse.
+unsafe fn read_value(
+    lua: *mut ffi::lua_State,
+    index: libc::c_int,
+    parents: &mut Vec<*const libc::c_void>,
+) -> AnyLuaValue {
+    if !ffi::lua_istable(lua, index) {
+        let mut lua = Lua::from_existing_state(lua, false);
+        return match LuaRead::lua_read_at_position(&mut lua, index) {
+            Ok(v) => v,
+            Err(_) => unreachable!(),
+        };
+    }
+
+    let table = ffi::lua_topointer(lua, index);
+    if parents.contains(&table)
+        || parents.len() >= MAX_TABLE_DEPTH
+        || ffi::lua_checkstack(lua, 2) == 0
+    {
+        return AnyLuaValue::LuaOther;
+    }
+
+    let index = ffi::lua_absindex(lua, index);
+    parents.push(table);
+    let mut entries = Vec::new();
+    ffi::lua_pushnil(lua);
+    while ffi::lua_next(lua, index) != 0 {
+        let key = read_value(lua, -2, parents);
+        let value = read_value(lua, -1, parents);
+        entries.push((key, value));
+        ffi::lua_pop(lua, 1);
+    }
+    parents.pop();
+
+    AnyLuaValue::LuaArray(entries)
+}
+
+#[inline]
+unsafe fn push_value(lua: *mut ffi::lua_State, value: AnyLuaValue) {
+    let mut lua = Lua::from_existing_state(lua, false);
+    value.push_no_err(&mut lua).forget_internal();
+}
+
+// The following functions operate on the function at the top of the stack.
+
+unsafe fn get_upvalue(lua: *mut ffi::lua_State, n: u32) -> Option<(String, AnyLuaValue)> {
+    let name = ffi::lua_getupvalue(lua, -1, n as libc::c_int);
+    if name.is_null() {
+        return None;
+    }
+    Some((owned_str(name), read_top(lua)))
+}
+
+unsafe fn get_upvalues(lua: *mut ffi::lua_State) -> Vec<(String, AnyLuaValue)> {
+    (1..)
+        .map(|n| get_upvalue(lua, n))
+        .take_while(|u| u.is_some())
+        .map(|u| u.unwrap())
+        .collect()
+}
+
+unsafe fn set_upvalue(lua: *mut ffi::lua_State, n: u32, value: AnyLuaValue) -> Option<String> {
+    push_value(lua, value);
+    let name = ffi::lua_setupvalue(lua, -2, n as libc::c_int);
+    if name.is_null() {
+        ffi::lua_pop(lua, 1);
+        return None;
+    }
+    Some(owned_str(name))
+}
+
+type HookClosure<'lua> = Box<dyn FnMut(&mut HookContext, HookEvent) + 'lua>;
+
+// The address of this static is used as the key of the hook closure in the registry.
+static HOOK_REGISTRY_KEY: u8 = 0;
+
+#[inline]
+fn hook_registry_key() -> *const libc::c_char {
+    &HOOK_REGISTRY_KEY as *const u8 as *const libc::c_char
+}
+
+type PanicPayload = Option<Box<dyn Any + Send>>;
+
+// The address of this static is used as the key in the registry of the payload of a panic caught
+// in a hook, which is resumed once the Lua code has returned to Rust. Since the payload belongs to
+// the context, it is dropped with it if it is never resumed.
+static HOOK_PANIC_REGISTRY_KEY: u8 = 0;
+
+#[inline]
+fn hook_panic_registry_key() -> *const libc::c_char {
+    &HOOK_PANIC_REGISTRY_KEY as *const u8 as *const libc::c_char
+}
+
+// Returns the location of the panic payload of the context, creating it if necessary.
+unsafe fn hook_panic_slot(lua: *mut ffi::lua_State) -> *mut PanicPayload {
+    ffi::lua_rawgetp(lua, ffi::LUA_REGISTRYINDEX, hook_panic_registry_key());
+    let mut slot = ffi::lua_touserdata(lua, -1) as *mut PanicPayload;
+    ffi::lua_pop(lua, 1);
+
+    if slot.is_null() {
+        slot = ffi::lua_newuserdata(lua, std::mem::size_of::<PanicPayload>() as _) as *mut _;
+        ptr::write(slot, None);
+
+        ffi::lua_newtable(lua);
+        ffi::lua_pushcfunction(lua, hook_panic_destructor);
+        ffi::lua_setfield(lua, -2, b"__gc\0".as_ptr() as *const _);
+        ffi::lua_setmetatable(lua, -2);
+
+        ffi::lua_rawsetp(lua, ffi::LUA_REGISTRYINDEX, hook_panic_registry_key());
+    }
+    slot
+}
+
+// Called when the userdata containing the panic payload is being dropped.
+extern "C" fn hook_panic_destructor(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        let obj = ffi::lua_touserdata(lua, -1);
+        ptr::drop_in_place(obj as *mut PanicPayload);
+        0
+    }
+}
+
+// Resumes the panic of a hook that happened while Lua code called from Rust was running in this
+// context, if any. Must be called whenever a protected call returns.
+#[inline]
+pub(crate) unsafe fn resume_hook_panic(lua: *mut ffi::lua_State) {
+    ffi::lua_rawgetp(lua, ffi::LUA_REGISTRYINDEX, hook_panic_registry_key());
+    let slot = ffi::lua_touserdata(lua, -1) as *mut PanicPayload;
+    ffi::lua_pop(lua, 1);
+
+    if let Some(payload) = slot.as_mut().and_then(Option::take) {
+        panic::resume_unwind(payload);
+    }
+}
+
+// Called by Lua whenever an event selected by the hook mask happens.
+extern "C" fn hook_wrapper(lua: *mut ffi::lua_State, ar: *mut ffi::lua_Debug) {
+    unsafe {
+        // The closure stays on the stack during the call so that it can't be collected if the
+        // hook replaces itself.
+        ffi::lua_rawgetp(lua, ffi::LUA_REGISTRYINDEX, hook_registry_key());
+        let closure = ffi::lua_touserdata(lua, -1) as *mut HookClosure<'static>;
+
+        let event = HookEvent::from_raw(&*ar);
+        if let (false, Some(event)) = (closure.is_null(), event) {
+            let mut context = HookContext {
+                lua,
+                ar,
+                info_loaded: false,
+            };
+            // Unwinding through Lua would abort, so the panic is turned into a Lua error that
+            // interrupts the code, and resumed when the code returns to Rust.
+            let result = panic::catch_unwind(AssertUnwindSafe(|| (*closure)(&mut context, event)));
+            if let Err(payload) = result {
+                *hook_panic_slot(lua) = Some(payload);
+                ffi::lua_pop(lua, 1);
+                raise(lua, "a hook panicked".to_owned());
+            }
+        }
+
+        ffi::lua_pop(lua, 1);
+    }
+}
+
+// Returns the address of the closure of the current hook, or null if there is no hook.
+pub(crate) unsafe fn current_hook(lua: *mut ffi::lua_State) -> *const libc::c_void {
+    ffi::lua_rawgetp(lua, ffi::LUA_REGISTRYINDEX, hook_registry_key());
+    let closure = ffi::lua_touserdata(lua, -1);
+    ffi::lua_pop(lua, 1);
+    closure
+}
+
+pub(crate) unsafe fn remove_hook(lua: *mut ffi::lua_State) {
+    ffi::lua_sethook(lua, None, 0, 0);
+    ffi::lua_pushnil(lua);
+    ffi::lua_rawsetp(lua, ffi::LUA_REGISTRYINDEX, hook_registry_key());
+}
+
+// Called when the userdata containing the hook closure is being dropped.
+extern "C" fn hook_destructor(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        let obj = ffi::lua_touserdata(lua, -1);
+        ptr::drop_in_place(obj as *mut HookClosure<'static>);
+        0
+    }
+}
+
+impl<'lua> Lua<'lua> {
+    /// Sets a function to be called by the interpreter when the events of `mask` happen.
+    ///
+    /// If `mask` contains `HookMask::COUNT`, the hook is also called every `count` instructions.
+    /// Replaces the previous hook, if any. Each `Lua` has its own hook, which is inherited by the
+    /// coroutines it creates.
+    ///
+    /// While the hook is running, other hooks are disabled.
+    ///
+    /// If the hook panics, the Lua code that triggered it is interrupted by an error, and the
+    /// panic is resumed once the code returns to Rust, for example by `execute` or `call`. A Lua
+    /// `pcall` can't prevent the panic from being resumed. Calling Lua from a callback that
+    /// is itself called by Lua returns to a C function, where resuming the panic aborts the
+    /// process. The panic belongs to the context: it is never resumed by another one, and it is
+    /// dropped with the context if it hasn't been resumed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{HookEvent, HookMask, Lua};
+    ///
+    /// let mut lines = Vec::new();
+    /// {
+    ///     let mut lua = Lua::new();
+    ///     lua.set_hook(HookMask::LINE, 0, |_, event| {
+    ///         if let HookEvent::Line(line) = event {
+    ///             lines.push(line);
+    ///         }
+    ///     });
+    ///     lua.execute::<()>("local a = 5\nlocal b = a * 2").unwrap();
+    /// }
+    /// assert_eq!(lines, vec![1, 2]);
+    /// ```
+    pub fn set_hook<F>(&mut self, mask: HookMask, count: u32, hook: F)
+    where
+        F: FnMut(&mut HookContext, HookEvent) + 'lua,
+    {
+        unsafe {
+            let lua = self.as_mut_lua().0;
+            let closure: HookClosure<'lua> = Box::new(hook);
+
+            let data = ffi::lua_newuserdata(lua, std::mem::size_of::<HookClosure>() as _);
+            ptr::write(data as *mut HookClosure<'lua>, closure);
+
+            ffi::lua_newtable(lua);
+            ffi::lua_pushcfunction(lua, hook_destructor);
+            ffi::lua_setfield(lua, -2, b"__gc\0".as_ptr() as *const _);
+            ffi::lua_setmetatable(lua, -2);
+
+            ffi::lua_rawsetp(lua, ffi::LUA_REGISTRYINDEX, hook_registry_key());
+            // Created now so that the hook doesn't have to allocate it when it panics.
+            hook_panic_slot(lua);
+            ffi::lua_sethook(lua, Some(hook_wrapper), mask.0, count as libc::c_int);
+        }
+    }
+
+    /// Removes the hook set with `set_hook`, if any.
+    #[inline]
+    pub fn remove_hook(&mut self) {
+        unsafe { remove_hook(self.as_mut_lua().0) }
+    }
+
+    /// Returns the events that the current hook is called for.
+    ///
+    /// Returns an empty mask if there is no hook.
+    #[inline]
+    pub fn hook_mask(&self) -> HookMask {
+        unsafe { HookMask(ffi::lua_gethookmask(self.lua.0)) }
+    }
+
+    /// Returns the count passed to `set_hook`.
+    #[inline]
+    pub fn hook_count(&self) -> u32 {
+        unsafe { ffi::lua_gethookcount(self.lua.0) as u32 }
+    }
+}
+
+impl<'lua, L> LuaFunction<L>
+where
+    L: AsMutLua<'lua>,
+{
+    /// Returns the name and value of the upvalue number `n` of the function, if it exists.
+    ///
+    /// Tables are read with their content. Where a table contains one of the tables that
+    /// enclose it, such as `_G` in `_ENV`, it is read as `LuaOther`.
+    ///
+    /// The upvalues of Rust and C functions have an empty name.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{AnyLuaValue, Lua, LuaFunction};
+    ///
+    /// let mut lua = Lua::new();
+    /// lua.execute::<()>("local a = 5; function foo() return a end").unwrap();
+    ///
+    /// let mut foo: LuaFunction<_> = lua.get("foo").unwrap();
+    /// assert_eq!(foo.upvalue(1), Some(("a".to_owned(), AnyLuaValue::LuaNumber(5.0))));
+    /// ```
+    #[inline]
+    pub fn upvalue(&mut self, n: u32) -> Option<(String, AnyLuaValue)> {
+        unsafe { get_upvalue(self.as_mut_lua().0, n) }
+    }
+
+    /// Returns the names and values of all the upvalues of the function.
+    #[inline]
+    pub fn upvalues(&mut self) -> Vec<(String, AnyLuaValue)> {
+        unsafe { get_upvalues(self.as_mut_lua().0) }
+    }
+
+    /// Modifies the value of the upvalue number `n` of the function. Returns its name, or `None`
+    /// if it doesn't exist.
+    #[inline]
+    pub fn set_upvalue(&mut self, n: u32, value: AnyLuaValue) -> Option<String> {
+        unsafe { set_upvalue(self.as_mut_lua().0, n, value) }
+    }
+
+    /// Returns the names of the parameters of the function.
+    ///
+    /// Always empty for Rust and C functions.
+    pub fn parameters(&mut self) -> Vec<String> {
+        unsafe {
+            let lua = self.as_mut_lua().0;
+            (1..)
+                .map(|n| ffi::lua_getlocal(lua, ptr::null(), n))
+                .take_while(|name| !name.is_null())
+                .map(|name| owned_str(name))
+                .collect()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use ffi;
+
+    use AnyLuaValue;
+    use AsMutLua;
+    use HookEvent;
+    use HookMask;
+    use Lua;
+    use LuaError;
+    use LuaFunction;
+
+    #[test]
+    fn line_hook() {
+        let mut lines = Vec::new();
+        {
+            let mut lua = Lua::new();
+            lua.set_hook(HookMask::LINE, 0, |_, event| match event {
+                HookEvent::Line(l) => lines.push(l),
+                _ => panic!("unexpected event {:?}", event),
+            });
+            lua.execute::<()>("local a = 1\n\nlocal b = 2\nlocal c = 3")
+                .unwrap();
+        }
+        assert_eq!(lines, vec![1, 3, 4]);
+    }
+
+    #[test]
+    fn unknown_hook_event() {
+        let unknown = ffi::lua_Debug {
+            event: 42,
+            ..Default::default()
+        };
+        assert_eq!(HookEvent::from_raw(&unknown), None);
+        let count = ffi::lua_Debug {
+            event: ffi::LUA_HOOKCOUNT,
+            ..Default::default()
+        };
+        assert_eq!(HookEvent::from_raw(&count), Some(HookEvent::Count));
+    }
+
+    #[test]
+    fn call_and_return_hook() {
+        let mut events = Vec::new();
+        {
+            let mut lua = Lua::new();
+            lua.execute::<()>("function foo() return 5 end").unwrap();
+            lua.set_hook(HookMask::CALL | HookMask::RETURN, 0, |ctx, event| {
+                let name = ctx.info().name().map(|n| n.to_owned());
+                events.push((event, name));
+            });
+            lua.execute::<()>("local a = foo()").unwrap();
+        }
+
+        assert_eq!(
+            events,
+            vec![
+                (HookEvent::Call, None),
+                (HookEvent::Call, Some("foo".to_owned())),
+                (HookEvent::Return, Some("foo".to_owned())),
+                (HookEvent::Return, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn panicking_hook() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set_hook(HookMask::LINE, 0, |_, _| panic!("hook panic"));
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            lua.execute::<()>("pcall(function() end)\nlocal a = 1")
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(payload.downcast_ref::<&str>(), Some(&"hook panic"));
+
+        lua.remove_hook();
+        let value: i32 = lua.execute("return 5").unwrap();
+        assert_eq!(value, 5);
+    }
+
+    #[test]
+    fn hook_panics_belong_to_their_context() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        struct Payload(Arc<AtomicBool>);
+        impl Drop for Payload {
+            fn drop(&mut self) {
+                self.0.store(true, Ordering::SeqCst);
+            }
+        }
+
+        let dropped = Arc::new(AtomicBool::new(false));
+        let mut first = Lua::new();
+        let mut second = Lua::new();
+        unsafe {
+            let payload = Box::new(Payload(dropped.clone()));
+            *super::hook_panic_slot(first.as_mut_lua().0) = Some(payload);
+        }
+
+        let value: i32 = second.execute("return 5").unwrap();
+        assert_eq!(value, 5);
+        assert!(!dropped.load(Ordering::SeqCst));
+
+        drop(first);
+        assert!(dropped.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn tail_call_hook() {
+        let mut events = Vec::new();
+        {
+            let mut lua = Lua::new();
+            lua.execute::<()>("function foo() return 5 end").unwrap();
+            lua.set_hook(HookMask::CALL, 0, |_, event| events.push(event));
+            lua.execute::<()>("return foo()").unwrap();
+        }
+        #[cfg(not(feature = "luajit"))]
+        assert_eq!(events, vec![HookEvent::Call, HookEvent::TailCall]);
+        #[cfg(feature = "luajit")]
+        assert_eq!(events, vec![HookEvent::Call, HookEvent::Call]);
+    }
+
+    #[test]
+    fn count_hook() {
+        let mut count = 0;
+        {
+            let mut lua = Lua::new();
+            lua.set_hook(HookMask::COUNT, 1, |_, event| {
+                assert_eq!(event, HookEvent::Count);
+                count += 1;
+            });
+            lua.execute::<()>("local a = 0; for i = 1, 10 do a = a + i end")
+                .unwrap();
+        }
+        assert!(count > 10);
+    }
+
+    #[test]
+    fn debug_info() {
+        let mut infos = Vec::new();
+        {
+            let mut lua = Lua::new();
+            lua.execute::<()>("function foo()\n  return 5\nend")
+                .unwrap();
+            lua.set_hook(HookMask::CALL, 0, |ctx, _| {
+                let info = ctx.info();
+                infos.push((
+                    info.what().to_owned(),
+                    info.namewhat().to_owned(),
+                    info.short_src().to_owned(),
+                    info.line_defined(),
+                    info.last_line_defined(),
+                ));
+            });
+            lua.execute::<()>("foo()").unwrap();
+        }
+
+        assert_eq!(infos[0].0, "main");
+        assert_eq!(
+            infos[1],
+            (
+                "Lua".to_owned(),
+                "global".to_owned(),
+                "[string \"chunk\"]".to_owned(),
+                Some(1),
+                Some(3)
+            )
+        );
+    }
+
+    #[test]
+    fn hooks_are_independent() {
+        let mut calls1 = 0;
+        let mut calls2 = 0;
+        {
+            let mut lua1 = Lua::new();
+            let mut lua2 = Lua::new();
+            lua1.set_hook(HookMask::CALL, 0, |_, _| calls1 += 1);
+            lua2.set_hook(HookMask::CALL, 0, |_, _| calls2 += 1);
+
+            lua1.execute::<()>("").unwrap();
+            lua1.execute::<()>("").unwrap();
+            lua2.execute::<()>("").unwrap();
+        }
+        assert_eq!(calls1, 2);
+        assert_eq!(calls2, 1);
+    }
+
+    #[test]
+    fn hook_can_be_replaced_and_removed() {
+        let mut lua = Lua::new();
+        lua.set_hook(HookMask::LINE, 0, |_, _| panic!("replaced hook called"));
+        lua.set_hook(HookMask::CALL | HookMask::COUNT, 100, |_, _| {});
+        assert_eq!(lua.hook_mask(), HookMask::CALL | HookMask::COUNT);
+        assert_eq!(lua.hook_count(), 100);
+        lua.execute::<()>("local a = 1").unwrap();
+
+        lua.remove_hook();
+        assert!(lua.hook_mask().is_empty());
+        lua.execute::<()>("local a = 1").unwrap();
+    }
+
+    #[test]
+    fn hook_in_coroutine() {
+        let mut lines = Vec::new();
+        {
+            let mut lua = Lua::new();
+            lua.openlibs();
+            lua.set_hook(HookMask::LINE, 0, |_, event| lines.push(event));
+            let r: Result<(), LuaError> = lua.execute(
+                "local co = coroutine.create(function()\n\
+                 return 1\n\
+                 end)\n\
+                 coroutine.resume(co)",
+            );
+            r.unwrap();
+        }
+        assert!(lines.contains(&HookEvent::Line(2)));
+    }
+
+    #[test]
+    fn stack_frames_in_hook() {
+        let mut frames = Vec::new();
+        {
+            let mut lua = Lua::new();
+            lua.execute::<()>("function foo(a)\n  local b = a * 2\n  return b\nend")
+                .unwrap();
+            lua.set_hook(HookMask::LINE, 0, |ctx, event| {
+                if event != HookEvent::Line(3) {
+                    return;
+                }
+                for frame in ctx.stack_frames() {
+                    let info = frame.info();
+                    let locals: Vec<_> = frame
+                        .locals()
+                        .into_iter()
+                        .filter(|(name, _)| !name.starts_with('('))
+                        .collect();
+                    frames.push((
+                        frame.level(),
+                        info.name().map(|n| n.to_owned()),
+                        info.current_line(),
+                        locals,
+                    ));
+                }
+            });
+            lua.execute::<()>("local x = 4\nfoo(x)").unwrap();
+        }
+
+        assert_eq!(
+            frames,
+            vec![
+                (
+                    0,
+                    Some("foo".to_owned()),
+                    Some(3),
+                    vec![
+                        ("a".to_owned(), AnyLuaValue::LuaNumber(4.0)),
+                        ("b".to_owned(), AnyLuaValue::LuaNumber(8.0)),
+                    ]
+                ),
+                (
+                    1,
+                    None,
+                    Some(2),
+                    vec![("x".to_owned(), AnyLuaValue::LuaNumber(4.0))]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn set_local_in_hook() {
+        let mut lua = Lua::new();
+        lua.set_hook(HookMask::LINE, 0, |ctx, event| {
+            if event == HookEvent::Line(2) {
+                let mut frames = ctx.stack_frames();
+                let name = frames[0].set_local(1, AnyLuaValue::LuaNumber(42.0));
+                assert_eq!(name, Some("a".to_owned()));
+                assert_eq!(frames[0].set_local(5, AnyLuaValue::LuaNil), None);
+            }
+        });
+        let a: i32 = lua.execute("local a = 1\nreturn a").unwrap();
+        assert_eq!(a, 42);
+    }
+
+    #[test]
+    fn frame_upvalues() {
+        let mut upvalues = Vec::new();
+        {
+            let mut lua = Lua::new();
+            lua.execute::<()>("local up = 'hello'\nfunction foo()\n  return up\nend")
+                .unwrap();
+            lua.set_hook(HookMask::LINE, 0, |ctx, event| {
+                if event == HookEvent::Line(3) {
+                    let mut frames = ctx.stack_frames();
+                    upvalues = frames[0].upvalues();
+                    frames[0].set_upvalue(1, AnyLuaValue::LuaString("world".to_owned()));
+                }
+            });
+            let r: String = lua.execute("return foo()").unwrap();
+            assert_eq!(r, "world");
+        }
+        assert_eq!(
+            upvalues,
+            vec![("up".to_owned(), AnyLuaValue::LuaString("hello".to_owned()))]
+        );
+    }
+
+    #[test]
+    fn function_upvalues() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("local a, b = 1, 2; function foo() return a + b end")
+            .unwrap();
+
+        let mut foo: LuaFunction<_> = lua.get("foo").unwrap();
+        assert_eq!(
+            foo.upvalues(),
+            vec![
+                ("a".to_owned(), AnyLuaValue::LuaNumber(1.0)),
+                ("b".to_owned(), AnyLuaValue::LuaNumber(2.0)),
+            ]
+        );
+        assert_eq!(foo.upvalue(3), None);
+
+        assert_eq!(
+            foo.set_upvalue(2, AnyLuaValue::LuaNumber(10.0)),
+            Some("b".to_owned())
+        );
+        assert_eq!(foo.set_upvalue(3, AnyLuaValue::LuaNil), None);
+        let r: i32 = foo.call().unwrap();
+        assert_eq!(r, 11);
+    }
+
+    #[test]
+    fn cyclic_upvalues() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.execute::<()>(
+            "local t = {} t.self = t t.x = 1
+             function foo() return t, print end",
+        )
+        .unwrap();
+
+        let mut foo: LuaFunction<_> = lua.get("foo").unwrap();
+        let upvalues = foo.upvalues();
+        // LuaJIT reads the global variables in the environment of the function, not in `_ENV`.
+        #[cfg(not(feature = "luajit"))]
+        assert_eq!(upvalues.len(), 2);
+        #[cfg(feature = "luajit")]
+        assert_eq!(upvalues.len(), 1);
+
+        assert_eq!(upvalues[0].0, "t");
+        match upvalues[0].1 {
+            AnyLuaValue::LuaArray(ref entries) => {
+                assert_eq!(entries.len(), 2);
+                assert!(entries.contains(&(
+                    AnyLuaValue::LuaString("self".to_owned()),
+                    AnyLuaValue::LuaOther
+                )));
+                assert!(entries.contains(&(
+                    AnyLuaValue::LuaString("x".to_owned()),
+                    AnyLuaValue::LuaNumber(1.0)
+                )));
+            }
+            ref v => panic!("{:?}", v),
+        }
+
+        #[cfg(not(feature = "luajit"))]
+        {
+            assert_eq!(upvalues[1].0, "_ENV");
+            match upvalues[1].1 {
+                AnyLuaValue::LuaArray(ref entries) => {
+                    assert!(entries.contains(&(
+                        AnyLuaValue::LuaString("_G".to_owned()),
+                        AnyLuaValue::LuaOther
+                    )));
+                }
+                ref v => panic!("{:?}", v),
+            }
+        }
+    }
+
+    #[test]
+    fn cyclic_locals() {
+        let mut locals = Vec::new();
+        {
+            let mut lua = Lua::new();
+            lua.set_hook(HookMask::LINE, 0, |ctx, event| {
+                if event == HookEvent::Line(2) {
+                    locals = ctx.stack_frames()[0].locals();
+                }
+            });
+            lua.execute::<()>("local t = {} t[t] = t\nreturn t")
+                .unwrap();
+        }
+        assert_eq!(
+            locals[0],
+            (
+                "t".to_owned(),
+                AnyLuaValue::LuaArray(vec![(AnyLuaValue::LuaOther, AnyLuaValue::LuaOther)])
+            )
+        );
+    }
+
+    #[test]
+    fn function_parameters() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("function foo(a, b, ...) local c end")
+            .unwrap();
+
+        let mut foo: LuaFunction<_> = lua.get("foo").unwrap();
+        assert_eq!(foo.parameters(), vec!["a".to_owned(), "b".to_owned()]);
+    }
+}
@@ -0,0 +1,1393 @@
+use ffi;
+use libc;
+
+use std::cell::RefCell;
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{CStr, CString};
+use std::io;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, ToSocketAddrs};
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use debug::stack_frames;
+use environment::set_chunk_env;
+use json::Json;
+
+use HookContext;
+use HookEvent;
+use HookMask;
+use Lua;
+use LuaCode;
+use Push;
+
+/// Debugger for the Lua code running in a `Lua` context, controlled by a client speaking the
+/// [Debug Adapter Protocol](https://microsoft.github.io/debug-adapter-protocol/), such as
+/// VS Code.
+///
+/// The debugger supports breakpoints, stepping into, over and out of functions, inspecting the
+/// call stack, the local variables and upvalues of each frame, and evaluating expressions in a
+/// frame. Evaluated statements can assign the local variables and upvalues of the frame.
+///
+/// Attaching waits for the client to finish its configuration (until it sends the
+/// `configurationDone` request). The debugger is then installed as the hook of the `Lua` context
+/// (see [the `set_hook` method](struct.Lua.html#method.set_hook)), which means that it replaces
+/// any hook that was previously set. Whenever the execution stops, the thread running the Lua
+/// code blocks until the client resumes it. Requests sent while the code is running, such as
+/// new breakpoints or `pause`, are handled before the next line is executed.
+///
+/// Breakpoints are matched against the name of the chunks. The path of a breakpoint matches a
+/// chunk named `@path` (the convention for chunks loaded from a file) or `=path`, or any chunk
+/// whose name is a suffix of the path, so that `@scripts/init.lua` matches a breakpoint in
+/// `/home/user/project/scripts/init.lua`.
+///
+/// # Example
+///
+/// ```no_run
+/// use hlua::{Debugger, Lua};
+///
+/// let mut lua = Lua::new();
+/// lua.openlibs();
+///
+/// // Waits for a client to connect on port 4711.
+/// let debugger = Debugger::listen(&mut lua, "127.0.0.1:4711").unwrap();
+/// lua.execute::<()>("assert(loadfile('script.lua'))()").unwrap();
+/// debugger.finish(&mut lua).unwrap();
+/// ```
+#[derive(Debug)]
+pub struct Debugger {
+    session: Rc<RefCell<Session>>,
+}
+
+impl Debugger {
+    /// Waits for a client to connect to `addr`, then attaches to `lua` as with `attach`.
+    pub fn listen<A>(lua: &mut Lua, addr: A) -> io::Result<Debugger>
+    where
+        A: ToSocketAddrs,
+    {
+        let listener = TcpListener::bind(addr)?;
+        Debugger::accept(lua, &listener)
+    }
+
+    /// Waits for a client to connect to `listener`, then attaches to `lua` as with `attach`.
+    pub fn accept(lua: &mut Lua, listener: &TcpListener) -> io::Result<Debugger> {
+        let (stream, _) = listener.accept()?;
+        let reader = stream.try_clone()?;
+        Debugger::attach(lua, reader, stream)
+    }
+
+    /// Attaches to `lua` using the standard input and output of the process to talk to the
+    /// client.
+    ///
+    /// Nothing else should be written to the standard output while the debugger is attached,
+    /// including by the Lua `print` function.
+    pub fn stdio(lua: &mut Lua) -> io::Result<Debugger> {
+        Debugger::attach(lua, io::stdin(), io::stdout())
+    }
+
+    /// Attaches to `lua`, receiving the messages of the client from `input` and sending the
+    /// replies to `output`.
+    ///
+    /// Messages are read from a separate thread, so that requests can be received while Lua code
+    /// is running. Returns an error if writing fails or if the client disconnects before it has
+    /// finished its configuration.
+    pub fn attach<R, W>(lua: &mut Lua, input: R, output: W) -> io::Result<Debugger>
+    where
+        R: Read + Send + 'static,
+        W: Write + 'static,
+    {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut input = BufReader::new(input);
+            while let Ok(Some(message)) = read_message(&mut input) {
+                if sender.send(message).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut session = Session {
+            incoming: receiver,
+            output: Box::new(output),
+            seq: 0,
+            configured: false,
+            detached: false,
+            breakpoints: HashMap::new(),
+            pending_stop: None,
+            step: None,
+            stopped_depth: 0,
+            #[cfg(feature = "luajit")]
+            lines: Vec::new(),
+            references: Vec::new(),
+        };
+
+        while !session.configured {
+            let message = session.incoming.recv().map_err(|_| {
+                io::Error::new(io::ErrorKind::UnexpectedEof, "debug client disconnected")
+            })?;
+            session.handle(None, &message)?;
+        }
+
+        let session = Rc::new(RefCell::new(session));
+        let hook_session = session.clone();
+        lua.set_hook(HookMask::LINE, 0, move |ctx, event| {
+            if let HookEvent::Line(line) = event {
+                hook_session.borrow_mut().on_line(ctx, line);
+            }
+        });
+
+        Ok(Debugger { session })
+    }
+
+    /// Detaches from `lua` and tells the client that the debugged program has terminated.
+    ///
+    /// Then waits up to one second for the client to acknowledge it with a `disconnect` request.
+    pub fn finish(self, lua: &mut Lua) -> io::Result<()> {
+        lua.remove_hook();
+
+        let mut session = self.session.borrow_mut();
+        if session.detached {
+            return Ok(());
+        }
+        session.send_event("terminated", Json::Object(Default::default()))?;
+        session.send_event("exited", Json::object(vec![("exitCode", Json::from(0))]))?;
+
+        let deadline = Instant::now() + Duration::from_secs(1);
+        while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+            let message = match session.incoming.recv_timeout(timeout) {
+                Ok(message) => message,
+                Err(_) => break,
+            };
+            if message.get("command").and_then(Json::as_str) == Some("disconnect") {
+                return session.respond(&message, Ok(Json::Null));
+            }
+            session.respond(&message, Err("the program has terminated".to_owned()))?;
+        }
+        Ok(())
+    }
+}
+
+// Ids that identify the single thread in the protocol.
+const THREAD_ID: i64 = 1;
+
+// Messages announcing a longer body are rejected rather than allocated.
+const MAX_MESSAGE_LENGTH: usize = 8 * 1024 * 1024;
+
+struct Session {
+    incoming: Receiver<Json>,
+    output: Box<dyn Write>,
+    // Sequence number of the last message sent.
+    seq: i64,
+    // True after the client has sent `configurationDone`.
+    configured: bool,
+    // True after the client has disconnected. The hook then does nothing.
+    detached: bool,
+    // Lines of the breakpoints, indexed by the path given by the client.
+    breakpoints: HashMap<String, BTreeSet<u32>>,
+    // If set, the execution stops at the next line for this reason.
+    pending_stop: Option<&'static str>,
+    step: Option<Step>,
+    // Number of frames on the stack when the execution stopped.
+    stopped_depth: usize,
+    // Line of the last line event of each frame of the stack.
+    #[cfg(feature = "luajit")]
+    lines: Vec<u32>,
+    // Values designated by a `variablesReference`, which is their index plus one. Only valid
+    // while the execution is stopped.
+    references: Vec<Reference>,
+}
+
+impl ::std::fmt::Debug for Session {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        f.debug_struct("Session")
+            .field("configured", &self.configured)
+            .field("detached", &self.detached)
+            .field("breakpoints", &self.breakpoints)
+            .finish()
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Step {
+    In,
+    Over(usize),
+    Out(usize),
+}
+
+#[derive(Debug, Copy, Clone)]
+enum Reference {
+    Locals(libc::c_int),
+    Upvalues(libc::c_int),
+    // Key of the table in the registry.
+    Table(libc::c_int),
+}
+
+impl Session {
+    fn on_line(&mut self, ctx: &mut HookContext, line: u32) {
+        if self.detached {
+            return;
+        }
+        let lua = ctx.raw_lua();
+        #[cfg(feature = "luajit")]
+        {
+            if unsafe { self.repeated_line(lua, line) } {
+                return;
+            }
+        }
+
+        loop {
+            match self.incoming.try_recv() {
+                Ok(message) => {
+                    if self.handle(None, &message).is_err() {
+                        self.detached = true;
+                    }
+                }
+                Err(TryRecvError::Empty) => break,
+                Err(TryRecvError::Disconnected) => {
+                    self.detached = true;
+                    break;
+                }
+            }
+        }
+        if self.detached {
+            return;
+        }
+
+        let mut reason = self.pending_stop;
+        if reason.is_none() {
+            reason = match self.step {
+                Some(Step::In) => Some("step"),
+                Some(Step::Over(depth)) if unsafe { stack_depth(lua) } <= depth => Some("step"),
+                Some(Step::Out(depth)) if unsafe { stack_depth(lua) } < depth => Some("step"),
+                _ => None,
+            };
+        }
+        if reason.is_none() && !self.breakpoints.is_empty() {
+            let info = ctx.info();
+            let chunk = chunk_name(info.source(), info.short_src());
+            if self.breakpoint_path(&chunk, line).is_some() {
+                reason = Some("breakpoint");
+            }
+        }
+
+        if let Some(reason) = reason {
+            if unsafe { self.stop(lua, reason) }.is_err() {
+                self.detached = true;
+            }
+        }
+    }
+
+    // LuaJIT reports the current line of a function again when a function that it called returns,
+    // where Lua only reports it if the execution continues on a new line. Returns true for these
+    // repeated events, which are ignored.
+    #[cfg(feature = "luajit")]
+    unsafe fn repeated_line(&mut self, lua: *mut ffi::lua_State, line: u32) -> bool {
+        let depth = stack_depth(lua);
+        let returned = self.lines.len() > depth;
+        self.lines.resize(depth, 0);
+        match self.lines.last_mut() {
+            Some(last) => {
+                let repeated = returned && *last == line;
+                *last = line;
+                repeated
+            }
+            None => false,
+        }
+    }
+
+    // Returns the path of the breakpoint set on `line` of `chunk`, if any.
+    fn breakpoint_path(&self, chunk: &str, line: u32) -> Option<&str> {
+        self.breakpoints
+            .iter()
+            .find(|(path, lines)| lines.contains(&line) && path_matches(path, chunk))
+            .map(|(path, _)| &path[..])
+    }
+
+    // Blocks until the client resumes the execution.
+    unsafe fn stop(&mut self, lua: *mut ffi::lua_State, reason: &str) -> io::Result<()> {
+        self.pending_stop = None;
+        self.step = None;
+        self.stopped_depth = stack_depth(lua);
+
+        let body = Json::object(vec![
+            ("reason", Json::from(reason)),
+            ("threadId", Json::from(THREAD_ID)),
+            ("allThreadsStopped", Json::from(true)),
+        ]);
+        let mut result = self.send_event("stopped", body);
+
+        while result.is_ok() {
+            match self.incoming.recv() {
+                Ok(message) => match self.handle(Some(lua), &message) {
+                    Ok(true) => break,
+                    Ok(false) => {}
+                    Err(err) => result = Err(err),
+                },
+                Err(_) => {
+                    self.detached = true;
+                    break;
+                }
+            }
+        }
+
+        for reference in self.references.drain(..) {
+            if let Reference::Table(key) = reference {
+                ffi::luaL_unref(lua, ffi::LUA_REGISTRYINDEX, key);
+            }
+        }
+        result
+    }
+
+    // Handles a message of the client. `lua` is set if the execution is stopped. Returns true if
+    // the execution must resume.
+    fn handle(&mut self, lua: Option<*mut ffi::lua_State>, message: &Json) -> io::Result<bool> {
+        let command = match message.get("command").and_then(Json::as_str) {
+            Some(command) => command,
+            None => return Ok(false),
+        };
+        let null = Json::Null;
+        let args = message.get("arguments").unwrap_or(&null);
+
+        let response = match (command, lua) {
+            ("initialize", _) => {
+                let capabilities = Json::object(vec![
+                    ("supportsConfigurationDoneRequest", Json::from(true)),
+                    ("supportsEvaluateForHovers", Json::from(true)),
+                ]);
+                self.respond(message, Ok(capabilities))?;
+                return self
+                    .send_event("initialized", Json::Object(Default::default()))
+                    .map(|_| false);
+            }
+            ("launch", _) | ("attach", _) => {
+                if args.get("stopOnEntry").and_then(Json::as_bool) == Some(true) {
+                    self.pending_stop = Some("entry");
+                }
+                Ok(Json::Null)
+            }
+            ("setBreakpoints", _) => Ok(self.set_breakpoints(args)),
+            ("setExceptionBreakpoints", _) => Ok(Json::Null),
+            ("configurationDone", _) => {
+                self.configured = true;
+                Ok(Json::Null)
+            }
+            ("threads", _) => {
+                let thread = Json::object(vec![
+                    ("id", Json::from(THREAD_ID)),
+                    ("name", Json::from("main")),
+                ]);
+                Ok(Json::object(vec![("threads", Json::from(vec![thread]))]))
+            }
+            ("pause", None) => {
+                self.pending_stop = Some("pause");
+                Ok(Json::Null)
+            }
+            ("pause", Some(_)) => Ok(Json::Null),
+            ("disconnect", _) => {
+                self.detached = true;
+                self.breakpoints.clear();
+                self.respond(message, Ok(Json::Null))?;
+                return Ok(true);
+            }
+            ("continue", Some(_)) => {
+                self.respond(message, Ok(Json::Null))?;
+                return Ok(true);
+            }
+            ("next", Some(_)) | ("stepIn", Some(_)) | ("stepOut", Some(_)) => {
+                self.step = Some(match command {
+                    "next" => Step::Over(self.stopped_depth),
+                    "stepIn" => Step::In,
+                    _ => Step::Out(self.stopped_depth),
+                });
+                self.respond(message, Ok(Json::Null))?;
+                return Ok(true);
+            }
+            ("stackTrace", Some(lua)) => Ok(unsafe { self.stack_trace(lua, args) }),
+            ("scopes", Some(_)) => Ok(self.scopes(args)),
+            ("variables", Some(lua)) => unsafe { self.variables(lua, args) },
+            ("evaluate", Some(lua)) => unsafe { self.evaluate(lua, args) },
+            ("stackTrace", None)
+            | ("scopes", None)
+            | ("variables", None)
+            | ("evaluate", None)
+            | ("continue", None)
+            | ("next", None)
+            | ("stepIn", None)
+            | ("stepOut", None) => Err("the execution is not stopped".to_owned()),
+            _ => Err(format!("unsupported request `{}`", command)),
+        };
+
+        self.respond(message, response).map(|_| false)
+    }
+
+    fn set_breakpoints(&mut self, args: &Json) -> Json {
+        let source = args.get("source");
+        let path = source
+            .and_then(|s| s.get("path").or_else(|| s.get("name")))
+            .and_then(Json::as_str)
+            .unwrap_or("")
+            .to_owned();
+
+        let lines: BTreeSet<u32> = match args.get("breakpoints").and_then(Json::as_array) {
+            Some(breakpoints) => breakpoints
+                .iter()
+                .filter_map(|b| b.get("line").and_then(Json::as_i64))
+                .map(|line| line as u32)
+                .collect(),
+            None => args
+                .get("lines")
+                .and_then(Json::as_array)
+                .unwrap_or(&[])
+                .iter()
+                .filter_map(Json::as_i64)
+                .map(|line| line as u32)
+                .collect(),
+        };
+
+        let breakpoints = lines
+            .iter()
+            .map(|&line| {
+                Json::object(vec![
+                    ("verified", Json::from(true)),
+                    ("line", Json::from(line)),
+                ])
+            })
+            .collect::<Vec<_>>();
+
+        if lines.is_empty() {
+            self.breakpoints.remove(&path);
+        } else {
+            self.breakpoints.insert(path, lines);
+        }
+        Json::object(vec![("breakpoints", Json::from(breakpoints))])
+    }
+
+    unsafe fn stack_trace(&mut self, lua: *mut ffi::lua_State, args: &Json) -> Json {
+        let frames = stack_frames(lua);
+        let start = args.get("startFrame").and_then(Json::as_i64).unwrap_or(0) as usize;
+        let levels = match args.get("levels").and_then(Json::as_i64) {
+            Some(levels) if levels > 0 => levels as usize,
+            _ => frames.len(),
+        };
+
+        let stack_frames = frames
+            .iter()
+            .skip(start)
+            .take(levels)
+            .map(|frame| {
+                let info = frame.info();
+                let name = match info.name() {
+                    Some(name) => name.to_owned(),
+                    None if info.what() == "main" => "main chunk".to_owned(),
+                    None => "?".to_owned(),
+                };
+
+                let mut members = vec![
+                    ("id", Json::from(frame.level() + 1)),
+                    ("name", Json::from(name)),
+                    ("line", Json::from(info.current_line().unwrap_or(0))),
+                    ("column", Json::from(1)),
+                ];
+                if info.what() != "C" {
+                    let chunk = chunk_name(info.source(), info.short_src());
+                    let mut source = vec![("name", Json::from(&chunk[..]))];
+                    if info.source().is_some_and(|s| s.starts_with('@')) {
+                        let path = self
+                            .breakpoints
+                            .keys()
+                            .find(|path| path_matches(path, &chunk))
+                            .unwrap_or(&chunk);
+                        source.push(("path", Json::from(&path[..])));
+                    }
+                    members.push(("source", Json::object(source)));
+                }
+                Json::object(members)
+            })
+            .collect::<Vec<_>>();
+
+        Json::object(vec![
+            ("stackFrames", Json::from(stack_frames)),
+            ("totalFrames", Json::from(frames.len() as i64)),
+        ])
+    }
+
+    fn scopes(&mut self, args: &Json) -> Json {
+        let level = args.get("frameId").and_then(Json::as_i64).unwrap_or(1) as libc::c_int - 1;
+
+        self.references.push(Reference::Locals(level));
+        let locals = Json::object(vec![
+            ("name", Json::from("Locals")),
+            (
+                "variablesReference",
+                Json::from(self.references.len() as i64),
+            ),
+            ("expensive", Json::from(false)),
+        ]);
+        self.references.push(Reference::Upvalues(level));
+        let upvalues = Json::object(vec![
+            ("name", Json::from("Upvalues")),
+            (
+                "variablesReference",
+                Json::from(self.references.len() as i64),
+            ),
+            ("expensive", Json::from(false)),
+        ]);
+
+        Json::object(vec![("scopes", Json::from(vec![locals, upvalues]))])
+    }
+
+    unsafe fn variables(&mut self, lua: *mut ffi::lua_State, args: &Json) -> Result<Json, String> {
+        let reference = args
+            .get("variablesReference")
+            .and_then(Json::as_i64)
+            .and_then(|r| self.references.get((r as usize).wrapping_sub(1)))
+            .cloned()
+            .ok_or_else(|| "invalid variables reference".to_owned())?;
+
+        let mut variables = Vec::new();
+        match reference {
+            Reference::Locals(level) => {
+                let mut ar = ffi::lua_Debug::default();
+                if ffi::lua_getstack(lua, level, &mut ar) != 0 {
+                    for n in 1.. {
+                        let name = ffi::lua_getlocal(lua, &ar, n);
+                        if name.is_null() {
+                            break;
+                        }
+                        let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+                        if !name.starts_with('(') {
+                            variables.push(self.variable(lua, name));
+                        }
+                        ffi::lua_pop(lua, 1);
+                    }
+                }
+            }
+            Reference::Upvalues(level) => {
+                let mut ar = ffi::lua_Debug::default();
+                if ffi::lua_getstack(lua, level, &mut ar) != 0 {
+                    ffi::lua_getinfo(lua, b"f\0".as_ptr() as *const _, &mut ar);
+                    for n in 1.. {
+                        let name = ffi::lua_getupvalue(lua, -1, n);
+                        if name.is_null() {
+                            break;
+                        }
+                        let name = CStr::from_ptr(name).to_string_lossy().into_owned();
+                        variables.push(self.variable(lua, name));
+                        ffi::lua_pop(lua, 1);
+                    }
+                    ffi::lua_pop(lua, 1);
+                }
+            }
+            Reference::Table(key) => {
+                ffi::lua_rawgeti(lua, ffi::LUA_REGISTRYINDEX, key as _);
+                ffi::lua_pushnil(lua);
+                while ffi::lua_next(lua, -2) != 0 {
+                    let name = if ffi::lua_type(lua, -2) == ffi::LUA_TSTRING {
+                        to_string(lua, -2)
+                    } else {
+                        format!("[{}]", describe(lua, -2))
+                    };
+                    variables.push(self.variable(lua, name));
+                    ffi::lua_pop(lua, 1);
+                }
+                ffi::lua_pop(lua, 1);
+            }
+        }
+
+        Ok(Json::object(vec![("variables", Json::from(variables))]))
+    }
+
+    // Evaluates an expression or a statement in the environment of a frame. The local variables
+    // and upvalues of the frame are copied into the environment, and the values that the code
+    // assigned to them are written back to the frame afterwards.
+    unsafe fn evaluate(&mut self, lua: *mut ffi::lua_State, args: &Json) -> Result<Json, String> {
+        let expression = args.get("expression").and_then(Json::as_str).unwrap_or("");
+        let level = args.get("frameId").and_then(Json::as_i64).unwrap_or(1) as libc::c_int - 1;
+        let top = ffi::lua_gettop(lua);
+
+        {
+            let mut state = Lua::from_existing_state(lua, false);
+            let code = format!("return {}", expression);
+            let pushed = LuaCode(&code).push_to_lua(&mut state);
+            match pushed {
+                Ok(guard) => {
+                    guard.forget_internal();
+                }
+                Err((_, state)) => match LuaCode(expression).push_to_lua(state) {
+                    Ok(guard) => {
+                        guard.forget_internal();
+                    }
+                    Err((err, _)) => {
+                        ffi::lua_settop(lua, top);
+                        return Err(err.to_string());
+                    }
+                },
+            }
+        }
+
+        ffi::lua_newtable(lua);
+        let env = ffi::lua_gettop(lua);
+        // The variable that each name of the environment comes from. Locals come after the
+        // upvalues and shadow them, like later locals shadow earlier ones.
+        let mut variables: HashMap<CString, Variable> = HashMap::new();
+        let mut ar = ffi::lua_Debug::default();
+        if ffi::lua_getstack(lua, level, &mut ar) != 0 {
+            ffi::lua_getinfo(lua, b"f\0".as_ptr() as *const _, &mut ar);
+            for n in 1.. {
+                let name = ffi::lua_getupvalue(lua, -1, n);
+                if name.is_null() {
+                    break;
+                }
+                variables.insert(CStr::from_ptr(name).to_owned(), Variable::Upvalue(n));
+                ffi::lua_setfield(lua, env, name);
+            }
+            ffi::lua_pop(lua, 1);
+
+            for n in 1.. {
+                let name = ffi::lua_getlocal(lua, &ar, n);
+                if name.is_null() {
+                    break;
+                }
+                if *name == b'(' as libc::c_char {
+                    ffi::lua_pop(lua, 1);
+                } else {
+                    variables.insert(CStr::from_ptr(name).to_owned(), Variable::Local(n));
+                    ffi::lua_setfield(lua, env, name);
+                }
+            }
+        }
+        ffi::lua_newtable(lua);
+        ffi::lua_pushglobaltable(lua);
+        ffi::lua_setfield(lua, -2, b"__index\0".as_ptr() as *const _);
+        ffi::lua_setmetatable(lua, env);
+        // A copy of the environment stays below the function, to read the assignments after
+        // the call.
+        ffi::lua_pushvalue(lua, env);
+        ffi::lua_insert(lua, top + 1);
+        set_chunk_env(lua);
+
+        let result = if ffi::lua_pcall(lua, 0, 1, 0) == 0 {
+            write_back(lua, level, top + 1, &variables);
+            let variable = self.variable(lua, String::new());
+            Ok(Json::object(vec![
+                (
+                    "result",
+                    variable.get("value").cloned().unwrap_or(Json::Null),
+                ),
+                ("type", variable.get("type").cloned().unwrap_or(Json::Null)),
+                (
+                    "variablesReference",
+                    variable
+                        .get("variablesReference")
+                        .cloned()
+                        .unwrap_or(Json::Null),
+                ),
+            ]))
+        } else {
+            Err(to_string(lua, -1))
+        };
+
+        ffi::lua_settop(lua, top);
+        result
+    }
+
+    // Describes the value at the top of the stack.
+    unsafe fn variable(&mut self, lua: *mut ffi::lua_State, name: String) -> Json {
+        let reference = if ffi::lua_type(lua, -1) == ffi::LUA_TTABLE {
+            ffi::lua_pushvalue(lua, -1);
+            let key = ffi::luaL_ref(lua, ffi::LUA_REGISTRYINDEX);
+            self.references.push(Reference::Table(key));
+            self.references.len() as i64
+        } else {
+            0
+        };
+
+        let type_name = ffi::lua_typename(lua, ffi::lua_type(lua, -1));
+        Json::object(vec![
+            ("name", Json::from(name)),
+            ("value", Json::from(describe(lua, -1))),
+            (
+                "type",
+                Json::from(CStr::from_ptr(type_name).to_string_lossy().into_owned()),
+            ),
+            ("variablesReference", Json::from(reference)),
+        ])
+    }
+
+    fn respond(&mut self, request: &Json, body: Result<Json, String>) -> io::Result<()> {
+        self.seq += 1;
+        let mut members = vec![
+            ("seq", Json::from(self.seq)),
+            ("type", Json::from("response")),
+            (
+                "request_seq",
+                request.get("seq").cloned().unwrap_or(Json::Null),
+            ),
+            (
+                "command",
+                request.get("command").cloned().unwrap_or(Json::Null),
+            ),
+        ];
+        match body {
+            Ok(body) => {
+                members.push(("success", Json::from(true)));
+                if body != Json::Null {
+                    members.push(("body", body));
+                }
+            }
+            Err(message) => {
+                members.push(("success", Json::from(false)));
+                members.push(("message", Json::from(message)));
+            }
+        }
+        write_message(&mut self.output, &Json::object(members))
+    }
+
+    fn send_event(&mut self, event: &str, body: Json) -> io::Result<()> {
+        self.seq += 1;
+        let message = Json::object(vec![
+            ("seq", Json::from(self.seq)),
+            ("type", Json::from("event")),
+            ("event", Json::from(event)),
+            ("body", body),
+        ]);
+        write_message(&mut self.output, &message)
+    }
+}
+
+// Variable of a frame copied into the environment of `evaluate`.
+#[derive(Debug, Copy, Clone)]
+enum Variable {
+    Local(libc::c_int),
+    Upvalue(libc::c_int),
+}
+
+// Writes the values of the environment at `env` back to the variables of the frame at `level`
+// that they were copied from, when they have changed.
+unsafe fn write_back(
+    lua: *mut ffi::lua_State,
+    level: libc::c_int,
+    env: libc::c_int,
+    variables: &HashMap<CString, Variable>,
+) {
+    let mut ar = ffi::lua_Debug::default();
+    if ffi::lua_getstack(lua, level, &mut ar) == 0 {
+        return;
+    }
+    ffi::lua_getinfo(lua, b"f\0".as_ptr() as *const _, &mut ar);
+    let function = ffi::lua_gettop(lua);
+
+    for (name, &variable) in variables {
+        // The environment falls back to the globals, which must not be read for nil variables.
+        ffi::lua_pushstring(lua, name.as_ptr());
+        ffi::lua_rawget(lua, env);
+        let old = match variable {
+            Variable::Local(n) => ffi::lua_getlocal(lua, &ar, n),
+            Variable::Upvalue(n) => ffi::lua_getupvalue(lua, function, n),
+        };
+        if old.is_null() {
+            ffi::lua_pop(lua, 1);
+            continue;
+        }
+        let changed = ffi::lua_rawequal(lua, -1, -2) == 0;
+        ffi::lua_pop(lua, if changed { 1 } else { 2 });
+        if !changed {
+            continue;
+        }
+        // Both functions pop the value.
+        match variable {
+            Variable::Local(n) => ffi::lua_setlocal(lua, &mut ar, n),
+            Variable::Upvalue(n) => ffi::lua_setupvalue(lua, function, n),
+        };
+    }
+
+    ffi::lua_pop(lua, 1);
+}
+
+// Turns the source of a function into the name under which the client sees its chunk.
+fn chunk_name(source: Option<&str>, short_src: &str) -> String {
+    match source {
+        Some(source) if source.starts_with('@') || source.starts_with('=') => {
+            source[1..].to_owned()
+        }
+        _ => short_src.to_owned(),
+    }
+}
+
+// Returns true if the breakpoint path given by the client designates the chunk.
+fn path_matches(path: &str, chunk: &str) -> bool {
+    let chunk = chunk.trim_start_matches("./");
+    if path == chunk {
+        return true;
+    }
+    path.ends_with(chunk) && path[..path.len() - chunk.len()].ends_with(['/', '\\'])
+}
+
+unsafe fn stack_depth(lua: *mut ffi::lua_State) -> usize {
+    let mut ar = ffi::lua_Debug::default();
+    let mut depth = 0;
+    while ffi::lua_getstack(lua, depth as libc::c_int, &mut ar) != 0 {
+        depth += 1;
+    }
+    depth
+}
+
+// Converts the string at the given index. Must only be called on strings and numbers.
+unsafe fn to_string(lua: *mut ffi::lua_State, index: libc::c_int) -> String {
+    let mut len = 0;
+    let ptr = ffi::lua_tolstring(lua, index, &mut len);
+    if ptr.is_null() {
+        return String::new();
+    }
+    let bytes = ::std::slice::from_raw_parts(ptr as *const u8, len);
+    String::from_utf8_lossy(bytes).into_owned()
+}
+
+// Returns a human-readable representation of a value, without calling any metamethod.
+unsafe fn describe(lua: *mut ffi::lua_State, index: libc::c_int) -> String {
+    match ffi::lua_type(lua, index) {
+        ffi::LUA_TNIL => "nil".to_owned(),
+        ffi::LUA_TBOOLEAN => (ffi::lua_toboolean(lua, index) != 0).to_string(),
+        ffi::LUA_TNUMBER => {
+            let number = ffi::lua_tonumberx(lua, index, ::std::ptr::null_mut());
+            if number.fract() == 0.0 && number.abs() < 1e15 {
+                (number as i64).to_string()
+            } else {
+                number.to_string()
+            }
+        }
+        ffi::LUA_TSTRING => {
+            // Converting a copy, in case the value is a key being iterated with `lua_next`.
+            ffi::lua_pushvalue(lua, index);
+            let string = to_string(lua, -1);
+            ffi::lua_pop(lua, 1);
+            format!("{:?}", string)
+        }
+        ty => {
+            let name = CStr::from_ptr(ffi::lua_typename(lua, ty));
+            format!(
+                "{}: {:p}",
+                name.to_string_lossy(),
+                ffi::lua_topointer(lua, index)
+            )
+        }
+    }
+}
+
+// Reads a message framed with a `Content-Length` header. Returns `None` at the end of the input.
+fn read_message<R>(input: &mut R) -> io::Result<Option<Json>>
+where
+    R: BufRead,
+{
+    let mut length = None;
+    loop {
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            if length.is_some() {
+                break;
+            }
+        } else if let Some(value) = line.strip_prefix("Content-Length:") {
+            length = value.trim().parse::<usize>().ok();
+        }
+    }
+
+    let length = length.unwrap_or(0);
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {} bytes is too long", length),
+        ));
+    }
+    let mut body = vec![0; length];
+    input.read_exact(&mut body)?;
+    let body =
+        String::from_utf8(body).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    Json::parse(&body)
+        .map(Some)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn write_message<W>(output: &mut W, message: &Json) -> io::Result<()>
+where
+    W: Write + ?Sized,
+{
+    let body = message.to_string();
+    let message = format!("Content-Length: {}\r\n\r\n{}", body.len(), body);
+    output.write_all(message.as_bytes())?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::{BufReader, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    use super::{read_message, write_message};
+    use json::Json;
+    use Debugger;
+    use Lua;
+
+    // Scripted client of the debug adapter protocol.
+    struct Client {
+        input: BufReader<TcpStream>,
+        output: TcpStream,
+        seq: i64,
+    }
+
+    impl Client {
+        fn connect(addr: ::std::net::SocketAddr) -> Client {
+            let output = TcpStream::connect(addr).unwrap();
+            Client {
+                input: BufReader::new(output.try_clone().unwrap()),
+                output,
+                seq: 0,
+            }
+        }
+
+        fn receive(&mut self) -> Json {
+            read_message(&mut self.input).unwrap().unwrap()
+        }
+
+        // Sends a request and returns the response, ignoring events received in between.
+        fn request(&mut self, command: &str, arguments: Json) -> Json {
+            self.seq += 1;
+            let message = Json::object(vec![
+                ("seq", Json::from(self.seq)),
+                ("type", Json::from("request")),
+                ("command", Json::from(command)),
+                ("arguments", arguments),
+            ]);
+            write_message(&mut self.output, &message).unwrap();
+
+            loop {
+                let message = self.receive();
+                if message.get("request_seq").and_then(Json::as_i64) == Some(self.seq) {
+                    assert_eq!(message.get("command").and_then(Json::as_str), Some(command));
+                    return message;
+                }
+            }
+        }
+
+        fn wait_event(&mut self, event: &str) -> Json {
+            loop {
+                let message = self.receive();
+                if message.get("event").and_then(Json::as_str) == Some(event) {
+                    return message.get("body").cloned().unwrap_or(Json::Null);
+                }
+            }
+        }
+
+        fn handshake(&mut self, path: &str, lines: &[u32]) {
+            let response = self.request(
+                "initialize",
+                Json::object(vec![("adapterID", Json::from("hlua"))]),
+            );
+            assert_eq!(response.get("success"), Some(&Json::Bool(true)));
+            self.wait_event("initialized");
+
+            let breakpoints = lines
+                .iter()
+                .map(|&line| Json::object(vec![("line", Json::from(line))]))
+                .collect::<Vec<_>>();
+            let response = self.request(
+                "setBreakpoints",
+                Json::object(vec![
+                    ("source", Json::object(vec![("path", Json::from(path))])),
+                    ("breakpoints", Json::from(breakpoints)),
+                ]),
+            );
+            let body = response.get("body").unwrap();
+            assert_eq!(
+                body.get("breakpoints").unwrap().as_array().unwrap().len(),
+                lines.len()
+            );
+
+            self.request("configurationDone", Json::Null);
+        }
+
+        // Returns the line and name of the frames of the stack.
+        fn stack(&mut self) -> Vec<(i64, String)> {
+            let response = self.request(
+                "stackTrace",
+                Json::object(vec![("threadId", Json::from(1))]),
+            );
+            let body = response.get("body").unwrap();
+            body.get("stackFrames")
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|f| {
+                    (
+                        f.get("line").unwrap().as_i64().unwrap(),
+                        f.get("name").unwrap().as_str().unwrap().to_owned(),
+                    )
+                })
+                .collect()
+        }
+
+        // Returns the name and value of the variables of a reference.
+        fn variables(&mut self, reference: i64) -> Vec<(String, String)> {
+            let args = Json::object(vec![("variablesReference", Json::from(reference))]);
+            let response = self.request("variables", args);
+            let body = response.get("body").unwrap();
+            body.get("variables")
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .iter()
+                .map(|v| {
+                    (
+                        v.get("name").unwrap().as_str().unwrap().to_owned(),
+                        v.get("value").unwrap().as_str().unwrap().to_owned(),
+                    )
+                })
+                .collect()
+        }
+
+        fn locals_reference(&mut self, frame: i64) -> i64 {
+            let response =
+                self.request("scopes", Json::object(vec![("frameId", Json::from(frame))]));
+            let scopes = response.get("body").unwrap().get("scopes").unwrap();
+            scopes.as_array().unwrap()[0]
+                .get("variablesReference")
+                .unwrap()
+                .as_i64()
+                .unwrap()
+        }
+
+        fn evaluate(&mut self, expression: &str, frame: i64) -> Json {
+            let args = Json::object(vec![
+                ("expression", Json::from(expression)),
+                ("frameId", Json::from(frame)),
+            ]);
+            self.request("evaluate", args)
+        }
+
+        fn wait_stop(&mut self) -> String {
+            let body = self.wait_event("stopped");
+            body.get("reason").unwrap().as_str().unwrap().to_owned()
+        }
+    }
+
+    const SCRIPT: &str = "local function add(a, b)\n\
+                                  \x20 local sum = a + b\n\
+                                  \x20 return sum\n\
+                                  end\n\
+                                  local t = { x = 1, y = 'two' }\n\
+                                  local r = add(t.x, 2)\n\
+                                  r = add(r, 3)\n\
+                                  result = r\n";
+
+    // Runs `script` as chunk `@scripts/test.lua` under a debugger driven by `client`.
+    fn run_debugged<F>(script: &str, client: F) -> Lua<'static>
+    where
+        F: FnOnce(Client) + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || client(Client::connect(addr)));
+
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set("source", script);
+        let debugger = Debugger::accept(&mut lua, &listener).unwrap();
+        lua.execute::<()>("assert(load(source, '@scripts/test.lua'))()")
+            .unwrap();
+        debugger.finish(&mut lua).unwrap();
+
+        client.join().unwrap();
+        lua
+    }
+
+    #[test]
+    fn message_length_limit() {
+        let mut input = &b"Content-Length: 7\r\n\r\n{\"a\":1}"[..];
+        let message = read_message(&mut input).unwrap().unwrap();
+        assert_eq!(message.get("a").and_then(Json::as_i64), Some(1));
+
+        // The body isn't allocated, so the missing bytes don't matter.
+        let mut input = &b"Content-Length: 1000000000\r\n\r\n{}"[..];
+        let err = read_message(&mut input).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn breakpoints_and_inspection() {
+        let mut lua = run_debugged(SCRIPT, |mut client| {
+            client.handshake("/home/user/project/scripts/test.lua", &[2, 8]);
+
+            assert_eq!(client.wait_stop(), "breakpoint");
+            let stack = client.stack();
+            assert_eq!(stack[0], (2, "add".to_owned()));
+            assert_eq!(stack[1], (6, "main chunk".to_owned()));
+
+            let response = client.request(
+                "stackTrace",
+                Json::object(vec![("threadId", Json::from(1))]),
+            );
+            let frame = &response
+                .get("body")
+                .unwrap()
+                .get("stackFrames")
+                .unwrap()
+                .as_array()
+                .unwrap()[0];
+            let source = frame.get("source").unwrap();
+            assert_eq!(
+                source.get("path").and_then(Json::as_str),
+                Some("/home/user/project/scripts/test.lua")
+            );
+
+            let locals = client.locals_reference(1);
+            let variables = client.variables(locals);
+            assert_eq!(
+                variables,
+                vec![
+                    ("a".to_owned(), "1".to_owned()),
+                    ("b".to_owned(), "2".to_owned())
+                ]
+            );
+
+            // Variables of the caller, with a table that can be expanded.
+            let locals = client.locals_reference(2);
+            let args = Json::object(vec![("variablesReference", Json::from(locals))]);
+            let response = client.request("variables", args);
+            let variables = response
+                .get("body")
+                .unwrap()
+                .get("variables")
+                .unwrap()
+                .as_array()
+                .unwrap()
+                .to_vec();
+            let t = variables
+                .iter()
+                .find(|v| v.get("name").and_then(Json::as_str) == Some("t"))
+                .unwrap();
+            let reference = t.get("variablesReference").unwrap().as_i64().unwrap();
+            assert!(reference > 0);
+            let mut fields = client.variables(reference);
+            fields.sort();
+            assert_eq!(
+                fields,
+                vec![
+                    ("x".to_owned(), "1".to_owned()),
+                    ("y".to_owned(), "\"two\"".to_owned())
+                ]
+            );
+
+            client.request("continue", Json::Null);
+            assert_eq!(client.wait_stop(), "breakpoint");
+            assert_eq!(client.stack()[0].0, 2);
+
+            client.request("continue", Json::Null);
+            assert_eq!(client.wait_stop(), "breakpoint");
+            assert_eq!(client.stack()[0], (8, "main chunk".to_owned()));
+
+            client.request("continue", Json::Null);
+            client.wait_event("terminated");
+            let response = client.request("stackTrace", Json::Null);
+            assert_eq!(response.get("success"), Some(&Json::Bool(false)));
+            client.request("disconnect", Json::Null);
+        });
+
+        let result: i32 = lua.get("result").unwrap();
+        assert_eq!(result, 6);
+    }
+
+    #[test]
+    fn stepping() {
+        run_debugged(SCRIPT, |mut client| {
+            client.handshake("scripts/test.lua", &[6]);
+            assert_eq!(client.wait_stop(), "breakpoint");
+
+            client.request("stepIn", Json::Null);
+            assert_eq!(client.wait_stop(), "step");
+            assert_eq!(client.stack()[0], (2, "add".to_owned()));
+
+            client.request("next", Json::Null);
+            assert_eq!(client.wait_stop(), "step");
+            assert_eq!(client.stack()[0], (3, "add".to_owned()));
+
+            client.request("stepOut", Json::Null);
+            assert_eq!(client.wait_stop(), "step");
+            assert_eq!(client.stack()[0].0, 7);
+
+            client.request("next", Json::Null);
+            assert_eq!(client.wait_stop(), "step");
+            assert_eq!(
+                client.stack(),
+                vec![(8, "main chunk".to_owned()), (1, "main chunk".to_owned())]
+            );
+
+            client.request("continue", Json::Null);
+            client.wait_event("terminated");
+            client.request("disconnect", Json::Null);
+        });
+    }
+
+    #[test]
+    fn evaluate() {
+        run_debugged(SCRIPT, |mut client| {
+            client.handshake("scripts/test.lua", &[3]);
+            assert_eq!(client.wait_stop(), "breakpoint");
+
+            let response = client.evaluate("sum * 10 + #tostring(a)", 1);
+            let body = response.get("body").unwrap();
+            assert_eq!(body.get("result").and_then(Json::as_str), Some("31"));
+            assert_eq!(body.get("type").and_then(Json::as_str), Some("number"));
+
+            let response = client.evaluate("t.y", 2);
+            let body = response.get("body").unwrap();
+            assert_eq!(body.get("result").and_then(Json::as_str), Some("\"two\""));
+
+            let response = client.evaluate("error('oops')", 1);
+            assert_eq!(response.get("success"), Some(&Json::Bool(false)));
+            assert!(response
+                .get("message")
+                .and_then(Json::as_str)
+                .unwrap()
+                .contains("oops"));
+
+            let response = client.evaluate("+", 1);
+            assert_eq!(response.get("success"), Some(&Json::Bool(false)));
+
+            let response = client.evaluate("nothing.field", 1);
+            assert_eq!(response.get("success"), Some(&Json::Bool(false)));
+            assert!(response
+                .get("message")
+                .and_then(Json::as_str)
+                .unwrap()
+                .contains("attempt to index"));
+
+            // Frames that don't exist only see the global variables.
+            let response = client.evaluate("sum", 10);
+            let body = response.get("body").unwrap();
+            assert_eq!(body.get("result").and_then(Json::as_str), Some("nil"));
+
+            client.request("disconnect", Json::Null);
+        });
+    }
+
+    #[test]
+    fn next_steps_over_calls() {
+        run_debugged(SCRIPT, |mut client| {
+            client.handshake("scripts/test.lua", &[5]);
+            assert_eq!(client.wait_stop(), "breakpoint");
+
+            client.request("next", Json::Null);
+            assert_eq!(client.wait_stop(), "step");
+            assert_eq!(client.stack()[0], (6, "main chunk".to_owned()));
+
+            client.request("next", Json::Null);
+            assert_eq!(client.wait_stop(), "step");
+            assert_eq!(client.stack()[0], (7, "main chunk".to_owned()));
+
+            // Stepping out of the main chunk runs it to the end.
+            client.request("stepOut", Json::Null);
+            client.wait_event("terminated");
+            client.request("disconnect", Json::Null);
+        });
+    }
+
+    #[test]
+    fn evaluate_assignments() {
+        let script = "local count = 0\n\
+                      local function bump(step)\n\
+                      \x20 count = count + step\n\
+                      \x20 return count\n\
+                      end\n\
+                      local n = bump(1)\n\
+                      result = n * 100 + count\n";
+        let mut lua = run_debugged(script, |mut client| {
+            client.handshake("scripts/test.lua", &[4]);
+            assert_eq!(client.wait_stop(), "breakpoint");
+
+            // `count` is an upvalue of `bump`, and `step` a local variable.
+            let response = client.evaluate("count, step = count + 10, 0", 1);
+            assert_eq!(response.get("success"), Some(&Json::Bool(true)));
+            let response = client.evaluate("count * 10 + step", 1);
+            let body = response.get("body").unwrap();
+            assert_eq!(body.get("result").and_then(Json::as_str), Some("110"));
+
+            // Nothing is written back when the evaluation fails.
+            let response = client.evaluate("count = 0 error('oops')", 1);
+            assert_eq!(response.get("success"), Some(&Json::Bool(false)));
+
+            client.request("continue", Json::Null);
+            client.wait_event("terminated");
+            client.request("disconnect", Json::Null);
+        });
+
+        let result: i32 = lua.get("result").unwrap();
+        assert_eq!(result, 1111);
+    }
+
+    #[test]
+    fn stop_on_entry() {
+        run_debugged(SCRIPT, |mut client| {
+            client.request("initialize", Json::Null);
+            client.request(
+                "launch",
+                Json::object(vec![("stopOnEntry", Json::from(true))]),
+            );
+            client.request("configurationDone", Json::Null);
+
+            assert_eq!(client.wait_stop(), "entry");
+            assert_eq!(client.stack(), vec![(1, "main chunk".to_owned())]);
+
+            client.request("stepIn", Json::Null);
+            assert_eq!(client.wait_stop(), "step");
+            let stack = client.stack();
+            assert_eq!(stack.len(), 2);
+            assert_eq!(stack[0].1, "main chunk");
+
+            client.request("continue", Json::Null);
+            client.wait_event("terminated");
+            client.request("disconnect", Json::Null);
+        });
+    }
+
+    #[test]
+    fn pause() {
+        run_debugged("while not done do end", |mut client| {
+            client.handshake("scripts/test.lua", &[]);
+
+            let response = client.request("pause", Json::object(vec![("threadId", Json::from(1))]));
+            assert_eq!(response.get("success"), Some(&Json::Bool(true)));
+            assert_eq!(client.wait_stop(), "pause");
+
+            let response = client.evaluate("rawset(_G, 'done', true)", 1);
+            assert_eq!(response.get("success"), Some(&Json::Bool(true)));
+
+            client.request("continue", Json::Null);
+            client.wait_event("terminated");
+            client.request("disconnect", Json::Null);
+        });
+    }
+
+    #[test]
+    fn client_disconnects_before_configuration() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        let client = thread::spawn(move || {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(b"Content-Length: 2\r\n\r\n{}").unwrap();
+        });
+
+        let mut lua = Lua::new();
+        let err = Debugger::accept(&mut lua, &listener).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+        client.join().unwrap();
+    }
+}
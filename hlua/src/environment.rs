@@ -0,0 +1,543 @@
+use ffi;
+use libc;
+
+#[cfg(not(feature = "luajit"))]
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::io::Cursor;
+
+use AsMutLua;
+use Lua;
+use LuaError;
+use LuaFunction;
+use LuaRead;
+use LuaTable;
+use Push;
+use PushGuard;
+use PushOne;
+use Void;
+
+/// Table to use as the global environment of some Lua code, giving it a read-only view of
+/// selected global variables.
+///
+/// When pushed, creates a new empty table whose metatable redirects reads of missing fields to
+/// the selected global variables, as they are at the time of the push. The code that runs in this
+/// environment can read these variables but not replace them: assigning a global variable creates
+/// it in the environment instead. The tables among the selected variables (such as `string`) are
+/// wrapped in read-only proxies, so that their content can't be modified either. Only the first
+/// level is protected: tables contained in these tables are accessible as is.
+///
+/// See [`Lua::execute_in_env`](struct.Lua.html#method.execute_in_env) and
+/// [`LuaFunction::set_environment`](struct.LuaFunction.html#method.set_environment).
+///
+/// # Example
+///
+/// ```
+/// use hlua::{Environment, Lua, LuaTable};
+///
+/// let mut lua = Lua::new();
+/// lua.openlibs();
+///
+/// // Each plugin has its own environment, stored in a variable that plugins can't access.
+/// lua.set("plugin_a", Environment::with_globals(&["string", "tostring"]));
+/// lua.set("plugin_b", Environment::with_globals(&["string", "tostring"]));
+///
+/// {
+///     let mut env: LuaTable<_> = lua.get("plugin_a").unwrap();
+///     env.execute_as_env::<(), _>("name = string.upper('a')").unwrap();
+/// }
+/// {
+///     let mut env: LuaTable<_> = lua.get("plugin_b").unwrap();
+///     let isolated: bool = env.execute_as_env("return name == nil").unwrap();
+///     assert!(isolated);
+///     assert!(env.execute_as_env::<(), _>("string.upper = nil").is_err());
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Environment {
+    globals: Vec<String>,
+}
+
+impl Environment {
+    /// Builds an environment that gives access to no global variable.
+    #[inline]
+    pub fn new() -> Environment {
+        Environment::default()
+    }
+
+    /// Builds an environment that gives a read-only access to the global variables whose names
+    /// are passed.
+    #[inline]
+    pub fn with_globals<S>(names: &[S]) -> Environment
+    where
+        S: AsRef<str>,
+    {
+        let mut env = Environment::new();
+        for name in names {
+            env = env.global(name.as_ref());
+        }
+        env
+    }
+
+    /// Adds a global variable to the ones that can be read from the environment.
+    #[inline]
+    pub fn global(mut self, name: &str) -> Environment {
+        self.globals.push(name.to_owned());
+        self
+    }
+}
+
+impl<'lua, L> Push<L> for Environment
+where
+    L: AsMutLua<'lua>,
+{
+    type Err = Void; // TODO: use `!` instead (https://github.com/rust-lang/rust/issues/35121)
+
+    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        unsafe {
+            let raw_lua = lua.as_mut_lua();
+            let l = raw_lua.0;
+
+            ffi::lua_newtable(l);
+            ffi::lua_newtable(l);
+            ffi::lua_newtable(l);
+            for name in &self.globals {
+                // Lua expects C strings, so names are truncated at the first nul character.
+                let name = CString::new(name.split('\0').next().unwrap()).unwrap();
+                ffi::lua_getglobal(l, name.as_ptr());
+                if ffi::lua_istable(l, -1) {
+                    push_read_only_proxy(l);
+                    ffi::lua_remove(l, -2);
+                }
+                ffi::lua_setfield(l, -2, name.as_ptr());
+            }
+            ffi::lua_setfield(l, -2, b"__index\0".as_ptr() as *const _);
+            ffi::lua_pushboolean(l, 0);
+            ffi::lua_setfield(l, -2, b"__metatable\0".as_ptr() as *const _);
+            ffi::lua_setmetatable(l, -2);
+
+            Ok(PushGuard {
+                lua,
+                size: 1,
+                raw_lua,
+            })
+        }
+    }
+}
+
+impl<'lua, L> PushOne<L> for Environment where L: AsMutLua<'lua> {}
+
+// Pushes a proxy of the table at the top of the stack, which allows reading but not modifying
+// the table.
+unsafe fn push_read_only_proxy(lua: *mut ffi::lua_State) {
+    ffi::lua_newtable(lua);
+    ffi::lua_newtable(lua);
+    ffi::lua_pushvalue(lua, -3);
+    ffi::lua_setfield(lua, -2, b"__index\0".as_ptr() as *const _);
+    ffi::lua_pushcfunction(lua, read_only_newindex);
+    ffi::lua_setfield(lua, -2, b"__newindex\0".as_ptr() as *const _);
+    ffi::lua_pushvalue(lua, -3);
+    ffi::lua_pushcclosure(lua, read_only_pairs, 1);
+    ffi::lua_setfield(lua, -2, b"__pairs\0".as_ptr() as *const _);
+    ffi::lua_pushvalue(lua, -3);
+    ffi::lua_pushcclosure(lua, read_only_len, 1);
+    ffi::lua_setfield(lua, -2, b"__len\0".as_ptr() as *const _);
+    ffi::lua_pushboolean(lua, 0);
+    ffi::lua_setfield(lua, -2, b"__metatable\0".as_ptr() as *const _);
+    ffi::lua_setmetatable(lua, -2);
+}
+
+extern "C" fn read_only_newindex(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        let msg = b"attempt to modify a read-only table";
+        ffi::lua_pushlstring(lua, msg.as_ptr() as *const _, msg.len() as libc::size_t);
+        ffi::lua_error(lua)
+    }
+}
+
+// `__pairs` metamethod of the proxies, iterating over the proxied table.
+extern "C" fn read_only_pairs(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        ffi::lua_pushcfunction(lua, read_only_next);
+        ffi::lua_pushvalue(lua, ffi::lua_upvalueindex(1));
+        ffi::lua_pushnil(lua);
+        3
+    }
+}
+
+extern "C" fn read_only_next(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        ffi::lua_settop(lua, 2);
+        if ffi::lua_next(lua, 1) != 0 {
+            2
+        } else {
+            ffi::lua_pushnil(lua);
+            1
+        }
+    }
+}
+
+extern "C" fn read_only_len(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        let len = ffi::lua_rawlen(lua, ffi::lua_upvalueindex(1));
+        ffi::lua_pushinteger(lua, len as ffi::lua_Integer);
+        1
+    }
+}
+
+// Sets the value at the top of the stack as the `_ENV` upvalue of the function below it, and pops
+// the value. Returns false if the function has no such upvalue.
+#[cfg(not(feature = "luajit"))]
+unsafe fn set_env_upvalue(lua: *mut ffi::lua_State) -> bool {
+    let mut n = 1;
+    loop {
+        let name = ffi::lua_getupvalue(lua, -2, n);
+        if name.is_null() {
+            ffi::lua_pop(lua, 1);
+            return false;
+        }
+        let is_env = CStr::from_ptr(name).to_bytes() == b"_ENV";
+        ffi::lua_pop(lua, 1);
+
+        if is_env {
+            ffi::lua_setupvalue(lua, -2, n);
+            return true;
+        }
+        n += 1;
+    }
+}
+
+// LuaJIT has no `_ENV`: the environment of a Lua function is a field of the function, which
+// `lua_setfenv` sets. The environment of C functions isn't used for global variables, and it
+// must be a table.
+#[cfg(feature = "luajit")]
+unsafe fn set_env_upvalue(lua: *mut ffi::lua_State) -> bool {
+    if ffi::lua_iscfunction(lua, -2) != 0 || !ffi::lua_istable(lua, -1) {
+        ffi::lua_pop(lua, 1);
+        return false;
+    }
+    ffi::lua_setfenv(lua, -2) != 0
+}
+
+// Sets the value at the top of the stack as the global environment of the main chunk below it,
+// and pops the value. Unlike `set_env_upvalue`, this works with stripped chunks, since the
+// `_ENV` upvalue of a main chunk is always the first one.
+#[cfg(not(feature = "luajit"))]
+pub(crate) unsafe fn set_chunk_env(lua: *mut ffi::lua_State) -> bool {
+    if ffi::lua_setupvalue(lua, -2, 1).is_null() {
+        ffi::lua_pop(lua, 1);
+        return false;
+    }
+    true
+}
+
+#[cfg(feature = "luajit")]
+pub(crate) unsafe fn set_chunk_env(lua: *mut ffi::lua_State) -> bool {
+    set_env_upvalue(lua)
+}
+
+// Same as `set_chunk_env`, but returns an error if the environment can't be set.
+unsafe fn check_chunk_env(lua: *mut ffi::lua_State) -> Result<(), LuaError> {
+    if set_chunk_env(lua) {
+        Ok(())
+    } else {
+        Err(LuaError::ExecutionError(
+            "the environment of the chunk can't be set".to_owned(),
+        ))
+    }
+}
+
+impl<'lua, L> LuaFunction<L>
+where
+    L: AsMutLua<'lua>,
+{
+    /// Sets the table in which the function reads and writes global variables.
+    ///
+    /// This modifies the `_ENV` upvalue of the function, which for a chunk loaded from source
+    /// code is its first upvalue. Returns false if the function has no such upvalue, which is the
+    /// case of Rust and C functions, and of Lua functions that don't access any global variable.
+    ///
+    /// Functions created by the same chunk share their `_ENV` upvalue. Setting the environment of
+    /// one of them changes it for all of them.
+    ///
+    /// With LuaJIT, which has no `_ENV`, this sets the environment of the function like `setfenv`
+    /// does instead. It returns false for Rust and C functions and if `env` isn't a table. Each
+    /// function has its own environment, which the functions it creates inherit when they are
+    /// created.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    ///
+    /// let mut lua = hlua::Lua::new();
+    /// let mut f = hlua::LuaFunction::load(&mut lua, "return a").unwrap();
+    ///
+    /// let mut env = HashMap::new();
+    /// env.insert("a", 12);
+    /// assert!(f.set_environment(env));
+    ///
+    /// let a: i32 = f.call().unwrap();
+    /// assert_eq!(a, 12);
+    /// ```
+    pub fn set_environment<E, Er>(&mut self, env: E) -> bool
+    where
+        E: for<'a> PushOne<&'a mut LuaFunction<L>, Err = Er>,
+        Er: Into<Void>,
+    {
+        let size = match env.push_to_lua(self) {
+            Ok(guard) => guard.forget_internal(),
+            Err(_) => unreachable!(),
+        };
+        debug_assert_eq!(size, 1);
+
+        unsafe { set_env_upvalue(self.as_mut_lua().0) }
+    }
+}
+
+impl<'lua> Lua<'lua> {
+    /// Executes some Lua code with `env` as its global environment.
+    ///
+    /// This does the same thing as [the `execute` method](#method.execute), except that the code
+    /// reads and writes global variables in `env` instead of the global table. `env` can be any
+    /// pushable value, usually an [`Environment`](struct.Environment.html) or a table built from
+    /// Rust such as a `HashMap`. To run code in an existing table, see
+    /// [`LuaTable::execute_as_env`](struct.LuaTable.html#method.execute_as_env).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{Environment, Lua};
+    ///
+    /// let mut lua = Lua::new();
+    /// lua.openlibs();
+    ///
+    /// let env = Environment::with_globals(&["math"]);
+    /// let v: i32 = lua.execute_in_env("x = math.abs(-3) return x", env).unwrap();
+    /// assert_eq!(v, 3);
+    ///
+    /// // `x` has been written to the environment, not to the global table.
+    /// let x: Option<i32> = lua.get("x");
+    /// assert_eq!(x, None);
+    /// ```
+    ///
+    /// The code can also be a precompiled chunk, such as one returned by
+    /// [`LuaFunction::dump`](struct.LuaFunction.html#method.dump), if the
+    /// [load mode](#method.set_load_mode) allows it. An `ExecutionError` is returned if the
+    /// environment can't be set, for example because the chunk is a dumped function that has no
+    /// upvalue, or with LuaJIT because `env` isn't a table.
+    pub fn execute_in_env<'a, T, C, E, Er>(&'a mut self, code: C, env: E) -> Result<T, LuaError>
+    where
+        T: for<'g> LuaRead<PushGuard<&'g mut PushGuard<&'a mut Lua<'lua>>>>,
+        C: AsRef<[u8]>,
+        E: for<'b> PushOne<&'b mut LuaFunction<PushGuard<&'a mut Lua<'lua>>>, Err = Er>,
+        Er: Into<Void>,
+    {
+        let mut f = LuaFunction::load_from_reader(self, Cursor::new(code.as_ref()))?;
+        let size = match env.push_to_lua(&mut f) {
+            Ok(guard) => guard.forget_internal(),
+            Err(_) => unreachable!(),
+        };
+        debug_assert_eq!(size, 1);
+        unsafe { check_chunk_env(f.as_mut_lua().0)? };
+        f.call()
+    }
+}
+
+impl<'lua, L> LuaTable<L>
+where
+    L: AsMutLua<'lua>,
+{
+    /// Executes some Lua code with this table as its global environment.
+    ///
+    /// This does the same thing as [`Lua::execute`](struct.Lua.html#method.execute), except that
+    /// the code reads and writes global variables in this table instead of the global table.
+    /// Since the table persists, it can be used to run several chunks in the same environment.
+    /// See [`Environment`](struct.Environment.html) for an example.
+    ///
+    /// As with [`Lua::execute_in_env`](struct.Lua.html#method.execute_in_env), the code can also
+    /// be a precompiled chunk, and an `ExecutionError` is returned if the environment can't be set.
+    pub fn execute_as_env<'a, T, C>(&'a mut self, code: C) -> Result<T, LuaError>
+    where
+        T: for<'g> LuaRead<PushGuard<&'g mut PushGuard<&'a mut LuaTable<L>>>>,
+        C: AsRef<[u8]>,
+    {
+        let index = unsafe { ffi::lua_absindex(self.as_mut_lua().0, self.offset(0)) };
+        let mut f = LuaFunction::load_from_reader(self, Cursor::new(code.as_ref()))?;
+        unsafe {
+            let lua = f.as_mut_lua().0;
+            ffi::lua_pushvalue(lua, index);
+            check_chunk_env(lua)?;
+        }
+        f.call()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use Environment;
+    use Lua;
+    use LuaError;
+    use LuaFunction;
+    use LuaTable;
+
+    #[test]
+    fn globals_are_isolated() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set("a", 5);
+
+        let v: i32 = lua
+            .execute_in_env("a = 12 b = a return a", Environment::new())
+            .unwrap();
+        assert_eq!(v, 12);
+
+        let a: i32 = lua.get("a").unwrap();
+        assert_eq!(a, 5);
+        let b: Option<i32> = lua.get("b");
+        assert_eq!(b, None);
+    }
+
+    #[test]
+    fn selected_globals_are_readable() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set("a", 5);
+
+        let env = Environment::with_globals(&["a", "string"]).global("tostring");
+        let v: String = lua
+            .execute_in_env(
+                "return tostring(a) .. string.rep('x', 2) .. tostring(print)",
+                env,
+            )
+            .unwrap();
+        assert_eq!(v, "5xxnil");
+    }
+
+    #[test]
+    fn tables_are_read_only() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+
+        let env = Environment::with_globals(&["string", "pairs"]);
+        match lua.execute_in_env::<(), _, _, _>("string.x = 1", env) {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(
+                    msg.contains("attempt to modify a read-only table"),
+                    "{}",
+                    msg
+                )
+            }
+            _ => panic!(),
+        }
+        let unchanged: bool = lua.execute("return string.x == nil").unwrap();
+        assert!(unchanged);
+
+        // The original table can still be iterated.
+        let env = Environment::with_globals(&["string", "pairs"]);
+        let count: i32 = lua
+            .execute_in_env(
+                "local n = 0 for k, v in pairs(string) do n = n + 1 end return n",
+                env,
+            )
+            .unwrap();
+        assert!(count > 10);
+
+        // Globals can be shadowed but not modified.
+        let env = Environment::with_globals(&["string"]);
+        lua.execute_in_env::<(), _, _, _>("string = nil", env)
+            .unwrap();
+        let string: Option<LuaTable<_>> = lua.get("string");
+        assert!(string.is_some());
+    }
+
+    #[test]
+    fn rust_table_as_environment() {
+        let mut lua = Lua::new();
+
+        let mut env = HashMap::new();
+        env.insert("a", 3);
+        env.insert("b", 4);
+        let v: i32 = lua.execute_in_env("return a * b", env).unwrap();
+        assert_eq!(v, 12);
+    }
+
+    #[test]
+    fn set_environment() {
+        let mut lua = Lua::new();
+        lua.set("a", 1);
+
+        let mut f = LuaFunction::load(&mut lua, "return a").unwrap();
+        let mut env = HashMap::new();
+        env.insert("a", 2);
+        assert!(f.set_environment(env));
+        let v: i32 = f.call().unwrap();
+        assert_eq!(v, 2);
+    }
+
+    #[test]
+    fn set_environment_without_env_upvalue() {
+        let mut lua = Lua::new();
+        lua.set("f", ::function0(|| 5));
+
+        let mut f: LuaFunction<_> = lua.get("f").unwrap();
+        assert!(!f.set_environment(Environment::new()));
+        let v: i32 = f.call().unwrap();
+        assert_eq!(v, 5);
+    }
+
+    #[test]
+    fn persistent_environment() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set("env", Environment::with_globals(&["math"]));
+
+        {
+            let mut env: LuaTable<_> = lua.get("env").unwrap();
+            env.execute_as_env::<(), _>("x = math.max(2, 7)").unwrap();
+            let x: i32 = env.execute_as_env("return x").unwrap();
+            assert_eq!(x, 7);
+        }
+
+        let x: Option<i32> = lua.get("x");
+        assert_eq!(x, None);
+        let x: i32 = lua.execute("return env.x").unwrap();
+        assert_eq!(x, 7);
+    }
+
+    #[test]
+    fn stripped_chunks() {
+        let mut lua = Lua::new();
+        let bytecode = LuaFunction::load(&mut lua, "x = 7 return x")
+            .unwrap()
+            .dump(true)
+            .unwrap();
+
+        let v: i32 = lua.execute_in_env(&bytecode, Environment::new()).unwrap();
+        assert_eq!(v, 7);
+        let x: Option<i32> = lua.get("x");
+        assert_eq!(x, None);
+
+        lua.execute::<()>("env = {}").unwrap();
+        let v: i32 = {
+            let mut env: LuaTable<_> = lua.get("env").unwrap();
+            env.execute_as_env(&bytecode).unwrap()
+        };
+        assert_eq!(v, 7);
+        let x: Option<i32> = lua.get("x");
+        assert_eq!(x, None);
+        let x: i32 = lua.execute("return env.x").unwrap();
+        assert_eq!(x, 7);
+    }
+
+    #[test]
+    fn syntax_error() {
+        let mut lua = Lua::new();
+        match lua.execute_in_env::<(), _, _, _>("x = ", Environment::new()) {
+            Err(LuaError::SyntaxError(_)) => {}
+            _ => panic!(),
+        }
+    }
+}
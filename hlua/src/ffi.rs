@@ -0,0 +1,290 @@
+//! Bindings of LuaJIT, completed with the functions of the API of Lua 5.2 that hlua uses.
+//!
+//! LuaJIT has the API of Lua 5.1. The functions defined here emulate the ones of Lua 5.2 with it,
+//! so that the rest of the crate doesn't depend on the backend:
+//!
+//! - The global table is at the `LUA_GLOBALSINDEX` pseudo-index instead of being stored in the
+//!   registry, so `LUA_RIDX_GLOBALS` doesn't exist and `lua_pushglobaltable` pushes the
+//!   pseudo-index.
+//! - Continuations don't exist: `lua_callk` and `lua_pcallk` only accept `None` as continuation.
+//! - The `luaopen_*` functions call the ones of LuaJIT through `lua_call`, as LuaJIT requires.
+//!   LuaJIT opens the `coroutine` library with the base library, so `luaopen_coroutine` opens
+//!   both.
+//!
+//! The `_ENV` upvalue of Lua 5.2 doesn't exist either. Functions have an environment instead,
+//! which `lua_setfenv` modifies.
+
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+
+use libc;
+use libc::c_int;
+
+use std::ffi::CStr;
+use std::ptr;
+
+pub use luajit_sys::*;
+
+pub type lua_Unsigned = libc::c_uint;
+
+/// LuaJIT doesn't report tail calls to the hooks, so this event is never received.
+pub const LUA_HOOKTAILCALL: c_int = LUA_HOOKTAILRET;
+
+#[inline(always)]
+pub unsafe fn lua_absindex(L: *mut lua_State, idx: c_int) -> c_int {
+    if idx > 0 || idx <= LUA_REGISTRYINDEX {
+        idx
+    } else {
+        lua_gettop(L) + idx + 1
+    }
+}
+
+#[inline(always)]
+pub unsafe fn lua_rawlen(L: *mut lua_State, idx: c_int) -> libc::size_t {
+    lua_objlen(L, idx)
+}
+
+#[inline(always)]
+pub unsafe fn lua_rawgetp(L: *mut lua_State, idx: c_int, p: *const libc::c_char) {
+    let idx = lua_absindex(L, idx);
+    lua_pushlightuserdata(L, p as *mut libc::c_void);
+    lua_rawget(L, idx)
+}
+
+#[inline(always)]
+pub unsafe fn lua_rawsetp(L: *mut lua_State, idx: c_int, p: *const libc::c_char) {
+    let idx = lua_absindex(L, idx);
+    lua_pushlightuserdata(L, p as *mut libc::c_void);
+    lua_insert(L, -2);
+    lua_rawset(L, idx)
+}
+
+#[inline(always)]
+pub unsafe fn lua_pushglobaltable(L: *mut lua_State) {
+    lua_pushvalue(L, LUA_GLOBALSINDEX)
+}
+
+/// Converts the number like Lua 5.2, which wraps the values that don't fit.
+#[inline(always)]
+pub unsafe fn lua_tounsignedx(L: *mut lua_State, idx: c_int, isnum: *mut c_int) -> lua_Unsigned {
+    lua_tonumberx(L, idx, isnum) as i64 as lua_Unsigned
+}
+
+#[inline(always)]
+pub unsafe fn lua_pushunsigned(L: *mut lua_State, n: lua_Unsigned) {
+    lua_pushnumber(L, n as lua_Number)
+}
+
+/// Loads a chunk like Lua 5.2. LuaJIT checks the mode too, but its error message doesn't say
+/// which kind of chunk has been rejected, so the mode is checked here with the first block.
+pub unsafe fn lua_load(
+    L: *mut lua_State,
+    reader: lua_Reader,
+    dt: *mut libc::c_void,
+    chunkname: *const libc::c_char,
+    mode: *const libc::c_char,
+) -> c_int {
+    // Returns the first block again, then the ones of the original reader unless the first one
+    // was the end of the chunk.
+    struct FirstBlock {
+        reader: lua_Reader,
+        data: *mut libc::c_void,
+        block: *const libc::c_char,
+        size: libc::size_t,
+        pending: bool,
+    }
+
+    extern "C" fn read(
+        L: *mut lua_State,
+        data: *mut libc::c_void,
+        size: *mut libc::size_t,
+    ) -> *const libc::c_char {
+        unsafe {
+            let first = &mut *(data as *mut FirstBlock);
+            if first.pending {
+                first.pending = false;
+                *size = first.size;
+                first.block
+            } else if first.block.is_null() || first.size == 0 {
+                *size = 0;
+                ptr::null()
+            } else {
+                (first.reader)(L, first.data, size)
+            }
+        }
+    }
+
+    let mut size = 0;
+    let block = reader(L, dt, &mut size);
+    if !block.is_null() && size != 0 {
+        let (kind, letter) = if *block as u8 == 0x1b {
+            (&b"binary\0"[..], b'b')
+        } else {
+            (&b"text\0"[..], b't')
+        };
+        if !CStr::from_ptr(mode).to_bytes().contains(&letter) {
+            lua_pushfstring(
+                L,
+                b"attempt to load a %s chunk (mode is '%s')\0".as_ptr() as *const _,
+                kind.as_ptr(),
+                mode,
+            );
+            return LUA_ERRSYNTAX;
+        }
+    }
+
+    let mut first = FirstBlock {
+        reader,
+        data: dt,
+        block,
+        size,
+        pending: true,
+    };
+    lua_loadx(
+        L,
+        read,
+        &mut first as *mut FirstBlock as *mut _,
+        chunkname,
+        mode,
+    )
+}
+
+#[inline(always)]
+pub unsafe fn lua_callk(
+    L: *mut lua_State,
+    nargs: c_int,
+    nresults: c_int,
+    _ctx: c_int,
+    k: Option<lua_CFunction>,
+) {
+    assert!(k.is_none(), "LuaJIT doesn't support continuations");
+    lua_call(L, nargs, nresults)
+}
+
+#[inline(always)]
+pub unsafe fn lua_pcallk(
+    L: *mut lua_State,
+    nargs: c_int,
+    nresults: c_int,
+    errfunc: c_int,
+    _ctx: c_int,
+    k: Option<lua_CFunction>,
+) -> c_int {
+    assert!(k.is_none(), "LuaJIT doesn't support continuations");
+    lua_pcall(L, nargs, nresults, errfunc)
+}
+
+/// Converts the value to a string like `tostring`, and pushes the result.
+pub unsafe fn luaL_tolstring(
+    L: *mut lua_State,
+    idx: c_int,
+    len: *mut libc::size_t,
+) -> *const libc::c_char {
+    if luaL_callmeta(L, idx, b"__tostring\0".as_ptr() as *const _) == 0 {
+        match lua_type(L, idx) {
+            LUA_TNUMBER | LUA_TSTRING => lua_pushvalue(L, idx),
+            LUA_TBOOLEAN if lua_toboolean(L, idx) != 0 => {
+                lua_pushstring(L, b"true\0".as_ptr() as *const _)
+            }
+            LUA_TBOOLEAN => lua_pushstring(L, b"false\0".as_ptr() as *const _),
+            LUA_TNIL => lua_pushstring(L, b"nil\0".as_ptr() as *const _),
+            _ => {
+                lua_pushfstring(
+                    L,
+                    b"%s: %p\0".as_ptr() as *const _,
+                    luaL_typename(L, idx),
+                    lua_topointer(L, idx),
+                );
+            }
+        }
+    }
+    lua_tolstring(L, -1, len)
+}
+
+pub unsafe fn luaL_getsubtable(L: *mut lua_State, idx: c_int, fname: *const libc::c_char) -> c_int {
+    lua_getfield(L, idx, fname);
+    if lua_istable(L, -1) {
+        return 1;
+    }
+    lua_pop(L, 1);
+    let idx = lua_absindex(L, idx);
+    lua_newtable(L);
+    lua_pushvalue(L, -1);
+    lua_setfield(L, idx, fname);
+    0
+}
+
+// Defines a function that calls the `luaopen_*` function of LuaJIT with the same name through
+// `lua_call`, and leaves the table of the library on the stack like Lua 5.2.
+macro_rules! luaopen_through_call {
+    ($name:ident) => {
+        pub unsafe extern "C" fn $name(L: *mut lua_State) -> c_int {
+            extern "C" fn open(L: *mut lua_State) -> c_int {
+                unsafe { ::luajit_sys::$name(L) }
+            }
+            lua_pushcfunction(L, open);
+            lua_call(L, 0, 1);
+            1
+        }
+    };
+}
+
+luaopen_through_call!(luaopen_base);
+luaopen_through_call!(luaopen_bit);
+luaopen_through_call!(luaopen_debug);
+luaopen_through_call!(luaopen_ffi);
+luaopen_through_call!(luaopen_io);
+luaopen_through_call!(luaopen_jit);
+luaopen_through_call!(luaopen_math);
+luaopen_through_call!(luaopen_os);
+luaopen_through_call!(luaopen_package);
+luaopen_through_call!(luaopen_string);
+luaopen_through_call!(luaopen_table);
+
+pub unsafe extern "C" fn luaopen_coroutine(L: *mut lua_State) -> c_int {
+    luaopen_base(L);
+    lua_getfield(L, -1, b"coroutine\0".as_ptr() as *const _);
+    lua_remove(L, -2);
+    1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lua52_functions() {
+        unsafe {
+            let L = luaL_newstate();
+            lua_pushinteger(L, 1);
+            lua_pushinteger(L, 2);
+            assert_eq!(lua_absindex(L, -1), 2);
+            assert_eq!(lua_absindex(L, LUA_REGISTRYINDEX), LUA_REGISTRYINDEX);
+            lua_settop(L, 0);
+
+            let key = b"key\0";
+            lua_pushinteger(L, 5);
+            lua_rawsetp(L, LUA_REGISTRYINDEX, key.as_ptr() as *const _);
+            lua_rawgetp(L, LUA_REGISTRYINDEX, key.as_ptr() as *const _);
+            assert_eq!(lua_tointegerx(L, -1, ptr::null_mut()), 5);
+            lua_pop(L, 1);
+
+            lua_pushnumber(L, -1.0);
+            assert_eq!(lua_tounsignedx(L, -1, ptr::null_mut()), lua_Unsigned::MAX);
+            lua_pop(L, 1);
+
+            lua_pushglobaltable(L);
+            assert_eq!(luaL_getsubtable(L, -1, b"sub\0".as_ptr() as *const _), 0);
+            lua_pop(L, 1);
+            assert_eq!(luaL_getsubtable(L, -1, b"sub\0".as_ptr() as *const _), 1);
+            let s = luaL_tolstring(L, -1, ptr::null_mut());
+            assert!(CStr::from_ptr(s).to_bytes().starts_with(b"table: "));
+            lua_settop(L, 0);
+
+            luaopen_coroutine(L);
+            lua_getfield(L, -1, b"wrap\0".as_ptr() as *const _);
+            assert!(lua_isfunction(L, -1));
+            lua_close(L);
+        }
+    }
+}
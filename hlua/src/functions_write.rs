@@ -11,6 +11,8 @@ use PushGuard;
 use PushOne;
 use Void;
 
+use stack_checks;
+
 use std::fmt::Display;
 use std::marker::PhantomData;
 use std::mem;
@@ -173,7 +175,7 @@ pub trait FunctionExt<P> {
 
 // Called when an object inside Lua is being dropped.
 #[inline]
-extern "C" fn closure_destructor_wrapper<T>(lua: *mut ffi::lua_State) -> libc::c_int {
+pub(crate) extern "C" fn closure_destructor_wrapper<T>(lua: *mut ffi::lua_State) -> libc::c_int {
     unsafe {
         let obj = ffi::lua_touserdata(lua, -1);
         ptr::drop_in_place((obj as *mut u8) as *mut T);
@@ -335,6 +337,8 @@ impl_function_ext!(A, B, C, D, E, F, G, H, I, J);
 #[derive(Debug)]
 pub struct InsideCallback {
     lua: LuaContext,
+    // Why reading the parameters failed, if more precise than a wrong type.
+    pub(crate) read_error: Option<String>,
 }
 
 unsafe impl<'a, 'lua> AsLua<'lua> for &'a InsideCallback {
@@ -386,6 +390,14 @@ where
 {
 }
 
+// Raises a Lua error with a message.
+pub(crate) unsafe fn raise(lua: *mut ffi::lua_State, msg: String) -> libc::c_int {
+    ffi::lua_pushlstring(lua, msg.as_ptr() as *const _, msg.len() as libc::size_t);
+    // The message must be dropped before `lua_error`, which doesn't return.
+    drop(msg);
+    ffi::lua_error(lua)
+}
+
 // this function is called when Lua wants to call one of our functions
 #[inline]
 extern "C" fn wrapper<T, P, R>(lua: *mut ffi::lua_State) -> libc::c_int
@@ -401,6 +413,7 @@ where
     // creating a temporary Lua context in order to pass it to push & read functions
     let mut tmp_lua = InsideCallback {
         lua: LuaContext(lua),
+        read_error: None,
     };
 
     // trying to read the arguments
@@ -408,13 +421,12 @@ where
     let args = match LuaRead::lua_read_at_position(&mut tmp_lua, -arguments_count as libc::c_int) {
         // TODO: what if the user has the wrong params?
         Err(_) => {
-            let err_msg = format!("wrong parameter types for callback function");
-            match err_msg.push_to_lua(&mut tmp_lua) {
-                Ok(p) => p.forget_internal(),
-                Err(_) => unreachable!(),
-            };
+            let err_msg = tmp_lua
+                .read_error
+                .take()
+                .unwrap_or_else(|| "wrong parameter types for callback function".to_owned());
             unsafe {
-                ffi::lua_error(lua);
+                raise(lua, err_msg);
             }
             unreachable!()
         }
@@ -428,6 +440,14 @@ where
         Ok(p) => p.forget_internal(),
         Err(_) => panic!(), // TODO: wrong
     };
+    // Panicking here would abort, so the imbalance is reported to the Lua code instead.
+    unsafe {
+        let expected = arguments_count + nb;
+        let operation = "the return of a Rust callback";
+        if let Some(msg) = stack_checks::unbalanced_top(lua, expected, operation) {
+            raise(lua, msg);
+        }
+    }
     nb as libc::c_int
 }
 
@@ -0,0 +1,237 @@
+use ffi;
+use libc;
+
+use AsLua;
+use AsMutLua;
+use Lua;
+
+/// Mode of operation of the garbage collector.
+///
+/// See [the `set_gc_mode` method](struct.Lua.html#method.set_gc_mode). Lua 5.3 and LuaJIT only
+/// have the incremental mode, so this type doesn't exist with the `lua53` and `luajit` features.
+#[cfg(any(feature = "lua52", feature = "lua54"))]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum GcMode {
+    /// The collector interleaves small steps of a full collection with the execution of the
+    /// program. This is the default.
+    Incremental,
+    /// The collector frequently traverses only the objects that have been created recently, and
+    /// only does full collections from time to time. This mode is experimental in Lua 5.2.
+    Generational,
+}
+
+impl<'lua> Lua<'lua> {
+    /// Performs a full garbage-collection cycle.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.execute::<()>("local t = {} for i = 1, 1000 do t[i] = {} end").unwrap();
+    ///
+    /// let before = lua.used_memory();
+    /// lua.gc_collect();
+    /// assert!(lua.used_memory() < before);
+    /// ```
+    #[inline]
+    pub fn gc_collect(&mut self) {
+        unsafe {
+            ffi::lua_gc(self.as_mut_lua().0, ffi::LUA_GCCOLLECT, 0);
+        }
+    }
+
+    /// Performs an incremental step of garbage collection, as much work as if `kbytes` kilobytes
+    /// had been allocated. If `kbytes` is zero, performs a single basic step.
+    ///
+    /// Returns true if the step finished a collection cycle. This can be used to spread the work
+    /// of the collector, for example by calling it between the frames of a game loop.
+    #[inline]
+    pub fn gc_step(&mut self, kbytes: u32) -> bool {
+        unsafe { ffi::lua_gc(self.as_mut_lua().0, ffi::LUA_GCSTEP, clamp(kbytes)) != 0 }
+    }
+
+    /// Stops the garbage collector. Memory is then only reclaimed by explicit calls to
+    /// `gc_collect` or `gc_step`, until `gc_restart` is called.
+    #[inline]
+    pub fn gc_stop(&mut self) {
+        unsafe {
+            ffi::lua_gc(self.as_mut_lua().0, ffi::LUA_GCSTOP, 0);
+        }
+    }
+
+    /// Restarts the garbage collector after a call to `gc_stop`.
+    #[inline]
+    pub fn gc_restart(&mut self) {
+        unsafe {
+            ffi::lua_gc(self.as_mut_lua().0, ffi::LUA_GCRESTART, 0);
+        }
+    }
+
+    /// Returns true if the garbage collector is running, in other words if it hasn't been
+    /// stopped.
+    #[inline]
+    pub fn gc_is_running(&self) -> bool {
+        unsafe { ffi::lua_gc(self.as_lua().0, ffi::LUA_GCISRUNNING, 0) != 0 }
+    }
+
+    /// Sets how long the collector waits before starting a new cycle, as a percentage of the
+    /// memory in use after the previous collection. The default is 200, which means that a new
+    /// cycle starts when the memory in use doubles.
+    ///
+    /// Returns the previous value. Lua 5.4 stores the value divided by 4, so it is rounded down to
+    /// a multiple of 4 and can't exceed 1023.
+    #[inline]
+    pub fn set_gc_pause(&mut self, percent: u32) -> u32 {
+        unsafe { ffi::lua_gc(self.as_mut_lua().0, ffi::LUA_GCSETPAUSE, clamp(percent)) as u32 }
+    }
+
+    /// Sets the speed of the collector relative to memory allocation, as a percentage. Larger
+    /// values make the collector more aggressive and the steps longer. The default is 200, or 100
+    /// with Lua 5.4.
+    ///
+    /// Returns the previous value. Lua 5.4 stores the value divided by 4, so it is rounded down to
+    /// a multiple of 4 and can't exceed 1023.
+    #[inline]
+    pub fn set_gc_step_multiplier(&mut self, percent: u32) -> u32 {
+        unsafe { ffi::lua_gc(self.as_mut_lua().0, ffi::LUA_GCSETSTEPMUL, clamp(percent)) as u32 }
+    }
+
+    /// Sets how much the memory in use must grow, as a percentage, before the generational
+    /// collector performs a full collection. The default is 200.
+    ///
+    /// Returns the previous value. Only available with Lua 5.2: the generational mode of Lua 5.4
+    /// has other parameters.
+    #[cfg(feature = "lua52")]
+    #[inline]
+    pub fn set_gc_major_increment(&mut self, percent: u32) -> u32 {
+        unsafe { ffi::lua_gc(self.as_mut_lua().0, ffi::LUA_GCSETMAJORINC, clamp(percent)) as u32 }
+    }
+
+    /// Switches the garbage collector between incremental and generational mode.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.set_gc_mode(hlua::GcMode::Generational);
+    /// ```
+    #[cfg(any(feature = "lua52", feature = "lua54"))]
+    #[inline]
+    pub fn set_gc_mode(&mut self, mode: GcMode) {
+        let what = match mode {
+            GcMode::Incremental => ffi::LUA_GCINC,
+            GcMode::Generational => ffi::LUA_GCGEN,
+        };
+
+        // Lua 5.4 takes the parameters of the mode, which keep their values when zero.
+        unsafe {
+            #[cfg(feature = "lua52")]
+            ffi::lua_gc(self.as_mut_lua().0, what, 0);
+            #[cfg(feature = "lua54")]
+            ffi::lua_gc(self.as_mut_lua().0, what, 0, 0, 0);
+        }
+    }
+
+    /// Returns the amount of memory in use by this context, in bytes.
+    #[inline]
+    pub fn used_memory(&self) -> usize {
+        unsafe {
+            let lua = self.as_lua().0;
+            let kbytes = ffi::lua_gc(lua, ffi::LUA_GCCOUNT, 0) as usize;
+            let bytes = ffi::lua_gc(lua, ffi::LUA_GCCOUNTB, 0) as usize;
+            kbytes * 1024 + bytes
+        }
+    }
+}
+
+#[cfg(not(feature = "lua54"))]
+#[inline]
+fn clamp(value: u32) -> libc::c_int {
+    value.min(libc::c_int::MAX as u32) as libc::c_int
+}
+
+// Lua 5.4 stores the parameters in a byte, after dividing them by 4.
+#[cfg(feature = "lua54")]
+#[inline]
+fn clamp(value: u32) -> libc::c_int {
+    value.min(1023) as libc::c_int
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "lua52", feature = "lua54"))]
+    use GcMode;
+    use Lua;
+
+    #[test]
+    fn collect_frees_memory() {
+        let mut lua = Lua::new();
+        lua.gc_stop();
+        lua.execute::<()>("local t = {} for i = 1, 10000 do t[i] = {} end")
+            .unwrap();
+
+        let before = lua.used_memory();
+        lua.gc_collect();
+        assert!(lua.used_memory() < before / 2);
+    }
+
+    #[test]
+    fn stop_and_restart() {
+        let mut lua = Lua::new();
+        assert!(lua.gc_is_running());
+
+        lua.gc_stop();
+        assert!(!lua.gc_is_running());
+        let before = lua.used_memory();
+        lua.execute::<()>("for i = 1, 10000 do local t = {} end")
+            .unwrap();
+        assert!(lua.used_memory() > before);
+
+        lua.gc_restart();
+        assert!(lua.gc_is_running());
+    }
+
+    #[test]
+    fn step_finishes_a_cycle() {
+        let mut lua = Lua::new();
+        lua.gc_stop();
+        lua.execute::<()>("for i = 1, 1000 do local t = {} end")
+            .unwrap();
+
+        let mut steps = 0;
+        while !lua.gc_step(0) {
+            steps += 1;
+            assert!(steps < 100000);
+        }
+    }
+
+    #[test]
+    fn parameters() {
+        let mut lua = Lua::new();
+        let default_step_multiplier = if cfg!(feature = "lua54") { 100 } else { 200 };
+        assert_eq!(lua.set_gc_pause(152), 200);
+        assert_eq!(lua.set_gc_pause(300), 152);
+        assert_eq!(lua.set_gc_step_multiplier(400), default_step_multiplier);
+        assert_eq!(lua.set_gc_step_multiplier(100), 400);
+        #[cfg(feature = "lua52")]
+        assert_eq!(lua.set_gc_major_increment(152), 200);
+        assert_eq!(lua.set_gc_pause(u32::MAX), 300);
+        #[cfg(feature = "lua54")]
+        assert_eq!(lua.set_gc_pause(0), 1020);
+    }
+
+    #[test]
+    #[cfg(any(feature = "lua52", feature = "lua54"))]
+    fn generational_mode() {
+        let mut lua = Lua::new();
+        lua.set_gc_mode(GcMode::Generational);
+        lua.execute::<()>("local t = {} for i = 1, 10000 do t[i % 10] = {} end")
+            .unwrap();
+        lua.gc_collect();
+
+        lua.set_gc_mode(GcMode::Incremental);
+        lua.execute::<()>("local t = {} for i = 1, 10000 do t[i % 10] = {} end")
+            .unwrap();
+        lua.gc_collect();
+    }
+}
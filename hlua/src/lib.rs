@@ -68,10 +68,11 @@
 //!   See the documentation of [the `Function` struct](struct.Function.html) for more information.
 //! - [The `AnyLuaValue` struct](struct.AnyLuaValue.html). This enumeration represents any possible
 //!   value in Lua.
-//! - The [`LuaCode`](struct.LuaCode.html) and
-//!   [`LuaCodeFromReader`](struct.LuaCodeFromReader.html) structs. Since pushing these structs can
-//!   result in an error, you need to use [`checked_set`](struct.Lua.html#method.checked_set)
-//!   instead of `set`.
+//! - The [`LuaCode`](struct.LuaCode.html),
+//!   [`LuaCodeFromReader`](struct.LuaCodeFromReader.html),
+//!   [`NamedLuaCode`](struct.NamedLuaCode.html) and [`LuaBytecode`](struct.LuaBytecode.html)
+//!   structs. Since pushing these structs can result in an error, you need to use
+//!   [`checked_set`](struct.Lua.html#method.checked_set) instead of `set`.
 //! - `Vec`s and `HashMap`s whose content is pushable.
 //! - As a special case, `Result` can be pushed only as the return type of a Rust function or
 //!   closure. If they contain an error, the Rust function call is considered to have failed.
@@ -105,11 +106,49 @@
 //! - TODO: userdata
 //!
 
-// Export the version of lua52_sys in use by this crate. This allows clients to perform low-level
-// Lua operations without worrying about semver.
+// Export the version of the Lua bindings in use by this crate. This allows clients to perform
+// low-level Lua operations without worrying about semver.
 extern crate libc;
+#[cfg(feature = "lua52")]
 #[doc(hidden)]
 pub extern crate lua52_sys as ffi;
+#[cfg(all(feature = "lua53", not(feature = "lua52")))]
+#[doc(hidden)]
+pub extern crate lua53_sys as ffi;
+#[cfg(all(feature = "lua54", not(any(feature = "lua52", feature = "lua53"))))]
+#[doc(hidden)]
+pub extern crate lua54_sys as ffi;
+#[cfg(feature = "luajit")]
+extern crate luajit_sys;
+// The LuaJIT bindings, completed with the functions of Lua 5.2 that the rest of the crate uses.
+#[cfg(all(
+    feature = "luajit",
+    not(any(feature = "lua52", feature = "lua53", feature = "lua54"))
+))]
+#[doc(hidden)]
+pub mod ffi;
+
+#[cfg(not(any(
+    feature = "lua52",
+    feature = "lua53",
+    feature = "lua54",
+    feature = "luajit"
+)))]
+compile_error!(
+    "one of the `lua52`, `lua53`, `lua54` and `luajit` features of hlua must be enabled"
+);
+#[cfg(any(
+    all(feature = "lua52", feature = "lua53"),
+    all(feature = "lua52", feature = "lua54"),
+    all(feature = "lua52", feature = "luajit"),
+    all(feature = "lua53", feature = "lua54"),
+    all(feature = "lua53", feature = "luajit"),
+    all(feature = "lua54", feature = "luajit")
+))]
+compile_error!(
+    "only one of the `lua52`, `lua53`, `lua54` and `luajit` features of hlua can be enabled; the \
+     default `lua52` requires `default-features = false`"
+);
 
 use std::borrow::Borrow;
 use std::convert::From;
@@ -120,29 +159,58 @@ use std::io;
 use std::io::Error as IoError;
 use std::io::Read;
 use std::marker::PhantomData;
+use std::path::Path;
 
 pub use any::{AnyHashableLuaValue, AnyLuaString, AnyLuaValue};
+pub use coverage::{Coverage, CoverageCollector};
+pub use debug::{DebugInfo, HookContext, HookEvent, HookMask, StackFrame};
+pub use debugger::Debugger;
+pub use environment::Environment;
 pub use functions_write::{function0, function1, function2, function3, function4, function5};
 pub use functions_write::{function10, function6, function7, function8, function9};
 pub use functions_write::{Function, InsideCallback};
+#[cfg(any(feature = "lua52", feature = "lua54"))]
+pub use gc::GcMode;
 pub use lua_functions::LuaFunction;
 pub use lua_functions::LuaFunctionCallError;
-pub use lua_functions::{LuaCode, LuaCodeFromReader};
+pub use lua_functions::{LoadMode, LuaBytecode, LuaCode, LuaCodeFromReader, NamedLuaCode};
 pub use lua_tables::LuaTable;
 pub use lua_tables::LuaTableIterator;
+pub use profiler::{FunctionProfile, Profile, Profiler};
+pub use sandbox::SandboxPolicy;
+pub use scripts::{precompile_scripts, DirectorySource, MemorySource, ScriptSource};
+pub use shared::{BorrowError, Shared, SharedMut, SharedRef};
 pub use tuples::TuplePushError;
-pub use userdata::UserdataOnStack;
-pub use userdata::{push_userdata, read_userdata};
+pub use userdata::{UserdataMut, UserdataOnStack, UserdataRef};
+pub use userdata::{push_userdata, read_userdata, read_userdata_ref};
+pub use userdata_methods::{MetaMethod, UserdataMethods};
 pub use values::StringInLua;
 
 mod any;
+#[cfg(feature = "lua52")]
+mod bytecode;
+mod coverage;
+mod debug;
+mod debugger;
+mod environment;
 mod functions_write;
+mod gc;
+mod json;
 mod lua_functions;
 mod lua_tables;
+#[cfg(feature = "luajit")]
+mod luajit_bytecode;
 mod macros;
+mod modules;
+mod profiler;
 mod rust_tables;
+mod sandbox;
+mod scripts;
+mod shared;
+mod stack_checks;
 mod tuples;
 mod userdata;
+mod userdata_methods;
 mod values;
 
 /// Main object of the library.
@@ -564,23 +632,41 @@ impl<'lua> Lua<'lua> {
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_base
     #[inline]
     pub fn open_base(&mut self) {
-        unsafe { ffi::luaopen_base(self.lua.0) }
+        unsafe {
+            ffi::luaopen_base(self.lua.0);
+        }
     }
 
-    /// Opens bit32 library.
+    /// Opens bit32 library. Lua 5.4 and LuaJIT don't have it.
     ///
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_bit32
+    #[cfg(any(feature = "lua52", feature = "lua53"))]
     #[inline]
     pub fn open_bit32(&mut self) {
-        unsafe { ffi::luaopen_bit32(self.lua.0) }
+        unsafe {
+            ffi::luaopen_bit32(self.lua.0);
+        }
+    }
+
+    /// Opens the bit library of LuaJIT, its equivalent of bit32.
+    ///
+    /// https://bitop.luajit.org/api.html
+    #[cfg(feature = "luajit")]
+    #[inline]
+    pub fn open_bit(&mut self) {
+        unsafe {
+            ffi::luaopen_bit(self.lua.0);
+        }
     }
 
-    /// Opens coroutine library.
+    /// Opens coroutine library. With LuaJIT, this also opens the base library.
     ///
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_coroutine
     #[inline]
     pub fn open_coroutine(&mut self) {
-        unsafe { ffi::luaopen_coroutine(self.lua.0) }
+        unsafe {
+            ffi::luaopen_coroutine(self.lua.0);
+        }
     }
 
     /// Opens debug library.
@@ -588,7 +674,9 @@ impl<'lua> Lua<'lua> {
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_debug
     #[inline]
     pub fn open_debug(&mut self) {
-        unsafe { ffi::luaopen_debug(self.lua.0) }
+        unsafe {
+            ffi::luaopen_debug(self.lua.0);
+        }
     }
 
     /// Opens io library.
@@ -596,7 +684,9 @@ impl<'lua> Lua<'lua> {
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_io
     #[inline]
     pub fn open_io(&mut self) {
-        unsafe { ffi::luaopen_io(self.lua.0) }
+        unsafe {
+            ffi::luaopen_io(self.lua.0);
+        }
     }
 
     /// Opens math library.
@@ -604,7 +694,9 @@ impl<'lua> Lua<'lua> {
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_math
     #[inline]
     pub fn open_math(&mut self) {
-        unsafe { ffi::luaopen_math(self.lua.0) }
+        unsafe {
+            ffi::luaopen_math(self.lua.0);
+        }
     }
 
     /// Opens os library.
@@ -612,7 +704,9 @@ impl<'lua> Lua<'lua> {
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_os
     #[inline]
     pub fn open_os(&mut self) {
-        unsafe { ffi::luaopen_os(self.lua.0) }
+        unsafe {
+            ffi::luaopen_os(self.lua.0);
+        }
     }
 
     /// Opens package library.
@@ -620,7 +714,9 @@ impl<'lua> Lua<'lua> {
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_package
     #[inline]
     pub fn open_package(&mut self) {
-        unsafe { ffi::luaopen_package(self.lua.0) }
+        unsafe {
+            ffi::luaopen_package(self.lua.0);
+        }
     }
 
     /// Opens string library.
@@ -628,7 +724,9 @@ impl<'lua> Lua<'lua> {
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_string
     #[inline]
     pub fn open_string(&mut self) {
-        unsafe { ffi::luaopen_string(self.lua.0) }
+        unsafe {
+            ffi::luaopen_string(self.lua.0);
+        }
     }
 
     /// Opens table library.
@@ -636,7 +734,20 @@ impl<'lua> Lua<'lua> {
     /// https://www.lua.org/manual/5.2/manual.html#pdf-luaopen_table
     #[inline]
     pub fn open_table(&mut self) {
-        unsafe { ffi::luaopen_table(self.lua.0) }
+        unsafe {
+            ffi::luaopen_table(self.lua.0);
+        }
+    }
+
+    /// Opens utf8 library, which appeared in Lua 5.3.
+    ///
+    /// https://www.lua.org/manual/5.3/manual.html#pdf-luaopen_utf8
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    #[inline]
+    pub fn open_utf8(&mut self) {
+        unsafe {
+            ffi::luaopen_utf8(self.lua.0);
+        }
     }
 
     /// Executes some Lua code in the context.
@@ -685,6 +796,56 @@ impl<'lua> Lua<'lua> {
         f.call()
     }
 
+    /// Executes some Lua code on the context, only accepting the kinds of chunks of `mode`.
+    ///
+    /// This does the same thing as [the `execute` method](#method.execute), but `mode` is used
+    /// instead of [the load mode of the context](#method.set_load_mode).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{LoadMode, Lua};
+    /// let mut lua = Lua::new();
+    ///
+    /// let val: i32 = lua.execute_with_mode("return 5", LoadMode::Text).unwrap();
+    /// assert_eq!(val, 5);
+    /// assert!(lua.execute_with_mode::<i32>("return 5", LoadMode::Binary).is_err());
+    /// ```
+    #[inline]
+    pub fn execute_with_mode<'a, T>(&'a mut self, code: &str, mode: LoadMode) -> Result<T, LuaError>
+    where
+        T: for<'g> LuaRead<PushGuard<&'g mut PushGuard<&'a mut Lua<'lua>>>>,
+    {
+        self.execute_from_reader_with_mode(code.as_bytes(), mode)
+    }
+
+    /// Executes some Lua code on the context, giving it a chunk name.
+    ///
+    /// This does the same thing as [the `execute` method](#method.execute), but error messages
+    /// and tracebacks refer to the code by `name` instead of `chunk`. See
+    /// [`NamedLuaCode`](struct.NamedLuaCode.html) for the conventions.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::Lua;
+    /// let mut lua = Lua::new();
+    /// lua.openlibs();
+    ///
+    /// match lua.execute_named::<()>("@scripts/init.lua", "\nerror('oops')") {
+    ///     Err(hlua::LuaError::ExecutionError(msg)) => assert_eq!(msg, "scripts/init.lua:2: oops"),
+    ///     _ => panic!(),
+    /// }
+    /// ```
+    #[inline]
+    pub fn execute_named<'a, T>(&'a mut self, name: &str, code: &str) -> Result<T, LuaError>
+    where
+        T: for<'g> LuaRead<PushGuard<&'g mut PushGuard<&'a mut Lua<'lua>>>>,
+    {
+        let mut f = lua_functions::LuaFunction::load_with_name(self, name, code)?;
+        f.call()
+    }
+
     /// Executes some Lua code on the context.
     ///
     /// This does the same thing as [the `execute` method](#method.execute), but the code to
@@ -714,6 +875,61 @@ impl<'lua> Lua<'lua> {
         f.call()
     }
 
+    /// Executes some Lua code on the context, only accepting the kinds of chunks of `mode`.
+    ///
+    /// This does the same thing as
+    /// [the `execute_from_reader` method](#method.execute_from_reader), but `mode` is used
+    /// instead of [the load mode of the context](#method.set_load_mode). For example, code that
+    /// comes from an untrusted source should be loaded with `LoadMode::Text`.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use std::fs::File;
+    /// use hlua::{LoadMode, Lua};
+    ///
+    /// let mut lua = Lua::new();
+    /// let script = File::open("untrusted.lua").unwrap();
+    /// lua.execute_from_reader_with_mode::<(), _>(script, LoadMode::Text).unwrap();
+    /// ```
+    #[inline]
+    pub fn execute_from_reader_with_mode<'a, T, R>(
+        &'a mut self,
+        code: R,
+        mode: LoadMode,
+    ) -> Result<T, LuaError>
+    where
+        T: for<'g> LuaRead<PushGuard<&'g mut PushGuard<&'a mut Lua<'lua>>>>,
+        R: Read,
+    {
+        let mut f = lua_functions::LuaFunction::load_from_reader_with_mode(self, code, mode)?;
+        f.call()
+    }
+
+    /// Executes the Lua code of a file on the context.
+    ///
+    /// This does the same thing as [the `execute` method](#method.execute), but the code is read
+    /// progressively from the file, and error messages refer to it by its path. See
+    /// [`LuaFunction::load_file`](struct.LuaFunction.html#method.load_file) for more information.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// use hlua::Lua;
+    ///
+    /// let mut lua = Lua::new();
+    /// lua.execute_file::<(), _>("script.lua").unwrap();
+    /// ```
+    #[inline]
+    pub fn execute_file<'a, T, P>(&'a mut self, path: P) -> Result<T, LuaError>
+    where
+        T: for<'g> LuaRead<PushGuard<&'g mut PushGuard<&'a mut Lua<'lua>>>>,
+        P: AsRef<Path>,
+    {
+        let mut f = lua_functions::LuaFunction::load_file(self, path)?;
+        f.call()
+    }
+
     /// Reads the value of a global variable.
     ///
     /// Returns `None` if the variable doesn't exist or has the wrong type.
@@ -968,6 +1184,7 @@ impl<L> Drop for PushGuard<L> {
     fn drop(&mut self) {
         if self.size != 0 {
             unsafe {
+                stack_checks::check_pop(self.raw_lua.0, self.size, "PushGuard");
                 ffi::lua_pop(self.raw_lua.0, self.size);
             }
         }
@@ -996,7 +1213,10 @@ mod tests {
     fn opening_all_libraries_doesnt_panic() {
         let mut lua = Lua::new();
         lua.open_base();
+        #[cfg(any(feature = "lua52", feature = "lua53"))]
         lua.open_bit32();
+        #[cfg(feature = "luajit")]
+        lua.open_bit();
         lua.open_coroutine();
         lua.open_debug();
         lua.open_io();
@@ -1005,5 +1225,7 @@ mod tests {
         lua.open_package();
         lua.open_string();
         lua.open_table();
+        #[cfg(any(feature = "lua53", feature = "lua54"))]
+        lua.open_utf8();
     }
 }
@@ -2,16 +2,27 @@ use ffi;
 use libc;
 
 use std::error::Error;
+use std::ffi::CString;
 use std::fmt;
-use std::io::Cursor;
+use std::fs::File;
 use std::io::Error as IoError;
-use std::io::Read;
+use std::io::ErrorKind as IoErrorKind;
+use std::io::{BufRead, BufReader, Cursor, Read, Write};
 use std::mem;
+use std::path::Path;
 use std::ptr;
+use std::slice;
 
 use AsLua;
 use AsMutLua;
 
+use debug;
+#[cfg(feature = "lua52")]
+use bytecode::strip as strip_bytecode;
+#[cfg(feature = "luajit")]
+use luajit_bytecode::strip as strip_bytecode;
+
+use Lua;
 use LuaContext;
 use LuaError;
 use LuaRead;
@@ -20,6 +31,108 @@ use PushGuard;
 use PushOne;
 use Void;
 
+/// Kinds of chunks that can be loaded: source code, precompiled bytecode, or both.
+///
+/// Lua doesn't verify precompiled chunks, and maliciously crafted bytecode can crash the
+/// interpreter. Code that comes from an untrusted source must be loaded as `Text`.
+///
+/// Each `Lua` context has a load mode, which applies to all the code loaded from Rust except
+/// [`LuaBytecode`](struct.LuaBytecode.html). It is `Both` by default. See
+/// [the `set_load_mode` method](struct.Lua.html#method.set_load_mode).
+///
+/// It can be overridden for a single chunk with
+/// [`Lua::execute_with_mode`](struct.Lua.html#method.execute_with_mode),
+/// [`Lua::execute_from_reader_with_mode`](struct.Lua.html#method.execute_from_reader_with_mode),
+/// [`LuaFunction::load_from_reader_with_mode`](struct.LuaFunction.html#method.load_from_reader_with_mode)
+/// or [`NamedLuaCode::mode`](struct.NamedLuaCode.html#method.mode).
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum LoadMode {
+    /// Only source code.
+    Text,
+    /// Only precompiled chunks.
+    Binary,
+    /// Both source code and precompiled chunks.
+    Both,
+}
+
+impl LoadMode {
+    #[inline]
+    pub(crate) fn as_c_str(self) -> &'static [u8] {
+        match self {
+            LoadMode::Text => b"t\0",
+            LoadMode::Binary => b"b\0",
+            LoadMode::Both => b"bt\0",
+        }
+    }
+}
+
+impl Default for LoadMode {
+    #[inline]
+    fn default() -> LoadMode {
+        LoadMode::Both
+    }
+}
+
+// The address of this static is used as the key of the load mode in the registry.
+static LOAD_MODE_REGISTRY_KEY: u8 = 0;
+
+#[inline]
+fn load_mode_registry_key() -> *const libc::c_char {
+    &LOAD_MODE_REGISTRY_KEY as *const u8 as *const libc::c_char
+}
+
+// Returns the load mode of a context.
+pub(crate) unsafe fn load_mode(lua: *mut ffi::lua_State) -> LoadMode {
+    ffi::lua_rawgetp(lua, ffi::LUA_REGISTRYINDEX, load_mode_registry_key());
+    let mode = match ffi::lua_tointegerx(lua, -1, ptr::null_mut()) {
+        1 => LoadMode::Text,
+        2 => LoadMode::Binary,
+        _ => LoadMode::Both,
+    };
+    ffi::lua_pop(lua, 1);
+    mode
+}
+
+impl<'lua> Lua<'lua> {
+    /// Sets which kinds of chunks can be loaded by this context.
+    ///
+    /// This applies to `execute`, `execute_from_reader`, `execute_file`, the `load` functions of
+    /// `LuaFunction`, and to pushing `LuaCode` or `LuaCodeFromReader`. It doesn't apply to
+    /// [`LuaBytecode`](struct.LuaBytecode.html), which is always loaded as a precompiled chunk,
+    /// nor to the `load` function of the Lua standard library, which has its own `mode`
+    /// parameter.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{LoadMode, Lua, LuaFunction};
+    ///
+    /// let mut lua = Lua::new();
+    /// let bytecode = LuaFunction::load(&mut lua, "return 5").unwrap().dump(false).unwrap();
+    ///
+    /// lua.set_load_mode(LoadMode::Text);
+    /// assert!(LuaFunction::load_from_reader(&mut lua, &bytecode[..]).is_err());
+    /// ```
+    pub fn set_load_mode(&mut self, mode: LoadMode) {
+        unsafe {
+            let lua = self.as_mut_lua().0;
+            let value = match mode {
+                LoadMode::Text => 1,
+                LoadMode::Binary => 2,
+                LoadMode::Both => 0,
+            };
+            ffi::lua_pushinteger(lua, value);
+            ffi::lua_rawsetp(lua, ffi::LUA_REGISTRYINDEX, load_mode_registry_key());
+        }
+    }
+
+    /// Returns which kinds of chunks can be loaded by this context.
+    #[inline]
+    pub fn load_mode(&self) -> LoadMode {
+        unsafe { load_mode(self.as_lua().0) }
+    }
+}
+
 /// Wrapper around a `&str`. When pushed, the content will be parsed as Lua code and turned into a
 /// function.
 ///
@@ -88,99 +201,257 @@ where
     type Err = LuaError;
 
     #[inline]
-    fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (LuaError, L)> {
-        unsafe {
-            struct ReadData<R> {
-                reader: R,
-                buffer: [u8; 128],
-                triggered_error: Option<IoError>,
-            }
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (LuaError, L)> {
+        load_chunk(lua, self.0, DEFAULT_CHUNK_NAME, None)
+    }
+}
 
-            let mut read_data = ReadData {
-                reader: self.0,
-                buffer: [0; 128],
-                triggered_error: None,
-            };
+impl<'lua, L, R> PushOne<L> for LuaCodeFromReader<R>
+where
+    L: AsMutLua<'lua>,
+    R: Read,
+{
+}
 
-            extern "C" fn reader<R>(
-                _: *mut ffi::lua_State,
-                data: *mut libc::c_void,
-                size: *mut libc::size_t,
-            ) -> *const libc::c_char
-            where
-                R: Read,
-            {
-                unsafe {
-                    let data: *mut ReadData<R> = data as *mut _;
-                    let data: &mut ReadData<R> = &mut *data;
-
-                    if data.triggered_error.is_some() {
-                        (*size) = 0;
-                        return data.buffer.as_ptr() as *const libc::c_char;
-                    }
+/// Lua code with a chunk name. When pushed, the content will be parsed as Lua code and turned into
+/// a function.
+///
+/// The chunk name appears in error messages and tracebacks, and is returned by the debug
+/// interface. By convention, a name that starts with `@` is the path of the file that contains
+/// the code, and a name that starts with `=` is displayed as is. For example with a syntax error
+/// on line 3, the error message starts with `scripts/init.lua:3:` if the name is
+/// `@scripts/init.lua`, with `init:3:` if the name is `=init`, and with `[string "init"]:3:` if
+/// the name is `init`. Code loaded without a name is named `chunk`.
+///
+/// Created with `LuaCode::named` or `LuaCodeFromReader::named`.
+///
+/// # Example
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+/// let code = hlua::LuaCode::named("@scripts/init.lua", "return 5 +");
+///
+/// match lua.checked_set("init", code) {
+///     Err(hlua::LuaError::SyntaxError(msg)) => assert!(msg.starts_with("scripts/init.lua:1:")),
+///     _ => panic!(),
+/// }
+/// ```
+#[derive(Debug)]
+pub struct NamedLuaCode<R> {
+    name: String,
+    reader: R,
+    mode: Option<LoadMode>,
+}
 
-                    match data.reader.read(&mut data.buffer) {
-                        Ok(len) => (*size) = len as libc::size_t,
-                        Err(e) => {
-                            (*size) = 0;
-                            data.triggered_error = Some(e);
-                        }
-                    };
+impl<'a> LuaCode<'a> {
+    /// Wraps Lua code and gives it a chunk name. See [`NamedLuaCode`](struct.NamedLuaCode.html).
+    #[inline]
+    pub fn named(name: &str, code: &'a str) -> NamedLuaCode<Cursor<&'a [u8]>> {
+        LuaCodeFromReader::named(name, Cursor::new(code.as_bytes()))
+    }
+}
 
-                    data.buffer.as_ptr() as *const libc::c_char
-                }
-            }
+impl<R> LuaCodeFromReader<R> {
+    /// Wraps a `Read` object and gives a chunk name to the code it contains. See
+    /// [`NamedLuaCode`](struct.NamedLuaCode.html).
+    #[inline]
+    pub fn named(name: &str, reader: R) -> NamedLuaCode<R> {
+        NamedLuaCode {
+            name: name.to_owned(),
+            reader,
+            mode: None,
+        }
+    }
+}
 
-            let (load_return_value, pushed_value) = {
-                let code = ffi::lua_load(
-                    lua.as_mut_lua().0,
-                    reader::<R>,
-                    &mut read_data as *mut ReadData<_> as *mut libc::c_void,
-                    b"chunk\0".as_ptr() as *const _,
-                    ptr::null(),
-                );
-                let raw_lua = lua.as_lua();
-                (
-                    code,
-                    PushGuard {
-                        lua: lua,
-                        size: 1,
-                        raw_lua: raw_lua,
-                    },
-                )
-            };
+impl<R> NamedLuaCode<R> {
+    /// Returns the chunk name.
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
 
-            if read_data.triggered_error.is_some() {
-                let error = read_data.triggered_error.unwrap();
-                return Err((LuaError::ReadError(error), pushed_value.into_inner()));
-            }
+    /// Sets which kinds of chunks are accepted, instead of the
+    /// [load mode of the context](struct.Lua.html#method.set_load_mode).
+    #[inline]
+    pub fn mode(mut self, mode: LoadMode) -> NamedLuaCode<R> {
+        self.mode = Some(mode);
+        self
+    }
+}
 
-            if load_return_value == 0 {
-                return Ok(pushed_value);
-            }
+impl<'lua, L, R> Push<L> for NamedLuaCode<R>
+where
+    L: AsMutLua<'lua>,
+    R: Read,
+{
+    type Err = LuaError;
 
-            let error_msg: String = LuaRead::lua_read(&pushed_value)
-                .ok()
-                .expect("can't find error message at the top of the Lua stack");
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (LuaError, L)> {
+        load_chunk(lua, self.reader, &self.name, self.mode)
+    }
+}
 
-            if load_return_value == ffi::LUA_ERRMEM {
-                panic!("LUA_ERRMEM");
-            }
+impl<'lua, L, R> PushOne<L> for NamedLuaCode<R>
+where
+    L: AsMutLua<'lua>,
+    R: Read,
+{
+}
 
-            if load_return_value == ffi::LUA_ERRSYNTAX {
-                return Err((LuaError::SyntaxError(error_msg), pushed_value.into_inner()));
-            }
+/// Wrapper around a precompiled Lua chunk, as produced by
+/// [`LuaFunction::dump`](struct.LuaFunction.html#method.dump) or by `luac`. When pushed, the
+/// chunk will be loaded and turned into a function.
+///
+/// Loading a precompiled chunk is faster than parsing the source code. Only chunks produced by
+/// the same version of Lua on a platform with the same sizes of integers and numbers can be
+/// loaded. Text is rejected.
+///
+/// > **Warning**: Lua doesn't verify precompiled chunks. Maliciously crafted bytecode can crash
+/// > the interpreter, so only load chunks that come from a trusted source.
+///
+/// Since pushing this value can fail if the chunk is invalid, you must use the `checked_set`
+/// method instead of `set`.
+///
+/// # Example
+///
+/// ```
+/// let mut lua = hlua::Lua::new();
+///
+/// let bytecode = {
+///     let mut f = hlua::LuaFunction::load(&mut lua, "return 5").unwrap();
+///     f.dump(false).unwrap()
+/// };
+///
+/// lua.checked_set("five", hlua::LuaBytecode(&bytecode)).unwrap();
+/// let r: i32 = lua.execute("return five()").unwrap();
+/// assert_eq!(r, 5);
+/// ```
+#[derive(Debug)]
+pub struct LuaBytecode<'a>(pub &'a [u8]);
 
-            panic!("Unknown error while calling lua_load");
-        }
+impl<'lua, 'c, L> Push<L> for LuaBytecode<'c>
+where
+    L: AsMutLua<'lua>,
+{
+    type Err = LuaError;
+
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (LuaError, L)> {
+        load_chunk(
+            lua,
+            Cursor::new(self.0),
+            DEFAULT_CHUNK_NAME,
+            Some(LoadMode::Binary),
+        )
     }
 }
 
-impl<'lua, L, R> PushOne<L> for LuaCodeFromReader<R>
+impl<'lua, 'c, L> PushOne<L> for LuaBytecode<'c> where L: AsMutLua<'lua> {}
+
+// Name given to the chunks that are loaded without a name.
+const DEFAULT_CHUNK_NAME: &str = "chunk";
+
+// Loads a chunk and pushes it as a function on the stack. If `mode` is `None`, the load mode of
+// the context is used.
+fn load_chunk<'lua, L, R>(
+    mut lua: L,
+    code: R,
+    name: &str,
+    mode: Option<LoadMode>,
+) -> Result<PushGuard<L>, (LuaError, L)>
 where
     L: AsMutLua<'lua>,
     R: Read,
 {
+    // Lua expects a C string, so the name is truncated at the first nul character.
+    let name = CString::new(name.split('\0').next().unwrap()).unwrap();
+    let mode = mode.unwrap_or_else(|| unsafe { load_mode(lua.as_lua().0) });
+
+    unsafe {
+        struct ReadData<R> {
+            reader: R,
+            buffer: [u8; 128],
+            triggered_error: Option<IoError>,
+        }
+
+        let mut read_data = ReadData {
+            reader: code,
+            buffer: [0; 128],
+            triggered_error: None,
+        };
+
+        extern "C" fn reader<R>(
+            _: *mut ffi::lua_State,
+            data: *mut libc::c_void,
+            size: *mut libc::size_t,
+        ) -> *const libc::c_char
+        where
+            R: Read,
+        {
+            unsafe {
+                let data: *mut ReadData<R> = data as *mut _;
+                let data: &mut ReadData<R> = &mut *data;
+
+                if data.triggered_error.is_some() {
+                    (*size) = 0;
+                    return data.buffer.as_ptr() as *const libc::c_char;
+                }
+
+                match data.reader.read(&mut data.buffer) {
+                    Ok(len) => (*size) = len as libc::size_t,
+                    Err(e) => {
+                        (*size) = 0;
+                        data.triggered_error = Some(e);
+                    }
+                };
+
+                data.buffer.as_ptr() as *const libc::c_char
+            }
+        }
+
+        let (load_return_value, pushed_value) = {
+            let code = ffi::lua_load(
+                lua.as_mut_lua().0,
+                reader::<R>,
+                &mut read_data as *mut ReadData<_> as *mut libc::c_void,
+                name.as_ptr(),
+                mode.as_c_str().as_ptr() as *const _,
+            );
+            let raw_lua = lua.as_lua();
+            (
+                code,
+                PushGuard {
+                    lua,
+                    size: 1,
+                    raw_lua,
+                },
+            )
+        };
+
+        if let Some(error) = read_data.triggered_error {
+            return Err((LuaError::ReadError(error), pushed_value.into_inner()));
+        }
+
+        if load_return_value == 0 {
+            return Ok(pushed_value);
+        }
+
+        let error_msg: String = LuaRead::lua_read(&pushed_value)
+            .ok()
+            .expect("can't find error message at the top of the Lua stack");
+
+        if load_return_value == ffi::LUA_ERRMEM {
+            panic!("LUA_ERRMEM");
+        }
+
+        if load_return_value == ffi::LUA_ERRSYNTAX {
+            return Err((LuaError::SyntaxError(error_msg), pushed_value.into_inner()));
+        }
+
+        panic!("Unknown error while calling lua_load");
+    }
 }
 
 /// Handle to a function in the Lua context.
@@ -298,6 +569,7 @@ where
 
             (pcall_return_value, guard)
         };
+        unsafe { debug::resume_hook_panic(pushed_value.raw_lua.0) };
 
         match pcall_return_value {
             0 => match LuaRead::lua_read(pushed_value) {
@@ -320,6 +592,111 @@ where
         }
     }
 
+    /// Turns the function into a precompiled chunk, which can be loaded later with
+    /// [`LuaBytecode`](struct.LuaBytecode.html).
+    ///
+    /// If `strip` is true, the debug information (source name, line numbers, names of local
+    /// variables and upvalues) is removed, which makes the chunk smaller. The upvalues of the
+    /// function are not saved: they are initialized with `nil` when the chunk is loaded, except for
+    /// the first upvalue of a main chunk, which is set to the global table.
+    ///
+    /// Returns an error if the function is not a Lua function, for example a Rust function.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// let mut f = hlua::LuaFunction::load(&mut lua, "return 5").unwrap();
+    /// let bytecode = f.dump(true).unwrap();
+    /// // Precompiled chunks start with the escape character, unlike source code.
+    /// assert!(bytecode.starts_with(b"\x1b"));
+    /// ```
+    #[inline]
+    pub fn dump(&mut self, strip: bool) -> Result<Vec<u8>, IoError> {
+        let mut output = Vec::new();
+        self.dump_to(&mut output, strip)?;
+        Ok(output)
+    }
+
+    /// Same as `dump`, but writes the precompiled chunk to `output`.
+    ///
+    /// Returns an error if the function is not a Lua function or if writing fails.
+    pub fn dump_to<W>(&mut self, mut output: W, strip: bool) -> Result<(), IoError>
+    where
+        W: Write,
+    {
+        // `lua_dump` of Lua 5.2 and LuaJIT always includes the debug information.
+        #[cfg(any(feature = "lua52", feature = "luajit"))]
+        if strip {
+            let chunk = self.dump(false)?;
+            let chunk = strip_bytecode(&chunk).ok_or_else(|| {
+                IoError::new(
+                    IoErrorKind::InvalidData,
+                    "unexpected precompiled chunk format",
+                )
+            })?;
+            return output.write_all(&chunk);
+        }
+
+        struct WriteData<'w> {
+            output: &'w mut dyn Write,
+            triggered_error: Option<IoError>,
+        }
+
+        extern "C" fn writer(
+            _: *mut ffi::lua_State,
+            p: *const libc::c_void,
+            size: libc::size_t,
+            data: *mut libc::c_void,
+        ) -> libc::c_int {
+            unsafe {
+                let data = &mut *(data as *mut WriteData);
+                let bytes = slice::from_raw_parts(p as *const u8, size);
+                match data.output.write_all(bytes) {
+                    Ok(()) => 0,
+                    Err(err) => {
+                        data.triggered_error = Some(err);
+                        1
+                    }
+                }
+            }
+        }
+
+        let mut data = WriteData {
+            output: &mut output,
+            triggered_error: None,
+        };
+
+        #[cfg(any(feature = "lua52", feature = "luajit"))]
+        let result = unsafe {
+            ffi::lua_dump(
+                self.variable.as_mut_lua().0,
+                writer,
+                &mut data as *mut WriteData as *mut libc::c_void,
+            )
+        };
+        #[cfg(not(any(feature = "lua52", feature = "luajit")))]
+        let result = unsafe {
+            ffi::lua_dump(
+                self.variable.as_mut_lua().0,
+                writer,
+                &mut data as *mut WriteData as *mut libc::c_void,
+                strip as libc::c_int,
+            )
+        };
+
+        if let Some(err) = data.triggered_error {
+            return Err(err);
+        }
+        if result != 0 {
+            return Err(IoError::new(
+                IoErrorKind::InvalidInput,
+                "only Lua functions can be dumped",
+            ));
+        }
+        Ok(())
+    }
+
     /// Builds a new `LuaFunction` from the code of a reader.
     ///
     /// Returns an error if reading from the `Read` object fails or if there is a syntax error in
@@ -347,6 +724,35 @@ where
         }
     }
 
+    /// Same as `load_from_reader`, but only accepts the kinds of chunks of `mode` instead of the
+    /// [load mode of the context](struct.Lua.html#method.set_load_mode).
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{LoadMode, Lua, LuaFunction};
+    ///
+    /// let mut lua = Lua::new();
+    /// let bytecode = LuaFunction::load(&mut lua, "return 5").unwrap().dump(false).unwrap();
+    ///
+    /// assert!(LuaFunction::load_from_reader_with_mode(&mut lua, &bytecode[..], LoadMode::Text)
+    ///     .is_err());
+    /// ```
+    #[inline]
+    pub fn load_from_reader_with_mode<R>(
+        lua: L,
+        code: R,
+        mode: LoadMode,
+    ) -> Result<LuaFunction<PushGuard<L>>, LuaError>
+    where
+        R: Read,
+    {
+        match load_chunk(lua, code, DEFAULT_CHUNK_NAME, Some(mode)) {
+            Ok(pushed) => Ok(LuaFunction { variable: pushed }),
+            Err((err, _)) => Err(err),
+        }
+    }
+
     /// Builds a new `LuaFunction` from a raw string.
     ///
     /// > **Note**: This is just a wrapper around `load_from_reader`. There is no advantage in
@@ -357,6 +763,126 @@ where
         let reader = Cursor::new(code.as_bytes());
         LuaFunction::load_from_reader(lua, reader)
     }
+
+    /// Builds a new `LuaFunction` from the content of a file.
+    ///
+    /// The file is read progressively, and the chunk name is `@` followed by the path, so that
+    /// error messages and tracebacks refer to the file. Like the standard `loadfile` function, a
+    /// first line starting with `#` (such as `#!/usr/bin/lua`) and a UTF-8 byte order mark are
+    /// ignored.
+    ///
+    /// Returns a `ReadError` if the file can't be opened or read, whose message contains the
+    /// path, or an error if there is a syntax error in the code.
+    ///
+    /// # Example
+    ///
+    /// ```no_run
+    /// let mut lua = hlua::Lua::new();
+    ///
+    /// let mut f = hlua::LuaFunction::load_file(&mut lua, "scripts/init.lua").unwrap();
+    /// f.call::<()>().unwrap();
+    /// ```
+    pub fn load_file<P>(lua: L, path: P) -> Result<LuaFunction<PushGuard<L>>, LuaError>
+    where
+        P: AsRef<Path>,
+    {
+        let path = path.as_ref();
+        let with_path = |err: IoError| {
+            LuaError::ReadError(IoError::new(
+                err.kind(),
+                format!("{}: {}", path.display(), err),
+            ))
+        };
+
+        let mut reader = BufReader::new(File::open(path).map_err(with_path)?);
+        // The comment is replaced with an empty line, so that line numbers remain correct.
+        let prefix: &[u8] = if skip_comment(&mut reader).map_err(with_path)? {
+            b"\n"
+        } else {
+            b""
+        };
+
+        let name = format!("@{}", path.display());
+        match LuaCodeFromReader::named(&name, prefix.chain(reader)).push_to_lua(lua) {
+            Ok(pushed) => Ok(LuaFunction { variable: pushed }),
+            Err((LuaError::ReadError(err), _)) => Err(with_path(err)),
+            Err((err, _)) => Err(err),
+        }
+    }
+
+    /// Builds a new `LuaFunction` from a raw string, and gives it a chunk name.
+    ///
+    /// The name appears in error messages and tracebacks. See
+    /// [`NamedLuaCode`](struct.NamedLuaCode.html) for the conventions.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.openlibs();
+    ///
+    /// let mut f = hlua::LuaFunction::load_with_name(&mut lua, "=init", "error('oops')").unwrap();
+    /// match f.call::<()>() {
+    ///     Err(hlua::LuaError::ExecutionError(msg)) => assert_eq!(msg, "init:1: oops"),
+    ///     _ => panic!(),
+    /// }
+    /// ```
+    #[inline]
+    pub fn load_with_name(
+        lua: L,
+        name: &str,
+        code: &str,
+    ) -> Result<LuaFunction<PushGuard<L>>, LuaError> {
+        LuaFunction::load_from_reader_with_name(lua, name, code.as_bytes())
+    }
+
+    /// Builds a new `LuaFunction` from the content of a `Read` object, and gives it a chunk name.
+    ///
+    /// The code doesn't need to be valid UTF-8, since Lua sources are sequences of bytes. See
+    /// [`NamedLuaCode`](struct.NamedLuaCode.html) for the conventions of chunk names.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    ///
+    /// let code: &[u8] = b"return #'caf\xe9'";
+    /// let mut f = hlua::LuaFunction::load_from_reader_with_name(&mut lua, "=latin1", code)
+    ///     .unwrap();
+    /// assert_eq!(f.call::<i32>().unwrap(), 4);
+    /// ```
+    #[inline]
+    pub fn load_from_reader_with_name<R>(
+        lua: L,
+        name: &str,
+        code: R,
+    ) -> Result<LuaFunction<PushGuard<L>>, LuaError>
+    where
+        R: Read,
+    {
+        match LuaCodeFromReader::named(name, code).push_to_lua(lua) {
+            Ok(pushed) => Ok(LuaFunction { variable: pushed }),
+            Err((err, _)) => Err(err),
+        }
+    }
+}
+
+// Skips the byte order mark and the first line of a file if it starts with `#`. Returns true if
+// a line has been skipped.
+pub(crate) fn skip_comment<R>(reader: &mut R) -> Result<bool, IoError>
+where
+    R: BufRead,
+{
+    if reader.fill_buf()?.starts_with(b"\xEF\xBB\xBF") {
+        reader.consume(3);
+    }
+
+    if reader.fill_buf()?.first() == Some(&b'#') {
+        reader.read_until(b'\n', &mut Vec::new())?;
+        return Ok(true);
+    }
+
+    Ok(false)
 }
 
 /// Error that can happen when calling a `LuaFunction`.
@@ -459,15 +985,23 @@ where
 
 #[cfg(test)]
 mod tests {
+    use LoadMode;
     use Lua;
+    use LuaBytecode;
+    use LuaCode;
+    use LuaCodeFromReader;
     use LuaError;
     use LuaFunction;
     use LuaFunctionCallError;
     use LuaTable;
     use Void;
 
+    use std::env;
     use std::error::Error;
+    use std::fs;
     use std::io::{Error as IoError, ErrorKind as IoErrorKind, Read};
+    use std::path::PathBuf;
+    use std::process;
 
     #[test]
     fn basic() {
@@ -567,12 +1101,289 @@ mod tests {
         match res {
             Ok(_) => panic!("Reading succeded"),
             Err(LuaError::ReadError(e)) => {
-                assert_eq!("oh no!", e.description())
+                assert_eq!("oh no!", e.to_string())
             }
             Err(_) => panic!("Unexpected error happened"),
         }
     }
 
+    #[test]
+    fn chunk_names() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        for &(name, prefix) in &[
+            ("@scripts/init.lua", "scripts/init.lua:2:"),
+            ("=init", "init:2:"),
+            ("init", "[string \"init\"]:2:"),
+        ] {
+            match LuaFunction::load_with_name(&mut lua, name, "\nazerazer") {
+                Err(LuaError::SyntaxError(msg)) => assert!(msg.starts_with(prefix), "{}", msg),
+                _ => panic!(),
+            };
+
+            match lua.execute_named::<()>(name, "\nerror('oops')") {
+                Err(LuaError::ExecutionError(msg)) => assert_eq!(msg, format!("{} oops", prefix)),
+                _ => panic!(),
+            };
+        }
+    }
+
+    #[test]
+    fn default_chunk_name() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        match lua.execute::<()>("error('oops')") {
+            Err(LuaError::ExecutionError(msg)) => assert_eq!(msg, "[string \"chunk\"]:1: oops"),
+            _ => panic!(),
+        };
+    }
+
+    #[test]
+    fn named_code() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.checked_set("f", LuaCode::named("=f", "error('oops')"))
+            .unwrap();
+
+        let reader = LuaCodeFromReader::named("@g.lua", "return 7".as_bytes());
+        assert_eq!(reader.name(), "@g.lua");
+        lua.checked_set("g", reader).unwrap();
+
+        let val: i32 = lua.execute("return g()").unwrap();
+        assert_eq!(val, 7);
+        match lua.execute::<()>("f()") {
+            Err(LuaError::ExecutionError(msg)) => assert!(msg.starts_with("f:1:"), "{}", msg),
+            _ => panic!(),
+        };
+    }
+
+    #[test]
+    fn nul_in_chunk_name() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        match lua.execute_named::<()>("=a\0b", "error('oops')") {
+            Err(LuaError::ExecutionError(msg)) => assert_eq!(msg, "a:1: oops"),
+            _ => panic!(),
+        };
+    }
+
+    // Writes a file in the temporary directory and returns its path.
+    fn temp_file(name: &str, content: &str) -> PathBuf {
+        let path = env::temp_dir().join(format!("hlua-{}-{}", process::id(), name));
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn load_file() {
+        let path = temp_file("load_file.lua", "local a = ...\nreturn 5");
+        let mut lua = Lua::new();
+        let mut f = LuaFunction::load_file(&mut lua, &path).unwrap();
+        let val: i32 = f.call().unwrap();
+        assert_eq!(val, 5);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn execute_file_error_names_the_file() {
+        let path = temp_file("execute_file_error.lua", "\nerror('oops')");
+        let mut lua = Lua::new();
+        lua.openlibs();
+        match lua.execute_file::<(), _>(&path) {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert_eq!(msg, format!("{}:2: oops", path.display()))
+            }
+            _ => panic!(),
+        };
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn execute_file_skips_shebang() {
+        let path = temp_file("shebang.lua", "\u{feff}#!/usr/bin/lua\nreturn 1 +");
+        let mut lua = Lua::new();
+        match lua.execute_file::<(), _>(&path) {
+            Err(LuaError::SyntaxError(msg)) => {
+                assert!(
+                    msg.starts_with(&format!("{}:2:", path.display())),
+                    "{}",
+                    msg
+                )
+            }
+            _ => panic!(),
+        };
+
+        fs::write(&path, "#!/usr/bin/lua\nreturn 12").unwrap();
+        let val: i32 = lua.execute_file(&path).unwrap();
+        assert_eq!(val, 12);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn execute_file_missing() {
+        let path = env::temp_dir().join("hlua-this-file-does-not-exist.lua");
+        let mut lua = Lua::new();
+        match lua.execute_file::<(), _>(&path) {
+            Err(LuaError::ReadError(err)) => {
+                assert_eq!(err.kind(), IoErrorKind::NotFound);
+                assert!(err.to_string().contains(&path.display().to_string()));
+            }
+            _ => panic!(),
+        };
+    }
+
+    #[test]
+    fn dump_and_load_bytecode() {
+        let mut lua = Lua::new();
+        let bytecode = {
+            let mut f = LuaFunction::load(&mut lua, "local a = 3\nreturn a * 4").unwrap();
+            f.dump(false).unwrap()
+        };
+
+        let mut f = LuaFunction::load_from_reader(&mut lua, &bytecode[..]).unwrap();
+        let val: i32 = f.call().unwrap();
+        assert_eq!(val, 12);
+    }
+
+    #[test]
+    fn dump_stripped() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        let code = "local function f(x)\n  local y = x + 1\n  return y\nend\n\
+                    return f(1) + #'abc' + (true and 1 or 0)";
+        let (full, stripped) = {
+            let mut f = LuaFunction::load_with_name(&mut lua, "@script.lua", code).unwrap();
+            (f.dump(false).unwrap(), f.dump(true).unwrap())
+        };
+        assert!(stripped.len() < full.len());
+        assert!(!String::from_utf8_lossy(&stripped).contains("script.lua"));
+
+        lua.checked_set("f", LuaBytecode(&stripped)).unwrap();
+        let val: i32 = lua.execute("return f()").unwrap();
+        assert_eq!(val, 6);
+    }
+
+    #[test]
+    fn dump_to_writer() {
+        let mut lua = Lua::new();
+        let mut f = LuaFunction::load(&mut lua, "return 1").unwrap();
+        let mut output = Vec::new();
+        f.dump_to(&mut output, false).unwrap();
+        assert_eq!(output, f.dump(false).unwrap());
+    }
+
+    #[test]
+    fn dump_rust_function() {
+        let mut lua = Lua::new();
+        lua.set("foo", ::function0(|| 5));
+        let mut foo: LuaFunction<_> = lua.get("foo").unwrap();
+        match foo.dump(false) {
+            Err(err) => assert_eq!(err.kind(), IoErrorKind::InvalidInput),
+            Ok(_) => panic!(),
+        }
+    }
+
+    #[test]
+    fn bytecode_rejects_text() {
+        let mut lua = Lua::new();
+        match lua.checked_set("f", LuaBytecode(b"return 5")) {
+            Err(LuaError::SyntaxError(_)) => (),
+            _ => panic!(),
+        };
+
+        match lua.checked_set("f", LuaBytecode(b"\x1bLua\x52")) {
+            Err(LuaError::SyntaxError(_)) => (),
+            _ => panic!(),
+        };
+    }
+
+    #[test]
+    fn load_mode() {
+        let mut lua = Lua::new();
+        assert_eq!(lua.load_mode(), LoadMode::Both);
+        let bytecode = LuaFunction::load(&mut lua, "return 5")
+            .unwrap()
+            .dump(false)
+            .unwrap();
+
+        lua.set_load_mode(LoadMode::Text);
+        assert_eq!(lua.load_mode(), LoadMode::Text);
+        match lua.execute_from_reader::<i32, _>(&bytecode[..]) {
+            Err(LuaError::SyntaxError(msg)) => assert!(msg.contains("binary"), "{}", msg),
+            _ => panic!(),
+        };
+        let val: i32 = lua.execute("return 6").unwrap();
+        assert_eq!(val, 6);
+
+        // Explicitly precompiled chunks are still accepted.
+        lua.checked_set("f", LuaBytecode(&bytecode)).unwrap();
+
+        lua.set_load_mode(LoadMode::Binary);
+        match lua.execute::<i32>("return 6") {
+            Err(LuaError::SyntaxError(msg)) => assert!(msg.contains("text"), "{}", msg),
+            _ => panic!(),
+        };
+        let val: i32 = lua.execute_from_reader(&bytecode[..]).unwrap();
+        assert_eq!(val, 5);
+
+        lua.set_load_mode(LoadMode::Both);
+        let val: i32 = lua.execute_from_reader(&bytecode[..]).unwrap();
+        assert_eq!(val, 5);
+    }
+
+    #[test]
+    fn load_mode_per_call() {
+        let mut lua = Lua::new();
+        let bytecode = LuaFunction::load(&mut lua, "return 5")
+            .unwrap()
+            .dump(false)
+            .unwrap();
+
+        match lua.execute_from_reader_with_mode::<i32, _>(&bytecode[..], LoadMode::Text) {
+            Err(LuaError::SyntaxError(msg)) => assert!(msg.contains("binary"), "{}", msg),
+            _ => panic!(),
+        };
+        match lua.execute_with_mode::<i32>("return 6", LoadMode::Binary) {
+            Err(LuaError::SyntaxError(msg)) => assert!(msg.contains("text"), "{}", msg),
+            _ => panic!(),
+        };
+        assert!(
+            LuaFunction::load_from_reader_with_mode(&mut lua, &bytecode[..], LoadMode::Text)
+                .is_err()
+        );
+
+        // The mode of the call takes precedence over the mode of the context.
+        lua.set_load_mode(LoadMode::Text);
+        let val: i32 = lua
+            .execute_from_reader_with_mode(&bytecode[..], LoadMode::Both)
+            .unwrap();
+        assert_eq!(val, 5);
+        {
+            let mut f =
+                LuaFunction::load_from_reader_with_mode(&mut lua, &bytecode[..], LoadMode::Binary)
+                    .unwrap();
+            assert_eq!(f.call::<i32>().unwrap(), 5);
+        }
+        let val: i32 = lua.execute_with_mode("return 6", LoadMode::Text).unwrap();
+        assert_eq!(val, 6);
+        assert_eq!(lua.load_mode(), LoadMode::Text);
+    }
+
+    #[test]
+    fn load_mode_of_named_code() {
+        let mut lua = Lua::new();
+        lua.set_load_mode(LoadMode::Binary);
+        let code = LuaCode::named("=f", "return 7").mode(LoadMode::Text);
+        lua.checked_set("f", code).unwrap();
+
+        lua.set_load_mode(LoadMode::Both);
+        let val: i32 = lua.execute("return f()").unwrap();
+        assert_eq!(val, 7);
+
+        let code = LuaCode::named("=g", "return 7").mode(LoadMode::Binary);
+        assert!(lua.checked_set("g", code).is_err());
+    }
+
     fn _assert_error() {
         // Compile-time trait checks.
         fn _assert<T: Error>(_: T) {}
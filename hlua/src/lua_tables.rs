@@ -11,6 +11,8 @@ use PushGuard;
 use PushOne;
 use Void;
 
+use stack_checks;
+
 /// Represents a table stored in the Lua context.
 ///
 /// Just like you can read variables as integers and strings, you can also read Lua table by
@@ -41,7 +43,7 @@ impl<L> LuaTable<L> {
     // For example if you push one element over the table, call `offset(-1)` to know where the
     // table is.
     #[inline]
-    fn offset(&self, offset: i32) -> i32 {
+    pub(crate) fn offset(&self, offset: i32) -> i32 {
         if self.index >= 0 || self.index == ffi::LUA_REGISTRYINDEX {
             // If this table is the registry or was indexed from the bottom of the stack, its
             // current position will be unchanged.
@@ -108,6 +110,7 @@ where
     #[inline]
     pub fn iter<K, V>(&mut self) -> LuaTableIterator<L, K, V> {
         unsafe {
+            stack_checks::check_table(self.table.as_mut_lua().0, self.offset(0), "LuaTable::iter");
             ffi::lua_pushnil(self.table.as_mut_lua().0);
 
             let raw_lua = self.table.as_lua();
@@ -159,9 +162,12 @@ where
             // TODO: remove this by simplifying the PushOne requirement ; however this is complex
             //       because of the empty_array method
             let mut me = self;
+            let top = stack_checks::top(me.as_mut_lua().0);
+            stack_checks::check_table(me.as_mut_lua().0, me.offset(0), "LuaTable::get");
 
             index.push_no_err(&mut me).assert_one_and_forget();
             ffi::lua_gettable(me.as_mut_lua().0, me.offset(-1));
+            stack_checks::check_top(me.as_mut_lua().0, top + 1, "LuaTable::get");
 
             let raw_lua = me.as_lua();
             let guard = PushGuard {
@@ -188,9 +194,13 @@ where
         E: Into<Void>,
     {
         unsafe {
+            let top = stack_checks::top(self.as_mut_lua().0);
+            stack_checks::check_table(self.as_mut_lua().0, self.offset(0), "LuaTable::into_get");
+
             index.push_no_err(&mut self).assert_one_and_forget();
 
             ffi::lua_gettable(self.as_mut_lua().0, self.offset(-1));
+            stack_checks::check_top(self.as_mut_lua().0, top + 1, "LuaTable::into_get");
 
             let raw_lua = self.as_lua();
             let guard = PushGuard {
@@ -248,6 +258,8 @@ where
         unsafe {
             let raw_lua = self.as_mut_lua().0;
             let my_offset = self.offset(-2);
+            let top = stack_checks::top(raw_lua);
+            stack_checks::check_table(raw_lua, self.offset(0), "LuaTable::set");
 
             let mut guard = match index.push_to_lua(self) {
                 Ok(guard) => {
@@ -270,6 +282,11 @@ where
             };
 
             guard.forget();
+            stack_checks::check_top(
+                raw_lua,
+                top + 2,
+                "pushing the key and value of LuaTable::set",
+            );
             ffi::lua_settable(raw_lua, my_offset);
             Ok(())
         }
@@ -285,6 +302,7 @@ where
         // TODO: cleaner implementation
         unsafe {
             let mut me = self;
+            stack_checks::check_table(me.as_mut_lua().0, me.offset(0), "LuaTable::empty_array");
             match index.clone().push_to_lua(&mut me) {
                 Ok(pushed) => {
                     assert_eq!(pushed.size, 1);
@@ -354,6 +372,12 @@ where
     #[inline]
     pub fn get_or_create_metatable(mut self) -> LuaTable<PushGuard<L>> {
         unsafe {
+            stack_checks::check_table(
+                self.table.as_mut_lua().0,
+                self.offset(0),
+                "LuaTable::get_or_create_metatable",
+            );
+
             // We put the metatable at the top of the stack.
             if ffi::lua_getmetatable(self.table.as_mut_lua().0, self.index) == 0 {
                 // No existing metatable ; create one then set it and reload it.
@@ -460,6 +484,8 @@ where
 
             // As a reminder, the key is always at the top of the stack unless `finished` is true.
 
+            let top = stack_checks::top(self.table.as_mut_lua().0);
+
             // This call pops the current key and pushes the next key and value at the top.
             if ffi::lua_next(self.table.as_mut_lua().0, self.table.offset(-1)) == 0 {
                 self.finished = true;
@@ -473,6 +499,7 @@ where
 
             // Removing the value, leaving only the key on the top of the stack.
             ffi::lua_pop(me.table.as_mut_lua().0, 1);
+            stack_checks::check_top(me.table.as_mut_lua().0, top, "LuaTableIterator::next");
 
             if key.is_none() || value.is_none() {
                 Some(None)
@@ -0,0 +1,190 @@
+// Support for the format of precompiled chunks of LuaJIT, as produced by `lua_dump`.
+
+// Signature, followed by the version of the format.
+const SIGNATURE: &[u8] = b"\x1bLJ";
+
+// Flag of the header set when the chunk has no debug information.
+const FLAG_STRIP: u64 = 0x02;
+
+/// Removes the debug information (source name, line numbers, names of local variables and
+/// upvalues) from a precompiled chunk, like `luajit -bs` does.
+///
+/// Returns `None` if the chunk is malformed.
+pub(crate) fn strip(chunk: &[u8]) -> Option<Vec<u8>> {
+    if !chunk.starts_with(SIGNATURE) || chunk.len() < SIGNATURE.len() + 1 {
+        return None;
+    }
+
+    let mut reader = Reader {
+        input: chunk,
+        pos: SIGNATURE.len() + 1,
+    };
+    let flags = reader.uleb128()?;
+    if flags & FLAG_STRIP != 0 {
+        return Some(chunk.to_vec());
+    }
+    let name = reader.uleb128()?;
+    reader.skip(name as usize)?;
+
+    let mut output = Vec::with_capacity(chunk.len());
+    output.extend_from_slice(&chunk[..SIGNATURE.len() + 1]);
+    write_uleb128(&mut output, flags | FLAG_STRIP);
+
+    // The prototypes follow, each preceded by its length, until a zero length.
+    loop {
+        let len = reader.uleb128()? as usize;
+        if len == 0 {
+            break;
+        }
+        let prototype = reader.skip(len)?;
+        let prototype = strip_prototype(prototype)?;
+        write_uleb128(&mut output, prototype.len() as u64);
+        output.extend_from_slice(&prototype);
+    }
+    if reader.pos != chunk.len() {
+        return None;
+    }
+    output.push(0);
+    Some(output)
+}
+
+// The debug information of a prototype is its size and two line numbers in its header, and its
+// content at the end, so the instructions and constants in between are copied as they are.
+fn strip_prototype(prototype: &[u8]) -> Option<Vec<u8>> {
+    // Flags, number of parameters, frame size and number of upvalues.
+    let mut reader = Reader {
+        input: prototype,
+        pos: 4,
+    };
+    // Numbers of constants and of instructions.
+    for _ in 0..3 {
+        reader.uleb128()?;
+    }
+    let header_end = reader.pos;
+
+    let debug = reader.uleb128()? as usize;
+    if debug != 0 {
+        // First line and number of lines.
+        reader.uleb128()?;
+        reader.uleb128()?;
+    }
+    let body_end = prototype.len().checked_sub(debug)?;
+    let body = prototype.get(reader.pos..body_end)?;
+
+    let mut output = Vec::with_capacity(header_end + body.len());
+    output.extend_from_slice(prototype.get(..header_end)?);
+    output.extend_from_slice(body);
+    Some(output)
+}
+
+struct Reader<'a> {
+    input: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn skip(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.input.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn uleb128(&mut self) -> Option<u64> {
+        let mut value: u64 = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.skip(1)?[0];
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+fn write_uleb128(output: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        output.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::strip;
+
+    use AnyLuaString;
+    use Lua;
+    use LuaBytecode;
+    use LuaFunction;
+
+    #[test]
+    fn malformed() {
+        assert!(strip(b"").is_none());
+        assert!(strip(b"return 5").is_none());
+        assert!(strip(b"\x1bLJ\x02\x08\x05name").is_none());
+    }
+
+    // Nested functions with upvalues, and constants of every type.
+    const CODE: &str = "local prefix = 'n\\0' .. 'x'\n\
+                        local function counter(start)\n\
+                        \x20 local n = start\n\
+                        \x20 return function(step)\n\
+                        \x20   n = n + (step or 1) * 0.5\n\
+                        \x20   if n == nil or n == true or n == false then return nil end\n\
+                        \x20   return prefix .. n\n\
+                        \x20 end\n\
+                        end\n\
+                        local c = counter(2)\n\
+                        c()\n\
+                        return c(4)";
+
+    fn dump(lua: &mut Lua) -> Vec<u8> {
+        let mut f = LuaFunction::load_with_name(lua, "@script.lua", CODE).unwrap();
+        f.dump(false).unwrap()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut lua = Lua::new();
+        let full = dump(&mut lua);
+        let stripped = strip(&full).unwrap();
+        assert!(stripped.len() < full.len());
+        assert!(!String::from_utf8_lossy(&stripped).contains("script.lua"));
+        assert!(!String::from_utf8_lossy(&stripped).contains("counter"));
+        assert_eq!(strip(&stripped), Some(stripped.clone()));
+
+        // Same result as the stripping of LuaJIT.
+        lua.openlibs();
+        lua.set("code", CODE);
+        let native: AnyLuaString = lua
+            .execute("return string.dump(load(code, '@script.lua'), true)")
+            .unwrap();
+        assert_eq!(native.0, stripped);
+
+        lua.checked_set("f", LuaBytecode(&stripped)).unwrap();
+        let value: String = lua.execute("return f()").unwrap();
+        assert_eq!(value, "n\0x4.5");
+    }
+
+    #[test]
+    fn truncated_or_corrupted() {
+        let mut lua = Lua::new();
+        let full = dump(&mut lua);
+        for len in 0..full.len() {
+            assert!(strip(&full[..len]).is_none(), "{}", len);
+        }
+
+        let mut longer = full.clone();
+        longer.push(0);
+        assert!(strip(&longer).is_none());
+
+        // Corrupted chunks may look valid, but must not make `strip` panic.
+        for i in 0..full.len() {
+            let mut corrupted = full.clone();
+            corrupted[i] ^= 0xff;
+            let _ = strip(&corrupted);
+        }
+    }
+}
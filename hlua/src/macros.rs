@@ -16,61 +16,60 @@ macro_rules! implement_lua_push {
     };
 }
 
+/// Used to implement reading a user data as a reference from the parameters of a callback.
+///
+/// Nothing prevented a callback from keeping such a reference after the object was collected, so
+/// callbacks now read user data of any type as a [`UserdataRef`](struct.UserdataRef.html) or a
+/// [`UserdataMut`](struct.UserdataMut.html) instead, and this macro does nothing. It is kept so
+/// that the code that uses it still compiles.
 #[macro_export]
 macro_rules! implement_lua_read {
-    ($ty:ty) => {
-        impl<'s, 'c> hlua::LuaRead<&'c mut hlua::InsideCallback> for &'s mut $ty {
-            #[inline]
-            fn lua_read_at_position(
-                lua: &'c mut hlua::InsideCallback,
-                index: i32,
-            ) -> Result<&'s mut $ty, &'c mut hlua::InsideCallback> {
-                // FIXME:
-                unsafe { ::std::mem::transmute($crate::read_userdata::<$ty>(lua, index)) }
-            }
-        }
-
-        impl<'s, 'c> hlua::LuaRead<&'c mut hlua::InsideCallback> for &'s $ty {
-            #[inline]
-            fn lua_read_at_position(
-                lua: &'c mut hlua::InsideCallback,
-                index: i32,
-            ) -> Result<&'s $ty, &'c mut hlua::InsideCallback> {
-                // FIXME:
-                unsafe { ::std::mem::transmute($crate::read_userdata::<$ty>(lua, index)) }
-            }
-        }
-
-        impl<'s, 'b, 'c> hlua::LuaRead<&'b mut &'c mut hlua::InsideCallback> for &'s mut $ty {
-            #[inline]
-            fn lua_read_at_position(
-                lua: &'b mut &'c mut hlua::InsideCallback,
-                index: i32,
-            ) -> Result<&'s mut $ty, &'b mut &'c mut hlua::InsideCallback> {
-                let ptr_lua = lua as *mut &mut hlua::InsideCallback;
-                let deref_lua = unsafe { ::std::ptr::read(ptr_lua) };
-                let res = Self::lua_read_at_position(deref_lua, index);
-                match res {
-                    Ok(x) => Ok(x),
-                    _ => Err(lua),
-                }
-            }
-        }
+    ($ty:ty) => {};
+}
 
-        impl<'s, 'b, 'c> hlua::LuaRead<&'b mut &'c mut hlua::InsideCallback> for &'s $ty {
-            #[inline]
-            fn lua_read_at_position(
-                lua: &'b mut &'c mut hlua::InsideCallback,
-                index: i32,
-            ) -> Result<&'s $ty, &'b mut &'c mut hlua::InsideCallback> {
-                let ptr_lua = lua as *mut &mut hlua::InsideCallback;
-                let deref_lua = unsafe { ::std::ptr::read(ptr_lua) };
-                let res = Self::lua_read_at_position(deref_lua, index);
-                match res {
-                    Ok(x) => Ok(x),
-                    _ => Err(lua),
-                }
-            }
-        }
-    };
+/// Embeds Lua scripts into the binary, and returns them as a
+/// [`MemorySource`](struct.MemorySource.html) to pass to
+/// [`Lua::set_script_source`](struct.Lua.html#method.set_script_source).
+///
+/// Each path is read with `include_bytes!`, so it is relative to the file that contains the macro
+/// invocation, and becomes the path of the script in the source. With `root = ...`, the paths
+/// are relative to the root directory instead, which can be absolute. For example the scripts
+/// precompiled by [`precompile_scripts`](fn.precompile_scripts.html) in a build script can be
+/// embedded with `root = concat!(env!("OUT_DIR"), "/scripts")`.
+///
+/// # Example
+///
+/// ```
+/// #[macro_use]
+/// extern crate hlua;
+///
+/// fn main() {
+///     let mut lua = hlua::Lua::new();
+///     lua.openlibs();
+///     lua.set_script_source(include_lua!(
+///         root = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scripts"),
+///         "init.lua",
+///         "mygame/audio.lua",
+///     ));
+///
+///     let msg: String = lua.execute("return require 'init'").unwrap();
+///     assert_eq!(msg, "playing jump at 5");
+/// }
+/// ```
+#[macro_export]
+macro_rules! include_lua {
+    (root = $root:expr, $($path:expr),+ $(,)*) => {{
+        let mut source = $crate::MemorySource::new();
+        $(
+            source.insert($path, &include_bytes!(concat!($root, "/", $path))[..]);
+        )+
+        source
+    }};
+    ($($path:expr),+ $(,)*) => {{
+        let mut source = $crate::MemorySource::new();
+        $(
+            source.insert($path, &include_bytes!($path)[..]);
+        )+
+        source
+    }};
 }
@@ -0,0 +1,200 @@
+use ffi;
+use libc;
+
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+
+use functions_write::closure_destructor_wrapper;
+
+use AsMutLua;
+use Lua;
+use LuaContext;
+use LuaRead;
+use LuaTable;
+use PushGuard;
+
+// Name of the table of the registry that contains the loaders of `package.preload`.
+const PRELOAD_TABLE: &[u8] = b"_PRELOAD\0";
+
+impl<'lua> Lua<'lua> {
+    /// Registers a module implemented in Rust, that scripts can load with `require`.
+    ///
+    /// The module is built the first time it is required, by calling `builder` with an empty
+    /// table that becomes the module. As with any module, the table is then cached in
+    /// `package.loaded`, and later calls to `require` return the same table. If the module has
+    /// already been loaded, registering it again only affects the contexts in which it isn't
+    /// cached anymore.
+    ///
+    /// The loader is stored in `package.preload`. This method can be called before the package
+    /// library is opened, but `require` must be available for the scripts to use the module.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new();
+    /// lua.openlibs();
+    ///
+    /// lua.register_module("mygame.audio", |module| {
+    ///     module.set("volume", 0.5);
+    ///     module.set("play", hlua::function1(|sound: String| format!("playing {}", sound)));
+    /// });
+    ///
+    /// let msg: String = lua.execute(r#"
+    ///     local audio = require "mygame.audio"
+    ///     return audio.play("jump.wav")
+    /// "#).unwrap();
+    /// assert_eq!(msg, "playing jump.wav");
+    /// ```
+    pub fn register_module<F>(&mut self, name: &str, builder: F)
+    where
+        F: 'lua + for<'a, 'b> FnMut(&'a mut LuaTable<PushGuard<&'b mut Lua<'lua>>>),
+    {
+        // Lua expects C strings, so the name is truncated at the first nul character.
+        let name = CString::new(name.split('\0').next().unwrap()).unwrap();
+
+        unsafe {
+            let lua = self.as_mut_lua().0;
+            ffi::luaL_getsubtable(
+                lua,
+                ffi::LUA_REGISTRYINDEX,
+                PRELOAD_TABLE.as_ptr() as *const _,
+            );
+
+            let data = ffi::lua_newuserdata(lua, mem::size_of::<F>() as libc::size_t);
+            ptr::write(data as *mut F, builder);
+            ffi::lua_newtable(lua);
+            ffi::lua_pushcfunction(lua, closure_destructor_wrapper::<F>);
+            ffi::lua_setfield(lua, -2, b"__gc\0".as_ptr() as *const _);
+            ffi::lua_setmetatable(lua, -2);
+            ffi::lua_pushcclosure(lua, module_loader::<F>, 1);
+
+            ffi::lua_setfield(lua, -2, name.as_ptr());
+            ffi::lua_pop(lua, 1);
+        }
+    }
+}
+
+// Loader of a module registered with `register_module`. The builder is the first upvalue.
+extern "C" fn module_loader<'lua, F>(lua: *mut ffi::lua_State) -> libc::c_int
+where
+    F: 'lua + for<'a, 'b> FnMut(&'a mut LuaTable<PushGuard<&'b mut Lua<'lua>>>),
+{
+    unsafe {
+        let builder = ffi::lua_touserdata(lua, ffi::lua_upvalueindex(1)) as *mut F;
+        let mut context = Lua::from_existing_state(lua, false);
+
+        ffi::lua_newtable(lua);
+        let guard = PushGuard {
+            lua: &mut context,
+            size: 1,
+            raw_lua: LuaContext(lua),
+        };
+        let mut module = match LuaTable::lua_read(guard) {
+            Ok(table) => table,
+            Err(_) => unreachable!(),
+        };
+        (*builder)(&mut module);
+
+        // The table is returned to `require`.
+        module.into_inner().forget_internal();
+        1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    use function0;
+    use Lua;
+    use LuaError;
+
+    #[test]
+    fn require_module() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.register_module("mygame.audio", |module| {
+            module.set("volume", 3);
+            module.set("double", function0(|| 6));
+        });
+
+        let v: i32 = lua
+            .execute("local audio = require 'mygame.audio' return audio.volume + audio.double()")
+            .unwrap();
+        assert_eq!(v, 9);
+    }
+
+    #[test]
+    fn loaded_lazily_and_cached() {
+        let count = Rc::new(Cell::new(0));
+
+        let mut lua = Lua::new();
+        lua.openlibs();
+        {
+            let count = count.clone();
+            lua.register_module("counted", move |module| {
+                count.set(count.get() + 1);
+                module.set("n", count.get());
+            });
+        }
+        assert_eq!(count.get(), 0);
+
+        let same: bool = lua
+            .execute("return require 'counted' == require 'counted'")
+            .unwrap();
+        assert!(same);
+        assert_eq!(count.get(), 1);
+
+        let cached: bool = lua
+            .execute("return package.loaded.counted == require 'counted'")
+            .unwrap();
+        assert!(cached);
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn registered_before_openlibs() {
+        let mut lua = Lua::new();
+        lua.register_module("early", |module| module.set("x", 1));
+        lua.openlibs();
+
+        let x: i32 = lua.execute("return require('early').x").unwrap();
+        assert_eq!(x, 1);
+    }
+
+    #[test]
+    fn closure_is_dropped() {
+        let dropped = Rc::new(Cell::new(false));
+        struct Guard(Rc<Cell<bool>>);
+        impl Drop for Guard {
+            fn drop(&mut self) {
+                self.0.set(true);
+            }
+        }
+
+        {
+            let mut lua = Lua::new();
+            let guard = Guard(dropped.clone());
+            lua.register_module("m", move |_| {
+                let _ = &guard;
+            });
+        }
+        assert!(dropped.get());
+    }
+
+    #[test]
+    fn unknown_module() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.register_module("known", |_| {});
+
+        match lua.execute::<()>("require 'unknown'") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("module 'unknown' not found"))
+            }
+            _ => panic!(),
+        }
+    }
+}
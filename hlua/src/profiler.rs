@@ -0,0 +1,651 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::io::Write;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
+use ffi;
+use libc;
+
+use debug::{current_hook, remove_hook};
+use AsMutLua;
+use DebugInfo;
+use HookContext;
+use HookEvent;
+use HookMask;
+use Lua;
+
+/// Records how much time is spent in each Lua function.
+///
+/// The profiler is installed as the hook of a `Lua` context (see
+/// [the `set_hook` method](struct.Lua.html#method.set_hook)), which means that it replaces any
+/// hook that was previously set, and that it has no cost at all when it isn't running. Dropping
+/// the profiler without calling `stop` removes the hook as well, unless another hook has replaced
+/// it in the meantime.
+///
+/// It works in one of two modes:
+///
+/// - Started with `start`, it instruments every call, return and line. The results are exact,
+///   but the hook slows down the code a lot.
+/// - Started with `start_sampling`, it looks at the call stack every given number of
+///   instructions. The results are statistical, but the overhead is much lower and can be tuned
+///   with the interval.
+///
+/// # Example
+///
+/// ```
+/// use hlua::{Lua, Profiler};
+///
+/// let mut lua = Lua::new();
+/// lua.execute::<()>("function foo() return 1 end").unwrap();
+///
+/// let profiler = Profiler::start(&mut lua);
+/// lua.execute::<()>("for i = 1, 10 do foo() end").unwrap();
+/// let profile = profiler.stop(&mut lua);
+///
+/// assert_eq!(profile.function("foo").unwrap().calls, 10);
+/// println!("{}", profile.folded_stacks());
+/// ```
+#[derive(Debug)]
+pub struct Profiler {
+    state: Rc<RefCell<ProfilerState>>,
+    // The context and the closure of the hook, to remove the hook when the profiler is dropped.
+    lua: *mut ffi::lua_State,
+    hook: *const libc::c_void,
+}
+
+impl Profiler {
+    /// Starts profiling the code executed by `lua`, by instrumenting every function call,
+    /// return and line.
+    #[inline]
+    pub fn start(lua: &mut Lua) -> Profiler {
+        let mask = HookMask::CALL | HookMask::RETURN | HookMask::LINE;
+        Profiler::install(lua, mask, 0)
+    }
+
+    /// Starts profiling the code executed by `lua`, by recording the call stack every `interval`
+    /// virtual machine instructions.
+    ///
+    /// The results only contain `samples`: calls, times and line hits are not recorded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `interval` is 0.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{Lua, Profiler};
+    ///
+    /// let mut lua = Lua::new();
+    /// lua.execute::<()>("function busy() local a = 0 for i = 1, 10000 do a = a + i end end")
+    ///     .unwrap();
+    ///
+    /// let profiler = Profiler::start_sampling(&mut lua, 100);
+    /// lua.execute::<()>("busy()").unwrap();
+    /// let profile = profiler.stop(&mut lua);
+    ///
+    /// assert!(profile.function("busy").unwrap().samples > 0);
+    /// ```
+    pub fn start_sampling(lua: &mut Lua, interval: u32) -> Profiler {
+        assert!(interval > 0, "the sampling interval must not be 0");
+        Profiler::install(lua, HookMask::COUNT, interval)
+    }
+
+    fn install(lua: &mut Lua, mask: HookMask, count: u32) -> Profiler {
+        let state = Rc::new(RefCell::new(ProfilerState::default()));
+
+        let hook_state = state.clone();
+        lua.set_hook(mask, count, move |ctx, event| {
+            hook_state.borrow_mut().on_event(ctx, event)
+        });
+
+        let lua = lua.as_mut_lua().0;
+        Profiler {
+            state,
+            lua,
+            hook: unsafe { current_hook(lua) },
+        }
+    }
+
+    /// Returns the results collected so far, without stopping the profiler.
+    ///
+    /// Functions that are still running are not accounted for.
+    #[inline]
+    pub fn snapshot(&self) -> Profile {
+        self.state.borrow().profile()
+    }
+
+    /// Removes the profiler from `lua` and returns the results.
+    ///
+    /// Functions that are still running (for example if the profiler is stopped from within
+    /// a Rust function called by Lua) are considered to return now. If another hook has replaced
+    /// the profiler in the meantime, that hook is left in place.
+    pub fn stop(self, lua: &mut Lua) -> Profile {
+        unsafe {
+            let lua = lua.as_mut_lua().0;
+            if current_hook(lua) == self.hook {
+                remove_hook(lua);
+            }
+        }
+
+        let mut state = self.state.borrow_mut();
+        let now = Instant::now();
+        while !state.stack.is_empty() {
+            state.pop(now);
+        }
+        state.profile()
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        // The closure of the hook owns a reference to the state until it is collected, which
+        // happens at the latest when the context is closed. The context is therefore still open
+        // if there is such a reference.
+        if Rc::strong_count(&self.state) > 1 {
+            unsafe {
+                if current_hook(self.lua) == self.hook {
+                    remove_hook(self.lua);
+                }
+            }
+        }
+    }
+}
+
+/// Results of a profiling session.
+#[derive(Debug, Clone, Default)]
+pub struct Profile {
+    /// Statistics about each function that has been called, in the order in which they have
+    /// been called for the first time.
+    pub functions: Vec<FunctionProfile>,
+
+    /// Number of times each line has been executed, indexed by source and line number. The source
+    /// is in the same format as `FunctionProfile::source`.
+    pub line_hits: BTreeMap<(String, u32), u64>,
+
+    /// Time spent in each call stack. Each stack is a list of indices within `functions`,
+    /// starting with the outermost function. The time is the exclusive time of the last function
+    /// of the stack.
+    pub stacks: BTreeMap<Vec<usize>, Duration>,
+
+    /// Number of times each call stack has been seen by a sampling profiler, in the same format
+    /// as `stacks`.
+    pub samples: BTreeMap<Vec<usize>, u64>,
+}
+
+/// Statistics about a function within a `Profile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionProfile {
+    /// Name of the function, as reported by Lua the first time it was called. Lua doesn't always
+    /// know the name of a function, in which case a name is built from the location of its
+    /// definition.
+    pub name: String,
+
+    /// Source of the chunk where the function was defined, as returned by
+    /// [`DebugInfo::source`](struct.DebugInfo.html#method.source), such as `@scripts/init.lua`,
+    /// `chunk` or `=[C]` for Rust and C functions.
+    pub source: String,
+
+    /// Printable description of `source`, such as `scripts/init.lua`, `[string "chunk"]` or
+    /// `[C]`. Lua truncates it if the source is long, so it can be the same for different
+    /// sources.
+    pub short_src: String,
+
+    /// Line where the definition of the function starts, if it is a Lua function.
+    pub line_defined: Option<u32>,
+
+    /// Number of times the function has been called.
+    pub calls: u64,
+
+    /// Total time spent in the function, including in the functions that it called.
+    pub inclusive_time: Duration,
+
+    /// Total time spent in the function itself, excluding the functions that it called.
+    pub exclusive_time: Duration,
+
+    /// Number of times a sampling profiler has seen the function running, excluding the times
+    /// when it was calling another function.
+    pub samples: u64,
+}
+
+impl Profile {
+    /// Returns the statistics of the first function that has the given name, if any.
+    pub fn function(&self, name: &str) -> Option<&FunctionProfile> {
+        self.functions.iter().find(|f| f.name == name)
+    }
+
+    /// Returns the number of times a line has been executed.
+    ///
+    /// The source is in the same format as `FunctionProfile::source`.
+    pub fn line_hits(&self, source: &str, line: u32) -> u64 {
+        self.line_hits
+            .get(&(source.to_owned(), line))
+            .cloned()
+            .unwrap_or(0)
+    }
+
+    /// Writes the call stacks in the "folded" format, which can be used as the input of
+    /// flamegraph generators such as `flamegraph.pl` or `inferno`.
+    ///
+    /// Each line contains the functions of a stack separated with `;`, followed with the
+    /// exclusive time in microseconds of the innermost function, or with the number of samples
+    /// for a sampling profiler.
+    pub fn write_folded_stacks<W>(&self, mut output: W) -> io::Result<()>
+    where
+        W: Write,
+    {
+        for (stack, time) in &self.stacks {
+            let micros = time.as_secs() * 1_000_000 + u64::from(time.subsec_micros());
+            writeln!(output, "{} {}", self.folded_stack(stack), micros)?;
+        }
+
+        for (stack, count) in &self.samples {
+            writeln!(output, "{} {}", self.folded_stack(stack), count)?;
+        }
+
+        Ok(())
+    }
+
+    /// Same as `write_folded_stacks`, but returns a `String`.
+    pub fn folded_stacks(&self) -> String {
+        let mut output = Vec::new();
+        self.write_folded_stacks(&mut output).unwrap();
+        String::from_utf8(output).unwrap()
+    }
+}
+
+impl Profile {
+    fn folded_stack(&self, stack: &[usize]) -> String {
+        let frames: Vec<String> = stack
+            .iter()
+            .map(|&f| self.functions[f].folded_name())
+            .collect();
+        frames.join(";")
+    }
+}
+
+impl FunctionProfile {
+    // Name of the function in the folded stacks output, where `;` is a separator.
+    fn folded_name(&self) -> String {
+        let name = match self.line_defined {
+            Some(line) => format!("{} ({}:{})", self.name, self.short_src, line),
+            None => format!("{} ({})", self.name, self.short_src),
+        };
+        name.replace(';', ":")
+    }
+}
+
+#[derive(Debug, Default)]
+struct ProfilerState {
+    functions: Vec<FunctionProfile>,
+    // Indices within `functions`, indexed by source and line where the function is defined (or
+    // name for Rust and C functions).
+    functions_index: HashMap<(String, Option<u32>, Option<String>), usize>,
+    line_hits: BTreeMap<(String, u32), u64>,
+    stacks: BTreeMap<Vec<usize>, Duration>,
+    samples: BTreeMap<Vec<usize>, u64>,
+    stack: Vec<Frame>,
+}
+
+#[derive(Debug)]
+struct Frame {
+    function: usize,
+    activation: (usize, usize),
+    start: Instant,
+    children_time: Duration,
+}
+
+impl ProfilerState {
+    fn on_event(&mut self, ctx: &mut HookContext, event: HookEvent) {
+        let now = Instant::now();
+        let activation = ctx.activation_id();
+
+        match event {
+            HookEvent::Call | HookEvent::TailCall => {
+                // Lua doesn't report the functions that are interrupted by an error. Everything
+                // above the caller of the new function has been interrupted, and so has every
+                // function of the thread if the new one is at the bottom of its stack. If the
+                // caller started before the profiler, a function that we consider as running in
+                // the same slot as the new call has been interrupted, with everything above it.
+                let interrupted = match ctx.caller_activation_id() {
+                    Some(caller) => match self.stack.iter().rposition(|f| f.activation == caller) {
+                        Some(pos) => Some(pos + 1),
+                        None => self.stack.iter().position(|f| f.activation == activation),
+                    },
+                    None => self
+                        .stack
+                        .iter()
+                        .position(|f| f.activation.0 == activation.0),
+                };
+                if let Some(pos) = interrupted {
+                    while self.stack.len() > pos {
+                        self.pop(now);
+                    }
+                }
+
+                let function = self.function_index(&ctx.info());
+                self.functions[function].calls += 1;
+                self.stack.push(Frame {
+                    function,
+                    activation,
+                    start: now,
+                    children_time: Duration::new(0, 0),
+                });
+            }
+            HookEvent::Return => {
+                if let Some(pos) = self.stack.iter().rposition(|f| f.activation == activation) {
+                    while self.stack.len() > pos {
+                        self.pop(now);
+                    }
+                }
+            }
+            HookEvent::Line(line) => {
+                let source = ctx.info().source().unwrap_or("").to_owned();
+                *self.line_hits.entry((source, line)).or_insert(0) += 1;
+            }
+            HookEvent::Count => {
+                let mut stack: Vec<usize> = ctx
+                    .stack_frames()
+                    .iter()
+                    .map(|frame| self.function_index(&frame.info()))
+                    .collect();
+                stack.reverse();
+
+                if let Some(&function) = stack.last() {
+                    self.functions[function].samples += 1;
+                    *self.samples.entry(stack).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    fn function_index(&mut self, info: &DebugInfo) -> usize {
+        let source = info.source().unwrap_or("");
+        let short_src = info.short_src();
+        let line_defined = info.line_defined();
+
+        // All the Rust and C functions have the same source, so we distinguish them by name.
+        let name = if line_defined.is_none() {
+            info.name().map(|n| n.to_owned())
+        } else {
+            None
+        };
+
+        let key = (source.to_owned(), line_defined, name);
+        if let Some(&index) = self.functions_index.get(&key) {
+            return index;
+        }
+
+        let name = match (info.name(), line_defined) {
+            (Some(name), _) => name.to_owned(),
+            (None, Some(0)) => "main chunk".to_owned(),
+            (None, Some(line)) => format!("function <{}:{}>", short_src, line),
+            (None, None) => "?".to_owned(),
+        };
+
+        let index = self.functions.len();
+        self.functions.push(FunctionProfile {
+            name,
+            source: source.to_owned(),
+            short_src: short_src.to_owned(),
+            line_defined,
+            calls: 0,
+            inclusive_time: Duration::new(0, 0),
+            exclusive_time: Duration::new(0, 0),
+            samples: 0,
+        });
+        self.functions_index.insert(key, index);
+        index
+    }
+
+    fn pop(&mut self, now: Instant) {
+        let frame = self.stack.pop().unwrap();
+        let elapsed = now - frame.start;
+        let exclusive = elapsed.checked_sub(frame.children_time).unwrap_or_default();
+
+        {
+            let function = &mut self.functions[frame.function];
+            function.exclusive_time += exclusive;
+            // For recursive functions, only the outermost call is counted.
+            if self.stack.iter().all(|f| f.function != frame.function) {
+                function.inclusive_time += elapsed;
+            }
+        }
+
+        let mut path: Vec<usize> = self.stack.iter().map(|f| f.function).collect();
+        path.push(frame.function);
+        *self.stacks.entry(path).or_default() += exclusive;
+
+        if let Some(parent) = self.stack.last_mut() {
+            parent.children_time += elapsed;
+        }
+    }
+
+    fn profile(&self) -> Profile {
+        Profile {
+            functions: self.functions.clone(),
+            line_hits: self.line_hits.clone(),
+            stacks: self.stacks.clone(),
+            samples: self.samples.clone(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use HookMask;
+    use Lua;
+    use Profiler;
+
+    #[test]
+    fn call_counts() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("function foo() return 1 end\nfunction bar() foo() foo() end")
+            .unwrap();
+
+        let profiler = Profiler::start(&mut lua);
+        lua.execute::<()>("for i = 1, 5 do bar() end").unwrap();
+        let profile = profiler.stop(&mut lua);
+
+        assert_eq!(profile.function("foo").unwrap().calls, 10);
+        assert_eq!(profile.function("bar").unwrap().calls, 5);
+        assert_eq!(profile.function("main chunk").unwrap().calls, 1);
+    }
+
+    #[test]
+    fn inclusive_and_exclusive_time() {
+        let mut lua = Lua::new();
+        lua.execute::<()>(
+            "function leaf() local a = 0; for i = 1, 10000 do a = a + i end end\n\
+             function outer() leaf() leaf() end",
+        )
+        .unwrap();
+
+        let profiler = Profiler::start(&mut lua);
+        lua.execute::<()>("outer()").unwrap();
+        let profile = profiler.stop(&mut lua);
+
+        let outer = profile.function("outer").unwrap();
+        let leaf = profile.function("leaf").unwrap();
+        assert!(outer.inclusive_time >= outer.exclusive_time);
+        assert!(outer.inclusive_time >= leaf.inclusive_time);
+        assert_eq!(leaf.inclusive_time, leaf.exclusive_time);
+    }
+
+    #[test]
+    fn recursion() {
+        let mut lua = Lua::new();
+        lua.execute::<()>(
+            "function fact(n) if n <= 1 then return 1 end return n * fact(n - 1) end",
+        )
+        .unwrap();
+
+        let profiler = Profiler::start(&mut lua);
+        lua.execute::<()>("fact(5)").unwrap();
+        let profile = profiler.stop(&mut lua);
+
+        let fact = profile.function("fact").unwrap();
+        assert_eq!(fact.calls, 5);
+        let main = profile.function("main chunk").unwrap();
+        assert!(main.inclusive_time >= fact.inclusive_time);
+    }
+
+    #[test]
+    fn line_hits() {
+        let mut lua = Lua::new();
+        let profiler = Profiler::start(&mut lua);
+        lua.execute::<()>("local a = 0\nfor i = 1, 3 do\n  a = a + i\nend")
+            .unwrap();
+        let profile = profiler.stop(&mut lua);
+
+        assert_eq!(profile.line_hits("chunk", 1), 1);
+        assert_eq!(profile.line_hits("chunk", 3), 3);
+        assert_eq!(profile.line_hits("chunk", 5), 0);
+    }
+
+    #[test]
+    fn line_hits_of_long_names() {
+        // Lua truncates the printable description of these names to the same string.
+        let first = format!("={}1", "x".repeat(80));
+        let second = format!("={}2", "x".repeat(80));
+
+        let mut lua = Lua::new();
+        let profiler = Profiler::start(&mut lua);
+        lua.execute_named::<()>(&first, "local a = 1").unwrap();
+        lua.execute_named::<()>(&second, "local a = 1\nlocal b = 2")
+            .unwrap();
+        let profile = profiler.stop(&mut lua);
+
+        assert_eq!(profile.line_hits(&first, 1), 1);
+        assert_eq!(profile.line_hits(&first, 2), 0);
+        assert_eq!(profile.line_hits(&second, 1), 1);
+        assert_eq!(profile.line_hits(&second, 2), 1);
+    }
+
+    #[test]
+    fn sampling() {
+        let mut lua = Lua::new();
+        lua.execute::<()>(
+            "function leaf() local a = 0; for i = 1, 10000 do a = a + i end end\n\
+             function outer() leaf() leaf() end",
+        )
+        .unwrap();
+
+        let profiler = Profiler::start_sampling(&mut lua, 10);
+        lua.execute::<()>("outer()").unwrap();
+        let profile = profiler.stop(&mut lua);
+
+        let leaf = profile.function("leaf").unwrap();
+        assert!(leaf.samples > 100);
+        assert_eq!(leaf.calls, 0);
+        assert!(profile.stacks.is_empty());
+        assert!(profile.line_hits.is_empty());
+
+        let total: u64 = profile.samples.values().sum();
+        let sampled: u64 = profile.functions.iter().map(|f| f.samples).sum();
+        assert_eq!(total, sampled);
+
+        let stacks: Vec<String> = profile
+            .folded_stacks()
+            .lines()
+            .map(|l| l.rsplit_once(' ').unwrap().0.to_owned())
+            .collect();
+        assert!(stacks.contains(
+            &"main chunk ([string \"chunk\"]:0);outer ([string \"chunk\"]:2);\
+              leaf ([string \"chunk\"]:1)"
+                .to_owned()
+        ));
+    }
+
+    #[test]
+    fn folded_stacks() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("function foo() end\nfunction bar() foo() end")
+            .unwrap();
+
+        let profiler = Profiler::start(&mut lua);
+        lua.execute::<()>("bar()").unwrap();
+        let profile = profiler.stop(&mut lua);
+
+        let stacks: Vec<String> = profile
+            .folded_stacks()
+            .lines()
+            .map(|l| l.rsplit_once(' ').unwrap().0.to_owned())
+            .collect();
+        assert!(stacks.contains(
+            &"main chunk ([string \"chunk\"]:0);bar ([string \"chunk\"]:2);\
+              foo ([string \"chunk\"]:1)"
+                .to_owned()
+        ));
+        assert_eq!(stacks.len(), 3);
+    }
+
+    #[test]
+    fn errors_unwind_the_stack() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.execute::<()>("function fail() error('oops') end\nfunction foo() return 1 end")
+            .unwrap();
+
+        let profiler = Profiler::start(&mut lua);
+        assert!(lua.execute::<()>("fail()").is_err());
+        lua.execute::<()>("foo()").unwrap();
+        lua.execute::<()>("pcall(fail)\nfoo()").unwrap();
+        let profile = profiler.stop(&mut lua);
+
+        assert_eq!(profile.function("foo").unwrap().calls, 2);
+        assert_eq!(profile.function("fail").unwrap().calls, 2);
+        // `foo` is always called by the main chunk, even after an error interrupted `fail`.
+        let foo = profile
+            .functions
+            .iter()
+            .position(|f| f.name == "foo")
+            .unwrap();
+        for stack in profile.stacks.keys().filter(|s| s.contains(&foo)) {
+            assert_eq!(stack.len(), 2);
+        }
+        let main = profile.function("main chunk").unwrap();
+        assert_eq!(main.calls, 3);
+    }
+
+    #[test]
+    fn no_hook_when_stopped() {
+        let mut lua = Lua::new();
+        let profiler = Profiler::start(&mut lua);
+        assert!(!lua.hook_mask().is_empty());
+        profiler.stop(&mut lua);
+        assert!(lua.hook_mask().is_empty());
+
+        // The hook that replaced the one of the profiler is kept.
+        let profiler = Profiler::start(&mut lua);
+        lua.execute::<()>("local a = 1").unwrap();
+        lua.set_hook(HookMask::LINE, 0, |_, _| {});
+        let profile = profiler.stop(&mut lua);
+        assert_eq!(lua.hook_mask(), HookMask::LINE);
+        assert_eq!(profile.function("main chunk").unwrap().calls, 1);
+    }
+
+    #[test]
+    fn no_hook_when_dropped() {
+        let mut lua = Lua::new();
+        let profiler = Profiler::start_sampling(&mut lua, 10);
+        assert!(!lua.hook_mask().is_empty());
+        drop(profiler);
+        assert!(lua.hook_mask().is_empty());
+
+        // The hook that replaced the one of the profiler is kept.
+        let profiler = Profiler::start(&mut lua);
+        lua.set_hook(HookMask::LINE, 0, |_, _| {});
+        drop(profiler);
+        assert_eq!(lua.hook_mask(), HookMask::LINE);
+
+        // The profiler can outlive the context.
+        let profiler = {
+            let mut lua = Lua::new();
+            Profiler::start(&mut lua)
+        };
+        drop(profiler);
+    }
+}
@@ -0,0 +1,601 @@
+use ffi;
+use libc;
+
+use std::collections::BTreeSet;
+use std::ffi::CStr;
+use std::ffi::CString;
+
+use LoadMode;
+use Lua;
+
+// Functions of the base library that are available by default.
+const SAFE_BASE_FUNCTIONS: &[&str] = &[
+    "_G",
+    "_VERSION",
+    "assert",
+    "error",
+    "getmetatable",
+    "ipairs",
+    "load",
+    "next",
+    "pairs",
+    "pcall",
+    "print",
+    "rawequal",
+    "rawget",
+    "rawlen",
+    "rawset",
+    "select",
+    "setmetatable",
+    "tonumber",
+    "tostring",
+    "type",
+    // LuaJIT follows Lua 5.1, whose `unpack` is a global function instead of `table.unpack`.
+    #[cfg(feature = "luajit")]
+    "unpack",
+    "xpcall",
+];
+
+// Libraries and library functions that are available by default.
+const SAFE_LIBRARIES: &[&str] = &[
+    #[cfg(any(feature = "lua52", feature = "lua53"))]
+    "bit32",
+    #[cfg(feature = "luajit")]
+    "bit",
+    "math",
+    "string",
+    "table",
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    "utf8",
+    "os.clock",
+    "os.date",
+    "os.time",
+];
+
+type OpenFunction = unsafe extern "C" fn(*mut ffi::lua_State) -> libc::c_int;
+
+// The address of this static is used as the key in the registry of the `searchers` table used by
+// `require` in a sandboxed context, where `package.searchers` isn't available.
+static SEARCHERS_REGISTRY_KEY: u8 = 0;
+
+#[inline]
+fn searchers_registry_key() -> *const libc::c_char {
+    &SEARCHERS_REGISTRY_KEY as *const u8 as *const libc::c_char
+}
+
+// Pushes the table of the searchers used by `require`, and returns true, or pushes nothing and
+// returns false if the package library isn't open.
+pub(crate) unsafe fn push_searchers(lua: *mut ffi::lua_State) -> bool {
+    ffi::lua_rawgetp(lua, ffi::LUA_REGISTRYINDEX, searchers_registry_key());
+    if ffi::lua_istable(lua, -1) {
+        return true;
+    }
+    ffi::lua_pop(lua, 1);
+
+    ffi::lua_getfield(
+        lua,
+        ffi::LUA_REGISTRYINDEX,
+        b"_LOADED\0".as_ptr() as *const _,
+    );
+    if ffi::lua_istable(lua, -1) {
+        ffi::lua_getfield(lua, -1, b"package\0".as_ptr() as *const _);
+        if ffi::lua_istable(lua, -1) {
+            ffi::lua_getfield(lua, -1, b"searchers\0".as_ptr() as *const _);
+            if ffi::lua_istable(lua, -1) {
+                ffi::lua_replace(lua, -3);
+                ffi::lua_pop(lua, 1);
+                return true;
+            }
+            ffi::lua_pop(lua, 1);
+        }
+        ffi::lua_pop(lua, 1);
+    }
+    ffi::lua_pop(lua, 1);
+    false
+}
+
+// The standard libraries other than the base library.
+const LIBRARIES: &[(&str, OpenFunction)] = &[
+    #[cfg(feature = "luajit")]
+    ("bit", ffi::luaopen_bit),
+    #[cfg(any(feature = "lua52", feature = "lua53"))]
+    ("bit32", ffi::luaopen_bit32),
+    ("coroutine", ffi::luaopen_coroutine),
+    ("debug", ffi::luaopen_debug),
+    #[cfg(feature = "luajit")]
+    ("ffi", ffi::luaopen_ffi),
+    ("io", ffi::luaopen_io),
+    #[cfg(feature = "luajit")]
+    ("jit", ffi::luaopen_jit),
+    ("math", ffi::luaopen_math),
+    ("os", ffi::luaopen_os),
+    ("package", ffi::luaopen_package),
+    ("string", ffi::luaopen_string),
+    ("table", ffi::luaopen_table),
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    ("utf8", ffi::luaopen_utf8),
+];
+
+/// Which parts of the standard library are available in a sandboxed context.
+///
+/// The policy is a list of allowed names. A name is either a global variable of the base library
+/// such as `"print"`, a whole library such as `"string"`, or a function of a library such as
+/// `"os.time"`. Functions can also be removed from an allowed library with `deny`.
+///
+/// The default policy, returned by `new`, allows the functions that can't access the file system,
+/// the environment of the process, or the internals of the interpreter:
+///
+/// - the base library, except `collectgarbage`, `dofile` and `loadfile`,
+/// - the `bit32` (with Lua 5.2 and 5.3), `bit` (with LuaJIT), `math`, `string`, `table` and `utf8`
+///   (with Lua 5.3 and 5.4) libraries,
+/// - `os.clock`, `os.date` and `os.time`.
+///
+/// It also restricts the loading of code to source code, as precompiled chunks aren't verified
+/// and can crash the interpreter. This applies both to Rust and to the `load` function of Lua.
+/// See [`Lua::with_sandbox`](struct.Lua.html#method.with_sandbox).
+///
+/// Allowing `require` opens the package library for it, but `require` then only finds the
+/// modules of `package.preload`, such as those added with
+/// [`Lua::register_module`](struct.Lua.html#method.register_module), and the scripts of
+/// [the script source](struct.Lua.html#method.set_script_source). The searchers that load Lua
+/// files and native libraries from `package.path` and `package.cpath` are removed, since they
+/// would give access to the file system and ignore the load mode. They are kept if the policy
+/// allows `package.searchers`, for example by allowing the whole `package` library.
+///
+/// # Example
+///
+/// ```
+/// use hlua::{Lua, SandboxPolicy};
+///
+/// let policy = SandboxPolicy::new().allow("io.write").deny("string.rep");
+/// let mut lua = Lua::with_sandbox(&policy);
+///
+/// let blocked: bool = lua.execute("return io.read == nil and string.rep == nil").unwrap();
+/// assert!(blocked);
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SandboxPolicy {
+    allowed: BTreeSet<String>,
+    denied: BTreeSet<String>,
+    load_mode: LoadMode,
+}
+
+impl SandboxPolicy {
+    /// Builds the default policy, which only allows functions that are safe to use from
+    /// untrusted code.
+    pub fn new() -> SandboxPolicy {
+        let mut policy = SandboxPolicy::empty();
+        for name in SAFE_BASE_FUNCTIONS.iter().chain(SAFE_LIBRARIES) {
+            policy.allowed.insert((*name).to_owned());
+        }
+        policy
+    }
+
+    /// Builds a policy that allows nothing. Only source code can be loaded.
+    #[inline]
+    pub fn empty() -> SandboxPolicy {
+        SandboxPolicy {
+            allowed: BTreeSet::new(),
+            denied: BTreeSet::new(),
+            load_mode: LoadMode::Text,
+        }
+    }
+
+    /// Allows a global variable of the base library, a library or a function of a library.
+    #[inline]
+    pub fn allow(mut self, name: &str) -> SandboxPolicy {
+        self.denied.remove(name);
+        self.allowed.insert(name.to_owned());
+        self
+    }
+
+    /// Removes a global variable of the base library, a library or a function of a library. The
+    /// rest of the library stays available.
+    #[inline]
+    pub fn deny(mut self, name: &str) -> SandboxPolicy {
+        self.allowed.remove(name);
+        self.denied.insert(name.to_owned());
+        self
+    }
+
+    /// Sets which kinds of chunks can be loaded. The default is `LoadMode::Text`.
+    #[inline]
+    pub fn load_mode(mut self, mode: LoadMode) -> SandboxPolicy {
+        self.load_mode = mode;
+        self
+    }
+
+    /// Returns true if the policy allows a global variable of the base library, a library or a
+    /// function of a library.
+    pub fn is_allowed(&self, name: &str) -> bool {
+        if self.denied.contains(name) {
+            return false;
+        }
+        if self.allowed.contains(name) {
+            return true;
+        }
+        match name.find('.') {
+            Some(pos) => self.is_allowed(&name[..pos]),
+            None => false,
+        }
+    }
+
+    // Returns true if something in the library is allowed.
+    fn opens_library(&self, library: &str) -> bool {
+        if self.denied.contains(library) {
+            return false;
+        }
+        let prefix = format!("{}.", library);
+        self.allowed
+            .iter()
+            .any(|name| name == library || name.starts_with(&prefix))
+    }
+
+    // Returns true if the library is allowed and none of its functions is denied.
+    fn allows_whole_library(&self, library: &str) -> bool {
+        let prefix = format!("{}.", library);
+        self.is_allowed(library) && !self.denied.iter().any(|name| name.starts_with(&prefix))
+    }
+
+    // Opens the libraries in a new context according to the policy.
+    unsafe fn apply(&self, lua: *mut ffi::lua_State) {
+        let top = ffi::lua_gettop(lua);
+
+        ffi::luaopen_base(lua);
+        ffi::lua_pop(lua, 1);
+
+        // `require` needs the package library even if the library itself isn't allowed.
+        let mut opened = Vec::new();
+        for &(name, open) in LIBRARIES {
+            let required = name == "package" && self.is_allowed("require");
+            if self.opens_library(name) || required {
+                open(lua);
+                opened.push((name, ffi::lua_gettop(lua)));
+            }
+        }
+
+        // Removes the global variables that aren't allowed, including those that have been
+        // created by opening the libraries, such as `require`.
+        ffi::lua_pushglobaltable(lua);
+        ffi::lua_pushnil(lua);
+        while ffi::lua_next(lua, -2) != 0 {
+            ffi::lua_pop(lua, 1);
+            let allowed = ffi::lua_type(lua, -1) == ffi::LUA_TSTRING
+                && self.is_allowed(&CStr::from_ptr(ffi::lua_tostring(lua, -1)).to_string_lossy());
+            if !allowed {
+                ffi::lua_pushvalue(lua, -1);
+                ffi::lua_pushnil(lua);
+                ffi::lua_rawset(lua, -4);
+            }
+        }
+        ffi::lua_pop(lua, 1);
+
+        for (name, index) in opened {
+            if name == "package" {
+                ffi::lua_getfield(lua, index, b"searchers\0".as_ptr() as *const _);
+                if ffi::lua_istable(lua, -1) {
+                    // Only the searcher of `package.preload` is kept.
+                    if !self.is_allowed("package.searchers") {
+                        let len = ffi::lua_rawlen(lua, -1) as libc::c_int;
+                        for i in 2..=len {
+                            ffi::lua_pushnil(lua);
+                            ffi::lua_rawseti(lua, -2, i as _);
+                        }
+                    }
+                    ffi::lua_rawsetp(lua, ffi::LUA_REGISTRYINDEX, searchers_registry_key());
+                } else {
+                    ffi::lua_pop(lua, 1);
+                }
+            }
+
+            if !self.allows_whole_library(name) {
+                ffi::lua_newtable(lua);
+                ffi::lua_pushnil(lua);
+                while ffi::lua_next(lua, index) != 0 {
+                    let allowed = ffi::lua_type(lua, -2) == ffi::LUA_TSTRING && {
+                        let field = CStr::from_ptr(ffi::lua_tostring(lua, -2)).to_string_lossy();
+                        self.is_allowed(&format!("{}.{}", name, field))
+                    };
+                    if allowed {
+                        ffi::lua_pushvalue(lua, -2);
+                        ffi::lua_insert(lua, -2);
+                        ffi::lua_rawset(lua, -4);
+                    } else {
+                        ffi::lua_pop(lua, 1);
+                    }
+                }
+                ffi::lua_replace(lua, index);
+            }
+
+            let c_name = CString::new(name).unwrap();
+
+            // `require` returns the restricted library as well.
+            ffi::lua_getfield(
+                lua,
+                ffi::LUA_REGISTRYINDEX,
+                b"_LOADED\0".as_ptr() as *const _,
+            );
+            if ffi::lua_istable(lua, -1) {
+                ffi::lua_pushvalue(lua, index);
+                ffi::lua_setfield(lua, -2, c_name.as_ptr());
+            }
+            ffi::lua_pop(lua, 1);
+
+            // The methods of strings are the functions of the string library.
+            if name == "string" {
+                ffi::lua_pushstring(lua, b"\0".as_ptr() as *const _);
+                if ffi::lua_getmetatable(lua, -1) != 0 {
+                    ffi::lua_pushvalue(lua, index);
+                    ffi::lua_setfield(lua, -2, b"__index\0".as_ptr() as *const _);
+                    ffi::lua_pop(lua, 1);
+                }
+                ffi::lua_pop(lua, 1);
+            }
+
+            if self.opens_library(name) {
+                ffi::lua_pushvalue(lua, index);
+                ffi::lua_setglobal(lua, c_name.as_ptr());
+            }
+        }
+
+        if self.load_mode != LoadMode::Both {
+            ffi::lua_getglobal(lua, b"load\0".as_ptr() as *const _);
+            if ffi::lua_isfunction(lua, -1) {
+                let mode = self.load_mode.as_c_str();
+                ffi::lua_pushstring(lua, mode.as_ptr() as *const _);
+                ffi::lua_pushcclosure(lua, restricted_load, 2);
+                ffi::lua_setglobal(lua, b"load\0".as_ptr() as *const _);
+            } else {
+                ffi::lua_pop(lua, 1);
+            }
+        }
+
+        ffi::lua_settop(lua, top);
+    }
+}
+
+impl Default for SandboxPolicy {
+    #[inline]
+    fn default() -> SandboxPolicy {
+        SandboxPolicy::new()
+    }
+}
+
+// Replacement of the `load` function of Lua, which calls the original function (first upvalue)
+// with the mode of the policy (second upvalue) instead of the mode passed by the caller.
+extern "C" fn restricted_load(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        // The environment is the fourth parameter, and passing `nil` isn't the same as passing
+        // nothing, so the number of parameters is preserved.
+        if ffi::lua_gettop(lua) < 3 {
+            ffi::lua_settop(lua, 3);
+        }
+        ffi::lua_pushvalue(lua, ffi::lua_upvalueindex(2));
+        ffi::lua_replace(lua, 3);
+        ffi::lua_pushvalue(lua, ffi::lua_upvalueindex(1));
+        ffi::lua_insert(lua, 1);
+        ffi::lua_call(lua, ffi::lua_gettop(lua) - 1, ffi::MULTRET);
+        ffi::lua_gettop(lua)
+    }
+}
+
+impl<'lua> Lua<'lua> {
+    /// Builds a new Lua context with the parts of the standard library that are safe to use from
+    /// untrusted code.
+    ///
+    /// This is the same as `Lua::with_sandbox(&SandboxPolicy::new())`. See
+    /// [`SandboxPolicy`](struct.SandboxPolicy.html) for what is available.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// let mut lua = hlua::Lua::new_sandboxed();
+    ///
+    /// let n: i32 = lua.execute("return math.max(string.len('abc'), 2)").unwrap();
+    /// assert_eq!(n, 3);
+    ///
+    /// let blocked: bool = lua.execute("return io == nil and os.execute == nil").unwrap();
+    /// assert!(blocked);
+    /// ```
+    #[inline]
+    pub fn new_sandboxed() -> Lua<'lua> {
+        Lua::with_sandbox(&SandboxPolicy::new())
+    }
+
+    /// Builds a new Lua context with the parts of the standard library allowed by a policy.
+    ///
+    /// The libraries are opened as with the `open_*` methods, then the global variables, library
+    /// functions and methods of strings that aren't allowed are removed. The load mode of the
+    /// context is set to the one of the policy.
+    pub fn with_sandbox(policy: &SandboxPolicy) -> Lua<'lua> {
+        let mut lua = Lua::new();
+        unsafe {
+            policy.apply(lua.lua.0);
+        }
+        lua.set_load_mode(policy.load_mode);
+        lua
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use Lua;
+    use LuaError;
+    use LuaFunction;
+    use SandboxPolicy;
+
+    fn is_nil(lua: &mut Lua, expr: &str) -> bool {
+        lua.execute(&format!("return {} == nil", expr)).unwrap()
+    }
+
+    #[test]
+    fn blocked_functions_are_absent() {
+        let mut lua = Lua::new_sandboxed();
+        for name in &[
+            "dofile",
+            "loadfile",
+            "collectgarbage",
+            "require",
+            "package",
+            "io",
+            "debug",
+            "coroutine",
+            "os.execute",
+            "os.exit",
+            "os.getenv",
+            "os.remove",
+            "os.rename",
+            "os.tmpname",
+        ] {
+            assert!(is_nil(&mut lua, name), "{} is available", name);
+        }
+    }
+
+    #[test]
+    fn safe_functions_are_present() {
+        let mut lua = Lua::new_sandboxed();
+        for name in &[
+            "print",
+            "pcall",
+            "load",
+            "_G",
+            "string.format",
+            "table.concat",
+            "math.floor",
+            #[cfg(any(feature = "lua52", feature = "lua53"))]
+            "bit32.band",
+            #[cfg(feature = "luajit")]
+            "bit.band",
+            "os.time",
+            "os.clock",
+            "os.date",
+        ] {
+            assert!(!is_nil(&mut lua, name), "{} is missing", name);
+        }
+
+        #[cfg(any(feature = "lua52", feature = "lua53"))]
+        {
+            let s: String = lua
+                .execute("return ('%d'):format(bit32.bor(1, 4))")
+                .unwrap();
+            assert_eq!(s, "5");
+        }
+    }
+
+    #[test]
+    fn portable_script() {
+        let mut lua = Lua::new_sandboxed();
+        let s: String = lua
+            .execute(
+                "local unpack = table.unpack or unpack\n\
+                 local t = {}\n\
+                 for i, v in ipairs({ 3, 1, 2 }) do t[#t + 1] = v * i end\n\
+                 table.sort(t)\n\
+                 local a, b, c = unpack(t)\n\
+                 local ok, err = pcall(error, 'x', 0)\n\
+                 return string.format('%d %d %d %s %s %d', a, b, c, tostring(ok), err, \
+                                      math.floor(select('#', unpack(t)) / 2))",
+            )
+            .unwrap();
+        assert_eq!(s, "2 3 6 false x 1");
+
+        #[cfg(feature = "luajit")]
+        assert!(!is_nil(&mut lua, "unpack"));
+        #[cfg(not(feature = "luajit"))]
+        assert!(!is_nil(&mut lua, "table.unpack"));
+    }
+
+    #[test]
+    fn allow_and_deny() {
+        let policy = SandboxPolicy::new()
+            .allow("os.getenv")
+            .allow("coroutine")
+            .deny("string.rep")
+            .deny("print");
+        assert!(policy.is_allowed("os.getenv"));
+        assert!(policy.is_allowed("string.format"));
+        assert!(!policy.is_allowed("string.rep"));
+        assert!(!policy.is_allowed("os.exit"));
+
+        let mut lua = Lua::with_sandbox(&policy);
+        assert!(!is_nil(&mut lua, "os.getenv"));
+        assert!(!is_nil(&mut lua, "coroutine.wrap"));
+        assert!(is_nil(&mut lua, "os.exit"));
+        assert!(is_nil(&mut lua, "print"));
+        assert!(is_nil(&mut lua, "string.rep"));
+        assert!(is_nil(&mut lua, "('x').rep"));
+        assert!(!is_nil(&mut lua, "('x').upper"));
+    }
+
+    #[test]
+    fn empty_policy() {
+        let mut lua = Lua::with_sandbox(&SandboxPolicy::empty().allow("math.pi"));
+        assert!(is_nil(&mut lua, "math.floor"));
+        assert!(!is_nil(&mut lua, "math.pi"));
+
+        match lua.execute::<()>("print('x')") {
+            Err(LuaError::ExecutionError(_)) => {}
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn require_returns_restricted_libraries() {
+        let policy = SandboxPolicy::new().allow("require");
+        let mut lua = Lua::with_sandbox(&policy);
+        assert!(is_nil(&mut lua, "package"));
+        let same: bool = lua.execute("return require('os') == os").unwrap();
+        assert!(same);
+        assert!(is_nil(&mut lua, "require('os').execute"));
+    }
+
+    #[test]
+    fn require_only_searches_preload() {
+        let policy = SandboxPolicy::new().allow("require");
+        let mut lua = Lua::with_sandbox(&policy);
+        lua.register_module("mod", |_| {});
+        let found: bool = lua.execute("return require('mod') ~= nil").unwrap();
+        assert!(found);
+
+        match lua.execute::<()>("require('hlua_missing_module')") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("package.preload"), "{}", msg);
+                assert!(!msg.contains("no file"), "{}", msg);
+            }
+            _ => panic!(),
+        }
+
+        // The searchers are kept if they are explicitly allowed.
+        let mut lua = Lua::with_sandbox(&policy.allow("package"));
+        match lua.execute::<()>("require('hlua_missing_module')") {
+            Err(LuaError::ExecutionError(msg)) => assert!(msg.contains("no file"), "{}", msg),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn binary_chunks_are_rejected() {
+        let mut lua = Lua::new_sandboxed();
+        let bytecode = LuaFunction::load(&mut lua, "return 5")
+            .unwrap()
+            .dump(false)
+            .unwrap();
+        assert!(LuaFunction::load_from_reader(&mut lua, &bytecode[..]).is_err());
+
+        lua.set(
+            "bytecode",
+            ::AnyLuaValue::LuaAnyString(::AnyLuaString(bytecode)),
+        );
+        let rejected: bool = lua
+            .execute("local f, err = load(bytecode, 'x', 'b') return f == nil")
+            .unwrap();
+        assert!(rejected);
+
+        let v: i32 = lua.execute("return load('return 7')()").unwrap();
+        assert_eq!(v, 7);
+        let v: i32 = lua
+            .execute("return load('return x', 'x', 't', { x = 8 })()")
+            .unwrap();
+        assert_eq!(v, 8);
+    }
+}
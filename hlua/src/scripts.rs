@@ -0,0 +1,760 @@
+use ffi;
+use libc;
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::io::Read;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::ptr;
+
+use environment::set_chunk_env;
+use functions_write::{closure_destructor_wrapper, raise};
+use lua_functions::{load_mode, skip_comment};
+
+use AsMutLua;
+use Lua;
+use LuaError;
+use LuaFunction;
+
+/// Storage from which the scripts of a Lua context are loaded, such as an archive of assets.
+///
+/// Once installed with [`Lua::set_script_source`](struct.Lua.html#method.set_script_source),
+/// the source is used by `require`, `dofile` and `loadfile`. Paths are virtual: they are relative,
+/// use `/` as separator, and are only meaningful to the source.
+pub trait ScriptSource {
+    /// Returns the content of the script at `path`.
+    ///
+    /// Must return an error of kind `NotFound` if there is no script at this path, so that
+    /// `require` can try the next candidate.
+    fn read(&self, path: &str) -> io::Result<Vec<u8>>;
+}
+
+/// Script source that keeps the scripts in memory.
+///
+/// # Example
+///
+/// ```
+/// use hlua::{Lua, MemorySource};
+///
+/// let mut source = MemorySource::new();
+/// source.insert("mygame/ai.lua", "return { level = 3 }");
+///
+/// let mut lua = Lua::new();
+/// lua.openlibs();
+/// lua.set_script_source(source);
+///
+/// let level: i32 = lua.execute("return require('mygame.ai').level").unwrap();
+/// assert_eq!(level, 3);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource {
+    scripts: HashMap<String, Vec<u8>>,
+}
+
+impl MemorySource {
+    /// Builds an empty source.
+    #[inline]
+    pub fn new() -> MemorySource {
+        MemorySource::default()
+    }
+
+    /// Adds a script, or replaces the script that has the same path.
+    #[inline]
+    pub fn insert<P, C>(&mut self, path: P, code: C)
+    where
+        P: Into<String>,
+        C: Into<Vec<u8>>,
+    {
+        self.scripts.insert(path.into(), code.into());
+    }
+}
+
+impl ScriptSource for MemorySource {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        match self.scripts.get(path) {
+            Some(code) => Ok(code.clone()),
+            None => Err(io::Error::new(io::ErrorKind::NotFound, "no such script")),
+        }
+    }
+}
+
+/// Script source that reads the scripts from a directory of the file system.
+///
+/// Paths that would escape the directory, such as `../secret.lua`, are rejected.
+#[derive(Debug, Clone)]
+pub struct DirectorySource {
+    root: PathBuf,
+}
+
+impl DirectorySource {
+    /// Builds a source that reads the scripts from `root`.
+    #[inline]
+    pub fn new<P>(root: P) -> DirectorySource
+    where
+        P: Into<PathBuf>,
+    {
+        DirectorySource { root: root.into() }
+    }
+}
+
+impl ScriptSource for DirectorySource {
+    fn read(&self, path: &str) -> io::Result<Vec<u8>> {
+        let mut full_path = self.root.clone();
+        for (i, component) in path.split('/').enumerate() {
+            match component {
+                "." => {}
+                "" if i != 0 => {}
+                "" | ".." => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "path outside of the script directory",
+                    ))
+                }
+                _ if component.contains('\\') || component.contains(':') => {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid path"))
+                }
+                _ => full_path.push(component),
+            }
+        }
+        fs::read(full_path)
+    }
+}
+
+// The addresses of these statics are used as keys in the registry, for the source of the context
+// and the function that searches it for modules.
+static SOURCE_REGISTRY_KEY: u8 = 0;
+static SEARCHER_REGISTRY_KEY: u8 = 0;
+
+#[inline]
+fn registry_key(key: &'static u8) -> *const libc::c_char {
+    key as *const u8 as *const libc::c_char
+}
+
+impl<'lua> Lua<'lua> {
+    /// Sets the storage from which scripts are loaded by `require`, `dofile` and `loadfile`.
+    ///
+    /// A module named `a.b` is looked for at the paths `a/b.lua` and `a/b/init.lua` of the
+    /// source. The searcher is added to `package.searchers` right after the one of
+    /// `package.preload`, so that it takes priority over the file system. It is only added if the
+    /// package library is open.
+    ///
+    /// The global functions `dofile` and `loadfile` are replaced with functions that only read
+    /// from the source. They are available even in a sandboxed context: in this case they give
+    /// access to the scripts of the source and nothing else.
+    ///
+    /// Chunks are named after their path in the source, which appears in error messages. Calling
+    /// this method again replaces the source.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// use hlua::{Lua, MemorySource};
+    ///
+    /// let mut source = MemorySource::new();
+    /// source.insert("config.lua", "return 1, 2");
+    /// source.insert("broken.lua", "\nerror('oops')");
+    ///
+    /// let mut lua = Lua::new_sandboxed();
+    /// lua.set_script_source(source);
+    ///
+    /// let sum: i32 = lua.execute("local a, b = dofile('config.lua') return a + b").unwrap();
+    /// assert_eq!(sum, 3);
+    ///
+    /// match lua.execute::<()>("dofile('broken.lua')") {
+    ///     Err(hlua::LuaError::ExecutionError(msg)) => assert_eq!(msg, "broken.lua:2: oops"),
+    ///     _ => panic!(),
+    /// }
+    /// ```
+    pub fn set_script_source<S>(&mut self, source: S)
+    where
+        S: ScriptSource + 'static,
+    {
+        unsafe {
+            let lua = self.as_mut_lua().0;
+
+            let source: Box<dyn ScriptSource> = Box::new(source);
+            let data = ffi::lua_newuserdata(lua, mem::size_of::<Box<dyn ScriptSource>>());
+            ptr::write(data as *mut Box<dyn ScriptSource>, source);
+            ffi::lua_newtable(lua);
+            ffi::lua_pushcfunction(lua, closure_destructor_wrapper::<Box<dyn ScriptSource>>);
+            ffi::lua_setfield(lua, -2, b"__gc\0".as_ptr() as *const _);
+            ffi::lua_setmetatable(lua, -2);
+            ffi::lua_rawsetp(
+                lua,
+                ffi::LUA_REGISTRYINDEX,
+                registry_key(&SOURCE_REGISTRY_KEY),
+            );
+
+            ffi::lua_pushcfunction(lua, dofile);
+            ffi::lua_setglobal(lua, b"dofile\0".as_ptr() as *const _);
+            ffi::lua_pushcfunction(lua, loadfile);
+            ffi::lua_setglobal(lua, b"loadfile\0".as_ptr() as *const _);
+
+            install_searcher(lua);
+        }
+    }
+}
+
+// Adds the searcher to the searchers used by `require` if the package library is open and the
+// searcher isn't there yet.
+unsafe fn install_searcher(lua: *mut ffi::lua_State) {
+    ffi::lua_rawgetp(
+        lua,
+        ffi::LUA_REGISTRYINDEX,
+        registry_key(&SEARCHER_REGISTRY_KEY),
+    );
+    if ffi::lua_isnil(lua, -1) {
+        ffi::lua_pop(lua, 1);
+        ffi::lua_pushcfunction(lua, searcher);
+        ffi::lua_pushvalue(lua, -1);
+        ffi::lua_rawsetp(
+            lua,
+            ffi::LUA_REGISTRYINDEX,
+            registry_key(&SEARCHER_REGISTRY_KEY),
+        );
+    }
+
+    if ::sandbox::push_searchers(lua) {
+        let len = ffi::lua_rawlen(lua, -1) as libc::c_int;
+        let installed = (1..=len).any(|i| {
+            ffi::lua_rawgeti(lua, -1, i as _);
+            let same = ffi::lua_rawequal(lua, -1, -3) != 0;
+            ffi::lua_pop(lua, 1);
+            same
+        });
+        if !installed {
+            let position = len.min(1) + 1;
+            for i in (position..=len).rev() {
+                ffi::lua_rawgeti(lua, -1, i as _);
+                ffi::lua_rawseti(lua, -2, (i + 1) as _);
+            }
+            ffi::lua_pushvalue(lua, -2);
+            ffi::lua_rawseti(lua, -2, position as _);
+        }
+        ffi::lua_pop(lua, 1);
+    }
+    ffi::lua_pop(lua, 1);
+}
+
+// Returns the source of the context, if any. The reference is valid until the source is replaced.
+unsafe fn script_source<'a>(lua: *mut ffi::lua_State) -> Option<&'a dyn ScriptSource> {
+    ffi::lua_rawgetp(
+        lua,
+        ffi::LUA_REGISTRYINDEX,
+        registry_key(&SOURCE_REGISTRY_KEY),
+    );
+    let data = ffi::lua_touserdata(lua, -1) as *const Box<dyn ScriptSource>;
+    ffi::lua_pop(lua, 1);
+    if data.is_null() {
+        None
+    } else {
+        Some(&**data)
+    }
+}
+
+// Reads a script from the source of the context.
+unsafe fn read_script(lua: *mut ffi::lua_State, path: &str) -> io::Result<Vec<u8>> {
+    match script_source(lua) {
+        Some(source) => source.read(path),
+        None => Err(io::Error::new(io::ErrorKind::NotFound, "no script source")),
+    }
+}
+
+// Loads the content of a script and pushes it as a function. On error, pushes nothing and returns
+// the error message.
+unsafe fn load_script(
+    lua: *mut ffi::lua_State,
+    path: &str,
+    content: &[u8],
+    mode: &CStr,
+) -> Result<(), String> {
+    extern "C" fn reader(
+        _: *mut ffi::lua_State,
+        data: *mut libc::c_void,
+        size: *mut libc::size_t,
+    ) -> *const libc::c_char {
+        unsafe {
+            let data = &mut *(data as *mut &[u8]);
+            let chunk = mem::take(data);
+            *size = chunk.len() as libc::size_t;
+            chunk.as_ptr() as *const libc::c_char
+        }
+    }
+
+    // As with files, the first line is skipped if it starts with `#`, and replaced with an empty
+    // line so that line numbers remain correct.
+    let mut code = content;
+    let with_newline;
+    if skip_comment(&mut code).unwrap_or(false) {
+        with_newline = [&b"\n"[..], code].concat();
+        code = &with_newline;
+    }
+
+    // Lua expects a C string, so the name is truncated at the first nul character.
+    let name = format!("@{}", path.split('\0').next().unwrap());
+    let name = CString::new(name).unwrap();
+
+    let status = ffi::lua_load(
+        lua,
+        reader,
+        &mut code as *mut &[u8] as *mut libc::c_void,
+        name.as_ptr(),
+        mode.as_ptr(),
+    );
+    if status == 0 {
+        return Ok(());
+    }
+
+    let msg = to_string(lua, -1).unwrap_or_default();
+    ffi::lua_pop(lua, 1);
+    Err(msg)
+}
+
+// Returns the string at an index of the stack, if it is a string or a number.
+unsafe fn to_string(lua: *mut ffi::lua_State, index: libc::c_int) -> Option<String> {
+    let mut len = 0;
+    let ptr = ffi::lua_tolstring(lua, index, &mut len);
+    if ptr.is_null() {
+        return None;
+    }
+    let bytes = ::std::slice::from_raw_parts(ptr as *const u8, len);
+    Some(String::from_utf8_lossy(bytes).into_owned())
+}
+
+// Returns the modes of the chunks that can be loaded: those requested, if any, that are allowed by
+// the load mode of the context.
+unsafe fn allowed_modes(lua: *mut ffi::lua_State, requested: Option<&str>) -> CString {
+    let allowed = load_mode(lua).as_c_str();
+    let allowed = &allowed[..allowed.len() - 1];
+    let modes: Vec<u8> = match requested {
+        Some(requested) => requested.bytes().filter(|m| allowed.contains(m)).collect(),
+        None => allowed.to_vec(),
+    };
+    CString::new(modes).unwrap()
+}
+
+// Searcher of `package.searchers`. Receives the name of a module, and returns a loader and the
+// path of the script, or a message that explains why the module hasn't been found.
+extern "C" fn searcher(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe fn search(lua: *mut ffi::lua_State) -> Result<libc::c_int, String> {
+        let name = to_string(lua, 1).unwrap_or_default();
+        let base = name.replace('.', "/");
+        let mode = allowed_modes(lua, None);
+
+        let mut not_found = String::new();
+        for path in &[format!("{}.lua", base), format!("{}/init.lua", base)] {
+            let loaded = match read_script(lua, path) {
+                Ok(content) => load_script(lua, path, &content, &mode),
+                Err(ref err) if err.kind() == io::ErrorKind::NotFound => {
+                    not_found.push_str(&format!("\n\tno file '{}' in the script source", path));
+                    continue;
+                }
+                Err(err) => Err(format!("cannot read {}: {}", path, err)),
+            };
+
+            return match loaded {
+                Ok(()) => {
+                    ffi::lua_pushlstring(lua, path.as_ptr() as *const _, path.len());
+                    // LuaJIT passes only the name of the module to the loader, so the path is
+                    // bound to it instead of being returned.
+                    if cfg!(feature = "luajit") {
+                        ffi::lua_pushcclosure(lua, call_with_path, 2);
+                        return Ok(1);
+                    }
+                    Ok(2)
+                }
+                Err(msg) => Err(format!(
+                    "error loading module '{}' from file '{}':\n\t{}",
+                    name, path, msg
+                )),
+            };
+        }
+
+        ffi::lua_pushlstring(lua, not_found.as_ptr() as *const _, not_found.len());
+        Ok(1)
+    }
+
+    unsafe {
+        match search(lua) {
+            Ok(results) => results,
+            Err(msg) => raise(lua, msg),
+        }
+    }
+}
+
+// Loader that calls the script in its first upvalue with the name of the module and the path of
+// the script in its second upvalue, like Lua 5.2 does.
+extern "C" fn call_with_path(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        ffi::lua_pushvalue(lua, ffi::lua_upvalueindex(1));
+        ffi::lua_pushvalue(lua, 1);
+        ffi::lua_pushvalue(lua, ffi::lua_upvalueindex(2));
+        ffi::lua_call(lua, 2, 1);
+        1
+    }
+}
+
+// Loads the script whose path is the first parameter and pushes it as a function.
+unsafe fn load_parameter(lua: *mut ffi::lua_State, mode: Option<&str>) -> Result<(), String> {
+    let path = match ffi::lua_type(lua, 1) {
+        ffi::LUA_TSTRING => to_string(lua, 1).unwrap(),
+        _ => return Err("bad argument #1 (string expected)".to_owned()),
+    };
+    let mode = allowed_modes(lua, mode);
+
+    match read_script(lua, &path) {
+        Ok(content) => load_script(lua, &path, &content, &mode),
+        Err(err) => Err(format!("cannot open {}: {}", path, err)),
+    }
+}
+
+// Replacement of the `dofile` function of Lua.
+extern "C" fn dofile(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        ffi::lua_settop(lua, 1);
+        if let Err(msg) = load_parameter(lua, None) {
+            return raise(lua, msg);
+        }
+        ffi::lua_call(lua, 0, ffi::MULTRET);
+        ffi::lua_gettop(lua) - 1
+    }
+}
+
+// Replacement of the `loadfile` function of Lua, whose parameters are the path, the mode and the
+// environment.
+extern "C" fn loadfile(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        let mode = if ffi::lua_isnoneornil(lua, 2) {
+            None
+        } else {
+            to_string(lua, 2)
+        };
+        let has_env = !ffi::lua_isnone(lua, 3);
+
+        match load_parameter(lua, mode.as_ref().map(|m| &m[..])) {
+            Ok(()) => {
+                if has_env {
+                    ffi::lua_pushvalue(lua, 3);
+                    set_chunk_env(lua);
+                }
+                1
+            }
+            Err(msg) => {
+                ffi::lua_pushnil(lua);
+                ffi::lua_pushlstring(lua, msg.as_ptr() as *const _, msg.len());
+                2
+            }
+        }
+    }
+}
+
+/// Precompiles the Lua scripts of a directory to bytecode. Meant to be called from build scripts.
+///
+/// Every `.lua` file of `source_dir` and its subdirectories is compiled with the version of Lua
+/// bundled with hlua, and written at the same relative path in `out_dir`, which is created if
+/// needed. Chunks are named after this relative path. If `strip` is true, the debug information
+/// is removed, which makes the bytecode smaller but error messages less precise. The compiled
+/// files can then be embedded with [`include_lua!`](macro.include_lua.html).
+///
+/// Bytecode depends on the platform (sizes of integers, endianness), so this only works if the
+/// scripts are compiled for the platform that runs the build. Besides, precompiled chunks can only
+/// be loaded by a context whose load mode accepts them, which excludes sandboxed contexts by
+/// default.
+///
+/// # Example
+///
+/// ```no_run
+/// // build.rs
+/// extern crate hlua;
+///
+/// fn main() {
+///     let out_dir = std::env::var("OUT_DIR").unwrap();
+///     println!("cargo:rerun-if-changed=scripts");
+///     hlua::precompile_scripts("scripts", format!("{}/scripts", out_dir), false).unwrap();
+/// }
+/// ```
+pub fn precompile_scripts<P, Q>(source_dir: P, out_dir: Q, strip: bool) -> Result<(), LuaError>
+where
+    P: AsRef<Path>,
+    Q: AsRef<Path>,
+{
+    let mut lua = Lua::new();
+    precompile_dir(&mut lua, source_dir.as_ref(), out_dir.as_ref(), "", strip)
+}
+
+// Precompiles the scripts of a directory, whose relative path is `prefix`.
+fn precompile_dir(
+    lua: &mut Lua,
+    dir: &Path,
+    out_dir: &Path,
+    prefix: &str,
+    strip: bool,
+) -> Result<(), LuaError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let path = format!("{}{}", prefix, name);
+
+        if entry.file_type()?.is_dir() {
+            let prefix = format!("{}/", path);
+            precompile_dir(lua, &entry.path(), &out_dir.join(&name), &prefix, strip)?;
+            continue;
+        }
+        if !name.ends_with(".lua") {
+            continue;
+        }
+
+        let content = fs::read(entry.path())?;
+        let mut code = &content[..];
+        let prefix: &[u8] = if skip_comment(&mut code)? { b"\n" } else { b"" };
+
+        let mut function = LuaFunction::load_from_reader_with_name(
+            &mut *lua,
+            &format!("@{}", path),
+            prefix.chain(code),
+        )?;
+        let bytecode = function.dump(strip)?;
+        fs::create_dir_all(out_dir)?;
+        fs::write(out_dir.join(&name), bytecode)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use DirectorySource;
+    use LoadMode;
+    use Lua;
+    use LuaError;
+    use MemorySource;
+    use ScriptSource;
+
+    fn source() -> MemorySource {
+        let mut source = MemorySource::new();
+        source.insert(
+            "mygame/ai.lua",
+            "local name, path = ...\nreturn { name = name, path = path }",
+        );
+        source.insert("mygame/init.lua", "return { init = true }");
+        source.insert("broken.lua", "return 1 +");
+        source.insert("failing.lua", "\n\nerror('oops')");
+        source.insert("values.lua", "#!/usr/bin/lua\nreturn 1, 2, x");
+        source
+    }
+
+    #[test]
+    fn require_from_source() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set_script_source(source());
+
+        let loaded: String = lua
+            .execute("local ai = require 'mygame.ai' return ai.name .. ' ' .. ai.path")
+            .unwrap();
+        assert_eq!(loaded, "mygame.ai mygame/ai.lua");
+
+        let init: bool = lua.execute("return require('mygame').init").unwrap();
+        assert!(init);
+
+        let cached: bool = lua
+            .execute("return package.loaded['mygame.ai'] == require('mygame.ai')")
+            .unwrap();
+        assert!(cached);
+    }
+
+    #[test]
+    fn require_errors() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set_script_source(source());
+
+        match lua.execute::<()>("require 'missing.module'") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("no file 'missing/module.lua' in the script source"));
+                assert!(msg.contains("no file 'missing/module/init.lua' in the script source"));
+            }
+            _ => panic!(),
+        }
+
+        match lua.execute::<()>("require 'broken'") {
+            Err(LuaError::ExecutionError(msg)) => assert!(
+                msg.contains(
+                    "error loading module 'broken' from file 'broken.lua':\n\tbroken.lua:1:"
+                ),
+                "{}",
+                msg
+            ),
+            _ => panic!(),
+        }
+
+        match lua.execute::<()>("require 'failing'") {
+            Err(LuaError::ExecutionError(msg)) => assert!(msg.starts_with("failing.lua:3: oops")),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn searcher_installed_once() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        let before: i32 = lua.execute("return #package.searchers").unwrap();
+        lua.set_script_source(MemorySource::new());
+        lua.set_script_source(source());
+        let after: i32 = lua.execute("return #package.searchers").unwrap();
+        assert_eq!(after, before + 1);
+
+        // The last source is used.
+        let init: bool = lua.execute("return require('mygame').init").unwrap();
+        assert!(init);
+    }
+
+    #[test]
+    fn dofile_and_loadfile() {
+        let mut lua = Lua::new_sandboxed();
+        lua.set_script_source(source());
+
+        let values: String = lua
+            .execute("x = 3 return table.concat({ dofile('values.lua') }, ' ')")
+            .unwrap();
+        assert_eq!(values, "1 2 3");
+
+        let x: i32 = lua
+            .execute("local f = loadfile('values.lua', 't', { x = 4 }) return select(3, f())")
+            .unwrap();
+        assert_eq!(x, 4);
+
+        let msg: String = lua
+            .execute("local f, msg = loadfile('values.lua', 'b') return msg")
+            .unwrap();
+        assert!(msg.contains("attempt to load a text chunk"), "{}", msg);
+
+        let msg: String = lua
+            .execute("local f, msg = loadfile('missing.lua') return msg")
+            .unwrap();
+        assert!(msg.starts_with("cannot open missing.lua"), "{}", msg);
+
+        match lua.execute::<()>("dofile('failing.lua')") {
+            Err(LuaError::ExecutionError(msg)) => assert_eq!(msg, "failing.lua:3: oops"),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn require_in_sandbox() {
+        let mut lua = Lua::with_sandbox(&::SandboxPolicy::new().allow("require"));
+        lua.set_script_source(source());
+        let init: bool = lua.execute("return require('mygame').init").unwrap();
+        assert!(init);
+    }
+
+    #[test]
+    fn sandbox_blocks_binary_chunks() {
+        let mut source = MemorySource::new();
+        {
+            let mut lua = Lua::new();
+            let mut f = ::LuaFunction::load(&mut lua, "return 5").unwrap();
+            source.insert("compiled.lua", f.dump(false).unwrap());
+        }
+
+        let mut lua = Lua::new_sandboxed();
+        lua.set_script_source(source);
+        match lua.execute::<()>("dofile('compiled.lua')") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("attempt to load a binary chunk"), "{}", msg)
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn directory_source() {
+        let name = format!("hlua-directory-source-{}", process::id());
+        let dir = env::temp_dir().join(&name);
+        fs::create_dir_all(dir.join("lib")).unwrap();
+        fs::write(dir.join("lib").join("util.lua"), "return 42").unwrap();
+
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set_script_source(DirectorySource::new(&dir));
+
+        let v: i32 = lua.execute("return require 'lib.util'").unwrap();
+        assert_eq!(v, 42);
+        let v: i32 = lua.execute("return dofile('./lib/util.lua')").unwrap();
+        assert_eq!(v, 42);
+
+        let parent = format!("../{}/lib/util.lua", name);
+        for path in &[&parent[..], "/etc/passwd"] {
+            let msg: String = lua
+                .execute(&format!("local f, msg = loadfile('{}') return msg", path))
+                .unwrap();
+            assert!(
+                msg.contains("path outside of the script directory"),
+                "{}",
+                msg
+            );
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn precompile() {
+        let dir = env::temp_dir().join(format!("hlua-precompile-{}", process::id()));
+        let out_dir = dir.join("out");
+        fs::create_dir_all(dir.join("src").join("mygame")).unwrap();
+        fs::write(dir.join("src").join("init.lua"), "#!/usr/bin/lua\nreturn 1").unwrap();
+        fs::write(
+            dir.join("src").join("mygame").join("ai.lua"),
+            "local ai = {}\nfunction ai.fail() error('oops') end\nreturn ai",
+        )
+        .unwrap();
+        fs::write(dir.join("src").join("notes.txt"), "not lua").unwrap();
+        fs::write(dir.join("src").join("latin1.lua"), b"return 'caf\xe9'").unwrap();
+
+        ::precompile_scripts(dir.join("src"), &out_dir, false).unwrap();
+        assert!(!out_dir.join("notes.txt").exists());
+
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set_script_source(DirectorySource::new(&out_dir));
+        let init = DirectorySource::new(&out_dir).read("init.lua").unwrap();
+        #[cfg(not(feature = "luajit"))]
+        assert!(init.starts_with(b"\x1bLua"));
+        #[cfg(feature = "luajit")]
+        assert!(init.starts_with(b"\x1bLJ"));
+
+        let v: i32 = lua.execute("return require 'init'").unwrap();
+        assert_eq!(v, 1);
+        let latin1: ::AnyLuaValue = lua.execute("return require 'latin1'").unwrap();
+        assert_eq!(
+            latin1,
+            ::AnyLuaValue::LuaAnyString(::AnyLuaString(b"caf\xe9".to_vec()))
+        );
+        match lua.execute::<()>("require('mygame.ai').fail()") {
+            Err(LuaError::ExecutionError(msg)) => assert_eq!(msg, "mygame/ai.lua:2: oops"),
+            _ => panic!(),
+        }
+
+        // Sandboxed contexts only load source code by default.
+        let mut lua = Lua::with_sandbox(&::SandboxPolicy::new().load_mode(LoadMode::Both));
+        lua.set_script_source(DirectorySource::new(&out_dir));
+        let v: i32 = lua.execute("return dofile('init.lua')").unwrap();
+        assert_eq!(v, 1);
+
+        fs::write(dir.join("src").join("broken.lua"), "return +").unwrap();
+        match ::precompile_scripts(dir.join("src"), &out_dir, true) {
+            Err(LuaError::SyntaxError(msg)) => assert!(msg.starts_with("broken.lua:1:"), "{}", msg),
+            _ => panic!(),
+        }
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
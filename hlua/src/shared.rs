@@ -0,0 +1,483 @@
+use std::any::Any;
+use std::cell::UnsafeCell;
+use std::error::Error;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::sync::atomic::{AtomicIsize, Ordering};
+use std::sync::Arc;
+
+use ffi;
+
+use push_userdata;
+use userdata::{is_mutably_borrowed, userdata_ptr};
+use AsLua;
+use AsMutLua;
+use InsideCallback;
+use LuaRead;
+use Push;
+use PushGuard;
+use PushOne;
+use UserdataOnStack;
+use Void;
+
+/// Object owned by both Rust and Lua.
+///
+/// Pushing a value with [`push_userdata`](fn.push_userdata.html) moves it into Lua, and Rust can
+/// then only access it from the callbacks that receive it. A `Shared` is a reference-counted
+/// handle instead: pushing it gives Lua a handle to the same object, and the Rust code that keeps
+/// a clone can still read and modify it.
+///
+/// Accesses are checked at runtime, like with a `RefCell`. Callbacks receive the object by
+/// reading a [`SharedRef`](struct.SharedRef.html) or a [`SharedMut`](struct.SharedMut.html)
+/// parameter. If the object is already borrowed in a conflicting way, for example because the
+/// same object is passed twice to a callback that modifies it, the callback isn't called and
+/// a Lua error is raised instead.
+///
+/// The metatable of the userdata is the one of the `Shared<T>` type, which can be filled with
+/// [`Lua::register_userdata`](struct.Lua.html#method.register_userdata).
+///
+/// # Example
+///
+/// ```
+/// use hlua::{Shared, SharedMut};
+///
+/// let counter = Shared::new(0);
+///
+/// let mut lua = hlua::Lua::new();
+/// lua.set("counter", counter.clone());
+/// lua.set("increment", hlua::function1(|mut c: SharedMut<i32>| *c += 1));
+///
+/// lua.execute::<()>("increment(counter) increment(counter)").unwrap();
+/// assert_eq!(*counter.borrow(), 2);
+/// ```
+pub struct Shared<T> {
+    cell: Arc<SharedCell<T>>,
+}
+
+struct SharedCell<T> {
+    // Number of `SharedRef`s, or -1 if there is a `SharedMut`.
+    borrows: AtomicIsize,
+    value: UnsafeCell<T>,
+}
+
+// Same requirements as `RwLock`.
+unsafe impl<T: Send> Send for SharedCell<T> {}
+unsafe impl<T: Send + Sync> Sync for SharedCell<T> {}
+
+impl<T> Shared<T> {
+    /// Creates a new object.
+    #[inline]
+    pub fn new(value: T) -> Shared<T> {
+        Shared {
+            cell: Arc::new(SharedCell {
+                borrows: AtomicIsize::new(0),
+                value: UnsafeCell::new(value),
+            }),
+        }
+    }
+
+    /// Borrows the object immutably.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the object is borrowed mutably.
+    #[inline]
+    pub fn borrow(&self) -> SharedRef<T> {
+        self.try_borrow()
+            .expect("the shared object is already mutably borrowed")
+    }
+
+    /// Borrows the object immutably, or returns an error if it is borrowed mutably.
+    pub fn try_borrow(&self) -> Result<SharedRef<T>, BorrowError> {
+        let mut current = self.cell.borrows.load(Ordering::Relaxed);
+        loop {
+            if current < 0 {
+                return Err(BorrowError::AlreadyMutablyBorrowed);
+            }
+            match self.cell.borrows.compare_exchange_weak(
+                current,
+                current + 1,
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => {
+                    return Ok(SharedRef {
+                        cell: self.cell.clone(),
+                    })
+                }
+                Err(actual) => current = actual,
+            }
+        }
+    }
+
+    /// Borrows the object mutably.
+    ///
+    /// # Panic
+    ///
+    /// Panics if the object is already borrowed.
+    #[inline]
+    pub fn borrow_mut(&self) -> SharedMut<T> {
+        self.try_borrow_mut()
+            .expect("the shared object is already borrowed")
+    }
+
+    /// Borrows the object mutably, or returns an error if it is already borrowed.
+    pub fn try_borrow_mut(&self) -> Result<SharedMut<T>, BorrowError> {
+        match self
+            .cell
+            .borrows
+            .compare_exchange(0, -1, Ordering::Acquire, Ordering::Relaxed)
+        {
+            Ok(_) => Ok(SharedMut {
+                cell: self.cell.clone(),
+            }),
+            Err(n) if n < 0 => Err(BorrowError::AlreadyMutablyBorrowed),
+            Err(_) => Err(BorrowError::AlreadyBorrowed),
+        }
+    }
+
+    /// Returns true if both handles point to the same object.
+    #[inline]
+    pub fn ptr_eq(&self, other: &Shared<T>) -> bool {
+        Arc::ptr_eq(&self.cell, &other.cell)
+    }
+}
+
+impl<T> Clone for Shared<T> {
+    #[inline]
+    fn clone(&self) -> Shared<T> {
+        Shared {
+            cell: self.cell.clone(),
+        }
+    }
+}
+
+impl<T> fmt::Debug for Shared<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.try_borrow() {
+            Ok(value) => f.debug_tuple("Shared").field(&*value).finish(),
+            Err(_) => f.write_str("Shared(<borrowed>)"),
+        }
+    }
+}
+
+impl<'lua, L, T> Push<L> for Shared<T>
+where
+    L: AsMutLua<'lua>,
+    T: Send + Sync + 'static + Any,
+{
+    type Err = Void; // TODO: use ! instead
+
+    #[inline]
+    fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+        Ok(push_userdata(self, lua, |_| {}))
+    }
+}
+
+impl<'lua, L, T> PushOne<L> for Shared<T>
+where
+    L: AsMutLua<'lua>,
+    T: Send + Sync + 'static + Any,
+{
+}
+
+impl<'lua, L, T> LuaRead<L> for Shared<T>
+where
+    L: AsMutLua<'lua>,
+    T: 'static + Any,
+{
+    #[inline]
+    fn lua_read_at_position(lua: L, index: i32) -> Result<Shared<T>, L> {
+        let data: UserdataOnStack<Shared<T>, L> = LuaRead::lua_read_at_position(lua, index)?;
+        Ok((*data).clone())
+    }
+}
+
+/// Immutable borrow of a [`Shared`](struct.Shared.html) object.
+///
+/// Can be used as the parameter of a callback, in which case Lua must pass a `Shared<T>` that
+/// isn't borrowed mutably.
+pub struct SharedRef<T> {
+    cell: Arc<SharedCell<T>>,
+}
+
+impl<T> Deref for SharedRef<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T> Drop for SharedRef<T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.cell.borrows.fetch_sub(1, Ordering::Release);
+    }
+}
+
+impl<T> fmt::Debug for SharedRef<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Mutable borrow of a [`Shared`](struct.Shared.html) object.
+///
+/// Can be used as the parameter of a callback, in which case Lua must pass a `Shared<T>` that
+/// isn't borrowed at all.
+pub struct SharedMut<T> {
+    cell: Arc<SharedCell<T>>,
+}
+
+impl<T> Deref for SharedMut<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.cell.value.get() }
+    }
+}
+
+impl<T> DerefMut for SharedMut<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.cell.value.get() }
+    }
+}
+
+impl<T> Drop for SharedMut<T> {
+    #[inline]
+    fn drop(&mut self) {
+        self.cell.borrows.store(0, Ordering::Release);
+    }
+}
+
+impl<T> fmt::Debug for SharedMut<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Error when borrowing a [`Shared`](struct.Shared.html) object, or a user data read by a
+/// callback.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BorrowError {
+    /// The object is borrowed immutably and can't be borrowed mutably.
+    AlreadyBorrowed,
+    /// The object is borrowed mutably.
+    AlreadyMutablyBorrowed,
+}
+
+impl fmt::Display for BorrowError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BorrowError::AlreadyBorrowed => write!(f, "already borrowed"),
+            BorrowError::AlreadyMutablyBorrowed => write!(f, "already mutably borrowed"),
+        }
+    }
+}
+
+impl Error for BorrowError {
+    fn description(&self) -> &str {
+        match *self {
+            BorrowError::AlreadyBorrowed => "already borrowed",
+            BorrowError::AlreadyMutablyBorrowed => "already mutably borrowed",
+        }
+    }
+}
+
+// Reads the `Shared<T>` at `index` and borrows it with `borrow`. If the object can't be borrowed,
+// the reason is stored in the callback context, so that the Lua error describes it.
+fn read_borrow<T, B, F>(lua: &mut InsideCallback, index: i32, borrow: F) -> Option<B>
+where
+    T: 'static + Any,
+    F: FnOnce(&Shared<T>) -> Result<B, BorrowError>,
+{
+    // The handle itself isn't borrowed: only the object it points to is. It can still be
+    // borrowed mutably by a callback that reads it with `read_userdata`.
+    let result = unsafe {
+        let shared = userdata_ptr::<Shared<T>>(lua.as_lua().0, index);
+        if shared.is_null() {
+            return None;
+        }
+        if is_mutably_borrowed(lua.as_lua().0, index) {
+            Err(BorrowError::AlreadyMutablyBorrowed)
+        } else {
+            borrow(&*shared)
+        }
+    };
+
+    match result {
+        Ok(b) => Some(b),
+        Err(err) => {
+            let arg = unsafe { ffi::lua_absindex(lua.as_lua().0, index) };
+            lua.read_error = Some(format!("bad argument #{}: {}", arg, err));
+            None
+        }
+    }
+}
+
+macro_rules! impl_borrow_read {
+    ($ty:ident, $borrow:ident) => {
+        impl<'c, T> LuaRead<&'c mut InsideCallback> for $ty<T>
+        where
+            T: 'static + Any,
+        {
+            #[inline]
+            fn lua_read_at_position(
+                lua: &'c mut InsideCallback,
+                index: i32,
+            ) -> Result<$ty<T>, &'c mut InsideCallback> {
+                match read_borrow(lua, index, Shared::<T>::$borrow) {
+                    Some(b) => Ok(b),
+                    None => Err(lua),
+                }
+            }
+        }
+
+        impl<'b, 'c, T> LuaRead<&'b mut &'c mut InsideCallback> for $ty<T>
+        where
+            T: 'static + Any,
+        {
+            #[inline]
+            fn lua_read_at_position(
+                lua: &'b mut &'c mut InsideCallback,
+                index: i32,
+            ) -> Result<$ty<T>, &'b mut &'c mut InsideCallback> {
+                match read_borrow(lua, index, Shared::<T>::$borrow) {
+                    Some(b) => Ok(b),
+                    None => Err(lua),
+                }
+            }
+        }
+    };
+}
+
+impl_borrow_read!(SharedRef, try_borrow);
+impl_borrow_read!(SharedMut, try_borrow_mut);
+
+#[cfg(test)]
+mod tests {
+    use function1;
+    use function2;
+    use BorrowError;
+    use Lua;
+    use LuaError;
+    use Shared;
+    use SharedMut;
+    use SharedRef;
+
+    #[test]
+    fn shared_with_lua() {
+        let value = Shared::new(vec![1, 2]);
+
+        let mut lua = Lua::new();
+        lua.set("v", value.clone());
+        lua.set(
+            "push",
+            function2(|mut v: SharedMut<Vec<i32>>, x: i32| v.push(x)),
+        );
+        lua.set("len", function1(|v: SharedRef<Vec<i32>>| v.len() as i32));
+
+        lua.execute::<()>("push(v, 3)").unwrap();
+        assert_eq!(*value.borrow(), vec![1, 2, 3]);
+
+        value.borrow_mut().push(4);
+        let len: i32 = lua.execute("return len(v)").unwrap();
+        assert_eq!(len, 4);
+
+        let read: Shared<Vec<i32>> = lua.get("v").unwrap();
+        assert!(read.ptr_eq(&value));
+    }
+
+    #[test]
+    fn conflicting_borrows_raise_errors() {
+        let value = Shared::new(1);
+
+        let mut lua = Lua::new();
+        lua.set("a", value.clone());
+        lua.set(
+            "swap",
+            function2(|a: SharedMut<i32>, b: SharedMut<i32>| *a + *b),
+        );
+        lua.set(
+            "sum",
+            function2(|a: SharedRef<i32>, b: SharedRef<i32>| *a + *b),
+        );
+
+        // Two immutable borrows are fine.
+        let sum: i32 = lua.execute("return sum(a, a)").unwrap();
+        assert_eq!(sum, 2);
+
+        match lua.execute::<i32>("return swap(a, a)") {
+            Err(LuaError::ExecutionError(msg)) => assert!(
+                msg.contains("bad argument #2: already mutably borrowed"),
+                "{}",
+                msg
+            ),
+            _ => panic!(),
+        }
+
+        // The failed call has released its borrows.
+        assert!(value.try_borrow_mut().is_ok());
+
+        // Borrowed by Rust while the script runs.
+        let guard = value.borrow();
+        match lua.execute::<i32>("return swap(a, a)") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("bad argument #1: already borrowed"), "{}", msg)
+            }
+            _ => panic!(),
+        }
+        let sum: i32 = lua.execute("return sum(a, a)").unwrap();
+        assert_eq!(sum, 2);
+        drop(guard);
+    }
+
+    #[test]
+    fn borrow_rules() {
+        let value = Shared::new(String::from("x"));
+        {
+            let _a = value.borrow();
+            let _b = value.borrow();
+            assert_eq!(
+                value.try_borrow_mut().err(),
+                Some(BorrowError::AlreadyBorrowed)
+            );
+        }
+        {
+            let _m = value.borrow_mut();
+            assert_eq!(
+                value.try_borrow().err(),
+                Some(BorrowError::AlreadyMutablyBorrowed)
+            );
+        }
+        value.borrow_mut().push('y');
+        assert_eq!(&*value.borrow(), "xy");
+    }
+
+    #[test]
+    fn dropped_with_last_handle() {
+        use std::sync::Arc;
+
+        let marker = Arc::new(());
+        let value = Shared::new(marker.clone());
+        {
+            let mut lua = Lua::new();
+            lua.set("v", value);
+            assert_eq!(Arc::strong_count(&marker), 2);
+        }
+        assert_eq!(Arc::strong_count(&marker), 1);
+    }
+}
@@ -0,0 +1,182 @@
+//! Verifications of the Lua stack, enabled by the `debug-checks` feature.
+//!
+//! A `Push` or `LuaRead` implementation that leaves the stack in a different state than it
+//! announces corrupts the values of the code around it, and the problem usually shows up much
+//! later. These functions compare the stack with what the library expects at a few key places
+//! and panic as soon as they differ, or raise a Lua error inside the Rust callbacks. Without the
+//! feature, they compile to nothing.
+
+use ffi;
+use libc;
+
+use std::thread;
+
+/// Returns the current top of the stack if the checks are enabled, and 0 otherwise.
+#[inline(always)]
+pub(crate) unsafe fn top(lua: *mut ffi::lua_State) -> libc::c_int {
+    if cfg!(feature = "debug-checks") {
+        ffi::lua_gettop(lua)
+    } else {
+        0
+    }
+}
+
+/// Panics if the top of the stack isn't `expected` after `operation`, where `expected` has been
+/// computed from the result of `top`.
+#[inline(always)]
+pub(crate) unsafe fn check_top(lua: *mut ffi::lua_State, expected: libc::c_int, operation: &str) {
+    if let Some(msg) = unbalanced_top(lua, expected, operation) {
+        fail(msg);
+    }
+}
+
+/// Same as `check_top`, but returns the problem instead of panicking. Used inside the functions
+/// called by Lua, where a panic can't unwind and would abort the process.
+#[inline(always)]
+pub(crate) unsafe fn unbalanced_top(
+    lua: *mut ffi::lua_State,
+    expected: libc::c_int,
+    operation: &str,
+) -> Option<String> {
+    if cfg!(feature = "debug-checks") {
+        let actual = ffi::lua_gettop(lua);
+        if actual != expected {
+            return Some(format!(
+                "the Lua stack is unbalanced after {}: its top should be {} but is {} ({} values \
+                 {})",
+                operation,
+                expected,
+                actual,
+                (actual - expected).abs(),
+                if actual > expected {
+                    "too many"
+                } else {
+                    "missing"
+                }
+            ));
+        }
+    }
+    None
+}
+
+/// Panics if `count` values can't be popped by `owner`.
+#[inline(always)]
+pub(crate) unsafe fn check_pop(lua: *mut ffi::lua_State, count: libc::c_int, owner: &str) {
+    if cfg!(feature = "debug-checks") {
+        let actual = ffi::lua_gettop(lua);
+        if actual < count {
+            fail(format!(
+                "{} is about to pop {} values but the Lua stack only contains {}",
+                owner, count, actual
+            ));
+        }
+    }
+}
+
+/// Panics if the value at `index` isn't a table, before `operation` uses it as one.
+#[inline(always)]
+pub(crate) unsafe fn check_table(lua: *mut ffi::lua_State, index: libc::c_int, operation: &str) {
+    if cfg!(feature = "debug-checks") && !ffi::lua_istable(lua, index) {
+        let ty = ffi::lua_type(lua, index);
+        let name = if ty == ffi::LUA_TNONE {
+            "nothing".to_owned()
+        } else {
+            let name = ffi::lua_typename(lua, ty);
+            String::from_utf8_lossy(::std::ffi::CStr::from_ptr(name).to_bytes()).into_owned()
+        };
+        fail(format!(
+            "{} expected a table at index {} of the Lua stack but found {}",
+            operation, index, name
+        ));
+    }
+}
+
+#[cold]
+#[inline(never)]
+fn fail(msg: String) {
+    // Panicking while unwinding would abort without printing the message.
+    if thread::panicking() {
+        eprintln!("hlua: {}", msg);
+    } else {
+        panic!("hlua: {}", msg);
+    }
+}
+
+#[cfg(all(test, feature = "debug-checks"))]
+mod tests {
+    use ffi;
+
+    use AsMutLua;
+    use Lua;
+    use LuaTable;
+    use PushGuard;
+
+    #[test]
+    #[should_panic(expected = "PushGuard is about to pop 3 values")]
+    fn push_guard_pops_too_much() {
+        let mut lua = Lua::new();
+        unsafe {
+            ffi::lua_pushnil(lua.as_mut_lua().0);
+            let _guard = PushGuard::new(&mut lua, 3);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "expected a table at index -1 of the Lua stack but found number")]
+    fn table_moved() {
+        let mut lua = Lua::new();
+        lua.execute::<()>("a = {}").unwrap();
+        let mut table: LuaTable<_> = lua.get("a").unwrap();
+        unsafe {
+            ffi::lua_pushnumber(table.as_mut_lua().0, 1.0);
+        }
+        table.set(1, 2);
+    }
+
+    #[test]
+    fn unbalanced_callback() {
+        struct Liar;
+
+        impl<'lua, L> ::Push<L> for Liar
+        where
+            L: AsMutLua<'lua>,
+        {
+            type Err = ::Void;
+            fn push_to_lua(self, mut lua: L) -> Result<PushGuard<L>, (::Void, L)> {
+                unsafe {
+                    ffi::lua_pushnil(lua.as_mut_lua().0);
+                    ffi::lua_pushnil(lua.as_mut_lua().0);
+                    Ok(PushGuard::new(lua, 1))
+                }
+            }
+        }
+
+        let mut lua = Lua::new();
+        lua.set("f", ::function0(|| Liar));
+        match lua.execute::<()>("f()") {
+            Err(::LuaError::ExecutionError(msg)) => assert!(
+                msg.contains(
+                    "the Lua stack is unbalanced after the return of a Rust callback: its top \
+                     should be 1 but is 2 (1 values too many)"
+                ),
+                "{}",
+                msg
+            ),
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn balanced_operations() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set("f", ::function1(|a: i32| a + 1));
+        let v: i32 = lua.execute("local t = {f(1)} return t[1]").unwrap();
+        assert_eq!(v, 2);
+
+        let mut table: LuaTable<_> = lua.get("string").unwrap();
+        assert!(table.get::<::LuaFunction<_>, _, _>("len").is_some());
+        table.set("x", 5);
+        assert!(table.iter::<String, ::AnyLuaValue>().count() > 0);
+    }
+}
@@ -1,7 +1,11 @@
-use std::any::{Any, TypeId};
+use std::any::{type_name, Any, TypeId};
+use std::cell::Cell;
+use std::ffi::CString;
+use std::fmt;
 use std::marker::PhantomData;
 use std::mem;
 use std::ops::{Deref, DerefMut};
+use std::process;
 use std::ptr;
 
 use ffi;
@@ -9,21 +13,45 @@ use libc;
 
 use AsLua;
 use AsMutLua;
+use Lua;
 use LuaContext;
 use LuaRead;
 use Push;
 use PushGuard;
 
+use BorrowError;
 use InsideCallback;
 use LuaTable;
 
+// Written at the start of the memory of each userdata, right before the object.
+#[repr(C)]
+struct Header {
+    typeid: TypeId,
+    // Number of immutable borrows of the object by the running callbacks, or -1 if one of them
+    // borrows it mutably.
+    borrows: Cell<isize>,
+}
+
+// Offset of the object from the start of the userdata.
+#[inline]
+fn data_offset<T>() -> usize {
+    let align = mem::align_of::<T>();
+    (mem::size_of::<Header>() + align - 1) & !(align - 1)
+}
+
 // Called when an object inside Lua is being dropped.
 #[inline]
 extern "C" fn destructor_wrapper<T>(lua: *mut ffi::lua_State) -> libc::c_int {
     unsafe {
         let obj = ffi::lua_touserdata(lua, -1);
-        ptr::drop_in_place(obj as *mut TypeId);
-        ptr::drop_in_place((obj as *mut u8).offset(mem::size_of::<TypeId>() as isize) as *mut T);
+        // A `UserdataRef` or `UserdataMut` kept after its callback returned would point to freed
+        // memory, and unwinding out of a `__gc` metamethod isn't possible.
+        if (*(obj as *const Header)).borrows.get() != 0 {
+            eprintln!("hlua: a user data was collected while a callback still borrows it");
+            process::abort();
+        }
+        ptr::drop_in_place(obj as *mut Header);
+        ptr::drop_in_place((obj as *mut u8).add(data_offset::<T>()) as *mut T);
         0
     }
 }
@@ -38,6 +66,18 @@ extern "C" fn destructor_wrapper<T>(lua: *mut ffi::lua_State) -> libc::c_int {
 ///
 /// [See this link for more infos.](http://www.lua.org/manual/5.2/manual.html#2.4)
 ///
+/// # Shared metatables
+///
+/// All the objects of the same type `T` pushed in a Lua context share the same metatable. It is
+/// created and filled by the `metatable` closure the first time an object of this type is pushed,
+/// then stored in the registry and reused: later calls don't run their closure. Use
+/// [`Lua::register_userdata`](struct.Lua.html#method.register_userdata) to fill the metatable
+/// before any object is pushed, for example to register the methods of the type at startup.
+///
+/// Instead of filling the metatable by hand, the closure can use a
+/// [`UserdataMethods`](struct.UserdataMethods.html) builder, which also defines the operators of
+/// the object from its implementations of the standard traits.
+///
 /// # About the Drop trait
 ///
 /// When the Lua context detects that a userdata is no longer needed it calls the function at the
@@ -49,7 +89,7 @@ extern "C" fn destructor_wrapper<T>(lua: *mut ffi::lua_State) -> libc::c_int {
 ///
 /// # Arguments
 ///
-///  - `metatable`: Function that fills the metatable of the object.
+///  - `metatable`: Function that fills the metatable of the type, if it doesn't exist yet.
 ///
 #[inline]
 pub fn push_userdata<'lua, L, T, F>(data: T, mut lua: L, metatable: F) -> PushGuard<L>
@@ -59,92 +99,418 @@ where
     T: Send + 'static + Any,
 {
     unsafe {
-        let typeid = TypeId::of::<T>();
-
         let lua_data = {
-            let tot_size = mem::size_of_val(&typeid) + mem::size_of_val(&data);
+            let tot_size = data_offset::<T>() + mem::size_of_val(&data);
             ffi::lua_newuserdata(lua.as_mut_lua().0, tot_size as libc::size_t)
         };
 
         // We check the alignment requirements.
         debug_assert_eq!(lua_data as usize % mem::align_of_val(&data), 0);
-        // Since the size of a `TypeId` should always be a usize, this assert should pass every
-        // time as well.
-        debug_assert_eq!(mem::size_of_val(&typeid) % mem::align_of_val(&data), 0);
+        debug_assert_eq!(lua_data as usize % mem::align_of::<Header>(), 0);
 
-        // We write the `TypeId` first, and the data right next to it.
-        ptr::write(lua_data as *mut TypeId, typeid);
-        let data_loc = (lua_data as *const u8).offset(mem::size_of_val(&typeid) as isize);
-        ptr::write(data_loc as *mut _, data);
+        // We write the header first, and the data right after it.
+        let header = Header {
+            typeid: TypeId::of::<T>(),
+            borrows: Cell::new(0),
+        };
+        ptr::write(lua_data as *mut Header, header);
+        let data_loc = (lua_data as *mut u8).add(data_offset::<T>());
+        ptr::write(data_loc as *mut T, data);
 
         let lua_raw = lua.as_mut_lua();
+        push_metatable::<_, T, _>(&mut lua, metatable, false);
+        ffi::lua_setmetatable(lua_raw.0, -2);
+    }
+
+    let raw_lua = lua.as_lua();
+    PushGuard {
+        lua,
+        size: 1,
+        raw_lua,
+    }
+}
+
+impl<'lua> Lua<'lua> {
+    /// Fills the metatable shared by the userdata of type `T`, creating it if necessary.
+    ///
+    /// The objects pushed with [`push_userdata`](fn.push_userdata.html) use this metatable
+    /// instead of building one, which makes it possible to register the methods of a type once
+    /// at startup. Calling this method when the metatable already exists modifies it, including
+    /// for the objects that have already been pushed.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[macro_use] extern crate hlua;
+    ///
+    /// struct Counter(i32);
+    /// implement_lua_push!(Counter, |_| {});
+    ///
+    /// # fn main() {
+    /// let mut lua = hlua::Lua::new();
+    /// lua.register_userdata::<Counter, _>(|mut metatable| {
+    ///     let mut index = metatable.empty_array("__index");
+    ///     index.set("get", hlua::function1(|c: hlua::UserdataRef<Counter>| c.0));
+    /// });
+    ///
+    /// lua.set("c", Counter(3));
+    /// let value: i32 = lua.execute("return c:get()").unwrap();
+    /// assert_eq!(value, 3);
+    /// # }
+    /// ```
+    pub fn register_userdata<T, F>(&mut self, metatable: F)
+    where
+        F: FnOnce(LuaTable<&mut PushGuard<&mut Lua<'lua>>>),
+        T: Send + 'static + Any,
+    {
+        unsafe {
+            push_metatable::<_, T, _>(self, metatable, true);
+            ffi::lua_pop(self.as_mut_lua().0, 1);
+        }
+    }
+}
+
+// Name of the metatable of the userdata of type `T` in the registry.
+fn metatable_name<T>() -> CString
+where
+    T: 'static + Any,
+{
+    let name = format!("hlua.userdata {:?} {}", TypeId::of::<T>(), type_name::<T>());
+    CString::new(name).unwrap()
+}
 
-        // Creating a metatable.
-        ffi::lua_newtable(lua.as_mut_lua().0);
+// Pushes the metatable of the userdata of type `T`. If it is created, or if `always_fill` is
+// true, `fill` is called with the metatable.
+unsafe fn push_metatable<'lua, L, T, F>(lua: &mut L, fill: F, always_fill: bool)
+where
+    F: FnOnce(LuaTable<&mut PushGuard<&mut L>>),
+    L: AsMutLua<'lua>,
+    T: Send + 'static + Any,
+{
+    let name = metatable_name::<T>();
+    let created = ffi::luaL_newmetatable(lua.as_mut_lua().0, name.as_ptr()) != 0;
 
+    if created {
         // Index "__gc" in the metatable calls the object's destructor.
 
         // TODO: Could use std::intrinsics::needs_drop to avoid that if not needed.
         // After some discussion on IRC, it would be acceptable to add a reexport in libcore
         // without going through the RFC process.
-        {
-            match "__gc".push_to_lua(&mut lua) {
-                Ok(p) => p.forget(),
-                Err(_) => unreachable!(),
-            };
+        match "__gc".push_to_lua(&mut *lua) {
+            Ok(p) => p.forget(),
+            Err(_) => unreachable!(),
+        };
 
-            ffi::lua_pushcfunction(lua.as_mut_lua().0, destructor_wrapper::<T>);
-            ffi::lua_settable(lua.as_mut_lua().0, -3);
-        }
+        ffi::lua_pushcfunction(lua.as_mut_lua().0, destructor_wrapper::<T>);
+        ffi::lua_settable(lua.as_mut_lua().0, -3);
+    }
 
+    if created || always_fill {
         // Calling the metatable closure.
-        {
-            let raw_lua = lua.as_lua();
-            let mut guard = PushGuard {
-                lua: &mut lua,
-                size: 1,
-                raw_lua: raw_lua,
-            };
-            metatable(LuaRead::lua_read(&mut guard).ok().unwrap());
-            guard.forget();
-        }
-
-        ffi::lua_setmetatable(lua_raw.0, -2);
+        let raw_lua = lua.as_lua();
+        let mut guard = PushGuard {
+            lua,
+            size: 1,
+            raw_lua,
+        };
+        fill(LuaRead::lua_read(&mut guard).ok().unwrap());
+        guard.forget();
     }
+}
 
-    let raw_lua = lua.as_lua();
-    PushGuard {
-        lua: lua,
-        size: 1,
-        raw_lua: raw_lua,
+/// Reads a user data pushed with [`push_userdata`](fn.push_userdata.html) from the parameters
+/// of a callback, and borrows it mutably.
+///
+/// The borrow lasts until the returned `UserdataMut` is dropped. Until then, the object can't be
+/// borrowed again with this function or with [`read_userdata_ref`](fn.read_userdata_ref.html),
+/// for example because the same object is passed twice as a parameter, or by a callback called by
+/// this one. In that case an error is returned, and the error raised in Lua by the callback
+/// explains the problem.
+///
+/// This is the same as reading a `UserdataMut<T>` parameter.
+#[inline]
+pub fn read_userdata<T>(
+    lua: &mut InsideCallback,
+    index: i32,
+) -> Result<UserdataMut<T>, &mut InsideCallback>
+where
+    T: 'static + Any,
+{
+    unsafe {
+        match borrow_userdata::<T>(lua, index, true) {
+            Some((data, borrows)) => Ok(UserdataMut { data, borrows }),
+            None => Err(lua),
+        }
     }
 }
 
+/// Reads a user data pushed with [`push_userdata`](fn.push_userdata.html) from the parameters
+/// of a callback, and borrows it immutably.
+///
+/// The borrow lasts until the returned `UserdataRef` is dropped. Until then, the object can be
+/// borrowed immutably again, but not mutably with [`read_userdata`](fn.read_userdata.html).
 ///
+/// This is the same as reading a `UserdataRef<T>` parameter.
 #[inline]
-pub fn read_userdata<'t, 'c, T>(
-    lua: &'c mut InsideCallback,
+pub fn read_userdata_ref<T>(
+    lua: &mut InsideCallback,
     index: i32,
-) -> Result<&'t mut T, &'c mut InsideCallback>
+) -> Result<UserdataRef<T>, &mut InsideCallback>
 where
     T: 'static + Any,
 {
     unsafe {
-        let data_ptr = ffi::lua_touserdata(lua.as_lua().0, index);
-        if data_ptr.is_null() {
-            return Err(lua);
+        match borrow_userdata::<T>(lua, index, false) {
+            Some((data, borrows)) => Ok(UserdataRef { data, borrows }),
+            None => Err(lua),
+        }
+    }
+}
+
+// Returns a pointer to the object of type `T` at `index`, or null if there isn't one.
+#[inline]
+pub(crate) unsafe fn userdata_ptr<T>(lua: *mut ffi::lua_State, index: i32) -> *mut T
+where
+    T: 'static + Any,
+{
+    let data_ptr = ffi::lua_touserdata(lua, index);
+    if data_ptr.is_null() {
+        return ptr::null_mut();
+    }
+
+    let actual_typeid = data_ptr as *const TypeId;
+    if ptr::read_unaligned(actual_typeid) != TypeId::of::<T>() {
+        return ptr::null_mut();
+    }
+
+    (data_ptr as *mut u8).add(data_offset::<T>()) as *mut T
+}
+
+// Borrows the object of type `T` at `index`, and returns it with its borrow counter. Returns
+// `Ok(None)` if there isn't one.
+unsafe fn try_borrow<T>(
+    lua: *mut ffi::lua_State,
+    index: i32,
+    mutable: bool,
+) -> Result<Option<(*mut T, *const Cell<isize>)>, BorrowError>
+where
+    T: 'static + Any,
+{
+    let data = userdata_ptr::<T>(lua, index);
+    if data.is_null() {
+        return Ok(None);
+    }
+
+    let header = &*(ffi::lua_touserdata(lua, index) as *const Header);
+    let borrows = header.borrows.get();
+    if mutable && borrows > 0 {
+        return Err(BorrowError::AlreadyBorrowed);
+    } else if borrows < 0 {
+        return Err(BorrowError::AlreadyMutablyBorrowed);
+    }
+
+    header.borrows.set(if mutable { -1 } else { borrows + 1 });
+    Ok(Some((data, &header.borrows)))
+}
+
+// Same as `try_borrow`, but stores the reason of a failed borrow in the callback context, so that
+// the Lua error describes it.
+unsafe fn borrow_userdata<T>(
+    lua: &mut InsideCallback,
+    index: i32,
+    mutable: bool,
+) -> Option<(*mut T, *const Cell<isize>)>
+where
+    T: 'static + Any,
+{
+    match try_borrow::<T>(lua.as_lua().0, index, mutable) {
+        Ok(borrow) => borrow,
+        Err(err) => {
+            let arg = ffi::lua_absindex(lua.as_lua().0, index);
+            lua.read_error = Some(format!("bad argument #{}: {}", arg, err));
+            None
         }
+    }
+}
+
+// Returns true if the object at `index`, which must have been pushed by `push_userdata`, is
+// borrowed mutably by a running callback.
+#[inline]
+pub(crate) unsafe fn is_mutably_borrowed(lua: *mut ffi::lua_State, index: i32) -> bool {
+    let header = &*(ffi::lua_touserdata(lua, index) as *const Header);
+    header.borrows.get() < 0
+}
+
+/// Immutable borrow of a user data pushed with [`push_userdata`](fn.push_userdata.html).
+///
+/// Can be used as the parameter of a callback, in which case Lua must pass an object of type `T`
+/// that isn't borrowed mutably. The object stays borrowed until the `UserdataRef` is dropped.
+///
+/// The borrow must not outlive the object: if Lua collects an object that is still borrowed, for
+/// example because the borrow was stored somewhere by the callback, the process is aborted.
+pub struct UserdataRef<T> {
+    data: *const T,
+    borrows: *const Cell<isize>,
+}
+
+impl<T> UserdataRef<T>
+where
+    T: 'static + Any,
+{
+    // Borrows the object at `index` outside of a callback context. Returns `Ok(None)` if it isn't
+    // a user data of type `T`. Used by the metamethods generated by `UserdataMethods`.
+    pub(crate) unsafe fn new(
+        lua: *mut ffi::lua_State,
+        index: i32,
+    ) -> Result<Option<UserdataRef<T>>, BorrowError> {
+        let borrow = try_borrow::<T>(lua, index, false)?;
+        Ok(borrow.map(|(data, borrows)| UserdataRef { data, borrows }))
+    }
+}
 
-        let actual_typeid = data_ptr as *const TypeId;
-        if ptr::read_unaligned(actual_typeid) != TypeId::of::<T>() {
-            return Err(lua);
+impl<T> Deref for UserdataRef<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<T> Drop for UserdataRef<T> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe {
+            let borrows = &*self.borrows;
+            borrows.set(borrows.get() - 1);
         }
+    }
+}
+
+impl<T> fmt::Debug for UserdataRef<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+/// Mutable borrow of a user data pushed with [`push_userdata`](fn.push_userdata.html).
+///
+/// Can be used as the parameter of a callback, in which case Lua must pass an object of type `T`
+/// that isn't borrowed at all. The object stays borrowed until the `UserdataMut` is dropped.
+///
+/// The borrow must not outlive the object: if Lua collects an object that is still borrowed, for
+/// example because the borrow was stored somewhere by the callback, the process is aborted.
+///
+/// # Example
+///
+/// ```
+/// #[macro_use] extern crate hlua;
+/// use hlua::UserdataMut;
+///
+/// struct Counter(i32);
+/// implement_lua_push!(Counter, |_| {});
+///
+/// # fn main() {
+/// let mut lua = hlua::Lua::new();
+/// lua.set("c", Counter(3));
+/// lua.set("incr", hlua::function1(|mut c: UserdataMut<Counter>| c.0 += 1));
+///
+/// lua.execute::<()>("incr(c)").unwrap();
+/// # }
+/// ```
+///
+/// A callback can't take a reference to the object instead, since nothing would prevent it from
+/// keeping the reference after the object is collected:
+///
+/// ```compile_fail
+/// #[macro_use] extern crate hlua;
+///
+/// struct Counter(i32);
+/// implement_lua_push!(Counter, |_| {});
+/// implement_lua_read!(Counter);
+///
+/// # fn main() {
+/// let mut kept = Vec::new();
+/// let mut lua = hlua::Lua::new();
+/// lua.set("keep", hlua::function1(move |c: &'static mut Counter| kept.push(c)));
+/// # }
+/// ```
+pub struct UserdataMut<T> {
+    data: *mut T,
+    borrows: *const Cell<isize>,
+}
+
+impl<T> Deref for UserdataMut<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.data }
+    }
+}
+
+impl<T> DerefMut for UserdataMut<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.data }
+    }
+}
+
+impl<T> Drop for UserdataMut<T> {
+    #[inline]
+    fn drop(&mut self) {
+        unsafe { (*self.borrows).set(0) }
+    }
+}
 
-        let data = (data_ptr as *const u8).offset(mem::size_of::<TypeId>() as isize);
-        Ok(&mut *(data as *mut T))
+impl<T> fmt::Debug for UserdataMut<T>
+where
+    T: fmt::Debug,
+{
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
     }
 }
 
+macro_rules! impl_userdata_read {
+    ($ty:ident, $read:ident) => {
+        impl<'c, T> LuaRead<&'c mut InsideCallback> for $ty<T>
+        where
+            T: 'static + Any,
+        {
+            #[inline]
+            fn lua_read_at_position(
+                lua: &'c mut InsideCallback,
+                index: i32,
+            ) -> Result<$ty<T>, &'c mut InsideCallback> {
+                $read(lua, index)
+            }
+        }
+
+        impl<'b, 'c, T> LuaRead<&'b mut &'c mut InsideCallback> for $ty<T>
+        where
+            T: 'static + Any,
+        {
+            #[inline]
+            fn lua_read_at_position(
+                lua: &'b mut &'c mut InsideCallback,
+                index: i32,
+            ) -> Result<$ty<T>, &'b mut &'c mut InsideCallback> {
+                match $read(lua, index) {
+                    Ok(b) => Ok(b),
+                    Err(_) => Err(lua),
+                }
+            }
+        }
+    };
+}
+
+impl_userdata_read!(UserdataRef, read_userdata_ref);
+impl_userdata_read!(UserdataMut, read_userdata);
+
 /// Represents a user data located inside the Lua context.
 #[derive(Debug)]
 pub struct UserdataOnStack<T, L> {
@@ -161,13 +527,7 @@ where
     #[inline]
     fn lua_read_at_position(lua: L, index: i32) -> Result<UserdataOnStack<T, L>, L> {
         unsafe {
-            let data_ptr = ffi::lua_touserdata(lua.as_lua().0, index);
-            if data_ptr.is_null() {
-                return Err(lua);
-            }
-
-            let actual_typeid = data_ptr as *const TypeId;
-            if ptr::read_unaligned(actual_typeid) != TypeId::of::<T>() {
+            if userdata_ptr::<T>(lua.as_lua().0, index).is_null() {
                 return Err(lua);
             }
 
@@ -213,8 +573,12 @@ where
     fn deref(&self) -> &T {
         unsafe {
             let base = ffi::lua_touserdata(self.variable.as_lua().0, self.index);
-            let data = (base as *const u8).offset(mem::size_of::<TypeId>() as isize);
-            &*(data as *const T)
+            let header = &*(base as *const Header);
+            assert!(
+                header.borrows.get() >= 0,
+                "the user data is already mutably borrowed"
+            );
+            &*((base as *const u8).add(data_offset::<T>()) as *const T)
         }
     }
 }
@@ -228,8 +592,12 @@ where
     fn deref_mut(&mut self) -> &mut T {
         unsafe {
             let base = ffi::lua_touserdata(self.variable.as_mut_lua().0, self.index);
-            let data = (base as *const u8).offset(mem::size_of::<TypeId>() as isize);
-            &mut *(data as *mut T)
+            let header = &*(base as *const Header);
+            assert!(
+                header.borrows.get() == 0,
+                "the user data is already borrowed"
+            );
+            &mut *((base as *mut u8).add(data_offset::<T>()) as *mut T)
         }
     }
 }
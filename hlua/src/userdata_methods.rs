@@ -0,0 +1,1169 @@
+use std::any::{type_name, Any};
+use std::ffi::CStr;
+use std::fmt;
+use std::marker::PhantomData;
+use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+use std::ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr};
+use std::ptr;
+
+use ffi;
+use libc;
+
+use functions_write::raise;
+use userdata::UserdataRef;
+
+use push_userdata;
+use AsMutLua;
+use Lua;
+use LuaTable;
+use PushOne;
+use Void;
+
+/// Metamethod of a user data, called by Lua for an operator or a built-in function.
+///
+/// The `__gc` metamethod isn't part of this list, since hlua uses it to drop the object.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum MetaMethod {
+    /// `a + b`.
+    Add,
+    /// `a - b`.
+    Sub,
+    /// `a * b`.
+    Mul,
+    /// `a / b`.
+    Div,
+    /// `a % b`.
+    Mod,
+    /// `a ^ b`.
+    Pow,
+    /// `-a`.
+    Unm,
+    /// `a // b`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    IDiv,
+    /// `a & b`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    BAnd,
+    /// `a | b`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    BOr,
+    /// `a ~ b`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    BXor,
+    /// `a << b`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    Shl,
+    /// `a >> b`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    Shr,
+    /// `~a`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    BNot,
+    /// `a .. b`.
+    Concat,
+    /// `#a`.
+    Len,
+    /// `a == b`, when both operands are user data.
+    Eq,
+    /// `a < b`, and `a > b` with the operands swapped.
+    Lt,
+    /// `a <= b`, and `a >= b` with the operands swapped.
+    Le,
+    /// `a(...)`.
+    Call,
+    /// `tostring(a)`.
+    ToString,
+    /// Reading a field of the object, `a.b`.
+    Index,
+    /// Writing a field of the object, `a.b = c`.
+    NewIndex,
+}
+
+impl MetaMethod {
+    /// Returns the name of the metamethod in the metatable, such as `"__add"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            MetaMethod::Add => "__add",
+            MetaMethod::Sub => "__sub",
+            MetaMethod::Mul => "__mul",
+            MetaMethod::Div => "__div",
+            MetaMethod::Mod => "__mod",
+            MetaMethod::Pow => "__pow",
+            MetaMethod::Unm => "__unm",
+            #[cfg(any(feature = "lua53", feature = "lua54"))]
+            MetaMethod::IDiv => "__idiv",
+            #[cfg(any(feature = "lua53", feature = "lua54"))]
+            MetaMethod::BAnd => "__band",
+            #[cfg(any(feature = "lua53", feature = "lua54"))]
+            MetaMethod::BOr => "__bor",
+            #[cfg(any(feature = "lua53", feature = "lua54"))]
+            MetaMethod::BXor => "__bxor",
+            #[cfg(any(feature = "lua53", feature = "lua54"))]
+            MetaMethod::Shl => "__shl",
+            #[cfg(any(feature = "lua53", feature = "lua54"))]
+            MetaMethod::Shr => "__shr",
+            #[cfg(any(feature = "lua53", feature = "lua54"))]
+            MetaMethod::BNot => "__bnot",
+            MetaMethod::Concat => "__concat",
+            MetaMethod::Len => "__len",
+            MetaMethod::Eq => "__eq",
+            MetaMethod::Lt => "__lt",
+            MetaMethod::Le => "__le",
+            MetaMethod::Call => "__call",
+            MetaMethod::ToString => "__tostring",
+            MetaMethod::Index => "__index",
+            MetaMethod::NewIndex => "__newindex",
+        }
+    }
+}
+
+// Pushes a value captured when it was added to the builder.
+type Pusher = Box<dyn FnOnce(*mut ffi::lua_State)>;
+
+/// Builder for the metatable of a user data of type `T`.
+///
+/// Gathers the methods of the objects, callable with `obj:method(...)`, and the metamethods that
+/// define how they behave with operators. The `with_*` methods define metamethods from the
+/// implementations of the standard traits by `T`. The content is written in the metatable passed
+/// to `apply`, usually the one passed to the closure of
+/// [`push_userdata`](fn.push_userdata.html) or
+/// [`Lua::register_userdata`](struct.Lua.html#method.register_userdata).
+///
+/// The operands of the metamethods defined by the `with_*` methods must both be of type `T`,
+/// otherwise a Lua error is raised. The results of the arithmetic operators are pushed as new
+/// user data of type `T`.
+///
+/// # Example
+///
+/// ```
+/// #[macro_use] extern crate hlua;
+///
+/// use std::fmt;
+/// use std::ops::Add;
+/// use hlua::{MetaMethod, UserdataMethods, UserdataRef};
+///
+/// #[derive(Clone, PartialEq)]
+/// struct Vec2(f64, f64);
+///
+/// impl Add for Vec2 {
+///     type Output = Vec2;
+///     fn add(self, other: Vec2) -> Vec2 { Vec2(self.0 + other.0, self.1 + other.1) }
+/// }
+///
+/// impl fmt::Display for Vec2 {
+///     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+///         write!(f, "({}, {})", self.0, self.1)
+///     }
+/// }
+///
+/// implement_lua_push!(Vec2, |metatable| {
+///     UserdataMethods::<Vec2>::new()
+///         .with_add()
+///         .with_partial_eq()
+///         .with_display()
+///         .add_meta_method(MetaMethod::Len, hlua::function1(|v: UserdataRef<Vec2>| v.0.hypot(v.1)))
+///         .add_method("x", hlua::function1(|v: UserdataRef<Vec2>| v.0))
+///         .apply(metatable)
+/// });
+///
+/// # fn main() {
+/// let mut lua = hlua::Lua::new();
+/// lua.openlibs();
+/// lua.set("a", Vec2(1.0, 2.0));
+/// lua.set("b", Vec2(2.0, 2.0));
+///
+/// let s: String = lua.execute("return tostring(a + b)").unwrap();
+/// assert_eq!(s, "(3, 4)");
+/// let len: f64 = lua.execute("return #(a + b)").unwrap();
+/// assert_eq!(len, 5.0);
+/// let x: f64 = lua.execute("return (a + b):x()").unwrap();
+/// assert_eq!(x, 3.0);
+/// let eq: bool = lua.execute("return a + b == b + a").unwrap();
+/// assert!(eq);
+/// # }
+/// ```
+pub struct UserdataMethods<T> {
+    meta: Vec<(&'static str, Pusher)>,
+    methods: Vec<(String, Pusher)>,
+    getters: Vec<(String, Pusher)>,
+    setters: Vec<(String, Pusher)>,
+    marker: PhantomData<T>,
+}
+
+impl<T> UserdataMethods<T>
+where
+    T: Send + 'static + Any,
+{
+    /// Builds an empty set of methods.
+    #[inline]
+    pub fn new() -> UserdataMethods<T> {
+        UserdataMethods {
+            meta: Vec::new(),
+            methods: Vec::new(),
+            getters: Vec::new(),
+            setters: Vec::new(),
+            marker: PhantomData,
+        }
+    }
+
+    /// Adds a method, called by Lua with `obj:name(...)`. The object is the first parameter of
+    /// the function.
+    ///
+    /// See [`add_field_getter`](#method.add_field_getter) for how the name is looked up.
+    #[inline]
+    pub fn add_method<V, E>(mut self, name: &str, function: V) -> UserdataMethods<T>
+    where
+        V: for<'a> PushOne<&'a mut Lua<'static>, Err = E> + 'static,
+        E: Into<Void>,
+    {
+        self.methods.push((name.to_owned(), pusher(function)));
+        self
+    }
+
+    /// Adds a field that Lua reads with `obj.name`, by calling `getter` with the object.
+    ///
+    /// As soon as there is a field or a method, the `__index` metamethod is generated. It looks up
+    /// the fields first, then the methods, then calls the `__index` metamethod added with
+    /// [`add_meta_method`](#method.add_meta_method) if any. Reading any other name raises an
+    /// error.
+    ///
+    /// # Example
+    ///
+    /// ```
+    /// #[macro_use] extern crate hlua;
+    ///
+    /// use hlua::{UserdataMut, UserdataRef};
+    ///
+    /// struct Sound { volume: f64 }
+    ///
+    /// implement_lua_push!(Sound, |metatable| {
+    ///     hlua::UserdataMethods::<Sound>::new()
+    ///         .add_field_getter("volume", hlua::function1(|s: UserdataRef<Sound>| s.volume))
+    ///         .add_field_setter("volume", hlua::function2(|mut s: UserdataMut<Sound>, v: f64| {
+    ///             s.volume = v
+    ///         }))
+    ///         .apply(metatable)
+    /// });
+    ///
+    /// # fn main() {
+    /// let mut lua = hlua::Lua::new();
+    /// lua.set("sound", Sound { volume: 1.0 });
+    /// let volume: f64 = lua.execute("sound.volume = sound.volume / 2 return sound.volume").unwrap();
+    /// assert_eq!(volume, 0.5);
+    /// assert!(lua.execute::<()>("sound.pitch = 2").is_err());
+    /// # }
+    /// ```
+    #[inline]
+    pub fn add_field_getter<V, E>(mut self, name: &str, getter: V) -> UserdataMethods<T>
+    where
+        V: for<'a> PushOne<&'a mut Lua<'static>, Err = E> + 'static,
+        E: Into<Void>,
+    {
+        self.getters.push((name.to_owned(), pusher(getter)));
+        self
+    }
+
+    /// Adds a field that Lua writes with `obj.name = value`, by calling `setter` with the object
+    /// and the value.
+    ///
+    /// As soon as there is a setter, the `__newindex` metamethod is generated. It looks up the
+    /// setters, then calls the `__newindex` metamethod added with
+    /// [`add_meta_method`](#method.add_meta_method) if any. Writing any other name raises an
+    /// error, which says that the field is read-only if it has a getter.
+    #[inline]
+    pub fn add_field_setter<V, E>(mut self, name: &str, setter: V) -> UserdataMethods<T>
+    where
+        V: for<'a> PushOne<&'a mut Lua<'static>, Err = E> + 'static,
+        E: Into<Void>,
+    {
+        self.setters.push((name.to_owned(), pusher(setter)));
+        self
+    }
+
+    /// Adds a metamethod. The function receives the operands, or the object followed by the
+    /// parameters for `MetaMethod::Call`.
+    ///
+    /// Adding the same metamethod twice replaces the first one.
+    #[inline]
+    pub fn add_meta_method<V, E>(mut self, method: MetaMethod, function: V) -> UserdataMethods<T>
+    where
+        V: for<'a> PushOne<&'a mut Lua<'static>, Err = E> + 'static,
+        E: Into<Void>,
+    {
+        self.meta.push((method.name(), pusher(function)));
+        self
+    }
+
+    /// Defines `+` with the implementation of `Add` by `T`.
+    #[inline]
+    pub fn with_add(self) -> UserdataMethods<T>
+    where
+        T: Add<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::Add, meta_add::<T>)
+    }
+
+    /// Defines `-` with the implementation of `Sub` by `T`.
+    #[inline]
+    pub fn with_sub(self) -> UserdataMethods<T>
+    where
+        T: Sub<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::Sub, meta_sub::<T>)
+    }
+
+    /// Defines `*` with the implementation of `Mul` by `T`.
+    #[inline]
+    pub fn with_mul(self) -> UserdataMethods<T>
+    where
+        T: Mul<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::Mul, meta_mul::<T>)
+    }
+
+    /// Defines `/` with the implementation of `Div` by `T`.
+    #[inline]
+    pub fn with_div(self) -> UserdataMethods<T>
+    where
+        T: Div<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::Div, meta_div::<T>)
+    }
+
+    /// Defines `%` with the implementation of `Rem` by `T`.
+    #[inline]
+    pub fn with_rem(self) -> UserdataMethods<T>
+    where
+        T: Rem<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::Mod, meta_rem::<T>)
+    }
+
+    /// Defines the unary `-` with the implementation of `Neg` by `T`.
+    #[inline]
+    pub fn with_neg(self) -> UserdataMethods<T>
+    where
+        T: Neg<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::Unm, meta_neg::<T>)
+    }
+
+    /// Defines `//` with the implementation of `Div` by `T`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    #[inline]
+    pub fn with_idiv(self) -> UserdataMethods<T>
+    where
+        T: Div<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::IDiv, meta_div::<T>)
+    }
+
+    /// Defines `&` with the implementation of `BitAnd` by `T`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    #[inline]
+    pub fn with_bitand(self) -> UserdataMethods<T>
+    where
+        T: BitAnd<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::BAnd, meta_bitand::<T>)
+    }
+
+    /// Defines `|` with the implementation of `BitOr` by `T`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    #[inline]
+    pub fn with_bitor(self) -> UserdataMethods<T>
+    where
+        T: BitOr<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::BOr, meta_bitor::<T>)
+    }
+
+    /// Defines the binary `~` with the implementation of `BitXor` by `T`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    #[inline]
+    pub fn with_bitxor(self) -> UserdataMethods<T>
+    where
+        T: BitXor<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::BXor, meta_bitxor::<T>)
+    }
+
+    /// Defines `<<` with the implementation of `Shl` by `T`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    #[inline]
+    pub fn with_shl(self) -> UserdataMethods<T>
+    where
+        T: Shl<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::Shl, meta_shl::<T>)
+    }
+
+    /// Defines `>>` with the implementation of `Shr` by `T`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    #[inline]
+    pub fn with_shr(self) -> UserdataMethods<T>
+    where
+        T: Shr<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::Shr, meta_shr::<T>)
+    }
+
+    /// Defines the unary `~` with the implementation of `Not` by `T`.
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    #[inline]
+    pub fn with_not(self) -> UserdataMethods<T>
+    where
+        T: Not<Output = T> + Clone,
+    {
+        self.add_c_function(MetaMethod::BNot, meta_not::<T>)
+    }
+
+    /// Defines `==` and `~=` with the implementation of `PartialEq` by `T`.
+    #[inline]
+    pub fn with_partial_eq(self) -> UserdataMethods<T>
+    where
+        T: PartialEq,
+    {
+        self.add_c_function(MetaMethod::Eq, meta_eq::<T>)
+    }
+
+    /// Defines `<`, `<=`, `>` and `>=` with the implementation of `PartialOrd` by `T`.
+    #[inline]
+    pub fn with_partial_ord(self) -> UserdataMethods<T>
+    where
+        T: PartialOrd,
+    {
+        self.add_c_function(MetaMethod::Lt, meta_lt::<T>)
+            .add_c_function(MetaMethod::Le, meta_le::<T>)
+    }
+
+    /// Defines `tostring` with the implementation of `Display` by `T`.
+    #[inline]
+    pub fn with_display(self) -> UserdataMethods<T>
+    where
+        T: fmt::Display,
+    {
+        self.add_c_function(MetaMethod::ToString, meta_tostring::<T>)
+    }
+
+    /// Writes the methods and metamethods in a metatable.
+    pub fn apply<'lua, L>(self, mut metatable: LuaTable<L>)
+    where
+        L: AsMutLua<'lua>,
+    {
+        unsafe {
+            let lua = metatable.as_mut_lua().0;
+            let index = ffi::lua_absindex(lua, metatable.offset(0));
+
+            for (name, push) in self.meta {
+                push(lua);
+                ffi::lua_setfield(lua, index, cstr(name).as_ptr() as *const _);
+            }
+
+            let has_index = !self.getters.is_empty() || !self.methods.is_empty();
+            let has_newindex = !self.setters.is_empty();
+
+            // The getters are needed by `__newindex` as well, to detect read-only fields.
+            push_table(lua, self.getters);
+            let getters = ffi::lua_gettop(lua);
+
+            if has_index {
+                ffi::lua_pushvalue(lua, getters);
+                push_table(lua, self.methods);
+                ffi::lua_getfield(lua, index, b"__index\0".as_ptr() as *const _);
+                push_str(lua, type_name::<T>());
+                ffi::lua_pushcclosure(lua, field_index, 4);
+                ffi::lua_setfield(lua, index, b"__index\0".as_ptr() as *const _);
+            }
+
+            if has_newindex {
+                push_table(lua, self.setters);
+                ffi::lua_pushvalue(lua, getters);
+                ffi::lua_getfield(lua, index, b"__newindex\0".as_ptr() as *const _);
+                push_str(lua, type_name::<T>());
+                ffi::lua_pushcclosure(lua, field_newindex, 4);
+                ffi::lua_setfield(lua, index, b"__newindex\0".as_ptr() as *const _);
+            }
+
+            ffi::lua_pop(lua, 1);
+        }
+    }
+
+    #[inline]
+    fn add_c_function(
+        mut self,
+        method: MetaMethod,
+        function: extern "C" fn(*mut ffi::lua_State) -> libc::c_int,
+    ) -> UserdataMethods<T> {
+        self.meta.push((
+            method.name(),
+            Box::new(move |lua| unsafe { ffi::lua_pushcfunction(lua, function) }),
+        ));
+        self
+    }
+}
+
+impl<T> Default for UserdataMethods<T>
+where
+    T: Send + 'static + Any,
+{
+    #[inline]
+    fn default() -> UserdataMethods<T> {
+        UserdataMethods::new()
+    }
+}
+
+impl<T> fmt::Debug for UserdataMethods<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("UserdataMethods")
+            .field("meta", &self.meta.iter().map(|m| m.0).collect::<Vec<_>>())
+            .field(
+                "methods",
+                &self.methods.iter().map(|m| &m.0).collect::<Vec<_>>(),
+            )
+            .field(
+                "getters",
+                &self.getters.iter().map(|m| &m.0).collect::<Vec<_>>(),
+            )
+            .field(
+                "setters",
+                &self.setters.iter().map(|m| &m.0).collect::<Vec<_>>(),
+            )
+            .finish()
+    }
+}
+
+fn pusher<V, E>(value: V) -> Pusher
+where
+    V: for<'a> PushOne<&'a mut Lua<'static>, Err = E> + 'static,
+    E: Into<Void>,
+{
+    Box::new(move |lua| unsafe {
+        let mut context = Lua::from_existing_state(lua, false);
+        value.push_no_err(&mut context).forget_internal();
+    })
+}
+
+// Pushes a table that contains the values of `entries`.
+unsafe fn push_table(lua: *mut ffi::lua_State, entries: Vec<(String, Pusher)>) {
+    ffi::lua_createtable(lua, 0, entries.len() as libc::c_int);
+    for (name, push) in entries {
+        push(lua);
+        ffi::lua_setfield(lua, -2, cstr(&name).as_ptr() as *const _);
+    }
+}
+
+unsafe fn push_str(lua: *mut ffi::lua_State, s: &str) {
+    ffi::lua_pushlstring(lua, s.as_ptr() as *const _, s.len() as libc::size_t);
+}
+
+// Returns the key at `index` and the type name in the upvalue `name_upvalue`, for error messages.
+unsafe fn describe_key(
+    lua: *mut ffi::lua_State,
+    index: i32,
+    name_upvalue: i32,
+) -> (String, String) {
+    let key = CStr::from_ptr(ffi::luaL_tolstring(lua, index, ptr::null_mut()))
+        .to_string_lossy()
+        .into_owned();
+    ffi::lua_pop(lua, 1);
+    let name = CStr::from_ptr(ffi::lua_tostring(lua, ffi::lua_upvalueindex(name_upvalue)))
+        .to_string_lossy()
+        .into_owned();
+    (key, name)
+}
+
+// Generated `__index` metamethod. The upvalues are the table of getters, the table of methods,
+// the `__index` metamethod to fall back to, and the name of the type.
+extern "C" fn field_index(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        ffi::lua_settop(lua, 2);
+
+        ffi::lua_pushvalue(lua, 2);
+        ffi::lua_rawget(lua, ffi::lua_upvalueindex(1));
+        if !ffi::lua_isnil(lua, -1) {
+            ffi::lua_pushvalue(lua, 1);
+            ffi::lua_call(lua, 1, 1);
+            return 1;
+        }
+        ffi::lua_pop(lua, 1);
+
+        ffi::lua_pushvalue(lua, 2);
+        ffi::lua_rawget(lua, ffi::lua_upvalueindex(2));
+        if !ffi::lua_isnil(lua, -1) {
+            return 1;
+        }
+        ffi::lua_pop(lua, 1);
+
+        if ffi::lua_isfunction(lua, ffi::lua_upvalueindex(3)) {
+            ffi::lua_pushvalue(lua, ffi::lua_upvalueindex(3));
+            ffi::lua_insert(lua, 1);
+            ffi::lua_call(lua, 2, 1);
+            return 1;
+        }
+        if !ffi::lua_isnil(lua, ffi::lua_upvalueindex(3)) {
+            ffi::lua_gettable(lua, ffi::lua_upvalueindex(3));
+            return 1;
+        }
+
+        let msg = {
+            let (key, name) = describe_key(lua, 2, 4);
+            format!("no field or method '{}' in {}", key, name)
+        };
+        raise(lua, msg)
+    }
+}
+
+// Generated `__newindex` metamethod. The upvalues are the table of setters, the table of getters,
+// the `__newindex` metamethod to fall back to, and the name of the type.
+extern "C" fn field_newindex(lua: *mut ffi::lua_State) -> libc::c_int {
+    unsafe {
+        ffi::lua_settop(lua, 3);
+
+        ffi::lua_pushvalue(lua, 2);
+        ffi::lua_rawget(lua, ffi::lua_upvalueindex(1));
+        if !ffi::lua_isnil(lua, -1) {
+            ffi::lua_pushvalue(lua, 1);
+            ffi::lua_pushvalue(lua, 3);
+            ffi::lua_call(lua, 2, 0);
+            return 0;
+        }
+        ffi::lua_pop(lua, 1);
+
+        if ffi::lua_isfunction(lua, ffi::lua_upvalueindex(3)) {
+            ffi::lua_pushvalue(lua, ffi::lua_upvalueindex(3));
+            ffi::lua_insert(lua, 1);
+            ffi::lua_call(lua, 3, 0);
+            return 0;
+        }
+        if !ffi::lua_isnil(lua, ffi::lua_upvalueindex(3)) {
+            ffi::lua_settable(lua, ffi::lua_upvalueindex(3));
+            return 0;
+        }
+
+        ffi::lua_pushvalue(lua, 2);
+        ffi::lua_rawget(lua, ffi::lua_upvalueindex(2));
+        let read_only = !ffi::lua_isnil(lua, -1);
+        ffi::lua_pop(lua, 1);
+
+        let msg = {
+            let (key, name) = describe_key(lua, 2, 4);
+            if read_only {
+                format!("field '{}' of {} is read-only", key, name)
+            } else {
+                format!("no field '{}' in {}", key, name)
+            }
+        };
+        raise(lua, msg)
+    }
+}
+
+// Lua expects C strings, so names are truncated at the first nul character.
+fn cstr(name: &str) -> Vec<u8> {
+    let mut name = name.split('\0').next().unwrap().as_bytes().to_vec();
+    name.push(0);
+    name
+}
+
+// Borrows the operand of type `T` at `index` until the returned guard is dropped, or returns a
+// description of the problem. The guards must be dropped before raising an error.
+unsafe fn operand<T>(lua: *mut ffi::lua_State, index: i32) -> Result<UserdataRef<T>, String>
+where
+    T: Any,
+{
+    match UserdataRef::new(lua, index) {
+        Ok(Some(data)) => Ok(data),
+        Ok(None) => {
+            let got = CStr::from_ptr(ffi::luaL_typename(lua, index)).to_string_lossy();
+            Err(format!(
+                "bad argument #{} ({} expected, got {})",
+                index,
+                type_name::<T>(),
+                got
+            ))
+        }
+        Err(err) => Err(format!("bad argument #{}: {}", index, err)),
+    }
+}
+
+// Pushes the result of a metamethod that returns a user data.
+unsafe fn push_result<T>(lua: *mut ffi::lua_State, value: T) -> libc::c_int
+where
+    T: Send + 'static + Any,
+{
+    let mut context = Lua::from_existing_state(lua, false);
+    // The metatable of `T` exists, since the operand has it.
+    push_userdata(value, &mut context, |_| {}).forget_internal();
+    1
+}
+
+macro_rules! binary_op {
+    ($name:ident, $tr:ident, $method:ident) => {
+        extern "C" fn $name<T>(lua: *mut ffi::lua_State) -> libc::c_int
+        where
+            T: $tr<Output = T> + Clone + Send + 'static + Any,
+        {
+            unsafe {
+                let result = operand::<T>(lua, 1)
+                    .and_then(|a| operand::<T>(lua, 2).map(|b| a.clone().$method(b.clone())));
+                match result {
+                    Ok(value) => push_result(lua, value),
+                    Err(msg) => raise(lua, msg),
+                }
+            }
+        }
+    };
+}
+
+binary_op!(meta_add, Add, add);
+binary_op!(meta_sub, Sub, sub);
+binary_op!(meta_mul, Mul, mul);
+binary_op!(meta_div, Div, div);
+binary_op!(meta_rem, Rem, rem);
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+binary_op!(meta_bitand, BitAnd, bitand);
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+binary_op!(meta_bitor, BitOr, bitor);
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+binary_op!(meta_bitxor, BitXor, bitxor);
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+binary_op!(meta_shl, Shl, shl);
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+binary_op!(meta_shr, Shr, shr);
+
+extern "C" fn meta_neg<T>(lua: *mut ffi::lua_State) -> libc::c_int
+where
+    T: Neg<Output = T> + Clone + Send + 'static + Any,
+{
+    unsafe {
+        match operand::<T>(lua, 1).map(|a| -a.clone()) {
+            Ok(value) => push_result(lua, value),
+            Err(msg) => raise(lua, msg),
+        }
+    }
+}
+
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+extern "C" fn meta_not<T>(lua: *mut ffi::lua_State) -> libc::c_int
+where
+    T: Not<Output = T> + Clone + Send + 'static + Any,
+{
+    unsafe {
+        match operand::<T>(lua, 1).map(|a| !a.clone()) {
+            Ok(value) => push_result(lua, value),
+            Err(msg) => raise(lua, msg),
+        }
+    }
+}
+
+macro_rules! comparison {
+    ($name:ident, $tr:ident, $op:tt) => {
+        extern "C" fn $name<T>(lua: *mut ffi::lua_State) -> libc::c_int
+        where
+            T: $tr + Any,
+        {
+            unsafe {
+                let result = operand::<T>(lua, 1).and_then(|a| operand::<T>(lua, 2).map(|b| *a $op *b));
+                match result {
+                    Ok(value) => {
+                        ffi::lua_pushboolean(lua, value as libc::c_int);
+                        1
+                    }
+                    Err(msg) => raise(lua, msg),
+                }
+            }
+        }
+    };
+}
+
+comparison!(meta_eq, PartialEq, ==);
+comparison!(meta_lt, PartialOrd, <);
+comparison!(meta_le, PartialOrd, <=);
+
+extern "C" fn meta_tostring<T>(lua: *mut ffi::lua_State) -> libc::c_int
+where
+    T: fmt::Display + Any,
+{
+    unsafe {
+        match operand::<T>(lua, 1).map(|a| a.to_string()) {
+            Ok(s) => {
+                ffi::lua_pushlstring(lua, s.as_ptr() as *const _, s.len() as libc::size_t);
+                1
+            }
+            Err(msg) => raise(lua, msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+    use std::ops::{Add, Div, Mul, Neg, Rem, Sub};
+
+    use function0;
+    use function1;
+    use function2;
+    use function3;
+    use AsMutLua;
+    use Lua;
+    use LuaError;
+    use MetaMethod;
+    use Push;
+    use PushGuard;
+    use PushOne;
+    use UserdataMethods;
+    use UserdataMut;
+    use UserdataOnStack;
+    use UserdataRef;
+    use Void;
+
+    #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+    struct Num(i32);
+
+    macro_rules! num_op {
+        ($tr:ident, $method:ident, $op:tt) => {
+            num_op!($tr, $method, Num, $op);
+        };
+        ($tr:ident, $method:ident, $ty:ident, $op:tt) => {
+            impl $tr for $ty {
+                type Output = $ty;
+                fn $method(self, other: $ty) -> $ty {
+                    $ty(self.0 $op other.0)
+                }
+            }
+        };
+    }
+
+    num_op!(Add, add, +);
+    num_op!(Sub, sub, -);
+    num_op!(Mul, mul, *);
+    num_op!(Div, div, /);
+    num_op!(Rem, rem, %);
+
+    impl Neg for Num {
+        type Output = Num;
+        fn neg(self) -> Num {
+            Num(-self.0)
+        }
+    }
+
+    impl fmt::Display for Num {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "Num({})", self.0)
+        }
+    }
+
+    impl<'lua, L> Push<L> for Num
+    where
+        L: AsMutLua<'lua>,
+    {
+        type Err = Void;
+        fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+            Ok(::push_userdata(self, lua, |metatable| {
+                UserdataMethods::<Num>::new()
+                    .with_add()
+                    .with_sub()
+                    .with_mul()
+                    .with_div()
+                    .with_rem()
+                    .with_neg()
+                    .with_partial_eq()
+                    .with_partial_ord()
+                    .with_display()
+                    .add_method("set", function2(|mut n: UserdataMut<Num>, v: i32| n.0 = v))
+                    .apply(metatable)
+            }))
+        }
+    }
+    impl<'lua, L> PushOne<L> for Num where L: AsMutLua<'lua> {}
+
+    fn lua() -> Lua<'static> {
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set("a", Num(7));
+        lua.set("b", Num(2));
+        lua
+    }
+
+    #[test]
+    fn operators_from_traits() {
+        let mut lua = lua();
+        let s: String = lua
+            .execute(
+                "return tostring(a + b) .. tostring(a - b) .. tostring(a * b) .. tostring(a / b) \
+                 .. tostring(a % b) .. tostring(-a)",
+            )
+            .unwrap();
+        assert_eq!(s, "Num(9)Num(5)Num(14)Num(3)Num(1)Num(-7)");
+
+        lua.set("five", Num(5));
+        let cmp: String = lua
+            .execute(
+                "return tostring(a == b) .. tostring(a ~= b) .. tostring(a == b + five) .. \
+                 tostring(a < b) .. tostring(a > b) .. tostring(a <= a) .. tostring(b >= a)",
+            )
+            .unwrap();
+        assert_eq!(cmp, "falsetruetruefalsetruetruefalse");
+    }
+
+    #[test]
+    fn operators_release_operands() {
+        let mut lua = lua();
+        let s: String = lua
+            .execute("local c = a + a a:set(3) return tostring(a) .. tostring(a < c)")
+            .unwrap();
+        assert_eq!(s, "Num(3)true");
+    }
+
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    #[test]
+    fn bitwise_operators() {
+        use std::ops::{BitAnd, BitOr, BitXor, Not, Shl, Shr};
+
+        #[derive(Clone, Copy)]
+        struct Bits(u8);
+
+        num_op!(BitAnd, bitand, Bits, &);
+        num_op!(BitOr, bitor, Bits, |);
+        num_op!(BitXor, bitxor, Bits, ^);
+        num_op!(Shl, shl, Bits, <<);
+        num_op!(Shr, shr, Bits, >>);
+        num_op!(Div, div, Bits, /);
+
+        impl Not for Bits {
+            type Output = Bits;
+            fn not(self) -> Bits {
+                Bits(!self.0)
+            }
+        }
+
+        impl fmt::Display for Bits {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{:08b}", self.0)
+            }
+        }
+
+        impl<'lua, L> Push<L> for Bits
+        where
+            L: AsMutLua<'lua>,
+        {
+            type Err = Void;
+            fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+                Ok(::push_userdata(self, lua, |metatable| {
+                    UserdataMethods::<Bits>::new()
+                        .with_idiv()
+                        .with_bitand()
+                        .with_bitor()
+                        .with_bitxor()
+                        .with_shl()
+                        .with_shr()
+                        .with_not()
+                        .with_display()
+                        .apply(metatable)
+                }))
+            }
+        }
+        impl<'lua, L> PushOne<L> for Bits where L: AsMutLua<'lua> {}
+
+        let mut lua = Lua::new();
+        lua.openlibs();
+        lua.set("a", Bits(0b1100));
+        lua.set("b", Bits(0b1010));
+        lua.set("one", Bits(1));
+        let s: String = lua
+            .execute(
+                "return table.concat({tostring(a & b), tostring(a | b), tostring(a ~ b),                  tostring(a << one), tostring(a >> one), tostring(~a), tostring(a // b)}, ' ')",
+            )
+            .unwrap();
+        assert_eq!(
+            s,
+            "00001000 00001110 00000110 00011000 00000110 11110011 00000001"
+        );
+    }
+
+    #[test]
+    fn wrong_operand() {
+        let mut lua = lua();
+        match lua.execute::<()>("return a + 1") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("bad argument #2 ("), "{}", msg);
+                assert!(msg.contains("Num expected, got number)"), "{}", msg);
+            }
+            _ => panic!(),
+        }
+    }
+
+    #[test]
+    fn manual_meta_methods() {
+        #[derive(Clone)]
+        struct List;
+
+        impl<'lua, L> Push<L> for List
+        where
+            L: AsMutLua<'lua>,
+        {
+            type Err = Void;
+            fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+                Ok(::push_userdata(self, lua, |metatable| {
+                    UserdataMethods::<List>::new()
+                        .add_meta_method(MetaMethod::Len, function0(|| 3))
+                        .add_meta_method(
+                            MetaMethod::Concat,
+                            function2(|_: ::AnyLuaValue, s: String| format!("list..{}", s)),
+                        )
+                        .add_meta_method(
+                            MetaMethod::Call,
+                            function2(|_: ::AnyLuaValue, x: i32| x * 2),
+                        )
+                        .add_meta_method(
+                            MetaMethod::Index,
+                            function2(|_: ::AnyLuaValue, k: String| format!("field {}", k)),
+                        )
+                        .add_method("first", function1(|_: ::AnyLuaValue| 1))
+                        .apply(metatable)
+                }))
+            }
+        }
+        impl<'lua, L> PushOne<L> for List where L: AsMutLua<'lua> {}
+
+        let mut lua = Lua::new();
+        lua.set("l", List);
+        let s: String = lua
+            .execute("return #l .. ' ' .. (l .. 'x') .. ' ' .. l(21) .. ' ' .. l:first() .. ' ' .. l.other")
+            .unwrap();
+        assert_eq!(s, "3 list..x 42 1 field other");
+    }
+
+    #[test]
+    fn fields() {
+        struct Sound {
+            volume: f64,
+            length: i32,
+        }
+
+        impl<'lua, L> Push<L> for Sound
+        where
+            L: AsMutLua<'lua>,
+        {
+            type Err = Void;
+            fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+                Ok(::push_userdata(self, lua, |metatable| {
+                    UserdataMethods::<Sound>::new()
+                        .add_field_getter("volume", function1(|s: UserdataRef<Sound>| s.volume))
+                        .add_field_setter(
+                            "volume",
+                            function2(|mut s: UserdataMut<Sound>, v: f64| s.volume = v),
+                        )
+                        .add_field_getter("length", function1(|s: UserdataRef<Sound>| s.length))
+                        .add_method(
+                            "length_twice",
+                            function1(|s: UserdataRef<Sound>| s.length * 2),
+                        )
+                        // Fields come before methods.
+                        .add_method("volume", function0(|| 0))
+                        .apply(metatable)
+                }))
+            }
+        }
+        impl<'lua, L> PushOne<L> for Sound where L: AsMutLua<'lua> {}
+
+        let mut lua = Lua::new();
+        lua.set(
+            "s",
+            Sound {
+                volume: 0.25,
+                length: 12,
+            },
+        );
+
+        let s: String = lua
+            .execute(
+                "local v = s.volume s.volume = 0.5 \
+                 return v .. ' ' .. s.volume .. ' ' .. s.length .. ' ' .. s:length_twice()",
+            )
+            .unwrap();
+        assert_eq!(s, "0.25 0.5 12 24");
+        {
+            let sound: UserdataOnStack<Sound, _> = lua.get("s").unwrap();
+            assert_eq!(sound.volume, 0.5);
+            assert_eq!(sound.length, 12);
+        }
+
+        match lua.execute::<()>("s.length = 3") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("field 'length' of "), "{}", msg);
+                assert!(msg.contains("Sound is read-only"), "{}", msg);
+            }
+            _ => panic!(),
+        }
+        match lua.execute::<()>("s.pitch = 3") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("no field 'pitch' in "), "{}", msg)
+            }
+            _ => panic!(),
+        }
+        match lua.execute::<()>("return s.pitch") {
+            Err(LuaError::ExecutionError(msg)) => {
+                assert!(msg.contains("no field or method 'pitch' in "), "{}", msg)
+            }
+            _ => panic!(),
+        }
+        let sound: UserdataOnStack<Sound, _> = lua.get("s").unwrap();
+        assert_eq!(sound.length, 12);
+    }
+
+    #[test]
+    fn fields_fall_back_to_meta_methods() {
+        struct Bag {
+            size: i32,
+            others: Vec<(String, i32)>,
+        }
+
+        impl<'lua, L> Push<L> for Bag
+        where
+            L: AsMutLua<'lua>,
+        {
+            type Err = Void;
+            fn push_to_lua(self, lua: L) -> Result<PushGuard<L>, (Void, L)> {
+                Ok(::push_userdata(self, lua, |metatable| {
+                    UserdataMethods::<Bag>::new()
+                        .add_field_getter("size", function1(|b: UserdataRef<Bag>| b.size))
+                        .add_field_setter(
+                            "size",
+                            function2(|mut b: UserdataMut<Bag>, v: i32| b.size = v),
+                        )
+                        .add_meta_method(
+                            MetaMethod::Index,
+                            function2(|_: ::AnyLuaValue, k: String| format!("dynamic {}", k)),
+                        )
+                        .add_meta_method(
+                            MetaMethod::NewIndex,
+                            function3(|mut b: UserdataMut<Bag>, k: String, v: i32| {
+                                b.others.push((k, v))
+                            }),
+                        )
+                        .apply(metatable)
+                }))
+            }
+        }
+        impl<'lua, L> PushOne<L> for Bag where L: AsMutLua<'lua> {}
+
+        let mut lua = Lua::new();
+        lua.set(
+            "b",
+            Bag {
+                size: 1,
+                others: Vec::new(),
+            },
+        );
+        let s: String = lua
+            .execute("b.size = 2 b.other = 3 return b.size .. ' ' .. b.other")
+            .unwrap();
+        assert_eq!(s, "2 dynamic other");
+
+        let bag: UserdataOnStack<Bag, _> = lua.get("b").unwrap();
+        assert_eq!(bag.size, 2);
+        assert_eq!(bag.others, vec![("other".to_owned(), 3)]);
+    }
+}
@@ -51,7 +51,9 @@ macro_rules! integer_impl(
 integer_impl!(i8);
 integer_impl!(i16);
 integer_impl!(i32);
-// integer_impl!(i64)   // data loss
+// Lua 5.3 and later have 64-bit integers. Lua 5.2 and LuaJIT would lose data.
+#[cfg(any(feature = "lua53", feature = "lua54"))]
+integer_impl!(i64);
 
 macro_rules! unsigned_impl(
     ($t:ident) => (
@@ -446,6 +448,27 @@ mod tests {
         assert_eq!(x, 2);
     }
 
+    #[test]
+    #[cfg(any(feature = "lua53", feature = "lua54"))]
+    fn readwrite_native_integers() {
+        let mut lua = Lua::new();
+        lua.openlibs();
+
+        lua.set("a", i64::MAX);
+        let x: i64 = lua.get("a").unwrap();
+        assert_eq!(x, i64::MAX);
+        assert_eq!(
+            lua.execute::<String>("return math.type(a)").unwrap(),
+            "integer"
+        );
+
+        // Floats are only read as integers if they have an exact integer value.
+        lua.set("b", 3.0);
+        lua.set("c", 3.5);
+        assert_eq!(lua.get::<i32, _>("b"), Some(3));
+        assert_eq!(lua.get::<i32, _>("c"), None);
+    }
+
     #[test]
     fn readwrite_floats() {
         let mut lua = Lua::new();
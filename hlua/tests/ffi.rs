@@ -9,7 +9,10 @@ fn get_version() {
     let lua = hlua::Lua::new();
     let state_ptr = lua.as_lua().state_ptr();
 
+    #[cfg(feature = "lua54")]
+    let version = unsafe { hlua::ffi::lua_version(state_ptr) as i32 };
+    #[cfg(not(feature = "lua54"))]
     let version = unsafe { *hlua::ffi::lua_version(state_ptr) as i32 };
-    // 502 = Lua 5.2
-    assert_eq!(version, 502);
+    // 502 = Lua 5.2, 503 = Lua 5.3, 504 = Lua 5.4
+    assert_eq!(version, hlua::ffi::LUA_VERSION_NUM);
 }
@@ -0,0 +1,43 @@
+#[macro_use]
+extern crate hlua;
+
+#[test]
+fn embedded_scripts() {
+    let mut lua = hlua::Lua::new_sandboxed();
+    lua.set_script_source(include_lua!("scripts/mygame/audio.lua"));
+
+    let msg: String = lua
+        .execute("return dofile('scripts/mygame/audio.lua').play('jump')")
+        .unwrap();
+    assert_eq!(msg, "playing jump");
+
+    // Chunks are named after the path as written.
+    match lua.execute::<()>("dofile('scripts/mygame/audio.lua').fail()") {
+        Err(hlua::LuaError::ExecutionError(msg)) => {
+            assert_eq!(msg, "scripts/mygame/audio.lua:11: no sound card")
+        }
+        _ => panic!(),
+    }
+}
+
+#[test]
+fn embedded_scripts_with_root() {
+    let mut lua = hlua::Lua::new();
+    lua.openlibs();
+    lua.set_script_source(include_lua!(
+        root = concat!(env!("CARGO_MANIFEST_DIR"), "/tests/scripts"),
+        "init.lua",
+        "mygame/audio.lua",
+    ));
+
+    let msg: String = lua.execute("return require 'init'").unwrap();
+    assert_eq!(msg, "playing jump at 5");
+
+    // Chunks are named after the path relative to the root.
+    match lua.execute::<()>("require('mygame.audio').fail()") {
+        Err(hlua::LuaError::ExecutionError(msg)) => {
+            assert_eq!(msg, "mygame/audio.lua:11: no sound card")
+        }
+        _ => panic!(),
+    }
+}
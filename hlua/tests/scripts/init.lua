@@ -0,0 +1,3 @@
+-- Entry point of the test scripts.
+local audio = require 'mygame.audio'
+return audio.play('jump') .. ' at ' .. audio.volume
@@ -0,0 +1,14 @@
+#!/usr/bin/env lua
+local audio = {}
+
+audio.volume = 5
+
+function audio.play(sound)
+    return 'playing ' .. sound
+end
+
+function audio.fail()
+    error('no sound card')
+end
+
+return audio
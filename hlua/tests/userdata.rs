@@ -1,3 +1,4 @@
+#[macro_use]
 extern crate hlua;
 
 #[test]
@@ -275,3 +276,235 @@ fn multiple_userdata() {
         collapse(19.25, Integer(96), big_integer.clone())
     );
 }
+
+#[test]
+fn shared_metatable() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    static CALLS: AtomicUsize = AtomicUsize::new(0);
+
+    struct Foo;
+    impl<'lua, L> hlua::Push<L> for Foo
+    where
+        L: hlua::AsMutLua<'lua>,
+    {
+        type Err = hlua::Void;
+        fn push_to_lua(self, lua: L) -> Result<hlua::PushGuard<L>, (hlua::Void, L)> {
+            Ok(hlua::push_userdata(self, lua, |mut table| {
+                CALLS.fetch_add(1, Ordering::SeqCst);
+                table.set("__index", vec![("test", hlua::function0(|| 5))]);
+            }))
+        }
+    }
+    impl<'lua, L> hlua::PushOne<L> for Foo where L: hlua::AsMutLua<'lua> {}
+
+    let mut lua = hlua::Lua::new();
+    lua.open_base();
+    lua.set("a", Foo);
+    lua.set("b", Foo);
+    lua.set("c", Foo);
+    assert_eq!(CALLS.load(Ordering::SeqCst), 1);
+
+    let same: bool = lua
+        .execute("return getmetatable(a) == getmetatable(b) and getmetatable(b) == getmetatable(c)")
+        .unwrap();
+    assert!(same);
+    let x: i32 = lua.execute("return c.test()").unwrap();
+    assert_eq!(x, 5);
+
+    // Each context has its own metatables.
+    let mut other = hlua::Lua::new();
+    other.set("a", Foo);
+    assert_eq!(CALLS.load(Ordering::SeqCst), 2);
+}
+
+#[test]
+fn register_userdata() {
+    struct Foo;
+    impl<'lua, L> hlua::Push<L> for Foo
+    where
+        L: hlua::AsMutLua<'lua>,
+    {
+        type Err = hlua::Void;
+        fn push_to_lua(self, lua: L) -> Result<hlua::PushGuard<L>, (hlua::Void, L)> {
+            Ok(hlua::push_userdata(self, lua, |_| {
+                panic!("the metatable is registered")
+            }))
+        }
+    }
+    impl<'lua, L> hlua::PushOne<L> for Foo where L: hlua::AsMutLua<'lua> {}
+
+    let mut lua = hlua::Lua::new();
+    lua.register_userdata::<Foo, _>(|mut metatable| {
+        metatable.set("__index", vec![("test", hlua::function0(|| 5))]);
+    });
+
+    lua.set("a", Foo);
+    let x: i32 = lua.execute("return a.test()").unwrap();
+    assert_eq!(x, 5);
+
+    // Registering again modifies the metatable of the existing objects.
+    lua.register_userdata::<Foo, _>(|mut metatable| {
+        metatable.set("__len", hlua::function0(|| 12));
+    });
+    let len: i32 = lua.execute("return #a").unwrap();
+    assert_eq!(len, 12);
+}
+
+#[test]
+fn registered_metatable_drops_objects() {
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    static DROPPED: AtomicBool = AtomicBool::new(false);
+
+    struct Foo;
+    impl Drop for Foo {
+        fn drop(&mut self) {
+            DROPPED.store(true, Ordering::SeqCst);
+        }
+    }
+    impl<'lua, L> hlua::Push<L> for Foo
+    where
+        L: hlua::AsMutLua<'lua>,
+    {
+        type Err = hlua::Void;
+        fn push_to_lua(self, lua: L) -> Result<hlua::PushGuard<L>, (hlua::Void, L)> {
+            Ok(hlua::push_userdata(self, lua, |_| {}))
+        }
+    }
+    impl<'lua, L> hlua::PushOne<L> for Foo where L: hlua::AsMutLua<'lua> {}
+
+    {
+        let mut lua = hlua::Lua::new();
+        lua.register_userdata::<Foo, _>(|_| {});
+        lua.set("a", Foo);
+        assert!(!DROPPED.load(Ordering::SeqCst));
+    }
+    assert!(DROPPED.load(Ordering::SeqCst));
+}
+
+#[test]
+fn aliasing_borrows() {
+    use hlua::{UserdataMut, UserdataRef};
+
+    struct Counter(i32);
+    implement_lua_push!(Counter, |_| {});
+
+    let mut lua = hlua::Lua::new();
+    lua.open_base();
+    lua.set("a", Counter(1));
+    lua.set("b", Counter(2));
+    lua.set(
+        "add_to",
+        hlua::function2(|mut dst: UserdataMut<Counter>, src: UserdataMut<Counter>| {
+            dst.0 += src.0;
+            dst.0
+        }),
+    );
+    lua.set(
+        "sum",
+        hlua::function2(|x: UserdataRef<Counter>, y: UserdataRef<Counter>| x.0 + y.0),
+    );
+    lua.set(
+        "copy",
+        hlua::function2(|mut dst: UserdataMut<Counter>, src: UserdataRef<Counter>| dst.0 = src.0),
+    );
+
+    let v: i32 = lua.execute("return add_to(a, b)").unwrap();
+    assert_eq!(v, 3);
+
+    // Immutable borrows can alias.
+    let v: i32 = lua.execute("return sum(a, a)").unwrap();
+    assert_eq!(v, 6);
+
+    match lua.execute::<i32>("return add_to(a, a)") {
+        Err(hlua::LuaError::ExecutionError(msg)) => {
+            assert!(
+                msg.contains("bad argument #2: already mutably borrowed"),
+                "{}",
+                msg
+            )
+        }
+        _ => panic!(),
+    }
+    match lua.execute::<()>("copy(b, b)") {
+        Err(hlua::LuaError::ExecutionError(msg)) => {
+            assert!(
+                msg.contains("bad argument #2: already mutably borrowed"),
+                "{}",
+                msg
+            )
+        }
+        _ => panic!(),
+    }
+
+    // The borrows are released after the calls, including the failed ones.
+    let v: i32 = lua.execute("return add_to(b, a)").unwrap();
+    assert_eq!(v, 5);
+}
+
+#[test]
+fn borrows_released_on_wrong_parameters() {
+    use hlua::UserdataMut;
+
+    struct Counter(i32);
+    implement_lua_push!(Counter, |_| {});
+
+    let mut lua = hlua::Lua::new();
+    lua.set("a", Counter(1));
+    lua.set(
+        "add",
+        hlua::function2(|mut c: UserdataMut<Counter>, n: i32| {
+            c.0 += n;
+            c.0
+        }),
+    );
+
+    // The first parameter is borrowed before the second one fails to be read.
+    match lua.execute::<i32>("return add(a, {})") {
+        Err(hlua::LuaError::ExecutionError(msg)) => {
+            assert!(msg.contains("wrong parameter types"), "{}", msg)
+        }
+        _ => panic!(),
+    }
+
+    let v: i32 = lua.execute("return add(a, 2)").unwrap();
+    assert_eq!(v, 3);
+}
+
+#[test]
+fn borrows_kept_after_the_callback() {
+    use hlua::{UserdataMut, UserdataRef};
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct Counter(i32);
+    implement_lua_push!(Counter, |_| {});
+
+    let kept = Rc::new(RefCell::new(None));
+    let mut lua = hlua::Lua::new();
+    lua.set("a", Counter(1));
+    {
+        let kept = kept.clone();
+        lua.set(
+            "keep",
+            hlua::function1(move |c: UserdataRef<Counter>| *kept.borrow_mut() = Some(c)),
+        );
+    }
+    lua.set(
+        "incr",
+        hlua::function1(|mut c: UserdataMut<Counter>| c.0 += 1),
+    );
+
+    lua.execute::<()>("keep(a)").unwrap();
+    assert_eq!(kept.borrow().as_ref().unwrap().0, 1);
+    match lua.execute::<()>("incr(a)") {
+        Err(hlua::LuaError::ExecutionError(msg)) => {
+            assert!(msg.contains("bad argument #1: already borrowed"), "{}", msg)
+        }
+        _ => panic!(),
+    }
+
+    *kept.borrow_mut() = None;
+    lua.execute::<()>("incr(a)").unwrap();
+}
@@ -1,3 +1,16 @@
+//! Bindings for Lua 5.2.
+//!
+//! By default, the crate links to the Lua 5.2 of the system if pkg-config finds it, and builds
+//! the bundled sources otherwise. The `system` and `vendored` features force one or the other. The
+//! `LUA52_SYS_SOURCE` environment variable, set to `system`, `vendored` or `auto`, overrides the
+//! features.
+//!
+//! When the bundled sources are built, the `LUA52_SYS_DEFINES` environment variable can contain
+//! additional defines for `luaconf.h`, separated by spaces or commas, such as
+//! `LUA_USE_APICHECK LUAI_MAXCCALLS=400`. The `apicheck` feature enables the checks of the Lua API
+//! and the internal assertions of Lua in the bundled sources, which abort the process when the
+//! API is misused.
+
 #![allow(non_camel_case_types)]
 #![allow(non_snake_case)]
 #![allow(dead_code)]
@@ -8,6 +21,10 @@ extern crate libc;
 use libc::c_int;
 use std::{default, ptr};
 
+include!(concat!(env!("OUT_DIR"), "/version.rs"));
+
+pub const LUA_VERSION_NUM: c_int = 502;
+
 pub const MULTRET: c_int = -1;
 
 pub const LUAI_MAXSTACK: c_int = 1000000; // or 15000 with 32b        // TODO:
@@ -21,6 +38,7 @@ pub const LUA_ERRSYNTAX: c_int = 3;
 pub const LUA_ERRMEM: c_int = 4;
 pub const LUA_ERRGCMM: c_int = 5;
 pub const LUA_ERRERR: c_int = 6;
+pub const LUA_ERRFILE: c_int = LUA_ERRERR + 1;
 
 #[repr(C)]
 #[allow(missing_copy_implementations)]
@@ -71,7 +89,7 @@ pub const LUA_NOREF: c_int = -2;
 
 pub type lua_Number = libc::c_double;
 pub type lua_Integer = libc::ptrdiff_t;
-pub type lua_Unsigned = libc::c_ulong;
+pub type lua_Unsigned = libc::c_uint;
 
 pub const LUA_OPADD: c_int = 0;
 pub const LUA_OPSUB: c_int = 1;
@@ -103,12 +121,15 @@ pub const LUA_HOOKRET: c_int = 1;
 pub const LUA_HOOKLINE: c_int = 2;
 pub const LUA_HOOKCOUNT: c_int = 3;
 pub const LUA_HOOKTAILRET: c_int = 4;
+pub const LUA_HOOKTAILCALL: c_int = 4;
 
 pub const LUA_MASKCALL: c_int = 1 << LUA_HOOKCALL as usize;
 pub const LUA_MASKRET: c_int = 1 << LUA_HOOKRET as usize;
 pub const LUA_MASKLINE: c_int = 1 << LUA_HOOKLINE as usize;
 pub const LUA_MASKCOUNT: c_int = 1 << LUA_HOOKCOUNT as usize;
 
+pub const LUA_IDSIZE: usize = 60;
+
 #[repr(C)]
 #[allow(missing_copy_implementations)]
 pub struct lua_Debug {
@@ -124,8 +145,42 @@ pub struct lua_Debug {
     pub nparams: libc::c_uchar,
     pub isvararg: libc::c_char,
     pub istailcall: libc::c_char,
-    pub short_src: [libc::c_char; 60],
-    //i_ci: *CallInfo
+    pub short_src: [libc::c_char; LUA_IDSIZE],
+    // private to Lua, but must be allocated because `lua_getstack` writes it
+    pub i_ci: *mut libc::c_void,
+}
+
+pub const LUA_FILEHANDLE: &[u8] = b"FILE*\0";
+
+/// Size of the buffer embedded in a `luaL_Buffer`, which is `BUFSIZ` in `luaconf.h`.
+pub const LUAL_BUFFERSIZE: usize = libc::BUFSIZ as usize;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct luaL_Reg {
+    pub name: *const libc::c_char,
+    pub func: Option<lua_CFunction>,
+}
+
+/// String buffer of the auxiliary library.
+///
+/// Until the buffer grows larger than `LUAL_BUFFERSIZE`, `b` points to `initb`, so a buffer must
+/// not be moved between `luaL_buffinit` and `luaL_pushresult`.
+#[repr(C)]
+#[allow(missing_copy_implementations)]
+pub struct luaL_Buffer {
+    pub b: *mut libc::c_char,
+    pub size: libc::size_t,
+    pub n: libc::size_t,
+    pub L: *mut lua_State,
+    pub initb: [libc::c_char; LUAL_BUFFERSIZE],
+}
+
+#[repr(C)]
+#[allow(missing_copy_implementations)]
+pub struct luaL_Stream {
+    pub f: *mut libc::FILE,
+    pub closef: Option<lua_CFunction>,
 }
 
 extern "C" {
@@ -263,15 +318,135 @@ extern "C" {
     pub fn lua_upvalueid(L: *mut lua_State, fidx: c_int, n: c_int) -> *const libc::c_void;
     pub fn lua_upvaluejoin(L: *mut lua_State, fidx1: c_int, n1: c_int, fidx2: c_int, n2: c_int);
 
-    pub fn lua_sethook(L: *mut lua_State, func: lua_Hook, mask: c_int, count: c_int) -> c_int;
-    pub fn lua_gethook(L: *mut lua_State) -> lua_Hook;
+    pub fn lua_sethook(
+        L: *mut lua_State,
+        func: Option<lua_Hook>,
+        mask: c_int,
+        count: c_int,
+    ) -> c_int;
+    pub fn lua_gethook(L: *mut lua_State) -> Option<lua_Hook>;
     pub fn lua_gethookmask(L: *mut lua_State) -> c_int;
     pub fn lua_gethookcount(L: *mut lua_State) -> c_int;
 
     pub fn luaL_openlibs(L: *mut lua_State);
+
+    pub fn luaL_checkversion_(L: *mut lua_State, ver: lua_Number);
+
+    pub fn luaL_getmetafield(L: *mut lua_State, obj: c_int, e: *const libc::c_char) -> c_int;
+    pub fn luaL_callmeta(L: *mut lua_State, obj: c_int, e: *const libc::c_char) -> c_int;
+    pub fn luaL_tolstring(
+        L: *mut lua_State,
+        idx: c_int,
+        len: *mut libc::size_t,
+    ) -> *const libc::c_char;
+    pub fn luaL_argerror(L: *mut lua_State, numarg: c_int, extramsg: *const libc::c_char) -> c_int;
+    pub fn luaL_checklstring(
+        L: *mut lua_State,
+        numArg: c_int,
+        l: *mut libc::size_t,
+    ) -> *const libc::c_char;
+    pub fn luaL_optlstring(
+        L: *mut lua_State,
+        numArg: c_int,
+        def: *const libc::c_char,
+        l: *mut libc::size_t,
+    ) -> *const libc::c_char;
+    pub fn luaL_checknumber(L: *mut lua_State, numArg: c_int) -> lua_Number;
+    pub fn luaL_optnumber(L: *mut lua_State, nArg: c_int, def: lua_Number) -> lua_Number;
+    pub fn luaL_checkinteger(L: *mut lua_State, numArg: c_int) -> lua_Integer;
+    pub fn luaL_optinteger(L: *mut lua_State, nArg: c_int, def: lua_Integer) -> lua_Integer;
+    pub fn luaL_checkunsigned(L: *mut lua_State, numArg: c_int) -> lua_Unsigned;
+    pub fn luaL_optunsigned(L: *mut lua_State, numArg: c_int, def: lua_Unsigned) -> lua_Unsigned;
+
+    pub fn luaL_checkstack(L: *mut lua_State, sz: c_int, msg: *const libc::c_char);
+    pub fn luaL_checktype(L: *mut lua_State, narg: c_int, t: c_int);
+    pub fn luaL_checkany(L: *mut lua_State, narg: c_int);
+
+    pub fn luaL_newmetatable(L: *mut lua_State, tname: *const libc::c_char) -> c_int;
+    pub fn luaL_setmetatable(L: *mut lua_State, tname: *const libc::c_char);
+    pub fn luaL_testudata(
+        L: *mut lua_State,
+        ud: c_int,
+        tname: *const libc::c_char,
+    ) -> *mut libc::c_void;
+    pub fn luaL_checkudata(
+        L: *mut lua_State,
+        ud: c_int,
+        tname: *const libc::c_char,
+    ) -> *mut libc::c_void;
+
+    pub fn luaL_where(L: *mut lua_State, lvl: c_int);
+    pub fn luaL_error(L: *mut lua_State, fmt: *const libc::c_char, ...) -> c_int;
+
+    pub fn luaL_checkoption(
+        L: *mut lua_State,
+        narg: c_int,
+        def: *const libc::c_char,
+        lst: *const *const libc::c_char,
+    ) -> c_int;
+
+    pub fn luaL_fileresult(L: *mut lua_State, stat: c_int, fname: *const libc::c_char) -> c_int;
+    pub fn luaL_execresult(L: *mut lua_State, stat: c_int) -> c_int;
+
     pub fn luaL_ref(L: *mut lua_State, idx: c_int) -> c_int;
     pub fn luaL_unref(L: *mut lua_State, idx: c_int, ref_id: c_int);
 
+    pub fn luaL_loadfilex(
+        L: *mut lua_State,
+        filename: *const libc::c_char,
+        mode: *const libc::c_char,
+    ) -> c_int;
+    pub fn luaL_loadbufferx(
+        L: *mut lua_State,
+        buff: *const libc::c_char,
+        sz: libc::size_t,
+        name: *const libc::c_char,
+        mode: *const libc::c_char,
+    ) -> c_int;
+    pub fn luaL_loadstring(L: *mut lua_State, s: *const libc::c_char) -> c_int;
+
+    pub fn luaL_newstate() -> *mut lua_State;
+
+    pub fn luaL_len(L: *mut lua_State, idx: c_int) -> c_int;
+
+    pub fn luaL_gsub(
+        L: *mut lua_State,
+        s: *const libc::c_char,
+        p: *const libc::c_char,
+        r: *const libc::c_char,
+    ) -> *const libc::c_char;
+
+    pub fn luaL_setfuncs(L: *mut lua_State, l: *const luaL_Reg, nup: c_int);
+
+    pub fn luaL_getsubtable(L: *mut lua_State, idx: c_int, fname: *const libc::c_char) -> c_int;
+
+    pub fn luaL_traceback(
+        L: *mut lua_State,
+        L1: *mut lua_State,
+        msg: *const libc::c_char,
+        level: c_int,
+    );
+
+    pub fn luaL_requiref(
+        L: *mut lua_State,
+        modname: *const libc::c_char,
+        openf: lua_CFunction,
+        glb: c_int,
+    );
+
+    pub fn luaL_buffinit(L: *mut lua_State, B: *mut luaL_Buffer);
+    pub fn luaL_prepbuffsize(B: *mut luaL_Buffer, sz: libc::size_t) -> *mut libc::c_char;
+    pub fn luaL_addlstring(B: *mut luaL_Buffer, s: *const libc::c_char, l: libc::size_t);
+    pub fn luaL_addstring(B: *mut luaL_Buffer, s: *const libc::c_char);
+    pub fn luaL_addvalue(B: *mut luaL_Buffer);
+    pub fn luaL_pushresult(B: *mut luaL_Buffer);
+    pub fn luaL_pushresultsize(B: *mut luaL_Buffer, sz: libc::size_t);
+    pub fn luaL_buffinitsize(
+        L: *mut lua_State,
+        B: *mut luaL_Buffer,
+        sz: libc::size_t,
+    ) -> *mut libc::c_char;
+
     pub fn luaopen_base(L: *mut lua_State) -> c_int;
     pub fn luaopen_bit32(L: *mut lua_State) -> c_int;
     pub fn luaopen_coroutine(L: *mut lua_State) -> c_int;
@@ -377,6 +552,125 @@ pub unsafe fn lua_tostring(L: *mut lua_State, i: c_int) -> *const libc::c_char {
     lua_tolstring(L, i, ptr::null_mut())
 }
 
+#[inline(always)]
+pub unsafe fn luaL_checkversion(L: *mut lua_State) {
+    luaL_checkversion_(L, LUA_VERSION_NUM as lua_Number)
+}
+
+#[inline(always)]
+pub unsafe fn luaL_loadfile(L: *mut lua_State, filename: *const libc::c_char) -> c_int {
+    luaL_loadfilex(L, filename, ptr::null())
+}
+
+#[inline(always)]
+pub unsafe fn luaL_newlibtable(L: *mut lua_State, l: &[luaL_Reg]) {
+    lua_createtable(L, 0, l.len() as c_int)
+}
+
+/// Creates a table with the functions of `l`. Unlike the C macro, `l` must not end with a
+/// sentinel entry whose name is null.
+#[inline(always)]
+pub unsafe fn luaL_newlib(L: *mut lua_State, l: &[luaL_Reg]) {
+    let mut regs = l.to_vec();
+    regs.push(luaL_Reg {
+        name: ptr::null(),
+        func: None,
+    });
+    luaL_newlibtable(L, l);
+    luaL_setfuncs(L, regs.as_ptr(), 0);
+}
+
+#[inline(always)]
+pub unsafe fn luaL_argcheck(
+    L: *mut lua_State,
+    cond: bool,
+    numarg: c_int,
+    extramsg: *const libc::c_char,
+) {
+    if !cond {
+        luaL_argerror(L, numarg, extramsg);
+    }
+}
+
+#[inline(always)]
+pub unsafe fn luaL_checkstring(L: *mut lua_State, n: c_int) -> *const libc::c_char {
+    luaL_checklstring(L, n, ptr::null_mut())
+}
+
+#[inline(always)]
+pub unsafe fn luaL_optstring(
+    L: *mut lua_State,
+    n: c_int,
+    d: *const libc::c_char,
+) -> *const libc::c_char {
+    luaL_optlstring(L, n, d, ptr::null_mut())
+}
+
+#[inline(always)]
+pub unsafe fn luaL_checkint(L: *mut lua_State, n: c_int) -> c_int {
+    luaL_checkinteger(L, n) as c_int
+}
+
+#[inline(always)]
+pub unsafe fn luaL_optint(L: *mut lua_State, n: c_int, d: c_int) -> c_int {
+    luaL_optinteger(L, n, d as lua_Integer) as c_int
+}
+
+#[inline(always)]
+pub unsafe fn luaL_typename(L: *mut lua_State, i: c_int) -> *const libc::c_char {
+    lua_typename(L, lua_type(L, i))
+}
+
+#[inline(always)]
+pub unsafe fn luaL_dofile(L: *mut lua_State, filename: *const libc::c_char) -> c_int {
+    match luaL_loadfile(L, filename) {
+        LUA_OK => lua_pcall(L, 0, MULTRET, 0),
+        err => err,
+    }
+}
+
+#[inline(always)]
+pub unsafe fn luaL_dostring(L: *mut lua_State, s: *const libc::c_char) -> c_int {
+    match luaL_loadstring(L, s) {
+        LUA_OK => lua_pcall(L, 0, MULTRET, 0),
+        err => err,
+    }
+}
+
+#[inline(always)]
+pub unsafe fn luaL_getmetatable(L: *mut lua_State, tname: *const libc::c_char) {
+    lua_getfield(L, LUA_REGISTRYINDEX, tname)
+}
+
+#[inline(always)]
+pub unsafe fn luaL_loadbuffer(
+    L: *mut lua_State,
+    buff: *const libc::c_char,
+    sz: libc::size_t,
+    name: *const libc::c_char,
+) -> c_int {
+    luaL_loadbufferx(L, buff, sz, name, ptr::null())
+}
+
+#[inline(always)]
+pub unsafe fn luaL_addchar(B: *mut luaL_Buffer, c: libc::c_char) {
+    if (*B).n >= (*B).size {
+        luaL_prepbuffsize(B, 1);
+    }
+    *(*B).b.add((*B).n) = c;
+    (*B).n += 1;
+}
+
+#[inline(always)]
+pub unsafe fn luaL_addsize(B: *mut luaL_Buffer, s: libc::size_t) {
+    (*B).n += s;
+}
+
+#[inline(always)]
+pub unsafe fn luaL_prepbuffer(B: *mut luaL_Buffer) -> *mut libc::c_char {
+    luaL_prepbuffsize(B, LUAL_BUFFERSIZE as libc::size_t)
+}
+
 impl default::Default for lua_Debug {
     fn default() -> lua_Debug {
         lua_Debug {
@@ -392,7 +686,213 @@ impl default::Default for lua_Debug {
             nparams: 0,
             isvararg: 0,
             istailcall: 0,
-            short_src: [0; 60],
+            short_src: [0; LUA_IDSIZE],
+            i_ci: ptr::null_mut(),
+        }
+    }
+}
+
+// Compares the definitions of this crate with the sizes and offsets computed by the C compiler
+// in `layout.c`. A difference is a compile-time error.
+#[cfg(lua52_sys_layout)]
+mod layout {
+    use super::*;
+    use std::mem::{offset_of, size_of};
+
+    mod c {
+        include!(concat!(env!("OUT_DIR"), "/layout.rs"));
+    }
+
+    const _: () = assert!(size_of::<lua_Debug>() == c::SIZEOF_LUA_DEBUG);
+    const _: () = assert!(offset_of!(lua_Debug, short_src) == c::OFFSETOF_LUA_DEBUG_SHORT_SRC);
+    const _: () = assert!(offset_of!(lua_Debug, i_ci) == c::OFFSETOF_LUA_DEBUG_I_CI);
+    const _: () = assert!(size_of::<luaL_Buffer>() == c::SIZEOF_LUAL_BUFFER);
+    const _: () = assert!(offset_of!(luaL_Buffer, initb) == c::OFFSETOF_LUAL_BUFFER_INITB);
+    const _: () = assert!(size_of::<luaL_Reg>() == c::SIZEOF_LUAL_REG);
+    const _: () = assert!(size_of::<luaL_Stream>() == c::SIZEOF_LUAL_STREAM);
+    const _: () = assert!(size_of::<lua_Number>() == c::SIZEOF_LUA_NUMBER);
+    const _: () = assert!(size_of::<lua_Integer>() == c::SIZEOF_LUA_INTEGER);
+    const _: () = assert!(size_of::<lua_Unsigned>() == c::SIZEOF_LUA_UNSIGNED);
+    const _: () = assert!(LUA_IDSIZE == c::IDSIZE);
+    const _: () = assert!(LUAL_BUFFERSIZE == c::BUFFERSIZE);
+    const _: () = assert!(LUA_REGISTRYINDEX == c::REGISTRYINDEX);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::mem;
+
+    unsafe fn to_str<'a>(s: *const libc::c_char) -> &'a str {
+        CStr::from_ptr(s).to_str().unwrap()
+    }
+
+    extern "C" fn open_answer(L: *mut lua_State) -> c_int {
+        unsafe {
+            lua_newtable(L);
+            lua_pushinteger(L, 42);
+            lua_setfield(L, -2, b"value\0".as_ptr() as *const _);
+        }
+        1
+    }
+
+    extern "C" fn add(L: *mut lua_State) -> c_int {
+        unsafe {
+            let sum = luaL_checkinteger(L, 1) + luaL_optinteger(L, 2, 1);
+            lua_pushinteger(L, sum);
+        }
+        1
+    }
+
+    #[test]
+    fn metatables_and_udata() {
+        unsafe {
+            let L = luaL_newstate();
+            let tname = b"lua52_sys.Test\0".as_ptr() as *const _;
+
+            assert_eq!(luaL_newmetatable(L, tname), 1);
+            assert_eq!(luaL_newmetatable(L, tname), 0);
+            lua_pop(L, 2);
+
+            lua_newuserdata(L, 8);
+            assert!(luaL_testudata(L, -1, tname).is_null());
+            luaL_setmetatable(L, tname);
+            let ptr = lua_touserdata(L, -1);
+            assert_eq!(luaL_testudata(L, -1, tname), ptr);
+            assert_eq!(luaL_checkudata(L, -1, tname), ptr);
+
+            luaL_getmetatable(L, tname);
+            assert!(lua_getmetatable(L, -2) != 0);
+            assert!(lua_rawequal(L, -1, -2) != 0);
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn buffer() {
+        unsafe {
+            let L = luaL_newstate();
+            let mut buffer: luaL_Buffer = mem::zeroed();
+            luaL_buffinit(L, &mut buffer);
+            luaL_addstring(&mut buffer, b"hello\0".as_ptr() as *const _);
+            luaL_addchar(&mut buffer, b' ' as libc::c_char);
+            lua_pushinteger(L, 5);
+            luaL_addvalue(&mut buffer);
+            // More than the initial buffer, to check the growth.
+            for _ in 0..LUAL_BUFFERSIZE {
+                luaL_addchar(&mut buffer, b'!' as libc::c_char);
+            }
+            luaL_pushresult(&mut buffer);
+
+            let mut len = 0;
+            let s = lua_tolstring(L, -1, &mut len);
+            assert_eq!(len, 7 + LUAL_BUFFERSIZE);
+            assert!(to_str(s).starts_with("hello 5!!"));
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn load_and_strings() {
+        unsafe {
+            let L = luaL_newstate();
+            luaL_openlibs(L);
+
+            let code = b"return #({...}), 'x'";
+            let status = luaL_loadbufferx(
+                L,
+                code.as_ptr() as *const _,
+                code.len(),
+                b"=chunk\0".as_ptr() as *const _,
+                b"b\0".as_ptr() as *const _,
+            );
+            assert_eq!(status, LUA_ERRSYNTAX);
+            lua_pop(L, 1);
+            let status = luaL_loadbuffer(
+                L,
+                code.as_ptr() as *const _,
+                code.len(),
+                b"=chunk\0".as_ptr() as *const _,
+            );
+            assert_eq!(status, LUA_OK);
+            lua_pushinteger(L, 1);
+            lua_pushinteger(L, 2);
+            assert_eq!(lua_pcall(L, 2, 2, 0), LUA_OK);
+            assert_eq!(lua_tointegerx(L, -2, ptr::null_mut()), 2);
+            lua_pop(L, 2);
+
+            assert_eq!(
+                luaL_dostring(L, b"t = {1, 2, 3}\0".as_ptr() as *const _),
+                LUA_OK
+            );
+            lua_getglobal(L, b"t\0".as_ptr() as *const _);
+            assert_eq!(luaL_len(L, -1), 3);
+            assert_eq!(to_str(luaL_typename(L, -1)), "table");
+            assert_eq!(
+                to_str(luaL_tolstring(L, -1, ptr::null_mut())).find("table: "),
+                Some(0)
+            );
+            lua_pop(L, 2);
+
+            let s = luaL_gsub(
+                L,
+                b"a.b.c\0".as_ptr() as *const _,
+                b".\0".as_ptr() as *const _,
+                b"/\0".as_ptr() as *const _,
+            );
+            assert_eq!(to_str(s), "a/b/c");
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn traceback() {
+        unsafe {
+            let L = luaL_newstate();
+            luaL_traceback(L, L, b"oops\0".as_ptr() as *const _, 0);
+            let s = to_str(lua_tostring(L, -1));
+            assert!(s.starts_with("oops\nstack traceback:"), "{}", s);
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn requiref_and_newlib() {
+        unsafe {
+            let L = luaL_newstate();
+            luaL_openlibs(L);
+
+            luaL_requiref(L, b"answer\0".as_ptr() as *const _, open_answer, 1);
+            lua_pop(L, 1);
+
+            luaL_newlib(
+                L,
+                &[luaL_Reg {
+                    name: b"add\0".as_ptr() as *const _,
+                    func: Some(add),
+                }],
+            );
+            lua_setglobal(L, b"mylib\0".as_ptr() as *const _);
+
+            let code = b"return answer.value + mylib.add(1) + mylib.add(1, 2) \
+                         + (require 'answer' == answer and 100 or 0)\0";
+            assert_eq!(luaL_dostring(L, code.as_ptr() as *const _), LUA_OK);
+            assert_eq!(lua_tointegerx(L, -1, ptr::null_mut()), 42 + 2 + 3 + 100);
+
+            let code = b"return pcall(mylib.add, 'x')\0";
+            assert_eq!(luaL_dostring(L, code.as_ptr() as *const _), LUA_OK);
+            let msg = to_str(lua_tostring(L, -1));
+            assert!(msg.contains("bad argument #1"), "{}", msg);
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn version() {
+        assert!(LUA_RELEASE_VERSION.starts_with("5.2."));
+        unsafe {
+            assert_eq!(*lua_version(ptr::null_mut()), LUA_VERSION_NUM as lua_Number);
         }
     }
 }
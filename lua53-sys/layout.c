@@ -0,0 +1,29 @@
+/* Prints the sizes and offsets of the types of the Lua headers as Rust constants. The build
+   script runs it, and the crate compares them with its own definitions at compile time. */
+
+#include <stddef.h>
+#include <stdio.h>
+
+#include "lua.h"
+#include "lauxlib.h"
+
+#define SIZE(name, value) printf("pub const %s: usize = %lu;\n", name, (unsigned long) (value))
+
+int main(void) {
+    SIZE("SIZEOF_LUA_DEBUG", sizeof(lua_Debug));
+    SIZE("OFFSETOF_LUA_DEBUG_SHORT_SRC", offsetof(lua_Debug, short_src));
+    SIZE("OFFSETOF_LUA_DEBUG_I_CI", offsetof(lua_Debug, i_ci));
+    SIZE("SIZEOF_LUAL_BUFFER", sizeof(luaL_Buffer));
+    SIZE("OFFSETOF_LUAL_BUFFER_INITB", offsetof(luaL_Buffer, initb));
+    SIZE("SIZEOF_LUAL_REG", sizeof(luaL_Reg));
+    SIZE("SIZEOF_LUAL_STREAM", sizeof(luaL_Stream));
+    SIZE("SIZEOF_LUA_NUMBER", sizeof(lua_Number));
+    SIZE("SIZEOF_LUA_INTEGER", sizeof(lua_Integer));
+    SIZE("SIZEOF_LUA_UNSIGNED", sizeof(lua_Unsigned));
+    SIZE("SIZEOF_LUA_KCONTEXT", sizeof(lua_KContext));
+    SIZE("IDSIZE", LUA_IDSIZE);
+    SIZE("BUFFERSIZE", LUAL_BUFFERSIZE);
+    SIZE("NUMSIZES", LUAL_NUMSIZES);
+    printf("pub const REGISTRYINDEX: i32 = %d;\n", LUA_REGISTRYINDEX);
+    return 0;
+}
@@ -0,0 +1,237 @@
+extern crate cc;
+extern crate pkg_config;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Where the Lua library comes from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Source {
+    // The system library if pkg-config finds it, the vendored sources otherwise.
+    Auto,
+    System,
+    Vendored,
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=LUA54_SYS_SOURCE");
+    println!("cargo:rerun-if-env-changed=LUA54_SYS_DEFINES");
+
+    let source = source();
+    let defines = defines();
+    let apicheck = env::var_os("CARGO_FEATURE_APICHECK").is_some();
+
+    if source != Source::Vendored {
+        match pkg_config::find_library("lua5.4") {
+            Ok(library) => {
+                if !defines.is_empty() {
+                    println!(
+                        "cargo:warning=LUA54_SYS_DEFINES is ignored when linking to the system Lua"
+                    );
+                }
+                if apicheck {
+                    println!(
+                        "cargo:warning=the `apicheck` feature is ignored when linking to the \
+                         system Lua"
+                    );
+                }
+                let mut layout = cc::Build::new();
+                layout.includes(&library.include_paths);
+                check_layout(&layout);
+                write_version(&library.version, false);
+                return;
+            }
+            Err(err) => {
+                if source == Source::System {
+                    panic!("lua54-sys: could not find the system Lua 5.4: {}", err);
+                }
+            }
+        }
+    }
+
+    let mut build = cc::Build::new();
+
+    if env::var("CARGO_CFG_TARGET_OS") == Ok("linux".to_string()) {
+        // Enable `io.popen` support
+        build.define("LUA_USE_LINUX", None);
+    }
+
+    if apicheck {
+        // Failed checks abort the process through `assert`. The standard libraries of Lua use
+        // `lua_assert` without including `assert.h`, so it is included in every file.
+        build.define("LUA_USE_APICHECK", None);
+        build.define("lua_assert(c)", Some("assert(c)"));
+        if build.get_compiler().is_like_msvc() {
+            build.flag("/FIassert.h");
+        } else {
+            build.flag("-include").flag("assert.h");
+        }
+    }
+
+    for (name, value) in &defines {
+        build.define(name, value.as_ref().map(|v| &v[..]));
+    }
+
+    build.define("LUA_COMPAT_5_3", None).include("lua/src");
+    check_layout(&build);
+
+    build
+        .file("lua/src/lapi.c")
+        .file("lua/src/lcode.c")
+        .file("lua/src/lctype.c")
+        .file("lua/src/ldebug.c")
+        .file("lua/src/ldo.c")
+        .file("lua/src/ldump.c")
+        .file("lua/src/lfunc.c")
+        .file("lua/src/lgc.c")
+        .file("lua/src/llex.c")
+        .file("lua/src/lmem.c")
+        .file("lua/src/lobject.c")
+        .file("lua/src/lopcodes.c")
+        .file("lua/src/lparser.c")
+        .file("lua/src/lstate.c")
+        .file("lua/src/lstring.c")
+        .file("lua/src/ltable.c")
+        .file("lua/src/ltm.c")
+        .file("lua/src/lundump.c")
+        .file("lua/src/lvm.c")
+        .file("lua/src/lzio.c")
+        .file("lua/src/lauxlib.c")
+        .file("lua/src/lbaselib.c")
+        .file("lua/src/lcorolib.c")
+        .file("lua/src/ldblib.c")
+        .file("lua/src/liolib.c")
+        .file("lua/src/lmathlib.c")
+        .file("lua/src/loslib.c")
+        .file("lua/src/lstrlib.c")
+        .file("lua/src/ltablib.c")
+        .file("lua/src/lutf8lib.c")
+        .file("lua/src/loadlib.c")
+        .file("lua/src/linit.c")
+        .compile("liblua54.a");
+
+    write_version(&vendored_version(), true);
+}
+
+// Compiles and runs `layout.c`, which writes the sizes and offsets of the C types to `layout.rs`.
+// The crate compares them with its own definitions at compile time, so that a difference, for
+// example with a system library built with other options, fails the build instead of corrupting
+// memory. The program can't run when cross-compiling, in which case the checks are skipped.
+fn check_layout(build: &cc::Build) {
+    println!("cargo:rerun-if-changed=layout.c");
+    println!("cargo:rustc-check-cfg=cfg(lua54_sys_layout)");
+
+    if env::var("HOST") != env::var("TARGET") {
+        println!("cargo:warning=the layout of the Lua types isn't checked when cross-compiling");
+        return;
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let program = Path::new(&out_dir).join(if cfg!(windows) {
+        "layout.exe"
+    } else {
+        "layout"
+    });
+
+    let compiler = build.get_compiler();
+    let mut command = compiler.to_command();
+    command.arg("layout.c");
+    if compiler.is_like_msvc() {
+        command
+            .arg(format!("/Fe{}", program.display()))
+            .arg(format!("/Fo{}\\", out_dir));
+    } else {
+        command.arg("-o").arg(&program);
+    }
+    let status = command
+        .status()
+        .expect("lua54-sys: can't run the C compiler");
+    assert!(status.success(), "lua54-sys: can't compile layout.c");
+
+    let output = Command::new(&program)
+        .output()
+        .expect("lua54-sys: can't run the layout program");
+    assert!(
+        output.status.success(),
+        "lua54-sys: the layout program failed"
+    );
+    fs::write(Path::new(&out_dir).join("layout.rs"), output.stdout).unwrap();
+    println!("cargo:rustc-cfg=lua54_sys_layout");
+}
+
+// Determines the source of the library from the `LUA54_SYS_SOURCE` environment variable, then
+// from the features.
+fn source() -> Source {
+    let system = env::var_os("CARGO_FEATURE_SYSTEM").is_some();
+    let vendored = env::var_os("CARGO_FEATURE_VENDORED").is_some();
+
+    match env::var("LUA54_SYS_SOURCE") {
+        Ok(ref value) if value == "system" => return Source::System,
+        Ok(ref value) if value == "vendored" => return Source::Vendored,
+        Ok(ref value) if value == "auto" => return Source::Auto,
+        Ok(value) => panic!(
+            "lua54-sys: invalid LUA54_SYS_SOURCE `{}`, expected `system`, `vendored` or `auto`",
+            value
+        ),
+        Err(_) => {}
+    }
+
+    match (system, vendored) {
+        (true, true) => panic!(
+            "lua54-sys: the `system` and `vendored` features are mutually exclusive; use \
+             LUA54_SYS_SOURCE to choose"
+        ),
+        (true, false) => Source::System,
+        (false, true) => Source::Vendored,
+        (false, false) => Source::Auto,
+    }
+}
+
+// Parses the `LUA54_SYS_DEFINES` environment variable, a list of `NAME` or `NAME=VALUE` separated
+// by spaces or commas.
+fn defines() -> Vec<(String, Option<String>)> {
+    let defines = env::var("LUA54_SYS_DEFINES").unwrap_or_default();
+    defines
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|define| !define.is_empty())
+        .map(|define| match define.find('=') {
+            Some(pos) => (define[..pos].to_owned(), Some(define[pos + 1..].to_owned())),
+            None => (define.to_owned(), None),
+        })
+        .collect()
+}
+
+// Reads the release of the vendored sources in `lua.h`.
+fn vendored_version() -> String {
+    let header = fs::read_to_string("lua/src/lua.h").expect("lua54-sys: can't read lua.h");
+    let field = |name: &str| {
+        header
+            .lines()
+            .filter_map(|line| line.strip_prefix("#define "))
+            .filter_map(|line| line.strip_prefix(name))
+            .map(|value| value.trim().trim_matches('"').to_owned())
+            .next()
+            .expect("lua54-sys: missing version in lua.h")
+    };
+    format!(
+        "{}.{}.{}",
+        field("LUA_VERSION_MAJOR"),
+        field("LUA_VERSION_MINOR"),
+        field("LUA_VERSION_RELEASE")
+    )
+}
+
+fn write_version(version: &str, vendored: bool) {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let code = format!(
+        "/// Release of Lua that this crate is linked to, such as `\"5.4.7\"`.\n\
+         pub const LUA_RELEASE_VERSION: &str = {:?};\n\
+         /// True if the Lua library has been built from the sources vendored in this crate, false \
+         if it is the library of the system.\n\
+         pub const LUA_VENDORED: bool = {};\n",
+        version, vendored
+    );
+    fs::write(Path::new(&out_dir).join("version.rs"), code).unwrap();
+}
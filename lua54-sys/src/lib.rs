@@ -0,0 +1,1040 @@
+//! Bindings for Lua 5.4.
+//!
+//! By default, the crate links to the Lua 5.4 of the system if pkg-config finds it, and builds
+//! the bundled sources otherwise. The `system` and `vendored` features force one or the other. The
+//! `LUA54_SYS_SOURCE` environment variable, set to `system`, `vendored` or `auto`, overrides the
+//! features.
+//!
+//! When the bundled sources are built, the `LUA54_SYS_DEFINES` environment variable can contain
+//! additional defines for `luaconf.h`, separated by spaces or commas, such as
+//! `LUA_USE_APICHECK LUAI_MAXCCALLS=400`. The `apicheck` feature enables the checks of the Lua API
+//! and the internal assertions of Lua in the bundled sources, which abort the process when the
+//! API is misused. The bundled sources are built with `LUA_COMPAT_5_3`.
+//!
+//! The macros of `lua.h` that replaced functions of Lua 5.2 and 5.3, such as `lua_insert`,
+//! `lua_pushunsigned` or `lua_newuserdata`, are provided as functions.
+
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(dead_code)]
+#![allow(improper_ctypes)]
+
+extern crate libc;
+
+use libc::c_int;
+use std::mem::size_of;
+use std::{default, ptr};
+
+include!(concat!(env!("OUT_DIR"), "/version.rs"));
+
+pub const LUA_VERSION_NUM: c_int = 504;
+
+pub const MULTRET: c_int = -1;
+
+pub const LUAI_MAXSTACK: c_int = 1000000;
+pub const LUAI_FIRSTPSEUDOIDX: c_int = -LUAI_MAXSTACK - 1000;
+pub const LUA_REGISTRYINDEX: c_int = LUAI_FIRSTPSEUDOIDX;
+
+pub const LUA_OK: c_int = 0;
+pub const LUA_YIELD: c_int = 1;
+pub const LUA_ERRRUN: c_int = 2;
+pub const LUA_ERRSYNTAX: c_int = 3;
+pub const LUA_ERRMEM: c_int = 4;
+pub const LUA_ERRERR: c_int = 5;
+pub const LUA_ERRFILE: c_int = LUA_ERRERR + 1;
+
+#[repr(C)]
+#[allow(missing_copy_implementations)]
+pub struct lua_State;
+
+pub type lua_CFunction = extern "C" fn(L: *mut lua_State) -> c_int;
+
+pub type lua_KContext = libc::intptr_t;
+pub type lua_KFunction =
+    extern "C" fn(L: *mut lua_State, status: c_int, ctx: lua_KContext) -> c_int;
+
+pub type lua_Reader = extern "C" fn(
+    L: *mut lua_State,
+    ud: *mut libc::c_void,
+    sz: *mut libc::size_t,
+) -> *const libc::c_char;
+pub type lua_Writer = extern "C" fn(
+    L: *mut lua_State,
+    p: *const libc::c_void,
+    sz: libc::size_t,
+    ud: *mut libc::c_void,
+) -> libc::c_int;
+
+pub type lua_Alloc = extern "C" fn(
+    ud: *mut libc::c_void,
+    ptr: *mut libc::c_void,
+    osize: libc::size_t,
+    nsize: libc::size_t,
+) -> *mut libc::c_void;
+
+pub type lua_Hook = extern "C" fn(L: *mut lua_State, ar: *mut lua_Debug);
+
+pub type lua_WarnFunction =
+    extern "C" fn(ud: *mut libc::c_void, msg: *const libc::c_char, tocont: c_int);
+
+pub const LUA_TNONE: c_int = -1;
+
+pub const LUA_TNIL: c_int = 0;
+pub const LUA_TBOOLEAN: c_int = 1;
+pub const LUA_TLIGHTUSERDATA: c_int = 2;
+pub const LUA_TNUMBER: c_int = 3;
+pub const LUA_TSTRING: c_int = 4;
+pub const LUA_TTABLE: c_int = 5;
+pub const LUA_TFUNCTION: c_int = 6;
+pub const LUA_TUSERDATA: c_int = 7;
+pub const LUA_TTHREAD: c_int = 8;
+
+pub const LUA_MINSTACK: c_int = 20;
+
+pub const LUA_RIDX_MAINTHREAD: c_int = 1;
+pub const LUA_RIDX_GLOBALS: c_int = 2;
+
+pub const LUA_REFNIL: c_int = -1;
+pub const LUA_NOREF: c_int = -2;
+
+pub type lua_Number = libc::c_double;
+pub type lua_Integer = libc::c_longlong;
+pub type lua_Unsigned = libc::c_ulonglong;
+
+pub const LUA_OPADD: c_int = 0;
+pub const LUA_OPSUB: c_int = 1;
+pub const LUA_OPMUL: c_int = 2;
+pub const LUA_OPMOD: c_int = 3;
+pub const LUA_OPPOW: c_int = 4;
+pub const LUA_OPDIV: c_int = 5;
+pub const LUA_OPIDIV: c_int = 6;
+pub const LUA_OPBAND: c_int = 7;
+pub const LUA_OPBOR: c_int = 8;
+pub const LUA_OPBXOR: c_int = 9;
+pub const LUA_OPSHL: c_int = 10;
+pub const LUA_OPSHR: c_int = 11;
+pub const LUA_OPUNM: c_int = 12;
+pub const LUA_OPBNOT: c_int = 13;
+
+pub const LUA_OPEQ: c_int = 0;
+pub const LUA_OPLT: c_int = 1;
+pub const LUA_OPLE: c_int = 2;
+
+pub const LUA_GCSTOP: c_int = 0;
+pub const LUA_GCRESTART: c_int = 1;
+pub const LUA_GCCOLLECT: c_int = 2;
+pub const LUA_GCCOUNT: c_int = 3;
+pub const LUA_GCCOUNTB: c_int = 4;
+pub const LUA_GCSTEP: c_int = 5;
+pub const LUA_GCSETPAUSE: c_int = 6;
+pub const LUA_GCSETSTEPMUL: c_int = 7;
+pub const LUA_GCISRUNNING: c_int = 9;
+pub const LUA_GCGEN: c_int = 10;
+pub const LUA_GCINC: c_int = 11;
+
+pub const LUA_HOOKCALL: c_int = 0;
+pub const LUA_HOOKRET: c_int = 1;
+pub const LUA_HOOKLINE: c_int = 2;
+pub const LUA_HOOKCOUNT: c_int = 3;
+pub const LUA_HOOKTAILCALL: c_int = 4;
+
+pub const LUA_MASKCALL: c_int = 1 << LUA_HOOKCALL as usize;
+pub const LUA_MASKRET: c_int = 1 << LUA_HOOKRET as usize;
+pub const LUA_MASKLINE: c_int = 1 << LUA_HOOKLINE as usize;
+pub const LUA_MASKCOUNT: c_int = 1 << LUA_HOOKCOUNT as usize;
+
+pub const LUA_IDSIZE: usize = 60;
+
+#[repr(C)]
+#[allow(missing_copy_implementations)]
+pub struct lua_Debug {
+    pub event: c_int,
+    pub name: *const libc::c_char,
+    pub namewhat: *const libc::c_char,
+    pub what: *const libc::c_char,
+    pub source: *const libc::c_char,
+    pub srclen: libc::size_t,
+    pub currentline: c_int,
+    pub linedefined: c_int,
+    pub lastlinedefined: c_int,
+    pub nups: libc::c_uchar,
+    pub nparams: libc::c_uchar,
+    pub isvararg: libc::c_char,
+    pub istailcall: libc::c_char,
+    pub ftransfer: libc::c_ushort,
+    pub ntransfer: libc::c_ushort,
+    pub short_src: [libc::c_char; LUA_IDSIZE],
+    // private to Lua, but must be allocated because `lua_getstack` writes it
+    pub i_ci: *mut libc::c_void,
+}
+
+pub const LUA_FILEHANDLE: &[u8] = b"FILE*\0";
+
+/// Size of the buffer embedded in a `luaL_Buffer`, as computed in `luaconf.h`.
+pub const LUAL_BUFFERSIZE: usize = 16 * size_of::<*mut libc::c_void>() * size_of::<lua_Number>();
+
+/// Sizes of the numeric types, checked by `luaL_checkversion`.
+pub const LUAL_NUMSIZES: usize = size_of::<lua_Integer>() * 16 + size_of::<lua_Number>();
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct luaL_Reg {
+    pub name: *const libc::c_char,
+    pub func: Option<lua_CFunction>,
+}
+
+/// String buffer of the auxiliary library.
+///
+/// Until the buffer grows larger than `LUAL_BUFFERSIZE`, `b` points to `init`, so a buffer must
+/// not be moved between `luaL_buffinit` and `luaL_pushresult`.
+#[repr(C)]
+#[allow(missing_copy_implementations)]
+pub struct luaL_Buffer {
+    pub b: *mut libc::c_char,
+    pub size: libc::size_t,
+    pub n: libc::size_t,
+    pub L: *mut lua_State,
+    pub init: luaL_Buffer_init,
+}
+
+/// Initial storage of a `luaL_Buffer`, aligned like `LUAI_MAXALIGN`.
+#[repr(C)]
+#[allow(missing_copy_implementations)]
+pub union luaL_Buffer_init {
+    pub n: lua_Number,
+    pub u: libc::c_double,
+    pub s: *mut libc::c_void,
+    pub i: lua_Integer,
+    pub l: libc::c_long,
+    pub b: [libc::c_char; LUAL_BUFFERSIZE],
+}
+
+#[repr(C)]
+#[allow(missing_copy_implementations)]
+pub struct luaL_Stream {
+    pub f: *mut libc::FILE,
+    pub closef: Option<lua_CFunction>,
+}
+
+extern "C" {
+    pub fn lua_newstate(f: lua_Alloc, ud: *mut libc::c_void) -> *mut lua_State;
+    pub fn lua_close(L: *mut lua_State);
+    pub fn lua_newthread(L: *mut lua_State) -> *mut lua_State;
+    pub fn lua_closethread(L: *mut lua_State, from: *mut lua_State) -> c_int;
+    pub fn lua_resetthread(L: *mut lua_State) -> c_int;
+
+    pub fn lua_atpanic(L: *mut lua_State, panicf: lua_CFunction) -> lua_CFunction;
+
+    pub fn lua_version(L: *mut lua_State) -> lua_Number;
+
+    pub fn lua_absindex(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_gettop(L: *mut lua_State) -> c_int;
+    pub fn lua_settop(L: *mut lua_State, idx: c_int);
+    pub fn lua_pushvalue(L: *mut lua_State, idx: c_int);
+    pub fn lua_rotate(L: *mut lua_State, idx: c_int, n: c_int);
+    pub fn lua_copy(L: *mut lua_State, fromidx: c_int, toidx: c_int);
+    pub fn lua_checkstack(L: *mut lua_State, sz: c_int) -> c_int;
+
+    pub fn lua_xmove(from: *mut lua_State, to: *mut lua_State, n: c_int);
+
+    pub fn lua_isnumber(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_isstring(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_iscfunction(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_isinteger(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_isuserdata(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_type(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_typename(L: *mut lua_State, tp: c_int) -> *const libc::c_char;
+
+    pub fn lua_tonumberx(L: *mut lua_State, idx: c_int, isnum: *mut c_int) -> lua_Number;
+    pub fn lua_tointegerx(L: *mut lua_State, idx: c_int, isnum: *mut c_int) -> lua_Integer;
+    pub fn lua_toboolean(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_tolstring(
+        L: *mut lua_State,
+        idx: c_int,
+        len: *mut libc::size_t,
+    ) -> *const libc::c_char;
+    pub fn lua_rawlen(L: *mut lua_State, idx: c_int) -> lua_Unsigned;
+    pub fn lua_tocfunction(L: *mut lua_State, idx: c_int) -> Option<lua_CFunction>;
+    pub fn lua_touserdata(L: *mut lua_State, idx: c_int) -> *mut libc::c_void;
+    pub fn lua_tothread(L: *mut lua_State, idx: c_int) -> *mut lua_State;
+    pub fn lua_topointer(L: *mut lua_State, idx: c_int) -> *const libc::c_void;
+
+    pub fn lua_arith(L: *mut lua_State, op: c_int);
+    pub fn lua_rawequal(L: *mut lua_State, idx1: c_int, idx2: c_int) -> c_int;
+    pub fn lua_compare(L: *mut lua_State, idx1: c_int, idx2: c_int, op: c_int) -> c_int;
+
+    pub fn lua_pushnil(L: *mut lua_State);
+    pub fn lua_pushnumber(L: *mut lua_State, n: lua_Number);
+    pub fn lua_pushinteger(L: *mut lua_State, n: lua_Integer);
+    pub fn lua_pushlstring(L: *mut lua_State, s: *const libc::c_char, l: libc::size_t);
+    pub fn lua_pushstring(L: *mut lua_State, s: *const libc::c_char);
+    // TODO: lua_pushvfstring()
+    pub fn lua_pushfstring(L: *mut lua_State, fmt: *const libc::c_char, ...)
+        -> *const libc::c_char;
+    pub fn lua_pushcclosure(L: *mut lua_State, f: lua_CFunction, n: c_int);
+    pub fn lua_pushboolean(L: *mut lua_State, b: c_int);
+    pub fn lua_pushlightuserdata(L: *mut lua_State, p: *mut libc::c_void);
+    pub fn lua_pushthread(L: *mut lua_State) -> c_int;
+
+    pub fn lua_getglobal(L: *mut lua_State, var: *const libc::c_char) -> c_int;
+    pub fn lua_gettable(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_getfield(L: *mut lua_State, idx: c_int, k: *const libc::c_char) -> c_int;
+    pub fn lua_geti(L: *mut lua_State, idx: c_int, n: lua_Integer) -> c_int;
+    pub fn lua_rawget(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_rawgeti(L: *mut lua_State, idx: c_int, n: lua_Integer) -> c_int;
+    pub fn lua_rawgetp(L: *mut lua_State, idx: c_int, p: *const libc::c_char) -> c_int;
+    pub fn lua_createtable(L: *mut lua_State, narr: c_int, nrec: c_int);
+    pub fn lua_newuserdatauv(
+        L: *mut lua_State,
+        sz: libc::size_t,
+        nuvalue: c_int,
+    ) -> *mut libc::c_void;
+    pub fn lua_getmetatable(L: *mut lua_State, objindex: c_int) -> c_int;
+    pub fn lua_getiuservalue(L: *mut lua_State, idx: c_int, n: c_int) -> c_int;
+
+    pub fn lua_setglobal(L: *mut lua_State, var: *const libc::c_char);
+    pub fn lua_settable(L: *mut lua_State, idx: c_int);
+    pub fn lua_setfield(L: *mut lua_State, idx: c_int, k: *const libc::c_char);
+    pub fn lua_seti(L: *mut lua_State, idx: c_int, n: lua_Integer);
+    pub fn lua_rawset(L: *mut lua_State, idx: c_int);
+    pub fn lua_rawseti(L: *mut lua_State, idx: c_int, n: lua_Integer);
+    pub fn lua_rawsetp(L: *mut lua_State, idx: c_int, p: *const libc::c_char);
+    pub fn lua_setmetatable(L: *mut lua_State, objindex: c_int) -> c_int;
+    pub fn lua_setiuservalue(L: *mut lua_State, idx: c_int, n: c_int) -> c_int;
+
+    pub fn lua_callk(
+        L: *mut lua_State,
+        nargs: c_int,
+        nresults: c_int,
+        ctx: lua_KContext,
+        k: Option<lua_KFunction>,
+    );
+    pub fn lua_pcallk(
+        L: *mut lua_State,
+        nargs: c_int,
+        nresults: c_int,
+        errfunc: c_int,
+        ctx: lua_KContext,
+        k: Option<lua_KFunction>,
+    ) -> c_int;
+    pub fn lua_load(
+        L: *mut lua_State,
+        reader: lua_Reader,
+        dt: *mut libc::c_void,
+        chunkname: *const libc::c_char,
+        mode: *const libc::c_char,
+    ) -> c_int;
+    pub fn lua_dump(
+        L: *mut lua_State,
+        writer: lua_Writer,
+        data: *mut libc::c_void,
+        strip: c_int,
+    ) -> c_int;
+
+    pub fn lua_yieldk(
+        L: *mut lua_State,
+        nresults: c_int,
+        ctx: lua_KContext,
+        k: Option<lua_KFunction>,
+    ) -> c_int;
+    pub fn lua_resume(
+        L: *mut lua_State,
+        from: *mut lua_State,
+        narg: c_int,
+        nres: *mut c_int,
+    ) -> c_int;
+    pub fn lua_status(L: *mut lua_State) -> c_int;
+    pub fn lua_isyieldable(L: *mut lua_State) -> c_int;
+
+    pub fn lua_setwarnf(L: *mut lua_State, f: Option<lua_WarnFunction>, ud: *mut libc::c_void);
+    pub fn lua_warning(L: *mut lua_State, msg: *const libc::c_char, tocont: c_int);
+
+    pub fn lua_gc(L: *mut lua_State, what: c_int, ...) -> c_int;
+
+    pub fn lua_error(L: *mut lua_State) -> c_int;
+    pub fn lua_next(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_concat(L: *mut lua_State, n: c_int);
+    pub fn lua_len(L: *mut lua_State, idx: c_int);
+
+    pub fn lua_toclose(L: *mut lua_State, idx: c_int);
+    pub fn lua_closeslot(L: *mut lua_State, idx: c_int);
+
+    pub fn lua_stringtonumber(L: *mut lua_State, s: *const libc::c_char) -> libc::size_t;
+
+    pub fn lua_getallocf(L: *mut lua_State, ud: *mut *mut libc::c_void) -> lua_Alloc;
+    pub fn lua_setallocf(L: *mut lua_State, f: lua_Alloc, ud: *mut libc::c_void);
+
+    pub fn lua_getstack(L: *mut lua_State, level: c_int, ar: *mut lua_Debug) -> c_int;
+    pub fn lua_getinfo(L: *mut lua_State, what: *const libc::c_char, ar: *mut lua_Debug) -> c_int;
+    pub fn lua_getlocal(L: *mut lua_State, ar: *const lua_Debug, n: c_int) -> *const libc::c_char;
+    pub fn lua_setlocal(L: *mut lua_State, ar: *mut lua_Debug, n: c_int) -> *const libc::c_char;
+    pub fn lua_getupvalue(L: *mut lua_State, funcindex: c_int, n: c_int) -> *const libc::c_char;
+    pub fn lua_setupvalue(L: *mut lua_State, funcindex: c_int, n: c_int) -> *const libc::c_char;
+
+    pub fn lua_upvalueid(L: *mut lua_State, fidx: c_int, n: c_int) -> *const libc::c_void;
+    pub fn lua_upvaluejoin(L: *mut lua_State, fidx1: c_int, n1: c_int, fidx2: c_int, n2: c_int);
+
+    pub fn lua_sethook(L: *mut lua_State, func: Option<lua_Hook>, mask: c_int, count: c_int);
+    pub fn lua_gethook(L: *mut lua_State) -> Option<lua_Hook>;
+    pub fn lua_gethookmask(L: *mut lua_State) -> c_int;
+    pub fn lua_gethookcount(L: *mut lua_State) -> c_int;
+
+    pub fn lua_setcstacklimit(L: *mut lua_State, limit: libc::c_uint) -> c_int;
+
+    pub fn luaL_openlibs(L: *mut lua_State);
+
+    pub fn luaL_checkversion_(L: *mut lua_State, ver: lua_Number, sz: libc::size_t);
+
+    pub fn luaL_getmetafield(L: *mut lua_State, obj: c_int, e: *const libc::c_char) -> c_int;
+    pub fn luaL_callmeta(L: *mut lua_State, obj: c_int, e: *const libc::c_char) -> c_int;
+    pub fn luaL_tolstring(
+        L: *mut lua_State,
+        idx: c_int,
+        len: *mut libc::size_t,
+    ) -> *const libc::c_char;
+    pub fn luaL_argerror(L: *mut lua_State, numarg: c_int, extramsg: *const libc::c_char) -> c_int;
+    pub fn luaL_typeerror(L: *mut lua_State, arg: c_int, tname: *const libc::c_char) -> c_int;
+    pub fn luaL_checklstring(
+        L: *mut lua_State,
+        numArg: c_int,
+        l: *mut libc::size_t,
+    ) -> *const libc::c_char;
+    pub fn luaL_optlstring(
+        L: *mut lua_State,
+        numArg: c_int,
+        def: *const libc::c_char,
+        l: *mut libc::size_t,
+    ) -> *const libc::c_char;
+    pub fn luaL_checknumber(L: *mut lua_State, numArg: c_int) -> lua_Number;
+    pub fn luaL_optnumber(L: *mut lua_State, nArg: c_int, def: lua_Number) -> lua_Number;
+    pub fn luaL_checkinteger(L: *mut lua_State, numArg: c_int) -> lua_Integer;
+    pub fn luaL_optinteger(L: *mut lua_State, nArg: c_int, def: lua_Integer) -> lua_Integer;
+
+    pub fn luaL_checkstack(L: *mut lua_State, sz: c_int, msg: *const libc::c_char);
+    pub fn luaL_checktype(L: *mut lua_State, narg: c_int, t: c_int);
+    pub fn luaL_checkany(L: *mut lua_State, narg: c_int);
+
+    pub fn luaL_newmetatable(L: *mut lua_State, tname: *const libc::c_char) -> c_int;
+    pub fn luaL_setmetatable(L: *mut lua_State, tname: *const libc::c_char);
+    pub fn luaL_testudata(
+        L: *mut lua_State,
+        ud: c_int,
+        tname: *const libc::c_char,
+    ) -> *mut libc::c_void;
+    pub fn luaL_checkudata(
+        L: *mut lua_State,
+        ud: c_int,
+        tname: *const libc::c_char,
+    ) -> *mut libc::c_void;
+
+    pub fn luaL_where(L: *mut lua_State, lvl: c_int);
+    pub fn luaL_error(L: *mut lua_State, fmt: *const libc::c_char, ...) -> c_int;
+
+    pub fn luaL_checkoption(
+        L: *mut lua_State,
+        narg: c_int,
+        def: *const libc::c_char,
+        lst: *const *const libc::c_char,
+    ) -> c_int;
+
+    pub fn luaL_fileresult(L: *mut lua_State, stat: c_int, fname: *const libc::c_char) -> c_int;
+    pub fn luaL_execresult(L: *mut lua_State, stat: c_int) -> c_int;
+
+    pub fn luaL_ref(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn luaL_unref(L: *mut lua_State, idx: c_int, ref_id: c_int);
+
+    pub fn luaL_loadfilex(
+        L: *mut lua_State,
+        filename: *const libc::c_char,
+        mode: *const libc::c_char,
+    ) -> c_int;
+    pub fn luaL_loadbufferx(
+        L: *mut lua_State,
+        buff: *const libc::c_char,
+        sz: libc::size_t,
+        name: *const libc::c_char,
+        mode: *const libc::c_char,
+    ) -> c_int;
+    pub fn luaL_loadstring(L: *mut lua_State, s: *const libc::c_char) -> c_int;
+
+    pub fn luaL_newstate() -> *mut lua_State;
+
+    pub fn luaL_len(L: *mut lua_State, idx: c_int) -> lua_Integer;
+
+    pub fn luaL_gsub(
+        L: *mut lua_State,
+        s: *const libc::c_char,
+        p: *const libc::c_char,
+        r: *const libc::c_char,
+    ) -> *const libc::c_char;
+
+    pub fn luaL_setfuncs(L: *mut lua_State, l: *const luaL_Reg, nup: c_int);
+
+    pub fn luaL_getsubtable(L: *mut lua_State, idx: c_int, fname: *const libc::c_char) -> c_int;
+
+    pub fn luaL_traceback(
+        L: *mut lua_State,
+        L1: *mut lua_State,
+        msg: *const libc::c_char,
+        level: c_int,
+    );
+
+    pub fn luaL_requiref(
+        L: *mut lua_State,
+        modname: *const libc::c_char,
+        openf: lua_CFunction,
+        glb: c_int,
+    );
+
+    pub fn luaL_addgsub(
+        B: *mut luaL_Buffer,
+        s: *const libc::c_char,
+        p: *const libc::c_char,
+        r: *const libc::c_char,
+    );
+
+    pub fn luaL_buffinit(L: *mut lua_State, B: *mut luaL_Buffer);
+    pub fn luaL_prepbuffsize(B: *mut luaL_Buffer, sz: libc::size_t) -> *mut libc::c_char;
+    pub fn luaL_addlstring(B: *mut luaL_Buffer, s: *const libc::c_char, l: libc::size_t);
+    pub fn luaL_addstring(B: *mut luaL_Buffer, s: *const libc::c_char);
+    pub fn luaL_addvalue(B: *mut luaL_Buffer);
+    pub fn luaL_pushresult(B: *mut luaL_Buffer);
+    pub fn luaL_pushresultsize(B: *mut luaL_Buffer, sz: libc::size_t);
+    pub fn luaL_buffinitsize(
+        L: *mut lua_State,
+        B: *mut luaL_Buffer,
+        sz: libc::size_t,
+    ) -> *mut libc::c_char;
+
+    pub fn luaopen_base(L: *mut lua_State) -> c_int;
+    pub fn luaopen_coroutine(L: *mut lua_State) -> c_int;
+    pub fn luaopen_debug(L: *mut lua_State) -> c_int;
+    pub fn luaopen_io(L: *mut lua_State) -> c_int;
+    pub fn luaopen_math(L: *mut lua_State) -> c_int;
+    pub fn luaopen_os(L: *mut lua_State) -> c_int;
+    pub fn luaopen_package(L: *mut lua_State) -> c_int;
+    pub fn luaopen_string(L: *mut lua_State) -> c_int;
+    pub fn luaopen_table(L: *mut lua_State) -> c_int;
+    pub fn luaopen_utf8(L: *mut lua_State) -> c_int;
+}
+
+#[inline(always)]
+pub fn lua_upvalueindex(i: c_int) -> c_int {
+    LUA_REGISTRYINDEX - i
+}
+
+#[inline(always)]
+pub unsafe fn lua_call(L: *mut lua_State, nargs: c_int, nresults: c_int) {
+    lua_callk(L, nargs, nresults, 0, None)
+}
+
+#[inline(always)]
+pub unsafe fn lua_pcall(L: *mut lua_State, nargs: c_int, nresults: c_int, errfunc: c_int) -> c_int {
+    lua_pcallk(L, nargs, nresults, errfunc, 0, None)
+}
+
+#[inline(always)]
+pub unsafe fn lua_yield(L: *mut lua_State, nresults: c_int) -> c_int {
+    lua_yieldk(L, nresults, 0, None)
+}
+
+#[inline(always)]
+pub unsafe fn lua_tonumber(L: *mut lua_State, idx: c_int) -> lua_Number {
+    lua_tonumberx(L, idx, ptr::null_mut())
+}
+
+#[inline(always)]
+pub unsafe fn lua_tointeger(L: *mut lua_State, idx: c_int) -> lua_Integer {
+    lua_tointegerx(L, idx, ptr::null_mut())
+}
+
+#[inline(always)]
+pub unsafe fn lua_newuserdata(L: *mut lua_State, sz: libc::size_t) -> *mut libc::c_void {
+    lua_newuserdatauv(L, sz, 1)
+}
+
+#[inline(always)]
+pub unsafe fn lua_getuservalue(L: *mut lua_State, idx: c_int) -> c_int {
+    lua_getiuservalue(L, idx, 1)
+}
+
+#[inline(always)]
+pub unsafe fn lua_setuservalue(L: *mut lua_State, idx: c_int) -> c_int {
+    lua_setiuservalue(L, idx, 1)
+}
+
+#[inline(always)]
+pub unsafe fn lua_pop(L: *mut lua_State, n: c_int) {
+    lua_settop(L, -n - 1)
+}
+
+#[inline(always)]
+pub unsafe fn lua_newtable(L: *mut lua_State) {
+    lua_createtable(L, 0, 0)
+}
+
+#[inline(always)]
+pub unsafe fn lua_register(L: *mut lua_State, name: *const libc::c_char, f: lua_CFunction) {
+    lua_pushcfunction(L, f);
+    lua_setglobal(L, name)
+}
+
+#[inline(always)]
+pub unsafe fn lua_pushcfunction(L: *mut lua_State, f: lua_CFunction) {
+    lua_pushcclosure(L, f, 0)
+}
+
+#[inline(always)]
+pub unsafe fn lua_isfunction(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TFUNCTION
+}
+
+#[inline(always)]
+pub unsafe fn lua_istable(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TTABLE
+}
+
+#[inline(always)]
+pub unsafe fn lua_islightuserdata(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TLIGHTUSERDATA
+}
+
+#[inline(always)]
+pub unsafe fn lua_isnil(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TNIL
+}
+
+#[inline(always)]
+pub unsafe fn lua_isboolean(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TBOOLEAN
+}
+
+#[inline(always)]
+pub unsafe fn lua_isthread(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TTHREAD
+}
+
+#[inline(always)]
+pub unsafe fn lua_isnone(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TNONE
+}
+
+#[inline(always)]
+pub unsafe fn lua_isnoneornil(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) <= 0
+}
+
+// TODO: lua_pushliteral
+
+#[inline(always)]
+pub unsafe fn lua_pushglobaltable(L: *mut lua_State) {
+    lua_rawgeti(L, LUA_REGISTRYINDEX, LUA_RIDX_GLOBALS as lua_Integer);
+}
+
+#[inline(always)]
+pub unsafe fn lua_tostring(L: *mut lua_State, i: c_int) -> *const libc::c_char {
+    lua_tolstring(L, i, ptr::null_mut())
+}
+
+#[inline(always)]
+pub unsafe fn lua_insert(L: *mut lua_State, idx: c_int) {
+    lua_rotate(L, idx, 1)
+}
+
+#[inline(always)]
+pub unsafe fn lua_remove(L: *mut lua_State, idx: c_int) {
+    lua_rotate(L, idx, -1);
+    lua_pop(L, 1)
+}
+
+#[inline(always)]
+pub unsafe fn lua_replace(L: *mut lua_State, idx: c_int) {
+    lua_copy(L, -1, idx);
+    lua_pop(L, 1)
+}
+
+#[inline(always)]
+pub unsafe fn lua_pushunsigned(L: *mut lua_State, n: lua_Unsigned) {
+    lua_pushinteger(L, n as lua_Integer)
+}
+
+#[inline(always)]
+pub unsafe fn lua_tounsignedx(L: *mut lua_State, idx: c_int, isnum: *mut c_int) -> lua_Unsigned {
+    lua_tointegerx(L, idx, isnum) as lua_Unsigned
+}
+
+#[inline(always)]
+pub unsafe fn luaL_checkversion(L: *mut lua_State) {
+    luaL_checkversion_(L, LUA_VERSION_NUM as lua_Number, LUAL_NUMSIZES)
+}
+
+#[inline(always)]
+pub unsafe fn luaL_loadfile(L: *mut lua_State, filename: *const libc::c_char) -> c_int {
+    luaL_loadfilex(L, filename, ptr::null())
+}
+
+#[inline(always)]
+pub unsafe fn luaL_newlibtable(L: *mut lua_State, l: &[luaL_Reg]) {
+    lua_createtable(L, 0, l.len() as c_int)
+}
+
+/// Creates a table with the functions of `l`. Unlike the C macro, `l` must not end with a
+/// sentinel entry whose name is null.
+#[inline(always)]
+pub unsafe fn luaL_newlib(L: *mut lua_State, l: &[luaL_Reg]) {
+    let mut regs = l.to_vec();
+    regs.push(luaL_Reg {
+        name: ptr::null(),
+        func: None,
+    });
+    luaL_checkversion(L);
+    luaL_newlibtable(L, l);
+    luaL_setfuncs(L, regs.as_ptr(), 0);
+}
+
+#[inline(always)]
+pub unsafe fn luaL_argcheck(
+    L: *mut lua_State,
+    cond: bool,
+    numarg: c_int,
+    extramsg: *const libc::c_char,
+) {
+    if !cond {
+        luaL_argerror(L, numarg, extramsg);
+    }
+}
+
+#[inline(always)]
+pub unsafe fn luaL_checkstring(L: *mut lua_State, n: c_int) -> *const libc::c_char {
+    luaL_checklstring(L, n, ptr::null_mut())
+}
+
+#[inline(always)]
+pub unsafe fn luaL_optstring(
+    L: *mut lua_State,
+    n: c_int,
+    d: *const libc::c_char,
+) -> *const libc::c_char {
+    luaL_optlstring(L, n, d, ptr::null_mut())
+}
+
+#[inline(always)]
+pub unsafe fn luaL_checkint(L: *mut lua_State, n: c_int) -> c_int {
+    luaL_checkinteger(L, n) as c_int
+}
+
+#[inline(always)]
+pub unsafe fn luaL_optint(L: *mut lua_State, n: c_int, d: c_int) -> c_int {
+    luaL_optinteger(L, n, d as lua_Integer) as c_int
+}
+
+#[inline(always)]
+pub unsafe fn luaL_typename(L: *mut lua_State, i: c_int) -> *const libc::c_char {
+    lua_typename(L, lua_type(L, i))
+}
+
+#[inline(always)]
+pub unsafe fn luaL_dofile(L: *mut lua_State, filename: *const libc::c_char) -> c_int {
+    match luaL_loadfile(L, filename) {
+        LUA_OK => lua_pcall(L, 0, MULTRET, 0),
+        err => err,
+    }
+}
+
+#[inline(always)]
+pub unsafe fn luaL_dostring(L: *mut lua_State, s: *const libc::c_char) -> c_int {
+    match luaL_loadstring(L, s) {
+        LUA_OK => lua_pcall(L, 0, MULTRET, 0),
+        err => err,
+    }
+}
+
+#[inline(always)]
+pub unsafe fn luaL_getmetatable(L: *mut lua_State, tname: *const libc::c_char) -> c_int {
+    lua_getfield(L, LUA_REGISTRYINDEX, tname)
+}
+
+#[inline(always)]
+pub unsafe fn luaL_loadbuffer(
+    L: *mut lua_State,
+    buff: *const libc::c_char,
+    sz: libc::size_t,
+    name: *const libc::c_char,
+) -> c_int {
+    luaL_loadbufferx(L, buff, sz, name, ptr::null())
+}
+
+#[inline(always)]
+pub unsafe fn luaL_addchar(B: *mut luaL_Buffer, c: libc::c_char) {
+    if (*B).n >= (*B).size {
+        luaL_prepbuffsize(B, 1);
+    }
+    *(*B).b.add((*B).n) = c;
+    (*B).n += 1;
+}
+
+#[inline(always)]
+pub unsafe fn luaL_addsize(B: *mut luaL_Buffer, s: libc::size_t) {
+    (*B).n += s;
+}
+
+#[inline(always)]
+pub unsafe fn luaL_prepbuffer(B: *mut luaL_Buffer) -> *mut libc::c_char {
+    luaL_prepbuffsize(B, LUAL_BUFFERSIZE as libc::size_t)
+}
+
+impl default::Default for lua_Debug {
+    fn default() -> lua_Debug {
+        lua_Debug {
+            event: 0,
+            name: ptr::null(),
+            namewhat: ptr::null(),
+            what: ptr::null(),
+            source: ptr::null(),
+            srclen: 0,
+            currentline: 0,
+            linedefined: 0,
+            lastlinedefined: 0,
+            nups: 0,
+            nparams: 0,
+            isvararg: 0,
+            istailcall: 0,
+            ftransfer: 0,
+            ntransfer: 0,
+            short_src: [0; LUA_IDSIZE],
+            i_ci: ptr::null_mut(),
+        }
+    }
+}
+
+// Compares the definitions of this crate with the sizes and offsets computed by the C compiler
+// in `layout.c`. A difference is a compile-time error.
+#[cfg(lua54_sys_layout)]
+mod layout {
+    use super::*;
+    use std::mem::offset_of;
+
+    mod c {
+        include!(concat!(env!("OUT_DIR"), "/layout.rs"));
+    }
+
+    const _: () = assert!(size_of::<lua_Debug>() == c::SIZEOF_LUA_DEBUG);
+    const _: () = assert!(offset_of!(lua_Debug, srclen) == c::OFFSETOF_LUA_DEBUG_SRCLEN);
+    const _: () = assert!(offset_of!(lua_Debug, short_src) == c::OFFSETOF_LUA_DEBUG_SHORT_SRC);
+    const _: () = assert!(offset_of!(lua_Debug, i_ci) == c::OFFSETOF_LUA_DEBUG_I_CI);
+    const _: () = assert!(size_of::<luaL_Buffer>() == c::SIZEOF_LUAL_BUFFER);
+    const _: () = assert!(offset_of!(luaL_Buffer, init) == c::OFFSETOF_LUAL_BUFFER_INIT);
+    const _: () = assert!(size_of::<luaL_Reg>() == c::SIZEOF_LUAL_REG);
+    const _: () = assert!(size_of::<luaL_Stream>() == c::SIZEOF_LUAL_STREAM);
+    const _: () = assert!(size_of::<lua_Number>() == c::SIZEOF_LUA_NUMBER);
+    const _: () = assert!(size_of::<lua_Integer>() == c::SIZEOF_LUA_INTEGER);
+    const _: () = assert!(size_of::<lua_Unsigned>() == c::SIZEOF_LUA_UNSIGNED);
+    const _: () = assert!(size_of::<lua_KContext>() == c::SIZEOF_LUA_KCONTEXT);
+    const _: () = assert!(LUA_IDSIZE == c::IDSIZE);
+    const _: () = assert!(LUAL_BUFFERSIZE == c::BUFFERSIZE);
+    const _: () = assert!(LUAL_NUMSIZES == c::NUMSIZES);
+    const _: () = assert!(LUA_REGISTRYINDEX == c::REGISTRYINDEX);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::mem;
+
+    unsafe fn to_str<'a>(s: *const libc::c_char) -> &'a str {
+        CStr::from_ptr(s).to_str().unwrap()
+    }
+
+    extern "C" fn open_answer(L: *mut lua_State) -> c_int {
+        unsafe {
+            lua_newtable(L);
+            lua_pushinteger(L, 42);
+            lua_setfield(L, -2, b"value\0".as_ptr() as *const _);
+        }
+        1
+    }
+
+    extern "C" fn add(L: *mut lua_State) -> c_int {
+        unsafe {
+            let sum = luaL_checkinteger(L, 1) + luaL_optinteger(L, 2, 1);
+            lua_pushinteger(L, sum);
+        }
+        1
+    }
+
+    #[test]
+    fn metatables_and_udata() {
+        unsafe {
+            let L = luaL_newstate();
+            let tname = b"lua54_sys.Test\0".as_ptr() as *const _;
+
+            assert_eq!(luaL_newmetatable(L, tname), 1);
+            assert_eq!(luaL_newmetatable(L, tname), 0);
+            lua_pop(L, 2);
+
+            lua_newuserdata(L, 8);
+            assert!(luaL_testudata(L, -1, tname).is_null());
+            luaL_setmetatable(L, tname);
+            let ptr = lua_touserdata(L, -1);
+            assert_eq!(luaL_testudata(L, -1, tname), ptr);
+            assert_eq!(luaL_checkudata(L, -1, tname), ptr);
+
+            assert_eq!(luaL_getmetatable(L, tname), LUA_TTABLE);
+            assert!(lua_getmetatable(L, -2) != 0);
+            assert!(lua_rawequal(L, -1, -2) != 0);
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn buffer() {
+        unsafe {
+            let L = luaL_newstate();
+            let mut buffer: luaL_Buffer = mem::zeroed();
+            luaL_buffinit(L, &mut buffer);
+            luaL_addstring(&mut buffer, b"hello\0".as_ptr() as *const _);
+            luaL_addchar(&mut buffer, b' ' as libc::c_char);
+            lua_pushinteger(L, 5);
+            luaL_addvalue(&mut buffer);
+            // More than the initial buffer, to check the growth.
+            for _ in 0..LUAL_BUFFERSIZE {
+                luaL_addchar(&mut buffer, b'!' as libc::c_char);
+            }
+            luaL_pushresult(&mut buffer);
+
+            let mut len = 0;
+            let s = lua_tolstring(L, -1, &mut len);
+            assert_eq!(len, 7 + LUAL_BUFFERSIZE);
+            assert!(to_str(s).starts_with("hello 5!!"));
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn stack_macros() {
+        unsafe {
+            let L = luaL_newstate();
+            for i in 1..5 {
+                lua_pushinteger(L, i);
+            }
+            lua_insert(L, 1);
+            lua_remove(L, 2);
+            lua_pushinteger(L, 10);
+            lua_replace(L, 2);
+            let stack: Vec<_> = (1..=lua_gettop(L)).map(|i| lua_tointeger(L, i)).collect();
+            assert_eq!(stack, [4, 10, 3]);
+
+            lua_pushunsigned(L, u64::MAX);
+            assert!(lua_isinteger(L, -1) != 0);
+            assert_eq!(lua_tointeger(L, -1), -1);
+            assert_eq!(lua_tounsignedx(L, -1, ptr::null_mut()), u64::MAX);
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn load_and_strings() {
+        unsafe {
+            let L = luaL_newstate();
+            luaL_openlibs(L);
+
+            let code = b"return #({...}), 'x'";
+            let status = luaL_loadbufferx(
+                L,
+                code.as_ptr() as *const _,
+                code.len(),
+                b"=chunk\0".as_ptr() as *const _,
+                b"b\0".as_ptr() as *const _,
+            );
+            assert_eq!(status, LUA_ERRSYNTAX);
+            lua_pop(L, 1);
+            let status = luaL_loadbuffer(
+                L,
+                code.as_ptr() as *const _,
+                code.len(),
+                b"=chunk\0".as_ptr() as *const _,
+            );
+            assert_eq!(status, LUA_OK);
+            lua_pushinteger(L, 1);
+            lua_pushinteger(L, 2);
+            assert_eq!(lua_pcall(L, 2, 2, 0), LUA_OK);
+            assert_eq!(lua_tointegerx(L, -2, ptr::null_mut()), 2);
+            lua_pop(L, 2);
+
+            assert_eq!(
+                luaL_dostring(L, b"t = {1, 2, 3}\0".as_ptr() as *const _),
+                LUA_OK
+            );
+            assert_eq!(lua_getglobal(L, b"t\0".as_ptr() as *const _), LUA_TTABLE);
+            assert_eq!(luaL_len(L, -1), 3);
+            assert_eq!(to_str(luaL_typename(L, -1)), "table");
+            assert_eq!(
+                to_str(luaL_tolstring(L, -1, ptr::null_mut())).find("table: "),
+                Some(0)
+            );
+            lua_pop(L, 2);
+
+            let s = luaL_gsub(
+                L,
+                b"a.b.c\0".as_ptr() as *const _,
+                b".\0".as_ptr() as *const _,
+                b"/\0".as_ptr() as *const _,
+            );
+            assert_eq!(to_str(s), "a/b/c");
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn traceback() {
+        unsafe {
+            let L = luaL_newstate();
+            luaL_traceback(L, L, b"oops\0".as_ptr() as *const _, 0);
+            let s = to_str(lua_tostring(L, -1));
+            assert!(s.starts_with("oops\nstack traceback:"), "{}", s);
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn requiref_and_newlib() {
+        unsafe {
+            let L = luaL_newstate();
+            luaL_openlibs(L);
+
+            luaL_requiref(L, b"answer\0".as_ptr() as *const _, open_answer, 1);
+            lua_pop(L, 1);
+
+            luaL_newlib(
+                L,
+                &[luaL_Reg {
+                    name: b"add\0".as_ptr() as *const _,
+                    func: Some(add),
+                }],
+            );
+            lua_setglobal(L, b"mylib\0".as_ptr() as *const _);
+
+            let code = b"return answer.value + mylib.add(1) + mylib.add(1, 2) \
+                         + (require 'answer' == answer and 100 or 0)\0";
+            assert_eq!(luaL_dostring(L, code.as_ptr() as *const _), LUA_OK);
+            assert_eq!(lua_tointegerx(L, -1, ptr::null_mut()), 42 + 2 + 3 + 100);
+
+            let code = b"return pcall(mylib.add, 'x')\0";
+            assert_eq!(luaL_dostring(L, code.as_ptr() as *const _), LUA_OK);
+            let msg = to_str(lua_tostring(L, -1));
+            assert!(msg.contains("bad argument #1"), "{}", msg);
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn version() {
+        assert!(LUA_RELEASE_VERSION.starts_with("5.4."));
+        unsafe {
+            let L = luaL_newstate();
+            assert_eq!(lua_version(L), LUA_VERSION_NUM as lua_Number);
+            lua_close(L);
+        }
+    }
+}
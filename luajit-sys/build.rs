@@ -0,0 +1,254 @@
+extern crate cc;
+extern crate pkg_config;
+
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Where the LuaJIT library comes from.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Source {
+    // The system library if pkg-config finds it, the vendored sources otherwise.
+    Auto,
+    System,
+    Vendored,
+}
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=LUAJIT_SYS_SOURCE");
+    println!("cargo:rerun-if-env-changed=LUAJIT_SYS_DEFINES");
+
+    let source = source();
+    let defines = defines();
+    let apicheck = env::var_os("CARGO_FEATURE_APICHECK").is_some();
+
+    if source != Source::Vendored {
+        match pkg_config::find_library("luajit") {
+            Ok(library) => {
+                if !defines.is_empty() {
+                    println!(
+                        "cargo:warning=LUAJIT_SYS_DEFINES is ignored when linking to the system \
+                         LuaJIT"
+                    );
+                }
+                if apicheck {
+                    println!(
+                        "cargo:warning=the `apicheck` feature is ignored when linking to the \
+                         system LuaJIT"
+                    );
+                }
+                let mut layout = cc::Build::new();
+                layout.includes(&library.include_paths);
+                check_layout(&layout);
+                write_version(&library.version, false);
+                return;
+            }
+            Err(err) => {
+                if source == Source::System {
+                    panic!("luajit-sys: could not find the system LuaJIT: {}", err);
+                }
+            }
+        }
+    }
+
+    // LuaJIT generates its interpreter with tools that are built and run during the build, which
+    // its makefile handles. The sources are copied because the build writes next to them.
+    println!("cargo:rerun-if-changed=luajit");
+    if env::var("HOST") != env::var("TARGET") {
+        panic!(
+            "luajit-sys: the bundled LuaJIT can't be cross-compiled; set LUAJIT_SYS_SOURCE to \
+             `system` to link to a LuaJIT built for the target"
+        );
+    }
+    if env::var("CARGO_CFG_TARGET_ENV") == Ok("msvc".to_string()) {
+        panic!("luajit-sys: the bundled LuaJIT can't be built with MSVC");
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let build_dir = Path::new(&out_dir).join("luajit");
+    if build_dir.exists() {
+        fs::remove_dir_all(&build_dir).unwrap();
+    }
+    copy_dir(Path::new("luajit"), &build_dir);
+    let src_dir = build_dir.join("src");
+
+    // Without the external unwinding of the system, LuaJIT throws errors with its internal
+    // unwinding, which like the `longjmp` of Lua skips the frames of the functions written in Rust
+    // instead of unwinding through them.
+    let mut cflags = vec![
+        "-fPIC".to_owned(),
+        "-DLUAJIT_ENABLE_LUA52COMPAT".to_owned(),
+        "-DLUAJIT_NO_UNWIND".to_owned(),
+    ];
+    if apicheck {
+        cflags.push("-DLUA_USE_APICHECK".to_owned());
+        cflags.push("-DLUA_USE_ASSERT".to_owned());
+    }
+    for (name, value) in &defines {
+        cflags.push(match *value {
+            Some(ref value) => format!("-D{}={}", name, value),
+            None => format!("-D{}", name),
+        });
+    }
+
+    let compiler = cc::Build::new().get_compiler();
+    let mut make = Command::new("make");
+    make.current_dir(&src_dir)
+        .arg("libluajit.a")
+        .arg("BUILDMODE=static")
+        .arg(format!("CC={}", compiler.path().display()))
+        .arg(format!("XCFLAGS={}", cflags.join(" ")));
+    if let Ok(jobs) = env::var("NUM_JOBS") {
+        make.arg(format!("-j{}", jobs));
+    }
+    let status = make
+        .status()
+        .expect("luajit-sys: can't run make to build LuaJIT");
+    assert!(status.success(), "luajit-sys: the build of LuaJIT failed");
+
+    println!("cargo:rustc-link-search=native={}", src_dir.display());
+    println!("cargo:rustc-link-lib=static=luajit");
+    if env::var("CARGO_CFG_TARGET_OS") == Ok("linux".to_string()) {
+        println!("cargo:rustc-link-lib=m");
+        println!("cargo:rustc-link-lib=dl");
+    }
+
+    let mut layout = cc::Build::new();
+    layout.include(&src_dir);
+    check_layout(&layout);
+    write_version(&vendored_version(&src_dir), true);
+}
+
+fn copy_dir(from: &Path, to: &Path) {
+    fs::create_dir_all(to).unwrap();
+    for entry in fs::read_dir(from).unwrap() {
+        let entry = entry.unwrap();
+        let target = to.join(entry.file_name());
+        if entry.file_type().unwrap().is_dir() {
+            copy_dir(&entry.path(), &target);
+        } else {
+            fs::copy(entry.path(), target).unwrap();
+        }
+    }
+}
+
+// Compiles and runs `layout.c`, which writes the sizes and offsets of the C types to `layout.rs`.
+// The crate compares them with its own definitions at compile time, so that a difference, for
+// example with a system library built with other options, fails the build instead of corrupting
+// memory. The program can't run when cross-compiling, in which case the checks are skipped.
+fn check_layout(build: &cc::Build) {
+    println!("cargo:rerun-if-changed=layout.c");
+    println!("cargo:rustc-check-cfg=cfg(luajit_sys_layout)");
+
+    if env::var("HOST") != env::var("TARGET") {
+        println!("cargo:warning=the layout of the Lua types isn't checked when cross-compiling");
+        return;
+    }
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let program = Path::new(&out_dir).join(if cfg!(windows) {
+        "layout.exe"
+    } else {
+        "layout"
+    });
+
+    let compiler = build.get_compiler();
+    let mut command = compiler.to_command();
+    command.arg("layout.c");
+    if compiler.is_like_msvc() {
+        command
+            .arg(format!("/Fe{}", program.display()))
+            .arg(format!("/Fo{}\\", out_dir));
+    } else {
+        command.arg("-o").arg(&program);
+    }
+    let status = command
+        .status()
+        .expect("luajit-sys: can't run the C compiler");
+    assert!(status.success(), "luajit-sys: can't compile layout.c");
+
+    let output = Command::new(&program)
+        .output()
+        .expect("luajit-sys: can't run the layout program");
+    assert!(
+        output.status.success(),
+        "luajit-sys: the layout program failed"
+    );
+    fs::write(Path::new(&out_dir).join("layout.rs"), output.stdout).unwrap();
+    println!("cargo:rustc-cfg=luajit_sys_layout");
+}
+
+// Determines the source of the library from the `LUAJIT_SYS_SOURCE` environment variable, then
+// from the features.
+fn source() -> Source {
+    let system = env::var_os("CARGO_FEATURE_SYSTEM").is_some();
+    let vendored = env::var_os("CARGO_FEATURE_VENDORED").is_some();
+
+    match env::var("LUAJIT_SYS_SOURCE") {
+        Ok(ref value) if value == "system" => return Source::System,
+        Ok(ref value) if value == "vendored" => return Source::Vendored,
+        Ok(ref value) if value == "auto" => return Source::Auto,
+        Ok(value) => panic!(
+            "luajit-sys: invalid LUAJIT_SYS_SOURCE `{}`, expected `system`, `vendored` or `auto`",
+            value
+        ),
+        Err(_) => {}
+    }
+
+    match (system, vendored) {
+        (true, true) => panic!(
+            "luajit-sys: the `system` and `vendored` features are mutually exclusive; use \
+             LUAJIT_SYS_SOURCE to choose"
+        ),
+        (true, false) => Source::System,
+        (false, true) => Source::Vendored,
+        (false, false) => Source::Auto,
+    }
+}
+
+// Parses the `LUAJIT_SYS_DEFINES` environment variable, a list of `NAME` or `NAME=VALUE`
+// separated by spaces or commas.
+fn defines() -> Vec<(String, Option<String>)> {
+    let defines = env::var("LUAJIT_SYS_DEFINES").unwrap_or_default();
+    defines
+        .split(|c: char| c == ',' || c.is_whitespace())
+        .filter(|define| !define.is_empty())
+        .map(|define| match define.find('=') {
+            Some(pos) => (define[..pos].to_owned(), Some(define[pos + 1..].to_owned())),
+            None => (define.to_owned(), None),
+        })
+        .collect()
+}
+
+// Reads the release of the vendored sources in the `luajit.h` generated by the build, such as
+// `2.1.ROLLING` when the sources don't record the time of their commit.
+fn vendored_version(src_dir: &Path) -> String {
+    let header =
+        fs::read_to_string(src_dir.join("luajit.h")).expect("luajit-sys: can't read luajit.h");
+    header
+        .lines()
+        .filter_map(|line| line.strip_prefix("#define LUAJIT_VERSION\t"))
+        .map(|value| {
+            value
+                .trim()
+                .trim_matches('"')
+                .trim_start_matches("LuaJIT ")
+                .to_owned()
+        })
+        .next()
+        .expect("luajit-sys: missing version in luajit.h")
+}
+
+fn write_version(version: &str, vendored: bool) {
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let code = format!(
+        "/// Release of LuaJIT that this crate is linked to, such as `\"2.1.1700008891\"`.\n\
+         pub const LUA_RELEASE_VERSION: &str = {:?};\n\
+         /// True if LuaJIT has been built from the sources vendored in this crate, false if it is \
+         the library of the system.\n\
+         pub const LUA_VENDORED: bool = {};\n",
+        version, vendored
+    );
+    fs::write(Path::new(&out_dir).join("version.rs"), code).unwrap();
+}
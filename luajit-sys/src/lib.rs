@@ -0,0 +1,816 @@
+//! Bindings for LuaJIT 2.1.
+//!
+//! By default, the crate links to the LuaJIT of the system if pkg-config finds it, and builds the
+//! bundled sources otherwise. The `system` and `vendored` features force one or the other. The
+//! `LUAJIT_SYS_SOURCE` environment variable, set to `system`, `vendored` or `auto`, overrides the
+//! features.
+//!
+//! The bundled sources are built with LuaJIT's makefile, so building them requires `make` and
+//! isn't possible when cross-compiling or with MSVC. The `LUAJIT_SYS_DEFINES` environment variable
+//! can contain additional defines, separated by spaces or commas, such as `LUAJIT_DISABLE_JIT`.
+//! The `apicheck` feature enables the checks of the Lua API and the internal assertions of
+//! LuaJIT, which abort the process when the API is misused. The bundled sources are built with
+//! `LUAJIT_ENABLE_LUA52COMPAT`, which enables the extensions of Lua 5.2 such as `__pairs` and
+//! `__len` on tables, and with the internal unwinding of LuaJIT, so that errors skip the frames of
+//! Rust functions like the `longjmp` of Lua instead of unwinding through them.
+//!
+//! The API is the one of Lua 5.1, plus the few functions of Lua 5.2 that LuaJIT provides, such as
+//! `lua_tointegerx` or `lua_loadx`, and the functions of `luajit.h`. The macros of `lua.h` and
+//! `lauxlib.h` are provided as functions. Unlike the libraries of Lua 5.2, the `luaopen_*`
+//! functions must be called through `lua_call`.
+
+#![allow(non_camel_case_types)]
+#![allow(non_snake_case)]
+#![allow(dead_code)]
+#![allow(improper_ctypes)]
+
+extern crate libc;
+
+use libc::c_int;
+use std::{default, ptr};
+
+include!(concat!(env!("OUT_DIR"), "/version.rs"));
+
+pub const LUA_VERSION_NUM: c_int = 501;
+
+pub const MULTRET: c_int = -1;
+
+pub const LUA_REGISTRYINDEX: c_int = -10000;
+pub const LUA_ENVIRONINDEX: c_int = -10001;
+pub const LUA_GLOBALSINDEX: c_int = -10002;
+
+pub const LUA_OK: c_int = 0;
+pub const LUA_YIELD: c_int = 1;
+pub const LUA_ERRRUN: c_int = 2;
+pub const LUA_ERRSYNTAX: c_int = 3;
+pub const LUA_ERRMEM: c_int = 4;
+pub const LUA_ERRERR: c_int = 5;
+pub const LUA_ERRFILE: c_int = LUA_ERRERR + 1;
+
+#[repr(C)]
+#[allow(missing_copy_implementations)]
+pub struct lua_State;
+
+pub type lua_CFunction = extern "C" fn(L: *mut lua_State) -> c_int;
+
+pub type lua_Reader = extern "C" fn(
+    L: *mut lua_State,
+    ud: *mut libc::c_void,
+    sz: *mut libc::size_t,
+) -> *const libc::c_char;
+pub type lua_Writer = extern "C" fn(
+    L: *mut lua_State,
+    p: *const libc::c_void,
+    sz: libc::size_t,
+    ud: *mut libc::c_void,
+) -> libc::c_int;
+
+pub type lua_Alloc = extern "C" fn(
+    ud: *mut libc::c_void,
+    ptr: *mut libc::c_void,
+    osize: libc::size_t,
+    nsize: libc::size_t,
+) -> *mut libc::c_void;
+
+pub type lua_Hook = extern "C" fn(L: *mut lua_State, ar: *mut lua_Debug);
+
+pub const LUA_TNONE: c_int = -1;
+
+pub const LUA_TNIL: c_int = 0;
+pub const LUA_TBOOLEAN: c_int = 1;
+pub const LUA_TLIGHTUSERDATA: c_int = 2;
+pub const LUA_TNUMBER: c_int = 3;
+pub const LUA_TSTRING: c_int = 4;
+pub const LUA_TTABLE: c_int = 5;
+pub const LUA_TFUNCTION: c_int = 6;
+pub const LUA_TUSERDATA: c_int = 7;
+pub const LUA_TTHREAD: c_int = 8;
+
+pub const LUA_MINSTACK: c_int = 20;
+
+pub const LUA_REFNIL: c_int = -1;
+pub const LUA_NOREF: c_int = -2;
+
+pub type lua_Number = libc::c_double;
+pub type lua_Integer = libc::ptrdiff_t;
+
+pub const LUA_GCSTOP: c_int = 0;
+pub const LUA_GCRESTART: c_int = 1;
+pub const LUA_GCCOLLECT: c_int = 2;
+pub const LUA_GCCOUNT: c_int = 3;
+pub const LUA_GCCOUNTB: c_int = 4;
+pub const LUA_GCSTEP: c_int = 5;
+pub const LUA_GCSETPAUSE: c_int = 6;
+pub const LUA_GCSETSTEPMUL: c_int = 7;
+pub const LUA_GCISRUNNING: c_int = 9;
+
+pub const LUA_HOOKCALL: c_int = 0;
+pub const LUA_HOOKRET: c_int = 1;
+pub const LUA_HOOKLINE: c_int = 2;
+pub const LUA_HOOKCOUNT: c_int = 3;
+pub const LUA_HOOKTAILRET: c_int = 4;
+
+pub const LUA_MASKCALL: c_int = 1 << LUA_HOOKCALL as usize;
+pub const LUA_MASKRET: c_int = 1 << LUA_HOOKRET as usize;
+pub const LUA_MASKLINE: c_int = 1 << LUA_HOOKLINE as usize;
+pub const LUA_MASKCOUNT: c_int = 1 << LUA_HOOKCOUNT as usize;
+
+pub const LUA_IDSIZE: usize = 60;
+
+#[repr(C)]
+#[allow(missing_copy_implementations)]
+pub struct lua_Debug {
+    pub event: c_int,
+    pub name: *const libc::c_char,
+    pub namewhat: *const libc::c_char,
+    pub what: *const libc::c_char,
+    pub source: *const libc::c_char,
+    pub currentline: c_int,
+    pub nups: c_int,
+    pub linedefined: c_int,
+    pub lastlinedefined: c_int,
+    pub short_src: [libc::c_char; LUA_IDSIZE],
+    // private to LuaJIT, but must be allocated because `lua_getstack` writes it
+    pub i_ci: c_int,
+}
+
+pub const LUA_FILEHANDLE: &[u8] = b"FILE*\0";
+
+/// Size of the buffer embedded in a `luaL_Buffer`, which depends on `BUFSIZ` in `luaconf.h`.
+pub const LUAL_BUFFERSIZE: usize = if libc::BUFSIZ > 16384 {
+    8192
+} else {
+    libc::BUFSIZ as usize
+};
+
+pub const LUAJIT_MODE_MASK: c_int = 0x00ff;
+
+pub const LUAJIT_MODE_ENGINE: c_int = 0;
+pub const LUAJIT_MODE_DEBUG: c_int = 1;
+pub const LUAJIT_MODE_FUNC: c_int = 2;
+pub const LUAJIT_MODE_ALLFUNC: c_int = 3;
+pub const LUAJIT_MODE_ALLSUBFUNC: c_int = 4;
+pub const LUAJIT_MODE_TRACE: c_int = 5;
+pub const LUAJIT_MODE_WRAPCFUNC: c_int = 0x10;
+
+pub const LUAJIT_MODE_OFF: c_int = 0x0000;
+pub const LUAJIT_MODE_ON: c_int = 0x0100;
+pub const LUAJIT_MODE_FLUSH: c_int = 0x0200;
+
+pub type luaJIT_profile_callback =
+    extern "C" fn(data: *mut libc::c_void, L: *mut lua_State, samples: c_int, vmstate: c_int);
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct luaL_Reg {
+    pub name: *const libc::c_char,
+    pub func: Option<lua_CFunction>,
+}
+
+/// String buffer of the auxiliary library.
+///
+/// `p` points into `buffer`, so a buffer must not be moved between `luaL_buffinit` and
+/// `luaL_pushresult`.
+#[repr(C)]
+#[allow(missing_copy_implementations)]
+pub struct luaL_Buffer {
+    pub p: *mut libc::c_char,
+    pub lvl: c_int,
+    pub L: *mut lua_State,
+    pub buffer: [libc::c_char; LUAL_BUFFERSIZE],
+}
+
+extern "C" {
+    pub fn lua_newstate(f: lua_Alloc, ud: *mut libc::c_void) -> *mut lua_State;
+    pub fn lua_close(L: *mut lua_State);
+    pub fn lua_newthread(L: *mut lua_State) -> *mut lua_State;
+
+    pub fn lua_atpanic(L: *mut lua_State, panicf: lua_CFunction) -> lua_CFunction;
+
+    pub fn lua_version(L: *mut lua_State) -> *const lua_Number;
+
+    pub fn lua_gettop(L: *mut lua_State) -> c_int;
+    pub fn lua_settop(L: *mut lua_State, idx: c_int);
+    pub fn lua_pushvalue(L: *mut lua_State, idx: c_int);
+    pub fn lua_remove(L: *mut lua_State, idx: c_int);
+    pub fn lua_insert(L: *mut lua_State, idx: c_int);
+    pub fn lua_replace(L: *mut lua_State, idx: c_int);
+    pub fn lua_copy(L: *mut lua_State, fromidx: c_int, toidx: c_int);
+    pub fn lua_checkstack(L: *mut lua_State, sz: c_int) -> c_int;
+
+    pub fn lua_xmove(from: *mut lua_State, to: *mut lua_State, n: c_int);
+
+    pub fn lua_isnumber(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_isstring(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_iscfunction(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_isuserdata(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_type(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_typename(L: *mut lua_State, tp: c_int) -> *const libc::c_char;
+
+    pub fn lua_equal(L: *mut lua_State, idx1: c_int, idx2: c_int) -> c_int;
+    pub fn lua_rawequal(L: *mut lua_State, idx1: c_int, idx2: c_int) -> c_int;
+    pub fn lua_lessthan(L: *mut lua_State, idx1: c_int, idx2: c_int) -> c_int;
+
+    pub fn lua_tonumber(L: *mut lua_State, idx: c_int) -> lua_Number;
+    pub fn lua_tointeger(L: *mut lua_State, idx: c_int) -> lua_Integer;
+    pub fn lua_tonumberx(L: *mut lua_State, idx: c_int, isnum: *mut c_int) -> lua_Number;
+    pub fn lua_tointegerx(L: *mut lua_State, idx: c_int, isnum: *mut c_int) -> lua_Integer;
+    pub fn lua_toboolean(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_tolstring(
+        L: *mut lua_State,
+        idx: c_int,
+        len: *mut libc::size_t,
+    ) -> *const libc::c_char;
+    pub fn lua_objlen(L: *mut lua_State, idx: c_int) -> libc::size_t;
+    pub fn lua_tocfunction(L: *mut lua_State, idx: c_int) -> Option<lua_CFunction>;
+    pub fn lua_touserdata(L: *mut lua_State, idx: c_int) -> *mut libc::c_void;
+    pub fn lua_tothread(L: *mut lua_State, idx: c_int) -> *mut lua_State;
+    pub fn lua_topointer(L: *mut lua_State, idx: c_int) -> *const libc::c_void;
+
+    pub fn lua_pushnil(L: *mut lua_State);
+    pub fn lua_pushnumber(L: *mut lua_State, n: lua_Number);
+    pub fn lua_pushinteger(L: *mut lua_State, n: lua_Integer);
+    pub fn lua_pushlstring(L: *mut lua_State, s: *const libc::c_char, l: libc::size_t);
+    pub fn lua_pushstring(L: *mut lua_State, s: *const libc::c_char);
+    pub fn lua_pushfstring(L: *mut lua_State, fmt: *const libc::c_char, ...)
+        -> *const libc::c_char;
+    pub fn lua_pushcclosure(L: *mut lua_State, f: lua_CFunction, n: c_int);
+    pub fn lua_pushboolean(L: *mut lua_State, b: c_int);
+    pub fn lua_pushlightuserdata(L: *mut lua_State, p: *mut libc::c_void);
+    pub fn lua_pushthread(L: *mut lua_State) -> c_int;
+
+    pub fn lua_gettable(L: *mut lua_State, idx: c_int);
+    pub fn lua_getfield(L: *mut lua_State, idx: c_int, k: *const libc::c_char);
+    pub fn lua_rawget(L: *mut lua_State, idx: c_int);
+    pub fn lua_rawgeti(L: *mut lua_State, idx: c_int, n: c_int);
+    pub fn lua_createtable(L: *mut lua_State, narr: c_int, nrec: c_int);
+    pub fn lua_newuserdata(L: *mut lua_State, sz: libc::size_t) -> *mut libc::c_void;
+    pub fn lua_getmetatable(L: *mut lua_State, objindex: c_int) -> c_int;
+    pub fn lua_getfenv(L: *mut lua_State, idx: c_int);
+
+    pub fn lua_settable(L: *mut lua_State, idx: c_int);
+    pub fn lua_setfield(L: *mut lua_State, idx: c_int, k: *const libc::c_char);
+    pub fn lua_rawset(L: *mut lua_State, idx: c_int);
+    pub fn lua_rawseti(L: *mut lua_State, idx: c_int, n: c_int);
+    pub fn lua_setmetatable(L: *mut lua_State, objindex: c_int) -> c_int;
+    pub fn lua_setfenv(L: *mut lua_State, idx: c_int) -> c_int;
+
+    pub fn lua_call(L: *mut lua_State, nargs: c_int, nresults: c_int);
+    pub fn lua_pcall(L: *mut lua_State, nargs: c_int, nresults: c_int, errfunc: c_int) -> c_int;
+    pub fn lua_cpcall(L: *mut lua_State, func: lua_CFunction, ud: *mut libc::c_void) -> c_int;
+    pub fn lua_load(
+        L: *mut lua_State,
+        reader: lua_Reader,
+        dt: *mut libc::c_void,
+        chunkname: *const libc::c_char,
+    ) -> c_int;
+    pub fn lua_loadx(
+        L: *mut lua_State,
+        reader: lua_Reader,
+        dt: *mut libc::c_void,
+        chunkname: *const libc::c_char,
+        mode: *const libc::c_char,
+    ) -> c_int;
+    pub fn lua_dump(L: *mut lua_State, writer: lua_Writer, data: *mut libc::c_void) -> c_int;
+
+    pub fn lua_yield(L: *mut lua_State, nresults: c_int) -> c_int;
+    pub fn lua_resume(L: *mut lua_State, narg: c_int) -> c_int;
+    pub fn lua_status(L: *mut lua_State) -> c_int;
+    pub fn lua_isyieldable(L: *mut lua_State) -> c_int;
+
+    pub fn lua_gc(L: *mut lua_State, what: c_int, data: c_int) -> c_int;
+
+    pub fn lua_error(L: *mut lua_State) -> c_int;
+    pub fn lua_next(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_concat(L: *mut lua_State, n: c_int);
+
+    pub fn lua_getallocf(L: *mut lua_State, ud: *mut *mut libc::c_void) -> lua_Alloc;
+    pub fn lua_setallocf(L: *mut lua_State, f: lua_Alloc, ud: *mut libc::c_void);
+
+    pub fn lua_getstack(L: *mut lua_State, level: c_int, ar: *mut lua_Debug) -> c_int;
+    pub fn lua_getinfo(L: *mut lua_State, what: *const libc::c_char, ar: *mut lua_Debug) -> c_int;
+    pub fn lua_getlocal(L: *mut lua_State, ar: *const lua_Debug, n: c_int) -> *const libc::c_char;
+    pub fn lua_setlocal(L: *mut lua_State, ar: *const lua_Debug, n: c_int) -> *const libc::c_char;
+    pub fn lua_getupvalue(L: *mut lua_State, funcindex: c_int, n: c_int) -> *const libc::c_char;
+    pub fn lua_setupvalue(L: *mut lua_State, funcindex: c_int, n: c_int) -> *const libc::c_char;
+
+    pub fn lua_upvalueid(L: *mut lua_State, fidx: c_int, n: c_int) -> *mut libc::c_void;
+    pub fn lua_upvaluejoin(L: *mut lua_State, fidx1: c_int, n1: c_int, fidx2: c_int, n2: c_int);
+
+    pub fn lua_sethook(
+        L: *mut lua_State,
+        func: Option<lua_Hook>,
+        mask: c_int,
+        count: c_int,
+    ) -> c_int;
+    pub fn lua_gethook(L: *mut lua_State) -> Option<lua_Hook>;
+    pub fn lua_gethookmask(L: *mut lua_State) -> c_int;
+    pub fn lua_gethookcount(L: *mut lua_State) -> c_int;
+
+    pub fn luaJIT_setmode(L: *mut lua_State, idx: c_int, mode: c_int) -> c_int;
+    pub fn luaJIT_profile_start(
+        L: *mut lua_State,
+        mode: *const libc::c_char,
+        cb: luaJIT_profile_callback,
+        data: *mut libc::c_void,
+    );
+    pub fn luaJIT_profile_stop(L: *mut lua_State);
+    pub fn luaJIT_profile_dumpstack(
+        L: *mut lua_State,
+        fmt: *const libc::c_char,
+        depth: c_int,
+        len: *mut libc::size_t,
+    ) -> *const libc::c_char;
+
+    pub fn luaL_openlibs(L: *mut lua_State);
+
+    pub fn luaL_openlib(
+        L: *mut lua_State,
+        libname: *const libc::c_char,
+        l: *const luaL_Reg,
+        nup: c_int,
+    );
+    pub fn luaL_register(L: *mut lua_State, libname: *const libc::c_char, l: *const luaL_Reg);
+    pub fn luaL_getmetafield(L: *mut lua_State, obj: c_int, e: *const libc::c_char) -> c_int;
+    pub fn luaL_callmeta(L: *mut lua_State, obj: c_int, e: *const libc::c_char) -> c_int;
+    pub fn luaL_typerror(L: *mut lua_State, narg: c_int, tname: *const libc::c_char) -> c_int;
+    pub fn luaL_argerror(L: *mut lua_State, numarg: c_int, extramsg: *const libc::c_char) -> c_int;
+    pub fn luaL_checklstring(
+        L: *mut lua_State,
+        numArg: c_int,
+        l: *mut libc::size_t,
+    ) -> *const libc::c_char;
+    pub fn luaL_optlstring(
+        L: *mut lua_State,
+        numArg: c_int,
+        def: *const libc::c_char,
+        l: *mut libc::size_t,
+    ) -> *const libc::c_char;
+    pub fn luaL_checknumber(L: *mut lua_State, numArg: c_int) -> lua_Number;
+    pub fn luaL_optnumber(L: *mut lua_State, nArg: c_int, def: lua_Number) -> lua_Number;
+    pub fn luaL_checkinteger(L: *mut lua_State, numArg: c_int) -> lua_Integer;
+    pub fn luaL_optinteger(L: *mut lua_State, nArg: c_int, def: lua_Integer) -> lua_Integer;
+
+    pub fn luaL_checkstack(L: *mut lua_State, sz: c_int, msg: *const libc::c_char);
+    pub fn luaL_checktype(L: *mut lua_State, narg: c_int, t: c_int);
+    pub fn luaL_checkany(L: *mut lua_State, narg: c_int);
+
+    pub fn luaL_newmetatable(L: *mut lua_State, tname: *const libc::c_char) -> c_int;
+    pub fn luaL_setmetatable(L: *mut lua_State, tname: *const libc::c_char);
+    pub fn luaL_testudata(
+        L: *mut lua_State,
+        ud: c_int,
+        tname: *const libc::c_char,
+    ) -> *mut libc::c_void;
+    pub fn luaL_checkudata(
+        L: *mut lua_State,
+        ud: c_int,
+        tname: *const libc::c_char,
+    ) -> *mut libc::c_void;
+
+    pub fn luaL_where(L: *mut lua_State, lvl: c_int);
+    pub fn luaL_error(L: *mut lua_State, fmt: *const libc::c_char, ...) -> c_int;
+
+    pub fn luaL_checkoption(
+        L: *mut lua_State,
+        narg: c_int,
+        def: *const libc::c_char,
+        lst: *const *const libc::c_char,
+    ) -> c_int;
+
+    pub fn luaL_fileresult(L: *mut lua_State, stat: c_int, fname: *const libc::c_char) -> c_int;
+    pub fn luaL_execresult(L: *mut lua_State, stat: c_int) -> c_int;
+
+    pub fn luaL_ref(L: *mut lua_State, t: c_int) -> c_int;
+    pub fn luaL_unref(L: *mut lua_State, t: c_int, ref_id: c_int);
+
+    pub fn luaL_loadfile(L: *mut lua_State, filename: *const libc::c_char) -> c_int;
+    pub fn luaL_loadfilex(
+        L: *mut lua_State,
+        filename: *const libc::c_char,
+        mode: *const libc::c_char,
+    ) -> c_int;
+    pub fn luaL_loadbuffer(
+        L: *mut lua_State,
+        buff: *const libc::c_char,
+        sz: libc::size_t,
+        name: *const libc::c_char,
+    ) -> c_int;
+    pub fn luaL_loadbufferx(
+        L: *mut lua_State,
+        buff: *const libc::c_char,
+        sz: libc::size_t,
+        name: *const libc::c_char,
+        mode: *const libc::c_char,
+    ) -> c_int;
+    pub fn luaL_loadstring(L: *mut lua_State, s: *const libc::c_char) -> c_int;
+
+    pub fn luaL_newstate() -> *mut lua_State;
+
+    pub fn luaL_gsub(
+        L: *mut lua_State,
+        s: *const libc::c_char,
+        p: *const libc::c_char,
+        r: *const libc::c_char,
+    ) -> *const libc::c_char;
+
+    pub fn luaL_findtable(
+        L: *mut lua_State,
+        idx: c_int,
+        fname: *const libc::c_char,
+        szhint: c_int,
+    ) -> *const libc::c_char;
+
+    pub fn luaL_setfuncs(L: *mut lua_State, l: *const luaL_Reg, nup: c_int);
+    pub fn luaL_pushmodule(L: *mut lua_State, modname: *const libc::c_char, sizehint: c_int);
+
+    pub fn luaL_traceback(
+        L: *mut lua_State,
+        L1: *mut lua_State,
+        msg: *const libc::c_char,
+        level: c_int,
+    );
+
+    pub fn luaL_buffinit(L: *mut lua_State, B: *mut luaL_Buffer);
+    pub fn luaL_prepbuffer(B: *mut luaL_Buffer) -> *mut libc::c_char;
+    pub fn luaL_addlstring(B: *mut luaL_Buffer, s: *const libc::c_char, l: libc::size_t);
+    pub fn luaL_addstring(B: *mut luaL_Buffer, s: *const libc::c_char);
+    pub fn luaL_addvalue(B: *mut luaL_Buffer);
+    pub fn luaL_pushresult(B: *mut luaL_Buffer);
+
+    pub fn luaopen_base(L: *mut lua_State) -> c_int;
+    pub fn luaopen_bit(L: *mut lua_State) -> c_int;
+    pub fn luaopen_debug(L: *mut lua_State) -> c_int;
+    pub fn luaopen_ffi(L: *mut lua_State) -> c_int;
+    pub fn luaopen_io(L: *mut lua_State) -> c_int;
+    pub fn luaopen_jit(L: *mut lua_State) -> c_int;
+    pub fn luaopen_math(L: *mut lua_State) -> c_int;
+    pub fn luaopen_os(L: *mut lua_State) -> c_int;
+    pub fn luaopen_package(L: *mut lua_State) -> c_int;
+    pub fn luaopen_string(L: *mut lua_State) -> c_int;
+    pub fn luaopen_string_buffer(L: *mut lua_State) -> c_int;
+    pub fn luaopen_table(L: *mut lua_State) -> c_int;
+}
+
+#[inline(always)]
+pub fn lua_upvalueindex(i: c_int) -> c_int {
+    LUA_GLOBALSINDEX - i
+}
+
+#[inline(always)]
+pub unsafe fn lua_pop(L: *mut lua_State, n: c_int) {
+    lua_settop(L, -n - 1)
+}
+
+#[inline(always)]
+pub unsafe fn lua_newtable(L: *mut lua_State) {
+    lua_createtable(L, 0, 0)
+}
+
+#[inline(always)]
+pub unsafe fn lua_register(L: *mut lua_State, name: *const libc::c_char, f: lua_CFunction) {
+    lua_pushcfunction(L, f);
+    lua_setglobal(L, name)
+}
+
+#[inline(always)]
+pub unsafe fn lua_pushcfunction(L: *mut lua_State, f: lua_CFunction) {
+    lua_pushcclosure(L, f, 0)
+}
+
+#[inline(always)]
+pub unsafe fn lua_strlen(L: *mut lua_State, idx: c_int) -> libc::size_t {
+    lua_objlen(L, idx)
+}
+
+#[inline(always)]
+pub unsafe fn lua_isfunction(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TFUNCTION
+}
+
+#[inline(always)]
+pub unsafe fn lua_istable(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TTABLE
+}
+
+#[inline(always)]
+pub unsafe fn lua_islightuserdata(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TLIGHTUSERDATA
+}
+
+#[inline(always)]
+pub unsafe fn lua_isnil(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TNIL
+}
+
+#[inline(always)]
+pub unsafe fn lua_isboolean(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TBOOLEAN
+}
+
+#[inline(always)]
+pub unsafe fn lua_isthread(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TTHREAD
+}
+
+#[inline(always)]
+pub unsafe fn lua_isnone(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) == LUA_TNONE
+}
+
+#[inline(always)]
+pub unsafe fn lua_isnoneornil(L: *mut lua_State, idx: c_int) -> bool {
+    lua_type(L, idx) <= 0
+}
+
+#[inline(always)]
+pub unsafe fn lua_setglobal(L: *mut lua_State, var: *const libc::c_char) {
+    lua_setfield(L, LUA_GLOBALSINDEX, var)
+}
+
+#[inline(always)]
+pub unsafe fn lua_getglobal(L: *mut lua_State, var: *const libc::c_char) {
+    lua_getfield(L, LUA_GLOBALSINDEX, var)
+}
+
+#[inline(always)]
+pub unsafe fn lua_tostring(L: *mut lua_State, i: c_int) -> *const libc::c_char {
+    lua_tolstring(L, i, ptr::null_mut())
+}
+
+#[inline(always)]
+pub unsafe fn lua_getregistry(L: *mut lua_State) {
+    lua_pushvalue(L, LUA_REGISTRYINDEX)
+}
+
+#[inline(always)]
+pub unsafe fn lua_getgccount(L: *mut lua_State) -> c_int {
+    lua_gc(L, LUA_GCCOUNT, 0)
+}
+
+#[inline(always)]
+pub unsafe fn luaL_argcheck(
+    L: *mut lua_State,
+    cond: bool,
+    numarg: c_int,
+    extramsg: *const libc::c_char,
+) {
+    if !cond {
+        luaL_argerror(L, numarg, extramsg);
+    }
+}
+
+#[inline(always)]
+pub unsafe fn luaL_checkstring(L: *mut lua_State, n: c_int) -> *const libc::c_char {
+    luaL_checklstring(L, n, ptr::null_mut())
+}
+
+#[inline(always)]
+pub unsafe fn luaL_optstring(
+    L: *mut lua_State,
+    n: c_int,
+    d: *const libc::c_char,
+) -> *const libc::c_char {
+    luaL_optlstring(L, n, d, ptr::null_mut())
+}
+
+#[inline(always)]
+pub unsafe fn luaL_checkint(L: *mut lua_State, n: c_int) -> c_int {
+    luaL_checkinteger(L, n) as c_int
+}
+
+#[inline(always)]
+pub unsafe fn luaL_optint(L: *mut lua_State, n: c_int, d: c_int) -> c_int {
+    luaL_optinteger(L, n, d as lua_Integer) as c_int
+}
+
+#[inline(always)]
+pub unsafe fn luaL_typename(L: *mut lua_State, i: c_int) -> *const libc::c_char {
+    lua_typename(L, lua_type(L, i))
+}
+
+#[inline(always)]
+pub unsafe fn luaL_dofile(L: *mut lua_State, filename: *const libc::c_char) -> c_int {
+    match luaL_loadfile(L, filename) {
+        LUA_OK => lua_pcall(L, 0, MULTRET, 0),
+        err => err,
+    }
+}
+
+#[inline(always)]
+pub unsafe fn luaL_dostring(L: *mut lua_State, s: *const libc::c_char) -> c_int {
+    match luaL_loadstring(L, s) {
+        LUA_OK => lua_pcall(L, 0, MULTRET, 0),
+        err => err,
+    }
+}
+
+#[inline(always)]
+pub unsafe fn luaL_getmetatable(L: *mut lua_State, tname: *const libc::c_char) {
+    lua_getfield(L, LUA_REGISTRYINDEX, tname)
+}
+
+#[inline(always)]
+pub unsafe fn luaL_addchar(B: *mut luaL_Buffer, c: libc::c_char) {
+    if (*B).p >= (*B).buffer.as_mut_ptr().add(LUAL_BUFFERSIZE) {
+        luaL_prepbuffer(B);
+    }
+    *(*B).p = c;
+    (*B).p = (*B).p.add(1);
+}
+
+#[inline(always)]
+pub unsafe fn luaL_addsize(B: *mut luaL_Buffer, s: libc::size_t) {
+    (*B).p = (*B).p.add(s);
+}
+
+impl default::Default for lua_Debug {
+    fn default() -> lua_Debug {
+        lua_Debug {
+            event: 0,
+            name: ptr::null(),
+            namewhat: ptr::null(),
+            what: ptr::null(),
+            source: ptr::null(),
+            currentline: 0,
+            nups: 0,
+            linedefined: 0,
+            lastlinedefined: 0,
+            short_src: [0; LUA_IDSIZE],
+            i_ci: 0,
+        }
+    }
+}
+
+// Compares the definitions of this crate with the sizes and offsets computed by the C compiler
+// in `layout.c`. A difference is a compile-time error.
+#[cfg(luajit_sys_layout)]
+mod layout {
+    use super::*;
+    use std::mem::{offset_of, size_of};
+
+    mod c {
+        include!(concat!(env!("OUT_DIR"), "/layout.rs"));
+    }
+
+    const _: () = assert!(size_of::<lua_Debug>() == c::SIZEOF_LUA_DEBUG);
+    const _: () = assert!(offset_of!(lua_Debug, short_src) == c::OFFSETOF_LUA_DEBUG_SHORT_SRC);
+    const _: () = assert!(offset_of!(lua_Debug, i_ci) == c::OFFSETOF_LUA_DEBUG_I_CI);
+    const _: () = assert!(size_of::<luaL_Buffer>() == c::SIZEOF_LUAL_BUFFER);
+    const _: () = assert!(offset_of!(luaL_Buffer, buffer) == c::OFFSETOF_LUAL_BUFFER_BUFFER);
+    const _: () = assert!(size_of::<luaL_Reg>() == c::SIZEOF_LUAL_REG);
+    const _: () = assert!(size_of::<lua_Number>() == c::SIZEOF_LUA_NUMBER);
+    const _: () = assert!(size_of::<lua_Integer>() == c::SIZEOF_LUA_INTEGER);
+    const _: () = assert!(LUA_IDSIZE == c::IDSIZE);
+    const _: () = assert!(LUAL_BUFFERSIZE == c::BUFFERSIZE);
+    const _: () = assert!(LUA_REGISTRYINDEX == c::REGISTRYINDEX);
+    const _: () = assert!(LUA_GLOBALSINDEX == c::GLOBALSINDEX);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::CStr;
+    use std::mem;
+
+    unsafe fn to_str<'a>(s: *const libc::c_char) -> &'a str {
+        CStr::from_ptr(s).to_str().unwrap()
+    }
+
+    extern "C" fn add(L: *mut lua_State) -> c_int {
+        unsafe {
+            let sum = luaL_checkinteger(L, 1) + luaL_optinteger(L, 2, 1);
+            lua_pushinteger(L, sum);
+        }
+        1
+    }
+
+    #[test]
+    fn metatables_and_udata() {
+        unsafe {
+            let L = luaL_newstate();
+            let tname = b"luajit_sys.Test\0".as_ptr() as *const _;
+
+            assert_eq!(luaL_newmetatable(L, tname), 1);
+            assert_eq!(luaL_newmetatable(L, tname), 0);
+            lua_pop(L, 2);
+
+            lua_newuserdata(L, 8);
+            assert!(luaL_testudata(L, -1, tname).is_null());
+            luaL_setmetatable(L, tname);
+            let ptr = lua_touserdata(L, -1);
+            assert_eq!(luaL_testudata(L, -1, tname), ptr);
+            assert_eq!(luaL_checkudata(L, -1, tname), ptr);
+
+            luaL_getmetatable(L, tname);
+            assert!(lua_getmetatable(L, -2) != 0);
+            assert!(lua_rawequal(L, -1, -2) != 0);
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn buffer() {
+        unsafe {
+            let L = luaL_newstate();
+            let mut buffer: luaL_Buffer = mem::zeroed();
+            luaL_buffinit(L, &mut buffer);
+            luaL_addstring(&mut buffer, b"hello\0".as_ptr() as *const _);
+            luaL_addchar(&mut buffer, b' ' as libc::c_char);
+            lua_pushinteger(L, 5);
+            luaL_addvalue(&mut buffer);
+            // More than the buffer, to check that it is flushed to the stack.
+            for _ in 0..LUAL_BUFFERSIZE {
+                luaL_addchar(&mut buffer, b'!' as libc::c_char);
+            }
+            luaL_pushresult(&mut buffer);
+
+            let mut len = 0;
+            let s = lua_tolstring(L, -1, &mut len);
+            assert_eq!(len, 7 + LUAL_BUFFERSIZE);
+            assert!(to_str(s).starts_with("hello 5!!"));
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn load_and_globals() {
+        unsafe {
+            let L = luaL_newstate();
+            luaL_openlibs(L);
+
+            let code = b"return select('#', ...), 'x'";
+            let status = luaL_loadbufferx(
+                L,
+                code.as_ptr() as *const _,
+                code.len(),
+                b"=chunk\0".as_ptr() as *const _,
+                b"b\0".as_ptr() as *const _,
+            );
+            assert_eq!(status, LUA_ERRSYNTAX);
+            lua_pop(L, 1);
+            let status = luaL_loadbuffer(
+                L,
+                code.as_ptr() as *const _,
+                code.len(),
+                b"=chunk\0".as_ptr() as *const _,
+            );
+            assert_eq!(status, LUA_OK);
+            lua_pushinteger(L, 1);
+            lua_pushinteger(L, 2);
+            assert_eq!(lua_pcall(L, 2, 2, 0), LUA_OK);
+            assert_eq!(lua_tointegerx(L, -2, ptr::null_mut()), 2);
+            lua_pop(L, 2);
+
+            assert_eq!(
+                luaL_dostring(L, b"t = {1, 2, 3}\0".as_ptr() as *const _),
+                LUA_OK
+            );
+            lua_getglobal(L, b"t\0".as_ptr() as *const _);
+            assert_eq!(lua_objlen(L, -1), 3);
+            assert_eq!(to_str(luaL_typename(L, -1)), "table");
+            lua_pop(L, 1);
+
+            lua_pushcfunction(L, add);
+            lua_setglobal(L, b"add\0".as_ptr() as *const _);
+            let code = b"return add(1) + add(1, 2)\0";
+            assert_eq!(luaL_dostring(L, code.as_ptr() as *const _), LUA_OK);
+            assert_eq!(lua_tointegerx(L, -1, ptr::null_mut()), 2 + 3);
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn lua52_compat() {
+        unsafe {
+            let L = luaL_newstate();
+            luaL_openlibs(L);
+
+            let code = b"local t = setmetatable({}, {__len = function() return 7 end}) \
+                         return #t, table.unpack({1, 2})\0";
+            assert_eq!(luaL_dostring(L, code.as_ptr() as *const _), LUA_OK);
+            assert_eq!(lua_tointegerx(L, 1, ptr::null_mut()), 7);
+            assert_eq!(lua_gettop(L), 3);
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn traceback() {
+        unsafe {
+            let L = luaL_newstate();
+            luaL_traceback(L, L, b"oops\0".as_ptr() as *const _, 0);
+            let s = to_str(lua_tostring(L, -1));
+            assert!(s.starts_with("oops\nstack traceback:"), "{}", s);
+            lua_close(L);
+        }
+    }
+
+    #[test]
+    fn version() {
+        assert!(LUA_RELEASE_VERSION.starts_with("2.1."));
+        unsafe {
+            assert_eq!(*lua_version(ptr::null_mut()), LUA_VERSION_NUM as lua_Number);
+        }
+    }
+}